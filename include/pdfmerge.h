@@ -0,0 +1,56 @@
+/*
+ * C interface of pdf-merge, exported by the cdylib and staticlib builds with
+ * the `ffi` feature, see src/ffi.rs. Every function returns 0 on success and
+ * -1 on failure, in which case pdfmerge_last_error() describes the problem.
+ */
+#ifndef PDFMERGE_H
+#define PDFMERGE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/*
+ * Merges the n PDF or image files of paths into out_path. Fails when n is 0.
+ */
+int pdfmerge_merge_files(const char *const *paths, size_t n, const char *out_path);
+
+/*
+ * Number of pages of the PDF or image file at path (always 1 for images), or
+ * -1 on failure.
+ */
+int64_t pdfmerge_page_count(const char *path);
+
+/*
+ * Merges n in-memory documents. Each buffer is either a PDF or an image whose
+ * format is given by the extension of the matching names entry (names may be
+ * NULL when every buffer is a PDF). On success the merged PDF is returned
+ * through out_buffer and out_length and must be released with
+ * pdfmerge_free_buffer(). Fails when n is 0.
+ */
+int pdfmerge_merge_buffers(const uint8_t *const *buffers,
+                           const size_t *lengths,
+                           const char *const *names,
+                           size_t n,
+                           uint8_t **out_buffer,
+                           size_t *out_length);
+
+/*
+ * Releases a buffer returned by pdfmerge_merge_buffers().
+ */
+void pdfmerge_free_buffer(uint8_t *buffer, size_t length);
+
+/*
+ * Message of the last error raised on the calling thread, or NULL. The string
+ * stays valid until the next failing call on the same thread.
+ */
+const char *pdfmerge_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PDFMERGE_H */
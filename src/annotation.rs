@@ -0,0 +1,346 @@
+// Annotations of the pages: comments, highlights, links, ...
+use std::collections::HashSet;
+
+use anyhow::{Result, bail};
+use lopdf::{Dictionary, Document, Object, ObjectId};
+
+use crate::stamp::{add_resource, append_content};
+
+// Subtypes of the markup annotations reviewers leave, in the groups the
+// command line accepts besides the subtypes themselves.
+const COMMENTS: [&str; 3] = ["Text", "FreeText", "Popup"];
+const HIGHLIGHTS: [&str; 4] = ["Highlight", "Underline", "StrikeOut", "Squiggly"];
+
+// Annotation subtypes, as written in the PDF (`Highlight`, `Ink`, ...).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AnnotationFilter(HashSet<String>);
+
+impl AnnotationFilter {
+    // Parses subtype names, case-insensitively, and the `comments` and
+    // `highlights` groups.
+    pub fn parse(names: &[String]) -> Result<Self> {
+        const KNOWN: [&str; 22] = [
+            "Text",
+            "Link",
+            "FreeText",
+            "Line",
+            "Square",
+            "Circle",
+            "Polygon",
+            "PolyLine",
+            "Highlight",
+            "Underline",
+            "Squiggly",
+            "StrikeOut",
+            "Stamp",
+            "Caret",
+            "Ink",
+            "Popup",
+            "FileAttachment",
+            "Sound",
+            "Movie",
+            "Widget",
+            "Screen",
+            "Redact",
+        ];
+        let mut subtypes = HashSet::new();
+        for name in names {
+            let name = name.trim();
+            match name.to_ascii_lowercase().as_str() {
+                "comments" => subtypes.extend(COMMENTS.map(String::from)),
+                "highlights" => subtypes.extend(HIGHLIGHTS.map(String::from)),
+                _ => match KNOWN
+                    .iter()
+                    .find(|subtype| subtype.eq_ignore_ascii_case(name))
+                {
+                    Some(subtype) => {
+                        subtypes.insert(subtype.to_string());
+                    }
+                    None => bail!("unknown annotation subtype {:?}", name),
+                },
+            }
+        }
+        Ok(AnnotationFilter(subtypes))
+    }
+
+    // Without subtypes, everything but form fields, which belong to the
+    // document's form rather than to its pages.
+    fn matches(&self, subtype: &[u8]) -> bool {
+        if self.0.is_empty() {
+            return subtype != b"Widget";
+        }
+        self.0
+            .iter()
+            .any(|candidate| candidate.as_bytes() == subtype)
+    }
+}
+
+fn annotation_subtype(doc: &Document, annotation: &Object) -> Option<Vec<u8>> {
+    let (_, annotation) = doc.dereference(annotation).ok()?;
+    let subtype = annotation.as_dict().ok()?.get(b"Subtype").ok()?;
+    Some(doc.dereference(subtype).ok()?.1.as_name().ok()?.to_vec())
+}
+
+// The annotations of a page, resolved from a reference if needed.
+pub(crate) fn page_annotations(doc: &Document, page_id: ObjectId) -> Vec<Object> {
+    doc.get_dictionary(page_id)
+        .and_then(|page| page.get(b"Annots"))
+        .and_then(|annotations| doc.dereference(annotations))
+        .and_then(|(_, annotations)| annotations.as_array())
+        .cloned()
+        .unwrap_or_default()
+}
+
+pub(crate) fn set_page_annotations(
+    doc: &mut Document,
+    page_id: ObjectId,
+    annotations: Vec<Object>,
+) {
+    if let Ok(page) = doc.get_dictionary_mut(page_id) {
+        if annotations.is_empty() {
+            page.remove(b"Annots");
+        } else {
+            page.set("Annots", annotations);
+        }
+    }
+}
+
+// Removes the annotations for which `remove` is true from every page, along
+// with the pop-ups of the removed ones, and returns how many were removed.
+pub(crate) fn remove_annotations(
+    doc: &mut Document,
+    mut remove: impl FnMut(&Document, &Object) -> bool,
+) -> usize {
+    let mut removed = 0;
+    for page_id in doc.get_pages().into_values() {
+        let annotations = page_annotations(doc, page_id);
+        let removed_ids: HashSet<ObjectId> = annotations
+            .iter()
+            .filter(|annotation| remove(doc, annotation))
+            .filter_map(|annotation| annotation.as_reference().ok())
+            .collect();
+        let kept: Vec<Object> = annotations
+            .iter()
+            .filter(|annotation| {
+                if remove(doc, annotation) {
+                    return false;
+                }
+                // A pop-up goes with the annotation it belongs to.
+                let parent = doc
+                    .dereference(annotation)
+                    .and_then(|(_, annotation)| annotation.as_dict())
+                    .and_then(|annotation| annotation.get(b"Parent"))
+                    .and_then(Object::as_reference);
+                !parent.is_ok_and(|parent| removed_ids.contains(&parent))
+            })
+            .cloned()
+            .collect();
+        if kept.len() == annotations.len() {
+            continue;
+        }
+        removed += annotations.len() - kept.len();
+        // The removed annotations are deleted rather than left unreferenced in
+        // the file, where their content could still be read.
+        for annotation in &annotations {
+            if !kept.contains(annotation)
+                && let Ok(id) = annotation.as_reference()
+                && let Some(removed) = doc.objects.remove(&id)
+                && let Ok(popup_id) = removed
+                    .as_dict()
+                    .and_then(|removed| removed.get(b"Popup"))
+                    .and_then(Object::as_reference)
+            {
+                doc.objects.remove(&popup_id);
+            }
+        }
+        set_page_annotations(doc, page_id, kept);
+    }
+    removed
+}
+
+// Removes the annotations matching the filter from every page, and returns
+// how many were removed.
+pub fn strip_annotations(doc: &mut Document, filter: &AnnotationFilter) -> usize {
+    remove_annotations(doc, |doc, annotation| {
+        annotation_subtype(doc, annotation).is_some_and(|subtype| filter.matches(&subtype))
+    })
+}
+
+// Annotations left alone by flattening: links and form fields keep working
+// as annotations, pop-ups are only shown on demand.
+const NOT_FLATTENED: [&[u8]; 3] = [b"Link", b"Widget", b"Popup"];
+
+// `Hidden` and `NoView` annotation flags.
+const HIDDEN_FLAGS: i64 = 2 | 32;
+
+fn number_array<const N: usize>(doc: &Document, object: &Object) -> Option<[f32; N]> {
+    let values: Vec<f32> = doc
+        .dereference(object)
+        .ok()?
+        .1
+        .as_array()
+        .ok()?
+        .iter()
+        .filter_map(|value| doc.dereference(value).ok()?.1.as_float().ok())
+        .collect();
+    values.try_into().ok()
+}
+
+// The normal appearance of the annotation, in its current state for those
+// with several (check boxes, ...).
+fn normal_appearance(doc: &Document, annotation: &Dictionary) -> Option<ObjectId> {
+    let appearances = doc
+        .dereference(annotation.get(b"AP").ok()?)
+        .ok()?
+        .1
+        .as_dict()
+        .ok()?;
+    let normal = appearances.get(b"N").ok()?;
+    if let Ok(id) = normal.as_reference()
+        && doc.get_object(id).and_then(Object::as_stream).is_ok()
+    {
+        return Some(id);
+    }
+    let state = annotation.get(b"AS").and_then(Object::as_name).ok()?;
+    doc.dereference(normal)
+        .ok()?
+        .1
+        .as_dict()
+        .ok()?
+        .get(state)
+        .and_then(Object::as_reference)
+        .ok()
+}
+
+// Content drawing the appearance of the annotation in its rectangle, as
+// described in "Appearance Streams" of the PDF specification: the bounding
+// box of the appearance, once transformed by its matrix, is mapped onto the
+// rectangle of the annotation.
+fn appearance_content(
+    doc: &Document,
+    annotation: &Dictionary,
+    appearance_id: ObjectId,
+    name: &str,
+) -> Option<Vec<u8>> {
+    let appearance = doc.get_object(appearance_id).ok()?.as_stream().ok()?;
+    let [x1, y1, x2, y2] = number_array::<4>(doc, annotation.get(b"Rect").ok()?)?;
+    let [bx1, by1, bx2, by2] = number_array::<4>(doc, appearance.dict.get(b"BBox").ok()?)?;
+    let [a, b, c, d, e, f] = appearance
+        .dict
+        .get(b"Matrix")
+        .ok()
+        .and_then(|matrix| number_array::<6>(doc, matrix))
+        .unwrap_or([1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+
+    let corners = [(bx1, by1), (bx1, by2), (bx2, by1), (bx2, by2)]
+        .map(|(x, y)| (a * x + c * y + e, b * x + d * y + f));
+    let min_x = corners
+        .iter()
+        .map(|(x, _)| *x)
+        .fold(f32::INFINITY, f32::min);
+    let max_x = corners
+        .iter()
+        .map(|(x, _)| *x)
+        .fold(f32::NEG_INFINITY, f32::max);
+    let min_y = corners
+        .iter()
+        .map(|(_, y)| *y)
+        .fold(f32::INFINITY, f32::min);
+    let max_y = corners
+        .iter()
+        .map(|(_, y)| *y)
+        .fold(f32::NEG_INFINITY, f32::max);
+    if max_x - min_x <= 0.0 || max_y - min_y <= 0.0 {
+        return None;
+    }
+
+    let scale_x = (x2 - x1).abs() / (max_x - min_x);
+    let scale_y = (y2 - y1).abs() / (max_y - min_y);
+    Some(
+        format!(
+            "q {} 0 0 {} {} {} cm /{} Do Q\n",
+            scale_x,
+            scale_y,
+            x1.min(x2) - min_x * scale_x,
+            y1.min(y2) - min_y * scale_y,
+            name
+        )
+        .into_bytes(),
+    )
+}
+
+// Draws the appearance of the annotations (highlights, stamps, ink, ...) into
+// the content of their page and removes them, so that they show in every
+// viewer and can no longer be edited. Annotations without an appearance are
+// kept. Returns how many were flattened.
+pub fn flatten_annotations(doc: &mut Document) -> usize {
+    let mut flattened = Vec::new();
+    for page_id in doc.get_pages().into_values() {
+        let mut content = Vec::new();
+        let mut xobjects = Vec::new();
+        for annotation in page_annotations(doc, page_id) {
+            let Ok((_, Object::Dictionary(dictionary))) = doc.dereference(&annotation) else {
+                continue;
+            };
+            let subtype = dictionary.get(b"Subtype").and_then(Object::as_name);
+            let flags = dictionary.get(b"F").and_then(Object::as_i64).unwrap_or(0);
+            if subtype.is_ok_and(|subtype| NOT_FLATTENED.contains(&subtype))
+                || flags & HIDDEN_FLAGS != 0
+            {
+                continue;
+            }
+            let Some(appearance_id) = normal_appearance(doc, dictionary) else {
+                continue;
+            };
+            let name = format!("PdfMergeAnnot{}", xobjects.len());
+            if let Some(drawing) = appearance_content(doc, dictionary, appearance_id, &name) {
+                content.extend(drawing);
+                xobjects.push((name, appearance_id));
+                flattened.push(annotation);
+            }
+        }
+        if content.is_empty() {
+            continue;
+        }
+        for (name, appearance_id) in xobjects {
+            // Appearance streams may omit their type, which `Do` requires.
+            if let Ok(Object::Stream(appearance)) = doc.get_object_mut(appearance_id) {
+                appearance
+                    .dict
+                    .set("Type", Object::Name(b"XObject".to_vec()));
+                appearance
+                    .dict
+                    .set("Subtype", Object::Name(b"Form".to_vec()));
+            }
+            add_resource(doc, page_id, "XObject", &name, appearance_id);
+        }
+        append_content(doc, page_id, content);
+    }
+    remove_annotations(doc, |_, annotation| flattened.contains(annotation))
+}
+
+// Actions leading outside of the document: web links, other files and
+// applications.
+const EXTERNAL_ACTIONS: [&[u8]; 3] = [b"URI", b"GoToR", b"Launch"];
+
+fn is_external_link(doc: &Document, annotation: &Object) -> bool {
+    let Ok((_, Object::Dictionary(annotation))) = doc.dereference(annotation) else {
+        return false;
+    };
+    if annotation.get(b"Subtype").and_then(Object::as_name).ok() != Some(b"Link") {
+        return false;
+    }
+    annotation
+        .get(b"A")
+        .and_then(|action| doc.dereference(action))
+        .and_then(|(_, action)| action.as_dict())
+        .and_then(|action| action.get(b"S"))
+        .and_then(Object::as_name)
+        .is_ok_and(|action_type| EXTERNAL_ACTIONS.contains(&action_type))
+}
+
+// Removes the links to web pages and other files from every page, keeping the
+// links within the document, and returns how many were removed.
+pub fn strip_external_links(doc: &mut Document) -> usize {
+    remove_annotations(doc, is_external_link)
+}
@@ -0,0 +1,525 @@
+// Minimal ZIP reader, enough to extract the documents of an archive in
+// memory: stored and deflated entries, ZIP64, no encryption, no multi-disk
+// archives.
+use std::io::Read;
+
+use anyhow::{Context, Result, bail};
+use byteorder::{ByteOrder, LittleEndian};
+use flate2::read::DeflateDecoder;
+
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0605_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0201_4b50;
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+// Extra field holding the sizes and offset which do not fit in 32 bits.
+const ZIP64_EXTRA_FIELD: u16 = 0x0001;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+// Total size of the extracted entries, above which the archive is rejected
+// rather than expanded in memory, e.g. for a ZIP bomb.
+pub const MAX_EXTRACTED_SIZE: u64 = 2 << 30;
+
+pub struct ZipEntry {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+// An entry which cannot be extracted, with the reason, e.g. "it is encrypted".
+pub struct SkippedEntry {
+    pub name: String,
+    pub reason: String,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16> {
+    let slice = bytes
+        .get(offset..offset + 2)
+        .context("truncated ZIP archive")?;
+    Ok(LittleEndian::read_u16(slice))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    let slice = bytes
+        .get(offset..offset + 4)
+        .context("truncated ZIP archive")?;
+    Ok(LittleEndian::read_u32(slice))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64> {
+    let slice = bytes
+        .get(offset..offset + 8)
+        .context("truncated ZIP archive")?;
+    Ok(LittleEndian::read_u64(slice))
+}
+
+fn to_offset(value: u64) -> Result<usize> {
+    usize::try_from(value).context("corrupted ZIP archive")
+}
+
+fn find_end_of_central_directory(bytes: &[u8]) -> Result<usize> {
+    // The record is 22 bytes long plus a comment of at most 65535 bytes.
+    let min_offset = bytes.len().saturating_sub(22 + u16::MAX as usize);
+    (min_offset..=bytes.len().saturating_sub(22))
+        .rev()
+        .find(|&offset| read_u32(bytes, offset).ok() == Some(END_OF_CENTRAL_DIRECTORY_SIGNATURE))
+        .context("not a ZIP archive")
+}
+
+// The number of entries and the offset of the central directory, from the
+// ZIP64 record when the archive has one.
+fn central_directory(bytes: &[u8]) -> Result<(u64, usize)> {
+    let end = find_end_of_central_directory(bytes)?;
+    let entry_count = read_u16(bytes, end + 10)?;
+    let offset = read_u32(bytes, end + 16)?;
+    let locator = end.checked_sub(20);
+    match locator {
+        Some(locator) if read_u32(bytes, locator)? == ZIP64_LOCATOR_SIGNATURE => {
+            let record = to_offset(read_u64(bytes, locator + 8)?)?;
+            if read_u32(bytes, record)? != ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE {
+                bail!("corrupted ZIP64 end of central directory");
+            }
+            Ok((
+                read_u64(bytes, record + 32)?,
+                to_offset(read_u64(bytes, record + 48)?)?,
+            ))
+        }
+        _ => Ok((entry_count as u64, offset as usize)),
+    }
+}
+
+// The 64-bit values of the ZIP64 extra field, in order, for the fields of the
+// central directory set to 0xFFFFFFFF.
+fn zip64_values(extra: &[u8]) -> Result<Vec<u64>> {
+    let mut offset = 0;
+    while offset + 4 <= extra.len() {
+        let id = read_u16(extra, offset)?;
+        let length = read_u16(extra, offset + 2)? as usize;
+        let data = extra
+            .get(offset + 4..offset + 4 + length)
+            .context("truncated ZIP extra field")?;
+        if id == ZIP64_EXTRA_FIELD {
+            return Ok(data.chunks_exact(8).map(LittleEndian::read_u64).collect());
+        }
+        offset += 4 + length;
+    }
+    Ok(Vec::new())
+}
+
+// Where an entry is and how to extract it, from the central directory.
+struct EntryHeader {
+    flags: u16,
+    method: u16,
+    crc: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    local_header_offset: usize,
+}
+
+fn extract(bytes: &[u8], header: &EntryHeader) -> Result<Vec<u8>> {
+    let local_header_offset = header.local_header_offset;
+    if read_u32(bytes, local_header_offset)? != LOCAL_HEADER_SIGNATURE {
+        bail!("corrupted ZIP archive");
+    }
+    let name_length = read_u16(bytes, local_header_offset + 26)? as usize;
+    let extra_length = read_u16(bytes, local_header_offset + 28)? as usize;
+    let data_offset = local_header_offset + 30 + name_length + extra_length;
+    let data = bytes
+        .get(data_offset..)
+        .and_then(|data| data.get(..to_offset(header.compressed_size).ok()?))
+        .context("truncated ZIP archive")?;
+
+    // Never more than the declared size is decompressed, whatever the data.
+    let mut extracted = Vec::new();
+    match header.method {
+        STORED => extracted.extend_from_slice(data),
+        DEFLATED => {
+            DeflateDecoder::new(data)
+                .take(header.uncompressed_size + 1)
+                .read_to_end(&mut extracted)?;
+        }
+        method => bail!("unsupported ZIP compression method {}", method),
+    }
+    if extracted.len() as u64 != header.uncompressed_size {
+        bail!("size does not match the ZIP central directory");
+    }
+    if crc32fast::hash(&extracted) != header.crc {
+        bail!("CRC-32 mismatch");
+    }
+    Ok(extracted)
+}
+
+// Returns the files of the archive for which `wanted` returns true, in the
+// order of its central directory, which is the order they were added in.
+// Directories are ignored. The encrypted entries and those compressed with
+// another method than deflate are returned apart, so that the other entries
+// can still be used.
+pub fn read_zip_entries(
+    bytes: &[u8],
+    wanted: impl Fn(&str) -> bool,
+) -> Result<(Vec<ZipEntry>, Vec<SkippedEntry>)> {
+    let (entry_count, mut offset) = central_directory(bytes)?;
+
+    let mut headers = Vec::new();
+    let mut skipped = Vec::new();
+    for _ in 0..entry_count {
+        if read_u32(bytes, offset)? != CENTRAL_DIRECTORY_SIGNATURE {
+            bail!("corrupted ZIP central directory");
+        }
+        let name_length = read_u16(bytes, offset + 28)? as usize;
+        let extra_length = read_u16(bytes, offset + 30)? as usize;
+        let comment_length = read_u16(bytes, offset + 32)? as usize;
+        let name_bytes = bytes
+            .get(offset + 46..offset + 46 + name_length)
+            .context("truncated ZIP archive")?;
+        let extra = bytes
+            .get(offset + 46 + name_length..offset + 46 + name_length + extra_length)
+            .context("truncated ZIP archive")?;
+
+        let mut zip64 = zip64_values(extra)?.into_iter();
+        let mut field = |value: u32| -> Result<u64> {
+            if value != u32::MAX {
+                return Ok(value as u64);
+            }
+            zip64.next().context("missing ZIP64 extra field")
+        };
+        let uncompressed_size = field(read_u32(bytes, offset + 24)?)?;
+        let compressed_size = field(read_u32(bytes, offset + 20)?)?;
+        let local_header_offset = to_offset(field(read_u32(bytes, offset + 42)?)?)?;
+        let header = EntryHeader {
+            flags: read_u16(bytes, offset + 8)?,
+            method: read_u16(bytes, offset + 10)?,
+            crc: read_u32(bytes, offset + 16)?,
+            compressed_size,
+            uncompressed_size,
+            local_header_offset,
+        };
+        offset += 46 + name_length + extra_length + comment_length;
+
+        // Names are UTF-8 when bit 11 is set, and most archivers write UTF-8
+        // anyway, so the legacy code page is not decoded.
+        let name = String::from_utf8_lossy(name_bytes).into_owned();
+        if name.ends_with('/') || !wanted(&name) {
+            continue;
+        }
+        let reason = if header.flags & 1 != 0 {
+            Some("it is encrypted".to_string())
+        } else if header.method != STORED && header.method != DEFLATED {
+            Some(format!(
+                "unsupported ZIP compression method {}",
+                header.method
+            ))
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            skipped.push(SkippedEntry { name, reason });
+            continue;
+        }
+        headers.push((name, header));
+    }
+
+    // Checked before anything is decompressed, from the declared sizes which
+    // the extraction never exceeds.
+    let extracted_size = headers.iter().fold(0u64, |size, (_, header)| {
+        size.saturating_add(header.uncompressed_size)
+    });
+    if extracted_size > MAX_EXTRACTED_SIZE {
+        bail!(
+            "the archive expands to more than {} GiB",
+            MAX_EXTRACTED_SIZE >> 30
+        );
+    }
+    let mut entries = Vec::with_capacity(headers.len());
+    for (name, header) in headers {
+        let data = extract(bytes, &header).with_context(|| format!("cannot extract {}", name))?;
+        entries.push(ZipEntry { name, data });
+    }
+    Ok((entries, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use byteorder::WriteBytesExt;
+    use flate2::{Compression, write::DeflateEncoder};
+
+    use super::*;
+
+    // A ZIP archive of `(name, method, flags, data)` entries, with the sizes
+    // and offsets in ZIP64 records and extra fields when `zip64` is set.
+    fn build_zip(entries: &[(&str, u16, u16, &[u8])], zip64: bool) -> Vec<u8> {
+        let mut archive = Vec::new();
+        let mut central_directory = Vec::new();
+        for (name, method, flags, data) in entries {
+            let compressed = match *method {
+                DEFLATED => {
+                    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                    encoder.write_all(data).unwrap();
+                    encoder.finish().unwrap()
+                }
+                _ => data.to_vec(),
+            };
+            let offset = archive.len() as u32;
+            let header = |signature: u32, central: bool| {
+                let zip64 = zip64 && central;
+                let field = |value: u32| if zip64 { u32::MAX } else { value };
+                let mut extra = Vec::new();
+                if zip64 {
+                    extra.write_u16::<LittleEndian>(ZIP64_EXTRA_FIELD).unwrap();
+                    extra.write_u16::<LittleEndian>(24).unwrap();
+                    for value in [data.len(), compressed.len(), offset as usize] {
+                        extra.write_u64::<LittleEndian>(value as u64).unwrap();
+                    }
+                }
+                let mut header = Vec::new();
+                header.write_u32::<LittleEndian>(signature).unwrap();
+                if central {
+                    header.write_u16::<LittleEndian>(45).unwrap();
+                }
+                header.write_u16::<LittleEndian>(45).unwrap();
+                header.write_u16::<LittleEndian>(*flags).unwrap();
+                header.write_u16::<LittleEndian>(*method).unwrap();
+                header.extend([0; 4]);
+                header
+                    .write_u32::<LittleEndian>(crc32fast::hash(data))
+                    .unwrap();
+                header
+                    .write_u32::<LittleEndian>(field(compressed.len() as u32))
+                    .unwrap();
+                header
+                    .write_u32::<LittleEndian>(field(data.len() as u32))
+                    .unwrap();
+                header.write_u16::<LittleEndian>(name.len() as u16).unwrap();
+                header
+                    .write_u16::<LittleEndian>(extra.len() as u16)
+                    .unwrap();
+                if central {
+                    header.extend([0; 10]);
+                    header.write_u32::<LittleEndian>(field(offset)).unwrap();
+                }
+                header.extend(name.as_bytes());
+                header.extend(extra);
+                header
+            };
+            archive.extend(header(LOCAL_HEADER_SIGNATURE, false));
+            archive.extend(&compressed);
+            central_directory.extend(header(CENTRAL_DIRECTORY_SIGNATURE, true));
+        }
+        let central_directory_offset = archive.len() as u64;
+        archive.extend(&central_directory);
+        if zip64 {
+            let record_offset = archive.len() as u64;
+            archive
+                .write_u32::<LittleEndian>(ZIP64_END_OF_CENTRAL_DIRECTORY_SIGNATURE)
+                .unwrap();
+            archive.write_u64::<LittleEndian>(44).unwrap();
+            archive.extend([45, 0, 45, 0]);
+            archive.extend([0; 8]);
+            for value in [
+                entries.len() as u64,
+                entries.len() as u64,
+                central_directory.len() as u64,
+                central_directory_offset,
+            ] {
+                archive.write_u64::<LittleEndian>(value).unwrap();
+            }
+            archive
+                .write_u32::<LittleEndian>(ZIP64_LOCATOR_SIGNATURE)
+                .unwrap();
+            archive.write_u32::<LittleEndian>(0).unwrap();
+            archive.write_u64::<LittleEndian>(record_offset).unwrap();
+            archive.write_u32::<LittleEndian>(1).unwrap();
+        }
+        let field16 = |value: usize| if zip64 { u16::MAX } else { value as u16 };
+        let field32 = |value: u64| if zip64 { u32::MAX } else { value as u32 };
+        archive
+            .write_u32::<LittleEndian>(END_OF_CENTRAL_DIRECTORY_SIGNATURE)
+            .unwrap();
+        archive.extend([0; 4]);
+        archive
+            .write_u16::<LittleEndian>(field16(entries.len()))
+            .unwrap();
+        archive
+            .write_u16::<LittleEndian>(field16(entries.len()))
+            .unwrap();
+        archive
+            .write_u32::<LittleEndian>(central_directory.len() as u32)
+            .unwrap();
+        archive
+            .write_u32::<LittleEndian>(field32(central_directory_offset))
+            .unwrap();
+        archive.write_u16::<LittleEndian>(0).unwrap();
+        archive
+    }
+
+    fn zip(entries: &[(&str, u16, u16, &[u8])]) -> Vec<u8> {
+        build_zip(entries, false)
+    }
+
+    fn read_all(bytes: &[u8]) -> Result<Vec<ZipEntry>> {
+        let (entries, skipped) = read_zip_entries(bytes, |_| true)?;
+        assert!(skipped.is_empty());
+        Ok(entries)
+    }
+
+    fn names_and_data(entries: &[ZipEntry]) -> Vec<(&str, &[u8])> {
+        entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.data.as_slice()))
+            .collect()
+    }
+
+    #[test]
+    fn reads_stored_and_deflated_entries_in_order() {
+        let archive = zip(&[
+            ("b.pdf", STORED, 0, b"%PDF-1.7 b"),
+            ("docs/", STORED, 0, b""),
+            ("docs/a.pdf", DEFLATED, 0, b"%PDF-1.7 aaaaaaaaaaaaaaaa"),
+        ]);
+        let entries = read_all(&archive).unwrap();
+        assert_eq!(
+            names_and_data(&entries),
+            [
+                ("b.pdf", b"%PDF-1.7 b".as_slice()),
+                ("docs/a.pdf", b"%PDF-1.7 aaaaaaaaaaaaaaaa".as_slice()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_zip64_archives() {
+        let archive = build_zip(
+            &[
+                ("a.pdf", STORED, 0, b"%PDF-1.7 a"),
+                ("b.pdf", DEFLATED, 0, b"%PDF-1.7 bbbbbbbbbbbbbbbb"),
+            ],
+            true,
+        );
+        let entries = read_all(&archive).unwrap();
+        assert_eq!(
+            names_and_data(&entries),
+            [
+                ("a.pdf", b"%PDF-1.7 a".as_slice()),
+                ("b.pdf", b"%PDF-1.7 bbbbbbbbbbbbbbbb".as_slice()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_archives_with_a_comment() {
+        let mut archive = zip(&[("a.pdf", STORED, 0, b"a")]);
+        let comment = b"made by a scanner";
+        let length = archive.len();
+        archive[length - 2..].copy_from_slice(&(comment.len() as u16).to_le_bytes());
+        archive.extend(comment);
+        assert_eq!(read_all(&archive).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn reads_empty_archives() {
+        assert!(read_all(&zip(&[])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn extracts_only_the_wanted_entries() {
+        // The unwanted entry is corrupted, which goes unnoticed as it is not
+        // extracted.
+        let mut archive = zip(&[
+            ("a.pdf", STORED, 0, b"%PDF-1.7 a"),
+            ("notes.doc", STORED, 0, b"notes"),
+        ]);
+        let position = archive.windows(5).position(|data| data == b"notes");
+        archive[position.unwrap()] = b'N';
+        let (entries, skipped) = read_zip_entries(&archive, |name| name.ends_with(".pdf")).unwrap();
+        assert_eq!(
+            names_and_data(&entries),
+            [("a.pdf", b"%PDF-1.7 a".as_slice())]
+        );
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn skips_encrypted_entries_and_unsupported_methods() {
+        let archive = zip(&[
+            ("secret.pdf", STORED, 1, b"a"),
+            ("a.pdf", STORED, 0, b"a"),
+            ("b.pdf", 12, 0, b"b"),
+        ]);
+        let (entries, skipped) = read_zip_entries(&archive, |_| true).unwrap();
+        assert_eq!(names_and_data(&entries), [("a.pdf", b"a".as_slice())]);
+        let skipped: Vec<(&str, &str)> = skipped
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.reason.as_str()))
+            .collect();
+        assert_eq!(
+            skipped,
+            [
+                ("secret.pdf", "it is encrypted"),
+                ("b.pdf", "unsupported ZIP compression method 12"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_corrupted_entries() {
+        let mut archive = zip(&[("a.pdf", STORED, 0, b"%PDF-1.7")]);
+        let position = archive.windows(8).position(|data| data == b"%PDF-1.7");
+        archive[position.unwrap() + 5] = b'4';
+        let error = read_all(&archive).err().unwrap();
+        assert_eq!(
+            format!("{:#}", error),
+            "cannot extract a.pdf: CRC-32 mismatch"
+        );
+    }
+
+    #[test]
+    fn never_decompresses_more_than_the_declared_size() {
+        let data = [0; 1 << 20];
+        let mut archive = zip(&[("a.pdf", DEFLATED, 0, &data)]);
+        // Declares 16 bytes in the central directory.
+        let central_directory = archive
+            .windows(4)
+            .position(|bytes| bytes == CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes())
+            .unwrap();
+        archive[central_directory + 24..central_directory + 28]
+            .copy_from_slice(&16u32.to_le_bytes());
+        let error = read_all(&archive).err().unwrap();
+        assert_eq!(
+            format!("{:#}", error),
+            "cannot extract a.pdf: size does not match the ZIP central directory"
+        );
+    }
+
+    #[test]
+    fn rejects_archives_expanding_beyond_the_limit() {
+        let mut archive = zip(&[("a.pdf", DEFLATED, 0, b"a"), ("b.pdf", DEFLATED, 0, b"b")]);
+        // Each entry declares 1.5 GiB in the central directory.
+        let size = (3u32 << 29).to_le_bytes();
+        for offset in 0..archive.len() - 4 {
+            if archive[offset..offset + 4] == CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes() {
+                archive[offset + 24..offset + 28].copy_from_slice(&size);
+            }
+        }
+        let error = read_all(&archive).err().unwrap();
+        assert_eq!(error.to_string(), "the archive expands to more than 2 GiB");
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(read_all(b"%PDF-1.7").is_err());
+        assert!(read_all(b"").is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_archives() {
+        let archive = zip(&[("a.pdf", STORED, 0, b"%PDF-1.7")]);
+        let mut truncated = archive[20..].to_vec();
+        assert!(read_all(&truncated).is_err());
+        truncated = archive.clone();
+        truncated.drain(30..35);
+        assert!(read_all(&truncated).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+// Files attached to the inputs, listed in the `EmbeddedFiles` name tree of
+// their catalog. The merged document only has one catalog, the attachments of
+// every input are gathered into it.
+use anyhow::{Result, bail};
+use lopdf::{Dictionary, Document, Object, decode_text_string};
+
+use crate::metadata::text_string;
+
+// Guards against loops in the name trees of corrupted files.
+const MAX_DEPTH: usize = 32;
+
+// What to do with attachments of several inputs sharing a name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AttachmentPolicy {
+    // Keep all of them, numbering the later ones: `report (2).xlsx`.
+    #[default]
+    Rename,
+    KeepFirst,
+    KeepLast,
+    // Fail the merge.
+    Error,
+}
+
+impl AttachmentPolicy {
+    pub fn parse(policy: &str) -> Result<Self> {
+        Ok(match policy.trim().to_ascii_lowercase().as_str() {
+            "rename" => AttachmentPolicy::Rename,
+            "keep-first" => AttachmentPolicy::KeepFirst,
+            "keep-last" => AttachmentPolicy::KeepLast,
+            "error" => AttachmentPolicy::Error,
+            _ => bail!(
+                "unknown attachment policy {:?}, expected rename, keep-first, keep-last or error",
+                policy
+            ),
+        })
+    }
+}
+
+fn resolve<'a>(doc: &'a Document, object: &'a Object) -> Option<&'a Object> {
+    doc.dereference(object).ok().map(|(_, object)| object)
+}
+
+// Collects the keys and values of a name tree, in key order.
+fn name_tree_entries(
+    doc: &Document,
+    node: &Dictionary,
+    depth: usize,
+    entries: &mut Vec<(String, Object)>,
+) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+    if let Some(Ok(names)) = node
+        .get(b"Names")
+        .ok()
+        .and_then(|names| resolve(doc, names))
+        .map(Object::as_array)
+    {
+        for pair in names.chunks_exact(2) {
+            if let Ok(name) = decode_text_string(&pair[0]) {
+                entries.push((name, pair[1].clone()));
+            }
+        }
+    }
+    if let Some(Ok(kids)) = node
+        .get(b"Kids")
+        .ok()
+        .and_then(|kids| resolve(doc, kids))
+        .map(Object::as_array)
+    {
+        for kid in kids {
+            if let Some(Ok(kid)) = resolve(doc, kid).map(Object::as_dict) {
+                name_tree_entries(doc, kid, depth + 1, entries);
+            }
+        }
+    }
+}
+
+// The attachments of `doc`: their names and file specifications, which may
+// be references to objects of `doc`.
+pub fn embedded_files(doc: &Document) -> Vec<(String, Object)> {
+    let Some(Ok(tree)) = doc
+        .catalog()
+        .and_then(|catalog| catalog.get(b"Names"))
+        .ok()
+        .and_then(|names| resolve(doc, names))
+        .and_then(|names| names.as_dict().ok()?.get(b"EmbeddedFiles").ok())
+        .and_then(|tree| resolve(doc, tree))
+        .map(Object::as_dict)
+    else {
+        return Vec::new();
+    };
+    let mut entries = Vec::new();
+    name_tree_entries(doc, tree, 0, &mut entries);
+    entries
+}
+
+// `report.xlsx` numbered `report (2).xlsx`.
+fn numbered_name(name: &str, number: usize) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            format!("{} ({}).{}", stem, number, extension)
+        }
+        _ => format!("{} ({})", name, number),
+    }
+}
+
+// The attachments of the merged document, gathered input by input.
+#[derive(Debug)]
+pub struct Attachments {
+    policy: AttachmentPolicy,
+    // Names, file specifications and whether the name was changed.
+    files: Vec<(String, Object, bool)>,
+}
+
+impl Attachments {
+    pub fn new(policy: AttachmentPolicy) -> Self {
+        Attachments {
+            policy,
+            files: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, files: Vec<(String, Object)>) -> Result<()> {
+        for (name, file_spec) in files {
+            let existing = self.files.iter().position(|(other, _, _)| *other == name);
+            let Some(existing) = existing else {
+                self.files.push((name, file_spec, false));
+                continue;
+            };
+            match self.policy {
+                AttachmentPolicy::Rename => {
+                    let name = (2..)
+                        .map(|number| numbered_name(&name, number))
+                        .find(|numbered| self.files.iter().all(|(other, _, _)| other != numbered))
+                        .unwrap_or(name);
+                    self.files.push((name, file_spec, true));
+                }
+                AttachmentPolicy::KeepFirst => {}
+                AttachmentPolicy::KeepLast => self.files[existing] = (name, file_spec, false),
+                AttachmentPolicy::Error => {
+                    bail!("several inputs have an attachment named {:?}", name)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Replaces the attachments of the catalog of `doc` with the gathered ones,
+    // whose file specifications are objects of `doc` by now. Renamed files
+    // get their new name in their file specification too.
+    pub fn set(self, doc: &mut Document, catalog: &mut Dictionary) {
+        if self.files.is_empty() {
+            return;
+        }
+        let mut entries = Vec::with_capacity(self.files.len());
+        for (name, mut file_spec, renamed) in self.files {
+            if renamed {
+                let dictionary = match &mut file_spec {
+                    Object::Reference(id) => doc.get_dictionary_mut(*id).ok(),
+                    object => object.as_dict_mut().ok(),
+                };
+                if let Some(dictionary) = dictionary {
+                    dictionary.set("F", text_string(&name));
+                    dictionary.set("UF", text_string(&name));
+                }
+            }
+            entries.push((text_string(&name), file_spec));
+        }
+        // Name trees are sorted by the bytes of their keys.
+        entries.sort_by(|(a, _), (b, _)| {
+            a.as_str()
+                .unwrap_or_default()
+                .cmp(b.as_str().unwrap_or_default())
+        });
+        let names = entries
+            .into_iter()
+            .flat_map(|(name, file_spec)| [name, file_spec])
+            .collect();
+        let tree = Dictionary::from_iter(vec![("Names", Object::Array(names))]);
+        let names_id = catalog.get(b"Names").and_then(Object::as_reference).ok();
+        let names = match names_id {
+            Some(names_id) => doc.get_dictionary_mut(names_id).ok(),
+            None => {
+                if !catalog
+                    .get(b"Names")
+                    .is_ok_and(|names| names.as_dict().is_ok())
+                {
+                    catalog.set("Names", Dictionary::new());
+                }
+                catalog.get_mut(b"Names").and_then(Object::as_dict_mut).ok()
+            }
+        };
+        if let Some(names) = names {
+            names.set("EmbeddedFiles", tree);
+        }
+    }
+}
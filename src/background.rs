@@ -0,0 +1,87 @@
+// The first page of another PDF, such as a letterhead, drawn underneath or
+// on top of every page of a document. It is imported once as a form XObject
+// shared by all the pages.
+use anyhow::{Result, bail};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+
+use crate::{
+    index::page_to_form,
+    stamp::{add_resource, append_content, page_box},
+};
+
+// Resource name of the form, unlikely to clash with the page's own.
+const BACKGROUND_XOBJECT: &str = "PdfMergeBackground";
+const OVERLAY_XOBJECT: &str = "PdfMergeOverlay";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    Background,
+    Overlay,
+}
+
+// Draws `content` underneath the page, before its existing content.
+fn prepend_content(doc: &mut Document, page_id: ObjectId, content: Vec<u8>) {
+    let existing: Vec<Object> = match doc
+        .get_dictionary(page_id)
+        .and_then(|page| page.get(b"Contents"))
+    {
+        Ok(Object::Array(contents)) => contents.clone(),
+        Ok(contents) => vec![contents.clone()],
+        Err(_) => Vec::new(),
+    };
+
+    let content_id = doc.add_object(Stream::new(Dictionary::new(), content));
+    let mut contents = vec![Object::Reference(content_id)];
+    contents.extend(existing);
+    if let Ok(page) = doc.get_dictionary_mut(page_id) {
+        page.set("Contents", contents);
+    }
+}
+
+// Moves the objects of `other` into `doc` and returns the id its first page
+// has there. The pages tree and catalog of `other` come along but are not
+// referenced, they go with the next `remove_unreferenced_objects`.
+fn import_first_page(doc: &mut Document, mut other: Document) -> Result<ObjectId> {
+    other.renumber_objects_with(doc.max_id + 1);
+    let Some(page_id) = other.get_pages().into_values().next() else {
+        bail!("the document has no pages");
+    };
+    doc.max_id = doc.max_id.max(other.max_id);
+    doc.objects.extend(other.objects);
+    Ok(page_id)
+}
+
+// Draws the first page of `source` on every page of `doc`, scaled to fit the
+// page and centered on it.
+pub fn add_background(doc: &mut Document, source: Document, layer: Layer) -> Result<()> {
+    let source_page_id = import_first_page(doc, source)?;
+    let [sx0, sy0, sx1, sy1] = page_box(doc, source_page_id);
+    let form_id = page_to_form(doc, source_page_id);
+    let (source_width, source_height) = ((sx1 - sx0).abs(), (sy1 - sy0).abs());
+    if source_width == 0.0 || source_height == 0.0 {
+        bail!("the first page is empty");
+    }
+
+    let name = match layer {
+        Layer::Background => BACKGROUND_XOBJECT,
+        Layer::Overlay => OVERLAY_XOBJECT,
+    };
+    for page_id in doc.get_pages().into_values() {
+        add_resource(doc, page_id, "XObject", name, form_id);
+        let [x0, y0, x1, y1] = page_box(doc, page_id);
+        let (width, height) = ((x1 - x0).abs(), (y1 - y0).abs());
+        let scale = (width / source_width).min(height / source_height);
+        let left = x0.min(x1) + (width - source_width * scale) / 2.0 - sx0.min(sx1) * scale;
+        let bottom = y0.min(y1) + (height - source_height * scale) / 2.0 - sy0.min(sy1) * scale;
+        let content = format!(
+            "q {} 0 0 {} {} {} cm /{} Do Q\n",
+            scale, scale, left, bottom, name
+        )
+        .into_bytes();
+        match layer {
+            Layer::Background => prepend_content(doc, page_id, content),
+            Layer::Overlay => append_content(doc, page_id, content),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,157 @@
+// Inputs kept on disk as they are loaded with `--checkpoint`, so that running
+// a merge again after an interruption reuses the inputs already downloaded
+// and converted instead of starting over. The files of the inputs are removed
+// once the output is saved.
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use lopdf::Document;
+
+use crate::{
+    checksum::{sha256, to_hex},
+    input::{LoadedInput, is_stdin},
+    loader::{LoadOptions, load_input_with_retries},
+    log,
+    remote::is_url,
+    s3,
+};
+
+// Name of the checkpoint directory in the temporary directory.
+const CHECKPOINT_DIR_NAME: &str = "pdf-merge-checkpoint";
+
+// Files of the checkpoint written or read back by this run.
+static USED_FILES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    dir: PathBuf,
+}
+
+impl Checkpoint {
+    pub fn new(temp_dir: Option<&Path>) -> io::Result<Self> {
+        let dir = temp_dir
+            .map_or_else(env::temp_dir, Path::to_path_buf)
+            .join(CHECKPOINT_DIR_NAME);
+        fs::create_dir_all(&dir)?;
+        Ok(Checkpoint { dir })
+    }
+
+    // Identifies an input with the options it is converted with and, for
+    // local files, their size and modification time, so that an input
+    // changed since the interruption is loaded again. Stdin cannot be.
+    fn key(path: &Path, options: &LoadOptions) -> Option<String> {
+        if is_stdin(path) {
+            return None;
+        }
+        let mut key = format!("{:?}|{:?}", path, options.image);
+        if !is_url(path) && !s3::is_s3_url(path) {
+            let metadata = fs::metadata(path).ok()?;
+            key.push_str(&format!(
+                "|{}|{:?}",
+                metadata.len(),
+                metadata.modified().ok()?
+            ));
+        }
+        Some(to_hex(&sha256(key.as_bytes())))
+    }
+
+    fn list_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.list", key))
+    }
+
+    fn document_path(&self, key: &str, index: usize) -> PathBuf {
+        self.dir.join(format!("{}-{}.pdf", key, index))
+    }
+
+    // The inputs saved by a previous run, listed by path in the list file
+    // written once all of their documents are saved.
+    fn restore(&self, key: &str) -> Option<Vec<LoadedInput>> {
+        let list_path = self.list_path(key);
+        let list = fs::read_to_string(&list_path).ok()?;
+        let mut used_files = vec![list_path];
+        let inputs = list
+            .lines()
+            .enumerate()
+            .map(|(index, path)| {
+                let document_path = self.document_path(key, index);
+                let document = Document::load(&document_path).ok()?;
+                used_files.push(document_path);
+                Some(LoadedInput::new(PathBuf::from(path), document))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        USED_FILES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend(used_files);
+        Some(inputs)
+    }
+
+    fn store(&self, key: &str, inputs: &mut [LoadedInput]) -> Result<()> {
+        let mut list = String::new();
+        for (index, input) in inputs.iter_mut().enumerate() {
+            let document_path = self.document_path(key, index);
+            USED_FILES
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(document_path.clone());
+            input
+                .document
+                .save(&document_path)
+                .with_context(|| format!("cannot write {}", document_path.display()))?;
+            list.push_str(&input.path.to_string_lossy());
+            list.push('\n');
+        }
+        // Renamed into place so that an interrupted write leaves no list.
+        let list_path = self.list_path(key);
+        let staging_path = list_path.with_extension("list.tmp");
+        fs::write(&staging_path, list)
+            .and_then(|()| fs::rename(&staging_path, &list_path))
+            .with_context(|| format!("cannot write {}", list_path.display()))?;
+        USED_FILES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(list_path);
+        Ok(())
+    }
+
+    // Loads an input from the checkpoint when a previous run saved it there,
+    // otherwise loads it and saves it there. A checkpoint that cannot be
+    // written only costs the ability to resume.
+    pub fn load(
+        &self,
+        path: &Path,
+        options: &LoadOptions,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<LoadedInput>> {
+        let Some(key) = Self::key(path, options) else {
+            return load_input_with_retries(path, options, timeout);
+        };
+        if let Some(inputs) = self.restore(&key) {
+            log::info(&format!("Resuming {} from the checkpoint", path.display()));
+            return Ok(inputs);
+        }
+        let mut inputs = load_input_with_retries(path, options, timeout)?;
+        if let Err(e) = self.store(&key, &mut inputs) {
+            log::warning(&format!("{:#}", e));
+        }
+        Ok(inputs)
+    }
+
+    // Removes the files of the inputs of this run, once they are merged.
+    pub fn remove_used_files(&self) {
+        for path in USED_FILES
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .drain(..)
+        {
+            let _ = fs::remove_file(path);
+        }
+        // Only removed once no other run has inputs in it.
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
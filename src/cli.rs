@@ -0,0 +1,856 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, TimeDelta, Utc};
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use pm::{
+    attachment::AttachmentPolicy,
+    compression::CompressionLevel,
+    encryption::EncryptionAlgorithm,
+    image::image_document::DEFAULT_DPI,
+    merge::{BookmarkMode, DestinationFit, FontStyle, OpenActionSource, parse_color},
+    pagesize::PageSizeInMm,
+};
+use regex::Regex;
+
+// When the input directory or the output file is missing, the tool falls back
+// to file dialogs to ask for them.
+#[derive(Parser, Debug)]
+#[command(
+    name = "pdf-merge",
+    version,
+    about = "Merge the PDF and image files of a directory into a single PDF",
+    after_help = "Exit status: 0 on success, 1 on failure, 3 when the output was written without \
+                  some skipped inputs",
+    args_conflicts_with_subcommands = true,
+    group(ArgGroup::new("encrypted").args(["encrypt_to", "owner_password_key"]))
+)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// `<input_directory> <output_file>`: directory where the tool will search for .pdf files and
+    /// file to save the merged pdf result. Any other list of files, directories and `http(s)://`
+    /// URLs is merged as is and the output file is asked for with a save dialog. `-` reads a PDF
+    /// document from stdin
+    #[arg(value_name = "PATHS")]
+    pub paths: Vec<PathBuf>,
+
+    /// File to save the merged PDF to; all the positional arguments are then inputs
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// For file manager integrations, such as a macOS Quick Action running the shell script
+    /// `pdf-merge --quick-action "$@"`: all the positional arguments are inputs, and the output is
+    /// asked for with a save dialog opened next to them
+    #[arg(long, conflicts_with = "plan")]
+    pub quick_action: bool,
+
+    /// Open the merged PDF in the default viewer once it is saved
+    #[arg(long)]
+    pub open: bool,
+
+    /// Show a desktop notification when the merge is done or fails, for long merges running in
+    /// the background
+    #[arg(long)]
+    pub notify: bool,
+
+    /// Rename an existing output file to `<output>.bak` (or a timestamped name when that backup
+    /// exists too) instead of overwriting it
+    #[arg(long)]
+    pub backup: bool,
+
+    /// Print the checksum of the merged PDF
+    #[arg(long, value_enum, value_name = "ALGORITHM")]
+    pub checksum: Option<ChecksumAlgorithm>,
+
+    /// Also write the checksum to a `<output>.sha256` sidecar file
+    #[arg(long, requires = "checksum")]
+    pub checksum_file: bool,
+
+    /// Read the output back once written and check its page tree, its page count and its
+    /// bookmarks, failing on any structural error
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Produce identical bytes for identical inputs: images and text files are converted with a
+    /// fixed date, taken from `SOURCE_DATE_EPOCH` when set and 1970-01-01 otherwise
+    #[arg(long)]
+    pub deterministic: bool,
+
+    /// Document ID written to the trailer of the output, as hexadecimal
+    #[arg(long, value_name = "HEX", value_parser = parse_hex)]
+    pub document_id: Option<DocumentId>,
+
+    /// Encrypt the output, with AES-256 unless --encryption says otherwise, for the holder of an
+    /// X.509 RSA certificate (PEM or DER), who opens it with their private key. Can be repeated
+    /// for several recipients
+    #[arg(long, value_name = "CERT")]
+    pub encrypt_to: Vec<PathBuf>,
+
+    /// Encrypt the output, with AES-256 unless --encryption says otherwise, so that it opens
+    /// without a password but with the restrictions of --no-print, --no-copy and --no-modify,
+    /// which the owner password stored under KEY in the keyring lifts, see `store-password`
+    #[arg(long, value_name = "KEY")]
+    pub owner_password_key: Option<String>,
+
+    /// Algorithm of --encrypt-to and --owner-password-key: rc4-128, only for readers older than
+    /// Acrobat 7, aes-128 or aes-256
+    #[arg(
+        long,
+        value_name = "ALGORITHM",
+        value_parser = parse_encryption_algorithm,
+        default_value = "aes-256",
+        requires = "encrypted"
+    )]
+    pub encryption: EncryptionAlgorithm,
+
+    /// With --encrypt-to or --owner-password-key, do not let the readers print the output
+    #[arg(long, requires = "encrypted")]
+    pub no_print: bool,
+
+    /// With --encrypt-to or --owner-password-key, do not let the readers copy text and images out
+    /// of the output
+    #[arg(long, requires = "encrypted")]
+    pub no_copy: bool,
+
+    /// With --encrypt-to or --owner-password-key, do not let the readers modify the output, add
+    /// annotations, fill forms or insert, rotate and delete pages
+    #[arg(long, requires = "encrypted")]
+    pub no_modify: bool,
+
+    /// With --no-modify, still let the readers add annotations and fill forms
+    #[arg(long, requires = "no_modify")]
+    pub allow_annotations: bool,
+
+    /// Number of pages dropped at the start of every PDF input, such as a cover sheet or a fax
+    /// banner; a plan can override it per file
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub skip_first: usize,
+
+    /// Number of pages dropped at the end of every PDF input; a plan can override it per file
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub skip_last: usize,
+
+    /// Keep only the pages whose text matches a regular expression, e.g. `--grep 'Invoice
+    /// Total'`; inputs without such a page are left out. Text in images, such as scans without
+    /// a text layer, is not searched
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    pub grep: Option<Regex>,
+
+    /// With --grep, keep the whole inputs which have a matching page rather than only the
+    /// matching pages
+    #[arg(long, requires = "grep")]
+    pub grep_documents: bool,
+
+    /// Place an extra document at a position of the merge, once sorted: `extra.pdf@3` to make it
+    /// the third input, `extra.pdf@p10` to insert it after the tenth page. Can be repeated
+    #[arg(long, value_name = "FILE@POSITION", value_parser = parse_insertion)]
+    pub insert: Vec<Insertion>,
+
+    /// Insert a blank page after these pages of the merge, once sorted, e.g. `--blank-after
+    /// 10,25`; 0 inserts one before the first page. Blank pages have the size of --page-size
+    #[arg(long, value_name = "PAGES", value_delimiter = ',')]
+    pub blank_after: Vec<usize>,
+
+    /// Include an input several times in a row, e.g. `--repeat consent.pdf=3`. Can be repeated
+    #[arg(long, value_name = "FILE=COUNT", value_parser = parse_repeat)]
+    pub repeat: Vec<Repeat>,
+
+    /// Print the name of the file each page comes from in the bottom left corner of the page
+    #[arg(long)]
+    pub stamp_source: bool,
+
+    /// PDF whose first page, such as a letterhead, is drawn underneath every merged page,
+    /// scaled to fit; pages whose content starts with an opaque fill (scans) hide it
+    #[arg(long, value_name = "FILE")]
+    pub background: Option<PathBuf>,
+
+    /// PDF whose first page is drawn on top of every merged page, scaled to fit
+    #[arg(long, value_name = "FILE")]
+    pub overlay: Option<PathBuf>,
+
+    /// TrueType font of the text added to the pages, such as --stamp-source and the index;
+    /// otherwise Latin text is in Helvetica and other text in an embedded DejaVu Sans, which
+    /// covers most alphabets but not Chinese, Japanese or Korean
+    #[arg(long, value_name = "FILE")]
+    pub stamp_font: Option<PathBuf>,
+
+    /// Remove the annotations (comments, highlights, ...) of the inputs; only those of the given
+    /// subtypes with a list like `--strip-annotations=comments,highlights,Ink`
+    #[arg(
+        long,
+        value_name = "SUBTYPES",
+        num_args = 0..=1,
+        require_equals = true,
+        value_delimiter = ','
+    )]
+    pub strip_annotations: Option<Vec<String>>,
+
+    /// Draw the annotations (highlights, stamps, ink signatures, ...) into the page content, so
+    /// that they show in every viewer; links and form fields stay annotations
+    #[arg(long)]
+    pub flatten_annotations: bool,
+
+    /// Remove the links to web pages and other files, keeping the links within the output
+    #[arg(long)]
+    pub strip_links: bool,
+
+    /// Remove the text matching a regular expression from the pages and draw black boxes where
+    /// it was, e.g. `--redact-pattern '\b\d{3}-\d{2}-\d{4}\b'`. Can be repeated. Text in images,
+    /// such as scans, is not found, and annotations, bookmarks and metadata are left as is
+    #[arg(long, value_name = "REGEX", value_parser = parse_regex)]
+    pub redact_pattern: Vec<Regex>,
+
+    /// Start the output with an index of miniatures of the first page of every input, each
+    /// linking to it
+    #[arg(long)]
+    pub index_thumbnails: bool,
+
+    /// Downsample the images of the output drawn at more than --max-dpi, keeping their format
+    #[arg(long)]
+    pub recompress_images: bool,
+
+    /// Resolution the images are downsampled to with --recompress-images, in dots per inch
+    #[arg(long, value_name = "DPI", default_value_t = 150.0, value_parser = parse_dpi)]
+    pub max_dpi: f64,
+
+    /// Remove the page thumbnails embedded in the inputs, which viewers can make again
+    #[arg(long, conflicts_with = "thumbnails")]
+    pub strip_thumbnails: bool,
+
+    /// Embed a thumbnail in every page, for viewers that display them in their sidebar: images are
+    /// reduced and text is drawn as gray lines, vector graphics are left out
+    #[arg(long)]
+    pub thumbnails: bool,
+
+    /// Pages given a bookmark: `per-file` (the first page of every input), `per-page` or `none`
+    #[arg(long, value_name = "MODE", default_value = "per-file", value_parser = parse_bookmark_mode)]
+    pub bookmarks: BookmarkMode,
+
+    /// How the bookmarks show their page: `fit` the whole page, `fith` its width, or `xyz` its top
+    /// at the zoom level of the viewer
+    #[arg(long, value_name = "FIT", default_value = "fit", value_parser = parse_destination_fit)]
+    pub bookmark_fit: DestinationFit,
+
+    /// Color of the bookmarks, as #RRGGBB
+    #[arg(long, value_name = "COLOR", value_parser = parse_bookmark_color)]
+    pub bookmark_color: Option<[f32; 3]>,
+
+    /// Font style of the bookmarks: normal, bold, italic or bold-italic
+    #[arg(long, value_name = "STYLE", value_parser = parse_font_style)]
+    pub bookmark_font: Option<FontStyle>,
+
+    /// Number of bookmark levels kept, the bookmarks of the inputs being the first level and their
+    /// own outlines the next ones; 1 drops the outlines of the inputs
+    #[arg(long, value_name = "N", value_parser = parse_outline_depth)]
+    pub outline_depth: Option<usize>,
+
+    /// Markdown list of the bookmarks replacing the generated ones, nested by indentation, each
+    /// item linking to an input (`[Agenda](agenda.pdf)`) or to a page of the output
+    /// (`[Minutes](#page=7)`); items without a link point to their first child
+    #[arg(long, value_name = "FILE")]
+    pub outline: Option<PathBuf>,
+
+    /// Prefix the bookmark titles with their number in the bookmark hierarchy: 1, 1.1, 1.2, 2...
+    #[arg(long)]
+    pub number_bookmarks: bool,
+
+    /// What to do with attachments of several inputs sharing a name: `rename` the later ones
+    /// (`report (2).xlsx`), `keep-first`, `keep-last`, or fail with `error`
+    #[arg(long, value_name = "POLICY", default_value = "rename", value_parser = parse_attachment_policy)]
+    pub attachments: AttachmentPolicy,
+
+    /// Input whose open action (the page and zoom shown when the output is opened) is kept:
+    /// `first`, `last` or `none`
+    #[arg(long, value_name = "INPUT", default_value = "last", value_parser = parse_open_action_source)]
+    pub keep_open_action: OpenActionSource,
+
+    /// Number of bookmark levels expanded when the output is opened; 0 collapses them all
+    #[arg(long, value_name = "DEPTH", default_value_t = 1)]
+    pub bookmarks_open_depth: usize,
+
+    /// Copy the document information (title, author, ...) and XMP metadata of the first input to
+    /// the output; the flags below still override them
+    #[arg(long)]
+    pub inherit_metadata: bool,
+
+    /// Producer of the output; an empty value leaves it out
+    #[arg(long, value_name = "TEXT")]
+    pub producer: Option<String>,
+
+    /// Creator of the output; an empty value leaves it out
+    #[arg(long, value_name = "TEXT")]
+    pub creator: Option<String>,
+
+    /// Creation and modification dates of the output: `now`, `from-first-input`, `none` to leave
+    /// them out, or a date such as `2024-05-31` or `2024-05-31T12:00:00+02:00`
+    #[arg(long, value_name = "DATE", value_parser = parse_date_spec)]
+    pub date: Option<DateSpec>,
+
+    /// Creation date of the output, overriding --date
+    #[arg(long, value_name = "DATE", value_parser = parse_date_spec)]
+    pub creation_date: Option<DateSpec>,
+
+    /// Modification date of the output, overriding --date
+    #[arg(long, value_name = "DATE", value_parser = parse_date_spec)]
+    pub mod_date: Option<DateSpec>,
+
+    /// List the inputs that would be merged, in order, with their page counts and the size of
+    /// their first page, without merging them. Only the page tree of the PDFs is read
+    #[arg(long, conflicts_with = "plan")]
+    pub dry_run: bool,
+
+    /// Merge plan listing the inputs, one per line: a .csv file, separated by `,` or `;`, whose
+    /// first line names its columns, in any order. The only positional argument is then the output
+    /// file, unless it is given with --output. The columns are `path` (required, relative to the
+    /// plan), `pages` (e.g. `1-3,5`), `page operations` (e.g. `3:rotate=90 5:skip 7:scale=0.5`),
+    /// `rotation` (a multiple of 90), `skip first` and `skip last` (pages dropped before `pages`
+    /// applies, instead of --skip-first and --skip-last), `repeat` (number of times the input is
+    /// included in a row), `blank` (yes for a blank page instead of a file), `title`, `color`
+    /// (`#RRGGBB`), `style` (normal, bold, italic or bold-italic) and `open` (yes or no) of the
+    /// bookmark, `password` (of an encrypted PDF) and `password key` (of a password in the keyring,
+    /// see `store-password`)
+    #[arg(long, value_name = "FILE")]
+    pub plan: Option<PathBuf>,
+
+    /// Also merge the files of the subdirectories of the input directories, each subdirectory
+    /// getting a bookmark holding those of its files
+    #[arg(short, long)]
+    pub recursive: bool,
+
+    /// Include symlinked files of the input directory and, with --recursive, walk into its
+    /// symlinked directories
+    #[arg(long)]
+    pub follow_symlinks: bool,
+
+    /// Also merge the files without a supported extension whose content is a PDF or an image,
+    /// such as the extensionless exports of document management systems
+    #[arg(long)]
+    pub sniff: bool,
+
+    /// Also merge dotfiles, `~$` office lock files and files that are still being written
+    #[arg(long)]
+    pub include_hidden: bool,
+
+    /// Skip, with a warning, the input files larger than this size, e.g. `200M`
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_file_size: Option<u64>,
+
+    /// Fail as soon as the scan finds more than this many input files, before loading anything,
+    /// to catch a wrong input directory early
+    #[arg(long, value_name = "N")]
+    pub max_files: Option<usize>,
+
+    /// Stop adding inputs once the output would have more than this many pages, leaving out the
+    /// remaining inputs with a warning; the index of --index-thumbnails is not counted
+    #[arg(long, value_name = "N")]
+    pub max_pages_total: Option<usize>,
+
+    /// Keep only the files of the input directories whose name without extension matches a
+    /// regular expression, e.g. `--match '^INV-\d+'`; files given by name are always kept
+    #[arg(long = "match", value_name = "REGEX", value_parser = parse_regex)]
+    pub stem_pattern: Option<Regex>,
+
+    /// Leave out the input files last modified before this date, as `2024-01-01` (local time)
+    /// or RFC 3339
+    #[arg(long, value_name = "DATE", value_parser = parse_since)]
+    pub since: Option<DateTime<Utc>>,
+
+    /// Leave out the input files last modified after this date, as `2024-03-31` (included, in
+    /// local time) or RFC 3339
+    #[arg(long, value_name = "DATE", value_parser = parse_until)]
+    pub until: Option<DateTime<Utc>>,
+
+    /// Skip, with a warning, any input taking more than this many seconds to load
+    #[arg(long, value_name = "SECONDS")]
+    pub file_timeout: Option<u64>,
+
+    /// Keep retrying, for this many seconds, to open an input held by another process (such as
+    /// scanner software still writing it) before skipping it; 0 skips it right away
+    #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+    pub locked_wait: u64,
+
+    /// Keep each input in a checkpoint directory of the temporary directory once it is loaded,
+    /// so that running the same merge again after an interruption resumes from the last loaded
+    /// input; the checkpoint is removed once the output is saved
+    #[arg(long)]
+    pub checkpoint: bool,
+
+    /// Key of the password of the encrypted inputs in the keyring, see `store-password`
+    #[arg(long, value_name = "KEY")]
+    pub password_key: Option<String>,
+
+    /// Abort with a nonzero status on any anomaly: an input that cannot be loaded, a skipped
+    /// file, a warning
+    #[arg(long, conflicts_with = "lenient")]
+    pub strict: bool,
+
+    /// Skip the inputs that cannot be loaded and carry on with the others (the default)
+    #[arg(long)]
+    pub lenient: bool,
+
+    /// Fail on encrypted inputs without a password in the plan instead of asking for it
+    #[arg(long)]
+    pub no_prompt: bool,
+
+    /// Expand animated GIF and PNG images to a page per frame instead of their first frame only;
+    /// `--animation-frames=STEP` keeps one frame every STEP frames
+    #[arg(
+        long,
+        value_name = "STEP",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "1",
+        value_parser = parse_frame_step
+    )]
+    pub animation_frames: Option<usize>,
+
+    /// Size of the pages images are converted to: `auto` to size them to the image (scaled up to at
+    /// least A4), a name such as `a4`, `letter` or `a3^t` for landscape, or a size in `mm`, `cm`,
+    /// `in` or `pt` such as `210mmx297mm` or `8.5x11in`
+    #[arg(long, value_name = "SIZE", default_value = "a4", value_parser = parse_page_size)]
+    pub page_size: PageSize,
+
+    /// Scale every page of the output to this size, e.g. `a4`, `letter` or `8.5x11in`, centered on
+    /// it. Pages otherwise keep the size they have in their input
+    #[arg(long, value_name = "SIZE", value_parser = parse_fixed_page_size)]
+    pub force_size: Option<PageSizeInMm>,
+
+    /// Scale down the pages of the output larger than this size, e.g. `a3`, keeping their aspect
+    /// ratio; smaller pages are left as they are
+    #[arg(long, value_name = "SIZE", value_parser = parse_fixed_page_size, conflicts_with = "force_size")]
+    pub max_page_size: Option<PageSizeInMm>,
+
+    /// Stretch images over the whole page instead of keeping their aspect ratio
+    #[arg(long, conflicts_with = "scale")]
+    pub stretch_images: bool,
+
+    /// Draw images at this fraction of their size, e.g. `0.5`, centered on the page, instead of
+    /// fitting them to it
+    #[arg(long, value_name = "FACTOR", value_parser = parse_scale)]
+    pub scale: Option<f64>,
+
+    /// Density of the images that do not give theirs in their metadata, in dots per inch; it sets
+    /// their size with --scale
+    #[arg(long, value_name = "DPI", default_value_t = DEFAULT_DPI, value_parser = parse_dpi)]
+    pub assume_dpi: f64,
+
+    /// Order in which the PDF inputs are merged
+    #[arg(long, value_enum, default_value_t = SortOrder::Name)]
+    pub sort: SortOrder,
+
+    /// What to merge of PDF portfolios, whose content is in embedded files
+    #[arg(long, value_enum, default_value_t = PortfolioMode::Extract)]
+    pub portfolio: PortfolioMode,
+
+    /// Abort instead of warning when an input is digitally signed, as merging invalidates its
+    /// signature
+    #[arg(long)]
+    pub fail_on_signed: bool,
+
+    /// Review, reorder, exclude or rotate the inputs in the terminal before merging
+    #[arg(long)]
+    pub interactive: bool,
+
+    /// Memory budget, e.g. `512M` or `2G`. Inputs exceeding it are spilled to temporary files and
+    /// read back one at a time while merging, which is slower but uses about half the memory
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_memory: Option<u64>,
+
+    /// Effort put in compressing the streams of the output: `fast` for quick previews, `best` for
+    /// archives, or `none`
+    #[arg(long, value_name = "LEVEL", default_value = "default", value_parser = parse_compression)]
+    pub compress: CompressionLevel,
+
+    /// Write the output with uncompressed streams and a classic cross-reference table, to inspect
+    /// its objects in a text editor
+    #[arg(long, conflicts_with = "compress")]
+    pub debug_output: bool,
+
+    /// Directory of the temporary files: inputs spilled under `--max-memory` and the output being
+    /// written, which is copied next to its destination once complete. Defaults to the system
+    /// temporary directory for spilled inputs and to the output directory for the output
+    #[arg(long, value_name = "DIR")]
+    pub temp_dir: Option<PathBuf>,
+
+    /// Also append the diagnostics, without colors, to this file
+    #[arg(long, value_name = "FILE", global = true)]
+    pub log_file: Option<PathBuf>,
+
+    /// Format of the diagnostics: colored text, or JSON lines on stderr with `file_loaded`,
+    /// `file_skipped`, `pages_merged` and `saved` events
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, global = true)]
+    pub log_format: LogFormat,
+
+    /// Emit the progress of the merge as JSON lines on stderr, with the `phase`, the `file` being
+    /// loaded or saved and the `percent` done, for front-ends wrapping the command line
+    #[arg(long)]
+    pub progress_json: bool,
+
+    /// Print the time taken by loading, image conversion, merging, compression and saving, and
+    /// the size of the output against the size of the inputs
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Maximum size of a downloaded `http(s)://` input, in bytes
+    #[arg(long, default_value_t = 100 * 1024 * 1024)]
+    pub download_max_size: u64,
+
+    /// Timeout of the download of an `http(s)://` input, in seconds
+    #[arg(long, default_value_t = 60)]
+    pub download_timeout: u64,
+}
+
+// Parses a size in bytes with an optional binary `K`, `M`, `G` or `T` suffix.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let digits_end = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(digits_end);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size {:?}", size))?;
+    let multiplier: u64 = match unit
+        .trim()
+        .to_ascii_uppercase()
+        .trim_end_matches(['B', 'I'])
+    {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("invalid size unit {:?}", unit)),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size {:?} is too large", size))
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum DateSpec {
+    Now,
+    FromFirstInput,
+    None,
+    At(DateTime<Utc>),
+}
+
+fn parse_date_spec(date: &str) -> Result<DateSpec, String> {
+    match date {
+        "now" => return Ok(DateSpec::Now),
+        "from-first-input" => return Ok(DateSpec::FromFirstInput),
+        "none" => return Ok(DateSpec::None),
+        _ => {}
+    }
+    if let Ok(date) = DateTime::parse_from_rfc3339(date) {
+        return Ok(DateSpec::At(date.to_utc()));
+    }
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map(|date| DateSpec::At(date.and_time(NaiveTime::MIN).and_utc()))
+        .map_err(|_| format!("invalid date {:?}", date))
+}
+
+// A date of --since or --until: an RFC 3339 date and time, or a day in local
+// time, given as its first instant or with `end_of_day` its last one.
+fn parse_date_bound(date: &str, end_of_day: bool) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(date) {
+        return Ok(date.to_utc());
+    }
+    let invalid = || format!("invalid date {:?}", date);
+    let day = NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| invalid())?;
+    let first_day = if end_of_day {
+        day.succ_opt()
+    } else {
+        Some(day)
+    };
+    let start = first_day
+        .and_then(|day| {
+            day.and_time(NaiveTime::MIN)
+                .and_local_timezone(Local)
+                .earliest()
+        })
+        .ok_or_else(invalid)?
+        .to_utc();
+    Ok(if end_of_day {
+        start - TimeDelta::nanoseconds(1)
+    } else {
+        start
+    })
+}
+
+fn parse_since(date: &str) -> Result<DateTime<Utc>, String> {
+    parse_date_bound(date, false)
+}
+
+fn parse_until(date: &str) -> Result<DateTime<Utc>, String> {
+    parse_date_bound(date, true)
+}
+
+#[derive(Clone, Debug)]
+pub struct DocumentId(pub Vec<u8>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum InsertPosition {
+    // 1-based position among the inputs.
+    Input(usize),
+    // Number of pages of the merge before the inserted document.
+    AfterPage(usize),
+}
+
+#[derive(Clone, Debug)]
+pub struct Insertion {
+    pub path: PathBuf,
+    pub position: InsertPosition,
+}
+
+#[derive(Clone, Debug)]
+pub struct Repeat {
+    pub path: PathBuf,
+    pub count: usize,
+}
+
+fn parse_repeat(repeat: &str) -> Result<Repeat, String> {
+    let (path, count) = repeat
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected FILE=COUNT, got {:?}", repeat))?;
+    match count.parse() {
+        Ok(count) if count > 0 => Ok(Repeat {
+            path: PathBuf::from(path),
+            count,
+        }),
+        _ => Err(format!("invalid count {:?}, expected at least 1", count)),
+    }
+}
+
+fn parse_insertion(insertion: &str) -> Result<Insertion, String> {
+    let (path, position) = insertion
+        .rsplit_once('@')
+        .ok_or_else(|| format!("expected FILE@POSITION, got {:?}", insertion))?;
+    let position = match position.strip_prefix('p') {
+        Some(page) => page.parse().map(InsertPosition::AfterPage),
+        None => match position.parse() {
+            Ok(0) => return Err("input positions start at 1".to_string()),
+            index => index.map(InsertPosition::Input),
+        },
+    }
+    .map_err(|_| format!("invalid position {:?}, expected N or pN", position))?;
+    Ok(Insertion {
+        path: PathBuf::from(path),
+        position,
+    })
+}
+
+fn parse_hex(hex: &str) -> Result<DocumentId, String> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return Err("expected an even number of hexadecimal digits".to_string());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| format!("invalid hexadecimal {:?}", hex))
+        })
+        .collect::<Result<_, _>>()
+        .map(DocumentId)
+}
+
+fn parse_frame_step(step: &str) -> Result<usize, String> {
+    match step.parse() {
+        Ok(0) | Err(_) => Err(format!("invalid frame step {:?}", step)),
+        Ok(step) => Ok(step),
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum PageSize {
+    Auto,
+    Fixed(PageSizeInMm),
+}
+
+fn parse_page_size(page_size: &str) -> Result<PageSize, String> {
+    if page_size.trim().eq_ignore_ascii_case("auto") {
+        return Ok(PageSize::Auto);
+    }
+    parse_fixed_page_size(page_size).map(PageSize::Fixed)
+}
+
+fn parse_fixed_page_size(page_size: &str) -> Result<PageSizeInMm, String> {
+    PageSizeInMm::new(page_size).map_err(|e| e.to_string())
+}
+
+fn parse_scale(scale: &str) -> Result<f64, String> {
+    match scale.parse() {
+        Ok(scale) if scale > 0.0 && f64::is_finite(scale) => Ok(scale),
+        _ => Err(format!("invalid scale {:?}", scale)),
+    }
+}
+
+fn parse_dpi(dpi: &str) -> Result<f64, String> {
+    match dpi.parse() {
+        Ok(dpi) if dpi > 0.0 && f64::is_finite(dpi) => Ok(dpi),
+        _ => Err(format!("invalid density {:?}", dpi)),
+    }
+}
+
+fn parse_regex(pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| e.to_string())
+}
+
+fn parse_bookmark_mode(mode: &str) -> Result<BookmarkMode, String> {
+    BookmarkMode::parse(mode).map_err(|e| e.to_string())
+}
+
+fn parse_attachment_policy(policy: &str) -> Result<AttachmentPolicy, String> {
+    AttachmentPolicy::parse(policy).map_err(|e| e.to_string())
+}
+
+fn parse_encryption_algorithm(algorithm: &str) -> Result<EncryptionAlgorithm, String> {
+    EncryptionAlgorithm::parse(algorithm).map_err(|e| e.to_string())
+}
+
+fn parse_destination_fit(fit: &str) -> Result<DestinationFit, String> {
+    DestinationFit::parse(fit).map_err(|e| e.to_string())
+}
+
+fn parse_open_action_source(source: &str) -> Result<OpenActionSource, String> {
+    OpenActionSource::parse(source).map_err(|e| e.to_string())
+}
+
+fn parse_outline_depth(depth: &str) -> Result<usize, String> {
+    match depth.parse() {
+        Ok(depth) if depth > 0 => Ok(depth),
+        _ => Err(format!(
+            "invalid outline depth {:?}, expected 1 or more",
+            depth
+        )),
+    }
+}
+
+fn parse_bookmark_color(color: &str) -> Result<[f32; 3], String> {
+    parse_color(color).map_err(|e| e.to_string())
+}
+
+fn parse_compression(level: &str) -> Result<CompressionLevel, String> {
+    CompressionLevel::parse(level).map_err(|e| e.to_string())
+}
+
+fn parse_font_style(style: &str) -> Result<FontStyle, String> {
+    FontStyle::parse(style).map_err(|e| e.to_string())
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Serve merges over HTTP: `POST /merge` with a multipart upload or a JSON list of paths
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+
+        /// Directory the paths of JSON requests are resolved in; JSON requests are refused without
+        /// it
+        #[arg(long)]
+        root: Option<PathBuf>,
+
+        /// Maximum size of a request body, in bytes
+        #[arg(long, default_value_t = 256 * 1024 * 1024)]
+        max_body_size: usize,
+    },
+    /// Compare two PDFs: page count, page sizes, content of the pages and metadata. Exits with 0
+    /// when they match, 1 when they differ and 2 when they cannot be read
+    Compare { first: PathBuf, second: PathBuf },
+    /// Load the inputs of a directory as a merge would and report, per file, whether it merges
+    /// cleanly or what it would lose: encryption, signatures, forms, bookmarks, unsupported
+    /// formats. Exits with 0 when every file merges cleanly, 1 otherwise and 2 when the
+    /// directory cannot be read
+    Doctor {
+        dir: PathBuf,
+        /// Also look at the files of the subdirectories, as --recursive merges them
+        #[arg(short, long)]
+        recursive: bool,
+        /// Include symlinked files, and with --recursive symlinked directories, as
+        /// --follow-symlinks does
+        #[arg(long)]
+        follow_symlinks: bool,
+        /// Also look at the files --include-hidden merges
+        #[arg(long)]
+        include_hidden: bool,
+        /// Take the files without a supported extension by their content, as --sniff does
+        #[arg(long)]
+        sniff: bool,
+    },
+    /// Store a password of encrypted inputs in the keyring of the platform, for `--password-key`
+    /// or the `password key` column of plans, or the owner password of `--owner-password-key`
+    StorePassword {
+        /// Key to store the password under, e.g. the sender of the documents
+        key: String,
+    },
+    /// Add a "Merge to PDF…" entry to the context menu of folders in Explorer, and to its "Send
+    /// to" menu for files, which asks for the output and merges the selection (Windows only)
+    InstallShellIntegration {
+        /// Remove the entries instead
+        #[arg(long)]
+        remove: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Sort by file name
+    Name,
+    /// Sort PDF inputs by their `/CreationDate`; undated files come last
+    PdfDate,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortfolioMode {
+    /// Merge the PDFs embedded in the portfolio instead of its cover sheet
+    Extract,
+    /// Merge the cover sheet only
+    Cover,
+    /// Merge the cover sheet followed by the embedded PDFs
+    Both,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Sha256,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes_in_bytes() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size(" 1500 "), Ok(1500));
+        assert_eq!(parse_size("12B"), Ok(12));
+    }
+
+    #[test]
+    fn parses_binary_units() {
+        assert_eq!(parse_size("1K"), Ok(1024));
+        assert_eq!(parse_size("200M"), Ok(200 << 20));
+        assert_eq!(parse_size("200 MB"), Ok(200 << 20));
+        assert_eq!(parse_size("3gib"), Ok(3 << 30));
+        assert_eq!(parse_size("2T"), Ok(2 << 40));
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("-1M").is_err());
+        assert!(parse_size("1.5M").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("20000000T").is_err());
+    }
+}
@@ -0,0 +1,117 @@
+// `compare`: the differences between two PDFs that matter when checking that
+// a merge was reproduced, leaving aside object numbers and file layout which
+// change from one write to the next.
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use lopdf::{Document, ObjectId};
+use pm::{metadata::info_entries, page::get_inherited, stamp::page_box};
+
+use crate::checksum::{sha256, to_hex};
+
+// Width and height of the visible area of the page, in points, once rotated.
+fn page_size(doc: &Document, page_id: ObjectId) -> (f32, f32) {
+    let [x0, y0, x1, y1] = page_box(doc, page_id);
+    let rotate = get_inherited(doc, page_id, b"Rotate")
+        .and_then(|rotate| rotate.as_i64().ok())
+        .unwrap_or(0);
+    let (width, height) = ((x1 - x0).abs(), (y1 - y0).abs());
+    if rotate.rem_euclid(180) == 90 {
+        (height, width)
+    } else {
+        (width, height)
+    }
+}
+
+// Hash of what the page draws: its content streams, then the streams of the
+// images and forms of its resources in name order, decompressed so that the
+// compression level does not matter.
+fn page_hash(doc: &Document, page_id: ObjectId) -> String {
+    let mut data = doc.get_page_content(page_id).unwrap_or_default();
+    let xobjects = get_inherited(doc, page_id, b"Resources")
+        .and_then(|resources| doc.dereference(resources).ok()?.1.as_dict().ok())
+        .and_then(|resources| resources.get(b"XObject").ok())
+        .and_then(|xobjects| doc.dereference(xobjects).ok()?.1.as_dict().ok());
+    if let Some(xobjects) = xobjects {
+        let mut names: Vec<&Vec<u8>> = xobjects.iter().map(|(name, _)| name).collect();
+        names.sort();
+        for name in names {
+            let stream = xobjects
+                .get(name)
+                .and_then(|xobject| doc.dereference(xobject))
+                .and_then(|(_, xobject)| xobject.as_stream());
+            if let Ok(stream) = stream {
+                data.extend(name);
+                match stream.decompressed_content() {
+                    Ok(content) => data.extend(content),
+                    Err(_) => data.extend(&stream.content),
+                }
+            }
+        }
+    }
+    to_hex(&sha256(&data))
+}
+
+fn load(path: &Path) -> Result<Document> {
+    Document::load(path).with_context(|| format!("cannot read {}", path.display()))
+}
+
+// Returns the differences, one per line, none when the documents match.
+pub fn compare(first: &Path, second: &Path) -> Result<Vec<String>> {
+    let (first, second) = (load(first)?, load(second)?);
+    let mut differences = Vec::new();
+
+    let (first_pages, second_pages) = (first.get_pages(), second.get_pages());
+    if first_pages.len() != second_pages.len() {
+        differences.push(format!(
+            "page count: {} vs {}",
+            first_pages.len(),
+            second_pages.len()
+        ));
+    }
+    let pages = first_pages.into_iter().zip(second_pages.into_values());
+    for ((number, first_page), second_page) in pages {
+        let (first_size, second_size) = (
+            page_size(&first, first_page),
+            page_size(&second, second_page),
+        );
+        if first_size != second_size {
+            differences.push(format!(
+                "page {} size: {}x{} vs {}x{} pt",
+                number, first_size.0, first_size.1, second_size.0, second_size.1
+            ));
+        }
+        let (first_hash, second_hash) = (
+            page_hash(&first, first_page),
+            page_hash(&second, second_page),
+        );
+        if first_hash != second_hash {
+            differences.push(format!(
+                "page {} content: {} vs {}",
+                number,
+                &first_hash[..16],
+                &second_hash[..16]
+            ));
+        }
+    }
+
+    let (first_info, second_info) = (info_entries(&first), info_entries(&second));
+    let mut keys: Vec<&String> = first_info.keys().chain(second_info.keys()).collect();
+    keys.sort();
+    keys.dedup();
+    for key in keys {
+        let (first_value, second_value) = (first_info.get(key), second_info.get(key));
+        if first_value != second_value {
+            let show = |value: Option<&String>| {
+                value.map_or_else(|| "(none)".to_string(), |value| format!("{:?}", value))
+            };
+            differences.push(format!(
+                "metadata {}: {} vs {}",
+                key,
+                show(first_value),
+                show(second_value)
+            ));
+        }
+    }
+    Ok(differences)
+}
@@ -0,0 +1,81 @@
+// Flate compression of the streams of the output, at a chosen effort: fast
+// for quick previews, best for archives.
+use std::io::Write;
+
+use crate::parallel::*;
+use anyhow::{Result, bail};
+use flate2::{Compression, write::ZlibEncoder};
+use lopdf::{Document, Object, Stream, xref::XrefType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressionLevel {
+    // Streams are written as they are.
+    None,
+    Fast,
+    #[default]
+    Default,
+    Best,
+}
+
+impl CompressionLevel {
+    pub fn parse(level: &str) -> Result<Self> {
+        Ok(match level.trim().to_ascii_lowercase().as_str() {
+            "none" => CompressionLevel::None,
+            "fast" => CompressionLevel::Fast,
+            "default" => CompressionLevel::Default,
+            "best" => CompressionLevel::Best,
+            _ => bail!(
+                "unknown compression level {:?}, expected fast, default, best or none",
+                level
+            ),
+        })
+    }
+
+    fn flate(self) -> Option<Compression> {
+        match self {
+            CompressionLevel::None => None,
+            CompressionLevel::Fast => Some(Compression::fast()),
+            CompressionLevel::Default => Some(Compression::default()),
+            CompressionLevel::Best => Some(Compression::best()),
+        }
+    }
+}
+
+fn compress_stream(stream: &mut Stream, compression: Compression) {
+    if !stream.allows_compression || stream.dict.has(b"Filter") {
+        return;
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), compression);
+    // The dictionary grows by `/Filter /FlateDecode`.
+    if encoder.write_all(&stream.content).is_ok()
+        && let Ok(compressed) = encoder.finish()
+        && compressed.len() + 19 < stream.content.len()
+    {
+        stream.dict.set("Filter", "FlateDecode");
+        stream.set_content(compressed);
+    }
+}
+
+// Compresses the streams which have no filter yet, as `Document::compress`
+// does, but at `level` and on all the cores. Streams which do not shrink are
+// left as they are.
+pub fn compress_document(document: &mut Document, level: CompressionLevel) {
+    let Some(compression) = level.flate() else {
+        return;
+    };
+    let _span = crate::trace_span!("compress", objects = document.objects.len());
+    document.objects.par_iter_mut().for_each(|(_, object)| {
+        if let Object::Stream(stream) = object {
+            compress_stream(stream, compression);
+        }
+    });
+}
+
+// Makes the document readable in a text editor, for diagnosing merge bugs:
+// the streams are decompressed where their filters allow it (images in JPEG
+// stay as they are) and the cross-references are written as a classic table
+// rather than a compressed stream.
+pub fn prepare_debug_output(document: &mut Document) {
+    document.decompress();
+    document.reference_table.cross_reference_type = XrefType::CrossReferenceTable;
+}
@@ -0,0 +1,85 @@
+// Collapses byte-identical streams into one object: documents made from the
+// same template each bring their own copy of the same images, fonts, ICC
+// profiles and content streams, which the merged document only needs once.
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use lopdf::{Document, Object, ObjectId};
+
+// Maps each duplicate stream to the first identical one, in object order.
+// Streams are identical when both their content and their dictionary are.
+fn duplicate_streams(document: &Document) -> BTreeMap<ObjectId, ObjectId> {
+    let mut by_hash: HashMap<u64, Vec<ObjectId>> = HashMap::new();
+    let mut duplicates = BTreeMap::new();
+    for (&id, object) in &document.objects {
+        let Object::Stream(stream) = object else {
+            continue;
+        };
+        let mut hasher = DefaultHasher::new();
+        stream.content.hash(&mut hasher);
+        let candidates = by_hash.entry(hasher.finish()).or_default();
+        let original = candidates.iter().find(|candidate| {
+            matches!(
+                document.objects.get(candidate),
+                Some(Object::Stream(other))
+                    if other.content == stream.content && other.dict == stream.dict
+            )
+        });
+        match original {
+            Some(&original) => {
+                duplicates.insert(id, original);
+            }
+            None => candidates.push(id),
+        }
+    }
+    duplicates
+}
+
+fn replace_references(object: &mut Object, replacements: &BTreeMap<ObjectId, ObjectId>) {
+    match object {
+        Object::Reference(id) => {
+            if let Some(&replacement) = replacements.get(id) {
+                *id = replacement;
+            }
+        }
+        Object::Array(array) => {
+            for item in array {
+                replace_references(item, replacements);
+            }
+        }
+        Object::Dictionary(dictionary) => {
+            for (_, value) in dictionary.iter_mut() {
+                replace_references(value, replacements);
+            }
+        }
+        Object::Stream(stream) => {
+            for (_, value) in stream.dict.iter_mut() {
+                replace_references(value, replacements);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Returns the number of streams removed. Streams whose dictionaries only
+// differ by references to identical streams (two copies of an image, each
+// with its copy of the same soft mask) become identical once those are
+// collapsed, hence the passes until nothing changes.
+pub fn deduplicate_streams(document: &mut Document) -> usize {
+    let mut removed = 0;
+    loop {
+        let duplicates = duplicate_streams(document);
+        if duplicates.is_empty() {
+            return removed;
+        }
+        removed += duplicates.len();
+        for id in duplicates.keys() {
+            document.objects.remove(id);
+        }
+        for object in document.objects.values_mut() {
+            replace_references(object, &duplicates);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+// Free space check before merging, so that a full disk is reported up front
+// rather than as a write error once the merge is done.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Result, bail};
+
+use crate::{input::is_stdin, remote::is_url, s3::is_s3_url};
+
+// The output is about the size of its inputs; the margin covers the outline,
+// images growing once converted and the atomic write keeping the previous
+// output until the new one is complete.
+const ESTIMATE_MARGIN_PERCENT: u64 = 10;
+
+// Free space of the file system holding `dir`, in bytes, from `df` or, on
+// Windows, .NET's `DriveInfo`. `None` when it cannot be found out.
+#[cfg(not(windows))]
+fn available_space(dir: &Path) -> Option<u64> {
+    let output = Command::new("df").arg("-Pk").arg(dir).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // `Filesystem 1024-blocks Used Available Capacity Mounted on`, then the
+    // line of the file system.
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let available_kib: u64 = stdout
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse()
+        .ok()?;
+    Some(available_kib * 1024)
+}
+
+#[cfg(windows)]
+fn available_space(dir: &Path) -> Option<u64> {
+    use std::os::windows::process::CommandExt;
+
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+    let dir = fs::canonicalize(dir).ok()?;
+    let script = format!(
+        "[System.IO.DriveInfo]::new('{}').AvailableFreeSpace",
+        dir.display().to_string().replace('\'', "''")
+    );
+    let output = Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .creation_flags(CREATE_NO_WINDOW)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
+fn check_dir(dir: &Path, needed: u64) -> Result<()> {
+    let Some(available) = available_space(dir) else {
+        return Ok(());
+    };
+    if needed > available {
+        bail!(
+            "not enough free space for the output: it needs about {} MiB but only {} MiB are \
+             free in {}",
+            needed.div_ceil(1 << 20),
+            available >> 20,
+            dir.display()
+        );
+    }
+    Ok(())
+}
+
+// Fails when the directory of the output, or the temporary directory the
+// output is first written to, has clearly not enough free space for it,
+// estimated from the size of the local inputs. Remote inputs and outputs are
+// not checked, nor are file systems whose free space is unknown.
+pub fn check_free_space(
+    output_path: &Path,
+    inputs: &[PathBuf],
+    temp_dir: Option<&Path>,
+) -> Result<()> {
+    if is_s3_url(output_path) {
+        return Ok(());
+    }
+    let inputs_size: u64 = inputs
+        .iter()
+        .filter(|path| !is_stdin(path) && !is_url(path) && !is_s3_url(path))
+        .filter_map(|path| fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    let needed = inputs_size + inputs_size * ESTIMATE_MARGIN_PERCENT / 100;
+
+    let output_dir = match output_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    check_dir(output_dir, needed)?;
+    match temp_dir {
+        Some(temp_dir) => check_dir(temp_dir, needed),
+        None => Ok(()),
+    }
+}
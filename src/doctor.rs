@@ -0,0 +1,189 @@
+// `doctor`: what merging the inputs of a directory would do to each of them,
+// so that the files which would be skipped or lose something can be fixed
+// before the real merge.
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use lopdf::Document;
+use pm::{encryption::decrypt, outline::count_items_without_page, signature::signature_count};
+
+use crate::{
+    input::{ScanOptions, is_supported, scan_files, select_input_files, sniff_file},
+    loader::{LoadOptions, TimedOut, load_input_with_timeout},
+};
+
+// Image formats met in scanned or photographed documents which cannot be
+// converted, and are left out of directories.
+const UNSUPPORTED_IMAGE_EXTENSIONS: [&str; 10] = [
+    "avif", "heic", "heif", "ico", "jp2", "psd", "svg", "tif", "tiff", "webp",
+];
+
+pub struct Diagnosis {
+    pub path: PathBuf,
+    // Pages the file adds to the merge, none when it is left out.
+    pub pages: usize,
+    // What keeps the file from merging cleanly, one sentence each.
+    pub problems: Vec<String>,
+}
+
+impl Diagnosis {
+    fn left_out(path: PathBuf, problem: String) -> Self {
+        Diagnosis {
+            path,
+            pages: 0,
+            problems: vec![problem],
+        }
+    }
+}
+
+// The number of fields of the form of the document, if it has one.
+fn form_field_count(doc: &Document) -> usize {
+    doc.catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"AcroForm").ok())
+        .and_then(|form| doc.dereference(form).ok())
+        .and_then(|(_, form)| form.as_dict().ok())
+        .and_then(|form| form.get(b"Fields").ok())
+        .and_then(|fields| doc.dereference(fields).ok())
+        .and_then(|(_, fields)| fields.as_array().ok())
+        .map_or(0, Vec::len)
+}
+
+// What the merge loses of a loaded document.
+fn diagnose_document(mut document: Document) -> (usize, Vec<String>) {
+    let mut problems = Vec::new();
+    if document.is_encrypted() {
+        if decrypt(&mut document, "").is_err() {
+            problems.push(
+                "is encrypted with a password, which the merge needs: give it in a plan, with \
+                 --password-key or at the prompt"
+                    .to_string(),
+            );
+            return (document.get_pages().len(), problems);
+        }
+        problems.push(
+            "is encrypted without a password: it merges, but its restrictions on printing, \
+             copying and editing are lost"
+                .to_string(),
+        );
+    }
+    if signature_count(&document) > 0 {
+        problems.push(
+            "is digitally signed: its signatures will not be valid in the merged PDF".to_string(),
+        );
+    }
+    let fields = form_field_count(&document);
+    if fields > 0 {
+        problems.push(format!(
+            "has a form ({} fields): the merged PDF keeps the form of the last input only, \
+             the fields of the others stop being fillable",
+            fields
+        ));
+    }
+    let misdirected = count_items_without_page(&document);
+    if misdirected > 0 {
+        problems.push(format!(
+            "has bookmarks going to none of its pages ({}): they will go to the page of their \
+             parent",
+            misdirected
+        ));
+    }
+    (document.get_pages().len(), problems)
+}
+
+// Loads a candidate input as the merge would, then looks at what it loses.
+fn diagnose_file(
+    path: &Path,
+    load_options: &LoadOptions,
+    file_timeout: Option<Duration>,
+) -> Vec<Diagnosis> {
+    match load_input_with_timeout(path, load_options, file_timeout) {
+        Ok(loaded) if loaded.is_empty() => vec![Diagnosis::left_out(
+            path.to_path_buf(),
+            "will be left out, the archive holds no PDF, image or text file".to_string(),
+        )],
+        Ok(loaded) => loaded
+            .into_iter()
+            .map(|input| {
+                let (pages, problems) = diagnose_document(input.document);
+                Diagnosis {
+                    path: input.path,
+                    pages,
+                    problems,
+                }
+            })
+            .collect(),
+        Err(e) => {
+            let reason = match e.downcast_ref::<TimedOut>() {
+                Some(timed_out) => timed_out.to_string(),
+                None => e.root_cause().to_string(),
+            };
+            vec![Diagnosis::left_out(
+                path.to_path_buf(),
+                format!("will be skipped: {}", reason),
+            )]
+        }
+    }
+}
+
+// The files among `entries`, the files of the directory, which are not merged
+// for their format: the images that cannot be converted and, without --sniff,
+// the supported files misnamed.
+fn unsupported_files(entries: &[PathBuf], options: &ScanOptions) -> Vec<(PathBuf, String)> {
+    let mut found = Vec::new();
+    for path in entries.iter().filter(|path| !is_supported(path)) {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        if let Some(sniffed) = sniff_file(path) {
+            if !options.sniff {
+                found.push((
+                    path.clone(),
+                    format!(
+                        "is a {} file without its extension: it is left out without --sniff",
+                        sniffed
+                    ),
+                ));
+            }
+        } else if UNSUPPORTED_IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+            found.push((
+                path.clone(),
+                format!(
+                    "is a {} image, a format which cannot be merged: it is left out",
+                    extension
+                ),
+            ));
+        }
+    }
+    found.sort();
+    found
+}
+
+// The diagnosis of every file of `dir` the merge would take, in merge order,
+// followed by the files it would leave out for their format.
+pub fn diagnose_directory(
+    dir: &Path,
+    scan_options: &ScanOptions,
+    load_options: &LoadOptions,
+    file_timeout: Option<Duration>,
+) -> Result<Vec<Diagnosis>> {
+    // Scanned once for both, so that the same symlinks, ignore files and
+    // hidden files are followed or left out.
+    let entries =
+        scan_files(dir, scan_options).with_context(|| format!("cannot read {}", dir.display()))?;
+    let unsupported = unsupported_files(&entries, scan_options);
+    let mut diagnoses: Vec<Diagnosis> = select_input_files(entries, scan_options)
+        .iter()
+        .flat_map(|path| diagnose_file(path, load_options, file_timeout))
+        .collect();
+    diagnoses.extend(
+        unsupported
+            .into_iter()
+            .map(|(path, problem)| Diagnosis::left_out(path, problem)),
+    );
+    Ok(diagnoses)
+}
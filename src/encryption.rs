@@ -0,0 +1,766 @@
+use aes::{
+    Aes128, Aes256,
+    cipher::{
+        Block, BlockEncrypt, BlockEncryptMut, KeyInit, KeyIvInit,
+        block_padding::{NoPadding, Pkcs7},
+    },
+};
+use anyhow::{Result, bail};
+use lopdf::{Dictionary, Document, Object, ObjectId, StringFormat};
+use md5::{Digest, Md5};
+use rand_core::{OsRng, RngCore};
+use sha2::{Sha256, Sha384, Sha512};
+
+// Decrypts a password-protected document in place, so that its pages can be
+// merged. Documents which are not encrypted are left untouched.
+pub fn decrypt(document: &mut Document, password: &str) -> Result<()> {
+    if !document.is_encrypted() {
+        return Ok(());
+    }
+    if document.decrypt(password).is_err() {
+        bail!("wrong password");
+    }
+    Ok(())
+}
+
+// What readers let the users of an encrypted document do with it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Permissions {
+    pub print: bool,
+    pub copy: bool,
+    pub modify: bool,
+    // Adding annotations and filling forms, also allowed by `modify`.
+    pub annotate: bool,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Permissions {
+            print: true,
+            copy: true,
+            modify: true,
+            annotate: true,
+        }
+    }
+}
+
+impl Permissions {
+    // The `P` value of the encryption, bits of table 22 of ISO 32000-1. The
+    // two lowest bits are always clear, the unused ones always set.
+    pub fn bits(self) -> u32 {
+        let mut bits = 0xffff_fffc;
+        let mut clear = |allowed: bool, mask: u32| {
+            if !allowed {
+                bits &= !mask;
+            }
+        };
+        // Printing, and printing in high quality.
+        clear(self.print, 1 << 2 | 1 << 11);
+        // Copying; extracting for accessibility is always allowed.
+        clear(self.copy, 1 << 4);
+        // Modifying, and assembling (inserting, rotating or deleting pages).
+        clear(self.modify, 1 << 3 | 1 << 10);
+        // Annotating, and filling forms.
+        clear(self.modify || self.annotate, 1 << 5 | 1 << 8);
+        bits
+    }
+}
+
+// Algorithm of the encryption of the output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EncryptionAlgorithm {
+    // RC4 with a 128-bit key, for readers older than Acrobat 7.
+    Rc4,
+    Aes128,
+    #[default]
+    Aes256,
+}
+
+impl EncryptionAlgorithm {
+    pub fn parse(algorithm: &str) -> Result<Self> {
+        Ok(match algorithm.trim().to_ascii_lowercase().as_str() {
+            "rc4-128" => EncryptionAlgorithm::Rc4,
+            "aes-128" => EncryptionAlgorithm::Aes128,
+            "aes-256" => EncryptionAlgorithm::Aes256,
+            _ => bail!(
+                "unknown encryption algorithm {:?}, expected rc4-128, aes-128 or aes-256",
+                algorithm
+            ),
+        })
+    }
+
+    // Length of the file key, in bytes.
+    pub fn key_length(self) -> usize {
+        match self {
+            EncryptionAlgorithm::Rc4 | EncryptionAlgorithm::Aes128 => 16,
+            EncryptionAlgorithm::Aes256 => 32,
+        }
+    }
+}
+
+// Strings of the encryption dictionary itself, such as the recipients, and
+// cross-reference streams stay in the clear.
+fn is_encrypted_object(object: &Object) -> bool {
+    match object {
+        Object::Stream(stream) => {
+            stream.dict.get(b"Type").and_then(Object::as_name).ok() != Some(b"XRef")
+        }
+        _ => true,
+    }
+}
+
+fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut state: Vec<u8> = (0..=255).collect();
+    let mut j = 0u8;
+    for i in 0..256 {
+        j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+        state.swap(i, usize::from(j));
+    }
+    let (mut i, mut j) = (0u8, 0u8);
+    data.iter()
+        .map(|byte| {
+            i = i.wrapping_add(1);
+            j = j.wrapping_add(state[usize::from(i)]);
+            state.swap(usize::from(i), usize::from(j));
+            let index = state[usize::from(i)].wrapping_add(state[usize::from(j)]);
+            byte ^ state[usize::from(index)]
+        })
+        .collect()
+}
+
+// Encrypts strings and streams: AES-256 with the file key itself, RC4 and
+// AES-128 with a key made from the file key and the object number. AES data
+// is the IV followed by the padded data in CBC mode. IVs are made by
+// encrypting a different counter for every string and stream (NIST SP
+// 800-38A, appendix C), so that no random source is needed.
+struct Encryptor<'a> {
+    algorithm: EncryptionAlgorithm,
+    key: &'a [u8],
+    counter: u64,
+}
+
+impl Encryptor<'_> {
+    fn object_key(&self, (number, generation): ObjectId) -> Vec<u8> {
+        if self.algorithm == EncryptionAlgorithm::Aes256 {
+            return self.key.to_vec();
+        }
+        let mut hash = Md5::new();
+        hash.update(self.key);
+        hash.update(&number.to_le_bytes()[..3]);
+        hash.update(&generation.to_le_bytes()[..2]);
+        if self.algorithm == EncryptionAlgorithm::Aes128 {
+            hash.update(b"sAlT");
+        }
+        hash.finalize()[..self.key.len().min(16)].to_vec()
+    }
+
+    fn iv<C: BlockEncrypt + KeyInit>(&mut self, key: &[u8]) -> [u8; 16] {
+        self.counter += 1;
+        let mut iv = [0u8; 16];
+        iv[..8].copy_from_slice(&self.counter.to_be_bytes());
+        let mut block = Block::<C>::clone_from_slice(&iv);
+        C::new_from_slice(key)
+            .expect("AES keys have the length of the algorithm")
+            .encrypt_block(&mut block);
+        iv.copy_from_slice(&block);
+        iv
+    }
+
+    fn encrypt(&mut self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        let (iv, encrypted) = match self.algorithm {
+            EncryptionAlgorithm::Rc4 => return rc4(key, data),
+            EncryptionAlgorithm::Aes128 => {
+                let iv = self.iv::<Aes128>(key);
+                let encryptor = cbc::Encryptor::<Aes128>::new_from_slices(key, &iv);
+                (
+                    iv,
+                    encryptor.map(|e| e.encrypt_padded_vec_mut::<Pkcs7>(data)),
+                )
+            }
+            EncryptionAlgorithm::Aes256 => {
+                let iv = self.iv::<Aes256>(key);
+                let encryptor = cbc::Encryptor::<Aes256>::new_from_slices(key, &iv);
+                (
+                    iv,
+                    encryptor.map(|e| e.encrypt_padded_vec_mut::<Pkcs7>(data)),
+                )
+            }
+        };
+        let mut result = iv.to_vec();
+        result.extend(encrypted.expect("AES keys have the length of the algorithm"));
+        result
+    }
+
+    fn encrypt_strings(&mut self, key: &[u8], object: &mut Object) {
+        match object {
+            Object::String(bytes, _) => *bytes = self.encrypt(key, bytes),
+            Object::Array(items) => items
+                .iter_mut()
+                .for_each(|item| self.encrypt_strings(key, item)),
+            Object::Dictionary(dictionary) => dictionary
+                .iter_mut()
+                .for_each(|(_, value)| self.encrypt_strings(key, value)),
+            Object::Stream(stream) => stream
+                .dict
+                .iter_mut()
+                .for_each(|(_, value)| self.encrypt_strings(key, value)),
+            _ => {}
+        }
+    }
+}
+
+// The encryption dictionary of the public-key security handler: `recipients`
+// are the PKCS#7 envelopes of the seed for every recipient.
+pub fn public_key_dictionary(
+    algorithm: EncryptionAlgorithm,
+    recipients: Vec<Vec<u8>>,
+) -> Dictionary {
+    let recipients = Object::Array(
+        recipients
+            .into_iter()
+            .map(|recipient| Object::String(recipient, StringFormat::Hexadecimal))
+            .collect(),
+    );
+    let key_bits = Object::Integer(algorithm.key_length() as i64 * 8);
+    let (filter_method, version) = match algorithm {
+        // RC4 predates crypt filters, the recipients are in the dictionary.
+        EncryptionAlgorithm::Rc4 => {
+            return Dictionary::from_iter(vec![
+                ("Filter", Object::Name(b"Adobe.PubSec".to_vec())),
+                ("SubFilter", Object::Name(b"adbe.pkcs7.s4".to_vec())),
+                ("V", Object::Integer(2)),
+                ("Length", key_bits),
+                ("Recipients", recipients),
+            ]);
+        }
+        EncryptionAlgorithm::Aes128 => ("AESV2", 4),
+        EncryptionAlgorithm::Aes256 => ("AESV3", 5),
+    };
+    let crypt_filter = Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"CryptFilter".to_vec())),
+        ("CFM", Object::Name(filter_method.as_bytes().to_vec())),
+        ("Length", key_bits.clone()),
+        ("Recipients", recipients),
+        ("EncryptMetadata", Object::Boolean(true)),
+    ]);
+    Dictionary::from_iter(vec![
+        ("Filter", Object::Name(b"Adobe.PubSec".to_vec())),
+        ("SubFilter", Object::Name(b"adbe.pkcs7.s5".to_vec())),
+        ("V", Object::Integer(version)),
+        ("Length", key_bits),
+        (
+            "CF",
+            Object::Dictionary(Dictionary::from_iter(vec![(
+                "DefaultCryptFilter",
+                Object::Dictionary(crypt_filter),
+            )])),
+        ),
+        ("StmF", Object::Name(b"DefaultCryptFilter".to_vec())),
+        ("StrF", Object::Name(b"DefaultCryptFilter".to_vec())),
+    ])
+}
+
+// Encrypts the strings and streams of a document with the file `key`, to be
+// saved right after: the document cannot be edited any more. `dictionary` is
+// the encryption dictionary telling readers how to find the key.
+pub fn encrypt(
+    document: &mut Document,
+    algorithm: EncryptionAlgorithm,
+    key: &[u8],
+    dictionary: Dictionary,
+) -> Result<()> {
+    if key.len() != algorithm.key_length() {
+        bail!(
+            "a key of {} bytes cannot be used with {:?}",
+            key.len(),
+            algorithm
+        );
+    }
+    let mut encryptor = Encryptor {
+        algorithm,
+        key,
+        counter: 0,
+    };
+    for (id, object) in document.objects.iter_mut() {
+        if !is_encrypted_object(object) {
+            continue;
+        }
+        let object_key = encryptor.object_key(*id);
+        encryptor.encrypt_strings(&object_key, object);
+        if let Object::Stream(stream) = object {
+            let content = encryptor.encrypt(&object_key, &stream.content);
+            stream.set_content(content);
+        }
+    }
+
+    // Encrypted documents need an ID. It does not have to be secret but must
+    // not tell anything of the key, so it is taken from the encrypted streams.
+    if !document.trailer.has(b"ID") {
+        let mut hasher = Md5::new();
+        for (id, object) in &document.objects {
+            if let Object::Stream(stream) = object {
+                hasher.update(id.0.to_le_bytes());
+                hasher.update(&stream.content);
+            }
+        }
+        let id = Object::String(hasher.finalize().to_vec(), StringFormat::Hexadecimal);
+        document.trailer.set("ID", vec![id.clone(), id]);
+    }
+    let dictionary_id = document.add_object(dictionary);
+    document.trailer.set("Encrypt", dictionary_id);
+    Ok(())
+}
+
+// The hash of a password of the standard security handler of revision 6
+// (algorithm 2.B of ISO 32000-2), salted and, for the owner password, mixed
+// with the `U` entry.
+fn password_hash(password: &[u8], salt: &[u8], user_key: &[u8]) -> [u8; 32] {
+    let mut hash = Sha256::new();
+    hash.update(password);
+    hash.update(salt);
+    hash.update(user_key);
+    let mut k = hash.finalize().to_vec();
+    let mut round = 0;
+    loop {
+        let mut k1 = Vec::new();
+        for _ in 0..64 {
+            k1.extend(password);
+            k1.extend(&k);
+            k1.extend(user_key);
+        }
+        let e = cbc::Encryptor::<Aes128>::new_from_slices(&k[..16], &k[16..32])
+            .expect("the hashes are longer than an AES-128 key and IV")
+            .encrypt_padded_vec_mut::<NoPadding>(&k1);
+        let sum: u32 = e[..16].iter().map(|byte| u32::from(*byte)).sum();
+        k = match sum % 3 {
+            0 => Sha256::digest(&e).to_vec(),
+            1 => Sha384::digest(&e).to_vec(),
+            _ => Sha512::digest(&e).to_vec(),
+        };
+        round += 1;
+        if round >= 64 && u32::from(e[e.len() - 1]) + 32 <= round {
+            break;
+        }
+    }
+    let mut result = [0; 32];
+    result.copy_from_slice(&k[..32]);
+    result
+}
+
+// Encrypts the 32-byte file key with the hash of a password, for the `UE`
+// and `OE` entries.
+fn encrypt_file_key(hash: &[u8; 32], file_key: &[u8]) -> Vec<u8> {
+    cbc::Encryptor::<Aes256>::new_from_slices(hash, &[0; 16])
+        .expect("the hashes have the length of an AES-256 key")
+        .encrypt_padded_vec_mut::<NoPadding>(file_key)
+}
+
+// Padding of the passwords of the standard security handler up to revision
+// 4, which are always 32 bytes long.
+const PASSWORD_PADDING: [u8; 32] = [
+    0x28, 0xbf, 0x4e, 0x5e, 0x4e, 0x75, 0x8a, 0x41, 0x64, 0x00, 0x4e, 0x56, 0xff, 0xfa, 0x01, 0x08,
+    0x2e, 0x2e, 0x00, 0xb6, 0xd0, 0x68, 0x3e, 0x80, 0x2f, 0x0c, 0xa9, 0xfe, 0x64, 0x53, 0x69, 0x7a,
+];
+
+// A password up to revision 4: in PDFDocEncoding, which matches Latin-1 for
+// the characters people type, the others becoming `?`, then padded or cut
+// to 32 bytes.
+fn padded_password(password: &str) -> [u8; 32] {
+    let bytes: Vec<u8> = password
+        .chars()
+        .map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?'))
+        .take(32)
+        .collect();
+    let mut padded = PASSWORD_PADDING;
+    padded.copy_within(..32 - bytes.len(), bytes.len());
+    padded[..bytes.len()].copy_from_slice(&bytes);
+    padded
+}
+
+// RC4 with `key`, then 19 more times with the key XORed with the round
+// number, as in algorithms 3 and 5 of ISO 32000-1.
+fn rc4_rounds(key: &[u8], data: &[u8]) -> Vec<u8> {
+    (1..=19u8).fold(rc4(key, data), |data, round| {
+        let round_key: Vec<u8> = key.iter().map(|byte| byte ^ round).collect();
+        rc4(&round_key, &data)
+    })
+}
+
+// MD5 hashed again 50 times, keeping the first 16 bytes each time, as in
+// algorithms 2 and 3 of revisions 3 and 4.
+fn md5_rounds(hash: Md5) -> [u8; 16] {
+    let mut digest: [u8; 16] = hash.finalize().into();
+    for _ in 0..50 {
+        digest = Md5::digest(digest).into();
+    }
+    digest
+}
+
+// The `O` entry of revisions 3 and 4 (algorithm 3), for an empty user
+// password.
+fn owner_entry(owner_password: &str) -> Vec<u8> {
+    let key = md5_rounds(Md5::new_with_prefix(padded_password(owner_password)));
+    rc4_rounds(&key, &PASSWORD_PADDING)
+}
+
+// The file key of revisions 3 and 4 (algorithm 2), for an empty user
+// password and encrypted metadata.
+fn file_key(owner_entry: &[u8], permissions: Permissions, id: &[u8]) -> [u8; 16] {
+    let mut hash = Md5::new_with_prefix(PASSWORD_PADDING);
+    hash.update(owner_entry);
+    hash.update(permissions.bits().to_le_bytes());
+    hash.update(id);
+    md5_rounds(hash)
+}
+
+// The `U` entry of revisions 3 and 4 (algorithm 5), whose last 16 bytes are
+// arbitrary.
+fn user_entry(file_key: &[u8], id: &[u8]) -> Vec<u8> {
+    let mut hash = Md5::new_with_prefix(PASSWORD_PADDING);
+    hash.update(id);
+    let mut user = rc4_rounds(file_key, &hash.finalize());
+    user.extend([0; 16]);
+    user
+}
+
+fn string(bytes: &[u8]) -> Object {
+    Object::String(bytes.to_vec(), StringFormat::Hexadecimal)
+}
+
+// The crypt filter of the standard security handler, for AES.
+fn standard_crypt_filters(method: &str, key_length: i64) -> Object {
+    let crypt_filter = Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"CryptFilter".to_vec())),
+        ("CFM", Object::Name(method.as_bytes().to_vec())),
+        ("AuthEvent", Object::Name(b"DocOpen".to_vec())),
+        ("Length", Object::Integer(key_length)),
+    ]);
+    Object::Dictionary(Dictionary::from_iter(vec![(
+        "StdCF",
+        Object::Dictionary(crypt_filter),
+    )]))
+}
+
+// The encryption dictionary and file key of revision 3 (RC4) or 4
+// (AES-128), for a document whose first ID is `id`.
+fn rc4_or_aes128_encryption(
+    algorithm: EncryptionAlgorithm,
+    owner_password: &str,
+    permissions: Permissions,
+    id: &[u8],
+) -> (Dictionary, [u8; 16]) {
+    let owner = owner_entry(owner_password);
+    let file_key = file_key(&owner, permissions, id);
+    let mut dictionary = Dictionary::from_iter(vec![
+        ("Filter", Object::Name(b"Standard".to_vec())),
+        ("Length", Object::Integer(128)),
+        ("O", string(&owner)),
+        ("U", string(&user_entry(&file_key, id))),
+        ("P", Object::Integer(i64::from(permissions.bits() as i32))),
+    ]);
+    if algorithm == EncryptionAlgorithm::Rc4 {
+        dictionary.set("V", 2);
+        dictionary.set("R", 3);
+    } else {
+        dictionary.set("V", 4);
+        dictionary.set("R", 4);
+        dictionary.set("CF", standard_crypt_filters("AESV2", 16));
+        dictionary.set("StmF", Object::Name(b"StdCF".to_vec()));
+        dictionary.set("StrF", Object::Name(b"StdCF".to_vec()));
+        dictionary.set("EncryptMetadata", true);
+    }
+    (dictionary, file_key)
+}
+
+// The encryption dictionary of revision 6 (AES-256) for `file_key`. `salts`
+// are the validation and key salts of the user then of the owner password,
+// `perms_padding` the arbitrary end of `Perms`.
+fn aes256_encryption(
+    owner_password: &str,
+    permissions: Permissions,
+    file_key: &[u8; 32],
+    salts: &[u8; 32],
+    perms_padding: [u8; 4],
+) -> Dictionary {
+    // Passwords are UTF-8 and only their first 127 bytes count.
+    let owner_password = &owner_password.as_bytes()[..owner_password.len().min(127)];
+    let mut user = password_hash(b"", &salts[..8], b"").to_vec();
+    user.extend(&salts[..16]);
+    let user_encrypted_key = encrypt_file_key(&password_hash(b"", &salts[8..16], b""), file_key);
+    let mut owner = password_hash(owner_password, &salts[16..24], &user).to_vec();
+    owner.extend(&salts[16..]);
+    let owner_encrypted_key = encrypt_file_key(
+        &password_hash(owner_password, &salts[24..], &user),
+        file_key,
+    );
+
+    // The permissions again, encrypted with the file key so that they cannot
+    // be changed: `P`, four bytes set, `T` for the encrypted metadata, `adb`
+    // and four arbitrary bytes.
+    let mut perms = [0xff; 16];
+    perms[..4].copy_from_slice(&permissions.bits().to_le_bytes());
+    perms[8..12].copy_from_slice(b"Tadb");
+    perms[12..].copy_from_slice(&perms_padding);
+    let mut block = Block::<Aes256>::clone_from_slice(&perms);
+    Aes256::new_from_slice(file_key)
+        .expect("the file key has the length of an AES-256 key")
+        .encrypt_block(&mut block);
+
+    Dictionary::from_iter(vec![
+        ("Filter", Object::Name(b"Standard".to_vec())),
+        ("V", Object::Integer(5)),
+        ("R", Object::Integer(6)),
+        ("Length", Object::Integer(256)),
+        ("CF", standard_crypt_filters("AESV3", 32)),
+        ("StmF", Object::Name(b"StdCF".to_vec())),
+        ("StrF", Object::Name(b"StdCF".to_vec())),
+        ("O", string(&owner)),
+        ("U", string(&user)),
+        ("OE", string(&owner_encrypted_key)),
+        ("UE", string(&user_encrypted_key)),
+        ("P", Object::Integer(i64::from(permissions.bits() as i32))),
+        ("Perms", string(&block)),
+        ("EncryptMetadata", Object::Boolean(true)),
+    ])
+}
+
+// Encrypts the document with `algorithm` and the standard security handler,
+// so that it opens without a password with the restrictions of
+// `permissions`, which `owner_password` lifts.
+pub fn encrypt_with_owner_password(
+    document: &mut Document,
+    owner_password: &str,
+    algorithm: EncryptionAlgorithm,
+    permissions: Permissions,
+) -> Result<()> {
+    if algorithm == EncryptionAlgorithm::Aes256 {
+        let mut file_key = [0; 32];
+        OsRng.fill_bytes(&mut file_key);
+        let mut salts = [0; 32];
+        OsRng.fill_bytes(&mut salts);
+        let mut perms_padding = [0; 4];
+        OsRng.fill_bytes(&mut perms_padding);
+        let dictionary = aes256_encryption(
+            owner_password,
+            permissions,
+            &file_key,
+            &salts,
+            perms_padding,
+        );
+        return encrypt(document, algorithm, &file_key, dictionary);
+    }
+
+    // The file key of the older revisions depends on the ID, which is made
+    // first. It is random, as it would otherwise tell readers something of
+    // the content or of the key.
+    let id = match document
+        .trailer
+        .get(b"ID")
+        .and_then(Object::as_array)
+        .ok()
+        .and_then(|id| id.first()?.as_str().ok())
+    {
+        Some(id) => id.to_vec(),
+        None => {
+            let mut id = [0; 16];
+            OsRng.fill_bytes(&mut id);
+            document.trailer.set("ID", vec![string(&id), string(&id)]);
+            id.to_vec()
+        }
+    };
+    let (dictionary, file_key) =
+        rc4_or_aes128_encryption(algorithm, owner_password, permissions, &id);
+    encrypt(document, algorithm, &file_key, dictionary)
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::dictionary;
+
+    use super::*;
+
+    // The expected values come from an independent implementation of ISO
+    // 32000-2, in Python with the `cryptography` package.
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn entry(dictionary: &Dictionary, key: &[u8]) -> String {
+        hex(dictionary.get(key).and_then(Object::as_str).unwrap())
+    }
+
+    const NO_PRINT: Permissions = Permissions {
+        print: false,
+        copy: true,
+        modify: true,
+        annotate: true,
+    };
+
+    #[test]
+    fn encrypts_with_rc4() {
+        assert_eq!(hex(&rc4(b"Key", b"Plaintext")), "bbf316e8d940af0ad3");
+    }
+
+    #[test]
+    fn hashes_passwords_with_algorithm_2b() {
+        assert_eq!(
+            hex(&password_hash(b"", &[0; 8], b"")),
+            "439feba099a63d0d035a1e5fb67ff307329189584956425aff2d3bd3d15edc60"
+        );
+    }
+
+    #[test]
+    fn makes_the_entries_of_revision_6() {
+        let salts: [u8; 32] = std::array::from_fn(|i| i as u8);
+        let file_key: [u8; 32] = std::array::from_fn(|i| i as u8 + 32);
+        let dictionary = aes256_encryption("owner", NO_PRINT, &file_key, &salts, [1, 2, 3, 4]);
+        assert_eq!(
+            entry(&dictionary, b"U"),
+            "1403c04eb647d2e60452dfc4eb0a5e0cf322e8a83a759eabbd17d498a93ba041\
+             000102030405060708090a0b0c0d0e0f"
+        );
+        assert_eq!(
+            entry(&dictionary, b"UE"),
+            "1a453dfa4dff353f9b4c306b4e9f6e8fc1156b3ce8e2c38d59632b430ad54441"
+        );
+        assert_eq!(
+            entry(&dictionary, b"O"),
+            "0b608f77bb3088b186e45a3fb2a4e58d77c171f17e7982d5f75feed886f4f633\
+             101112131415161718191a1b1c1d1e1f"
+        );
+        assert_eq!(
+            entry(&dictionary, b"OE"),
+            "415b3491e57eb302deeda2d731311384d108ef832433c8d50253dca8193ba479"
+        );
+        assert_eq!(
+            entry(&dictionary, b"Perms"),
+            "d969e63be33e666bba91eefe125731b0"
+        );
+        assert_eq!(
+            dictionary.get(b"P").and_then(Object::as_i64).unwrap(),
+            -2056
+        );
+    }
+
+    #[test]
+    fn makes_the_entries_and_key_of_revisions_3_and_4() {
+        let id: Vec<u8> = (100..116).collect();
+        for algorithm in [EncryptionAlgorithm::Rc4, EncryptionAlgorithm::Aes128] {
+            let (dictionary, file_key) =
+                rc4_or_aes128_encryption(algorithm, "owner", NO_PRINT, &id);
+            assert_eq!(
+                entry(&dictionary, b"O"),
+                "566fa873ee33c797cd3b904fdadf814afa34df9a38f6ed41b984e2c6da2aa6f5"
+            );
+            assert_eq!(hex(&file_key), "5ee663a55b50d07e79128634ce2faaa2");
+            assert_eq!(
+                entry(&dictionary, b"U"),
+                "be77285f5e89f60c70a88f3f1e28f5d000000000000000000000000000000000"
+            );
+        }
+    }
+
+    #[test]
+    fn pads_and_cuts_passwords_of_revisions_3_and_4() {
+        assert_eq!(padded_password(""), PASSWORD_PADDING);
+        let padded = padded_password("é!");
+        assert_eq!(padded[..2], [0xe9, b'!']);
+        assert_eq!(padded[2..], PASSWORD_PADDING[..30]);
+        assert_eq!(padded_password("€").first(), Some(&b'?'));
+        assert_eq!(padded_password(&"x".repeat(40)), [b'x'; 32]);
+    }
+
+    #[test]
+    fn makes_object_keys() {
+        let key: Vec<u8> = (0..16).collect();
+        let object_key = |algorithm, id| {
+            let encryptor = Encryptor {
+                algorithm,
+                key: &key,
+                counter: 0,
+            };
+            hex(&encryptor.object_key(id))
+        };
+        assert_eq!(
+            object_key(EncryptionAlgorithm::Rc4, (12, 0)),
+            "e8f83ce0ea984179b0ac991f508f1fca"
+        );
+        assert_eq!(
+            object_key(EncryptionAlgorithm::Aes128, (12, 1)),
+            "64bff230e0714382a4b2fb25020d1966"
+        );
+        assert_eq!(object_key(EncryptionAlgorithm::Aes256, (12, 0)), hex(&key));
+    }
+
+    // A one page document with a title and a content stream.
+    fn document() -> Document {
+        let mut doc = Document::with_version("1.7");
+        let content_id = doc.add_object(lopdf::Stream::new(
+            Dictionary::new(),
+            b"BT /F1 12 Tf (Secret) Tj ET".to_vec(),
+        ));
+        let pages_id = doc.new_object_id();
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            "Contents" => content_id,
+        });
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        let info_id = doc.add_object(dictionary! {
+            "Title" => Object::string_literal("Quarterly report"),
+        });
+        doc.trailer.set("Root", catalog_id);
+        doc.trailer.set("Info", info_id);
+        doc
+    }
+
+    // `Document::decrypt` of lopdf expects crypt filters, which revision 3
+    // does not have, so the objects are decrypted one by one, with the key
+    // lopdf finds for the empty user password.
+    #[test]
+    fn opens_in_lopdf_without_a_password() {
+        for algorithm in [EncryptionAlgorithm::Rc4, EncryptionAlgorithm::Aes128] {
+            let mut doc = document();
+            encrypt_with_owner_password(&mut doc, "owner", algorithm, NO_PRINT).unwrap();
+            let mut bytes = Vec::new();
+            doc.save_to(&mut bytes).unwrap();
+
+            let encrypted = Document::load_mem(&bytes).unwrap();
+            assert!(encrypted.is_encrypted());
+            let key = lopdf::encryption::get_encryption_key(&encrypted, "", true).unwrap();
+            let decrypt = |id, object: &Object| {
+                let aes = algorithm == EncryptionAlgorithm::Aes128;
+                lopdf::encryption::decrypt_object(&key, id, object, aes).unwrap()
+            };
+            let info_id = encrypted
+                .trailer
+                .get(b"Info")
+                .unwrap()
+                .as_reference()
+                .unwrap();
+            let title = encrypted
+                .get_dictionary(info_id)
+                .unwrap()
+                .get(b"Title")
+                .unwrap();
+            assert_eq!(decrypt(info_id, title), b"Quarterly report");
+            let page_id = encrypted.page_iter().next().unwrap();
+            let content_id = encrypted.get_page_contents(page_id)[0];
+            let content = encrypted.get_object(content_id).unwrap();
+            assert_eq!(decrypt(content_id, content), b"BT /F1 12 Tf (Secret) Tj ET");
+        }
+    }
+}
@@ -0,0 +1,208 @@
+// C interface, enabled with the `ffi` feature. Every function returns 0 on
+// success and -1 on failure, panics included, in which case
+// `pdfmerge_last_error` describes the problem.
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString, c_char, c_int},
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    ptr, slice,
+};
+
+use anyhow::{Result, anyhow};
+
+use crate::{
+    image::image_document::ImageOptions,
+    merge::{Merger, save_atomically},
+    source::{is_pdf_file_name, load_bytes, load_file},
+};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(error: anyhow::Error) {
+    let message = CString::new(format!("{:#}", error).replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+// Runs the body of an exported function. A panic must not unwind into the
+// caller, where it is undefined behavior: it is reported as an error instead.
+fn catch_panics<T>(body: impl FnOnce() -> Result<T>) -> Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or(payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown error");
+        Err(anyhow!("pdf-merge crashed: {}", message))
+    })
+}
+
+fn to_status(body: impl FnOnce() -> Result<()>) -> c_int {
+    match catch_panics(body) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_last_error(e);
+            -1
+        }
+    }
+}
+
+#[cfg(unix)]
+fn c_str_to_path(c_str: &CStr) -> Result<PathBuf> {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    Ok(PathBuf::from(OsStr::from_bytes(c_str.to_bytes())))
+}
+
+// Paths are expected to be UTF-8 on the other platforms.
+#[cfg(not(unix))]
+fn c_str_to_path(c_str: &CStr) -> Result<PathBuf> {
+    Ok(PathBuf::from(c_str.to_str()?))
+}
+
+unsafe fn path_arg(path: *const c_char) -> Result<PathBuf> {
+    if path.is_null() {
+        return Err(anyhow!("path is null"));
+    }
+    c_str_to_path(unsafe { CStr::from_ptr(path) })
+}
+
+/// Merges the `n` PDF or image files of `paths` into `out_path`. Fails when
+/// `n` is 0.
+///
+/// # Safety
+///
+/// `paths` must point to `n` valid NUL-terminated strings and `out_path` must
+/// be a valid NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pdfmerge_merge_files(
+    paths: *const *const c_char,
+    n: usize,
+    out_path: *const c_char,
+) -> c_int {
+    to_status(|| {
+        if n == 0 {
+            return Err(anyhow!("no documents to merge"));
+        }
+        if paths.is_null() {
+            return Err(anyhow!("paths is null"));
+        }
+        let paths = unsafe { slice::from_raw_parts(paths, n) };
+        let out_path = unsafe { path_arg(out_path) }?;
+
+        let mut documents = Vec::with_capacity(n);
+        for path in paths {
+            let path = unsafe { path_arg(*path) }?;
+            documents.push(load_file(&path, &ImageOptions::default())?);
+        }
+
+        save_atomically(&mut Merger::new(documents).merge()?, &out_path, None)?;
+        Ok(())
+    })
+}
+
+/// Number of pages of the PDF or image file at `path` (always 1 for images),
+/// or -1 on failure.
+///
+/// # Safety
+///
+/// `path` must be a valid NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pdfmerge_page_count(path: *const c_char) -> i64 {
+    let result = catch_panics(|| {
+        let path = unsafe { path_arg(path) }?;
+        if !is_pdf_file_name(&path) {
+            // Images always make a single page, only check that they can be read.
+            load_file(&path, &ImageOptions::default())?;
+            return Ok(1);
+        }
+        Ok(load_file(&path, &ImageOptions::default())?
+            .get_pages()
+            .len() as i64)
+    });
+    result.unwrap_or_else(|e| {
+        set_last_error(e);
+        -1
+    })
+}
+
+/// Merges `n` in-memory documents. Each buffer is either a PDF or an image
+/// whose format is given by the extension of the matching `names` entry
+/// (`names` may be null when every buffer is a PDF). On success the merged
+/// PDF is returned through `out_buffer` and `out_length` and must be released
+/// with `pdfmerge_free_buffer`. Fails when `n` is 0.
+///
+/// # Safety
+///
+/// `buffers` and `lengths` must point to `n` entries describing valid memory
+/// regions, `names` must be null or point to `n` valid NUL-terminated strings,
+/// and `out_buffer` and `out_length` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pdfmerge_merge_buffers(
+    buffers: *const *const u8,
+    lengths: *const usize,
+    names: *const *const c_char,
+    n: usize,
+    out_buffer: *mut *mut u8,
+    out_length: *mut usize,
+) -> c_int {
+    to_status(|| {
+        if out_buffer.is_null() || out_length.is_null() {
+            return Err(anyhow!("output pointers are null"));
+        }
+        if n == 0 {
+            return Err(anyhow!("no documents to merge"));
+        }
+        if buffers.is_null() || lengths.is_null() {
+            return Err(anyhow!("buffers are null"));
+        }
+
+        let mut documents = Vec::with_capacity(n);
+        for index in 0..n {
+            let bytes = unsafe { slice::from_raw_parts(*buffers.add(index), *lengths.add(index)) };
+            let name = if names.is_null() {
+                PathBuf::from(format!("buffer {}", index))
+            } else {
+                unsafe { path_arg(*names.add(index)) }?
+            };
+            documents.push(load_bytes(bytes, &name, &ImageOptions::default())?);
+        }
+
+        let mut merged = Vec::new();
+        Merger::new(documents).merge()?.save_to(&mut merged)?;
+
+        let merged = merged.into_boxed_slice();
+        unsafe {
+            *out_length = merged.len();
+            *out_buffer = Box::into_raw(merged) as *mut u8;
+        }
+        Ok(())
+    })
+}
+
+/// Releases a buffer returned by `pdfmerge_merge_buffers`.
+///
+/// # Safety
+///
+/// `buffer` and `length` must come from a single successful call to
+/// `pdfmerge_merge_buffers`, and the buffer must not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pdfmerge_free_buffer(buffer: *mut u8, length: usize) {
+    if !buffer.is_null() {
+        drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(buffer, length)) });
+    }
+}
+
+/// Message of the last error raised on the calling thread, or null. The
+/// string stays valid until the next failing call on the same thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn pdfmerge_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
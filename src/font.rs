@@ -0,0 +1,368 @@
+// Font of the text added to the pages, such as stamps and index captions.
+// The standard Helvetica needs no embedding but only covers Latin-1, other
+// text is drawn with a TrueType font embedded as a subset holding the glyphs
+// of the text: DejaVu Sans, or a font given by the user.
+use std::collections::BTreeMap;
+
+use anyhow::{Result, bail};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use ttf_parser::{Face, GlyphId, Tag};
+
+use crate::stamp::literal_string;
+
+pub const DEFAULT_FONT: &[u8] = include_bytes!("../assets/fonts/DejaVuSans.ttf");
+
+// Tables a TrueType font embedded in a PDF needs, in tag order.
+const EMBEDDED_TABLES: [&[u8; 4]; 9] = [
+    b"cvt ", b"fpgm", b"glyf", b"head", b"hhea", b"hmtx", b"loca", b"maxp", b"prep",
+];
+
+// Flags of the components of composite glyphs.
+const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+const WE_HAVE_A_SCALE: u16 = 0x0008;
+const MORE_COMPONENTS: u16 = 0x0020;
+const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn table<'a>(face: &Face<'a>, tag: &[u8; 4]) -> Option<&'a [u8]> {
+    face.raw_face().table(Tag::from_bytes(tag))
+}
+
+// Checks that `data` is a TrueType font that can be embedded: fonts with
+// PostScript outlines (`.otf`) have no `glyf` table to subset.
+pub fn check_font(data: &[u8]) -> Result<()> {
+    let face = match Face::parse(data, 0) {
+        Ok(face) => face,
+        Err(e) => bail!("invalid TrueType font: {}", e),
+    };
+    if table(&face, b"glyf").is_none() {
+        bail!("the font has no TrueType outlines (glyf table)");
+    }
+    Ok(())
+}
+
+// Byte ranges of the glyphs in the `glyf` table, from the `loca` table.
+fn glyph_ranges(face: &Face) -> Option<Vec<(usize, usize)>> {
+    let head = table(face, b"head")?;
+    let loca = table(face, b"loca")?;
+    let long_offsets = read_u16(head, 50)? == 1;
+    let offsets: Vec<usize> = (0..=face.number_of_glyphs() as usize)
+        .map(|index| {
+            if long_offsets {
+                read_u32(loca, index * 4).map(|offset| offset as usize)
+            } else {
+                read_u16(loca, index * 2).map(|offset| offset as usize * 2)
+            }
+        })
+        .collect::<Option<_>>()?;
+    Some(offsets.windows(2).map(|pair| (pair[0], pair[1])).collect())
+}
+
+// Adds the glyphs composite glyphs are made of to `glyphs`.
+fn add_components(glyf: &[u8], ranges: &[(usize, usize)], glyphs: &mut Vec<u16>) {
+    let mut index = 0;
+    while index < glyphs.len() {
+        let Some(&(start, end)) = ranges.get(glyphs[index] as usize) else {
+            index += 1;
+            continue;
+        };
+        index += 1;
+        let Some(glyph) = glyf.get(start..end) else {
+            continue;
+        };
+        // Simple glyphs have a positive number of contours.
+        if glyph.len() < 10 || i16::from_be_bytes([glyph[0], glyph[1]]) >= 0 {
+            continue;
+        }
+        let mut offset = 10;
+        while let (Some(flags), Some(component)) =
+            (read_u16(glyph, offset), read_u16(glyph, offset + 2))
+        {
+            if !glyphs.contains(&component) {
+                glyphs.push(component);
+            }
+            offset += 4 + if flags & ARG_1_AND_2_ARE_WORDS != 0 {
+                4
+            } else {
+                2
+            };
+            offset += match flags {
+                _ if flags & WE_HAVE_A_SCALE != 0 => 2,
+                _ if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 => 4,
+                _ if flags & WE_HAVE_A_TWO_BY_TWO != 0 => 8,
+                _ => 0,
+            };
+            if flags & MORE_COMPONENTS == 0 {
+                break;
+            }
+        }
+    }
+}
+
+fn checksum(table: &[u8]) -> u32 {
+    table.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+// The font with only the tables a PDF needs and the outlines of `glyphs`,
+// the other glyphs left empty so that glyph ids do not change.
+fn subset(face: &Face, glyphs: &[u16]) -> Option<Vec<u8>> {
+    let glyf = table(face, b"glyf")?;
+    let ranges = glyph_ranges(face)?;
+    let mut glyphs = glyphs.to_vec();
+    // The `.notdef` glyph is required.
+    glyphs.push(0);
+    add_components(glyf, &ranges, &mut glyphs);
+
+    let mut new_glyf = Vec::new();
+    let mut new_loca = Vec::with_capacity((ranges.len() + 1) * 4);
+    for (glyph, &(start, end)) in ranges.iter().enumerate() {
+        new_loca.extend((new_glyf.len() as u32).to_be_bytes());
+        if glyphs.contains(&(glyph as u16)) {
+            new_glyf.extend(glyf.get(start..end)?);
+            new_glyf.resize(new_glyf.len().next_multiple_of(4), 0);
+        }
+    }
+    new_loca.extend((new_glyf.len() as u32).to_be_bytes());
+
+    let tables: Vec<(&[u8; 4], Vec<u8>)> = EMBEDDED_TABLES
+        .into_iter()
+        .filter_map(|tag| {
+            let table = match tag {
+                b"glyf" => new_glyf.clone(),
+                b"loca" => new_loca.clone(),
+                b"head" => {
+                    let mut head = table(face, tag)?.to_vec();
+                    // Long `loca` offsets, and no whole font checksum.
+                    head.get_mut(50..52)?.copy_from_slice(&1u16.to_be_bytes());
+                    head.get_mut(8..12)?.copy_from_slice(&[0; 4]);
+                    head
+                }
+                _ => table(face, tag)?.to_vec(),
+            };
+            Some((tag, table))
+        })
+        .collect();
+
+    let table_count = tables.len() as u16;
+    let entry_selector = table_count.ilog2() as u16;
+    let search_range = (1u16 << entry_selector) * 16;
+    let mut font = Vec::new();
+    font.extend(0x0001_0000u32.to_be_bytes());
+    font.extend(table_count.to_be_bytes());
+    font.extend(search_range.to_be_bytes());
+    font.extend(entry_selector.to_be_bytes());
+    font.extend((table_count * 16 - search_range).to_be_bytes());
+    let mut offset = 12 + tables.len() * 16;
+    for (tag, table) in &tables {
+        font.extend(*tag);
+        font.extend(checksum(table).to_be_bytes());
+        font.extend((offset as u32).to_be_bytes());
+        font.extend((table.len() as u32).to_be_bytes());
+        offset += table.len().next_multiple_of(4);
+    }
+    for (_, table) in tables {
+        font.extend(table);
+        font.resize(font.len().next_multiple_of(4), 0);
+    }
+    Some(font)
+}
+
+struct TrueTypeFont<'a> {
+    face: Face<'a>,
+    // Glyphs drawn so far, with the character each one shows.
+    glyphs: BTreeMap<u16, char>,
+}
+
+impl TrueTypeFont<'_> {
+    // Glyph widths in thousandths of the font size.
+    fn width(&self, glyph: u16) -> f32 {
+        let advance = self.face.glyph_hor_advance(GlyphId(glyph)).unwrap_or(0);
+        f32::from(advance) * 1000.0 / f32::from(self.face.units_per_em())
+    }
+
+    fn scale(&self, value: i16) -> i64 {
+        i64::from(value) * 1000 / i64::from(self.face.units_per_em())
+    }
+
+    fn to_unicode(&self) -> Stream {
+        let mut cmap = String::from(
+            "/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n\
+             /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+             /CMapName /Adobe-Identity-UCS def\n/CMapType 2 def\n\
+             1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n",
+        );
+        let glyphs: Vec<_> = self.glyphs.iter().collect();
+        // At most 100 mappings per block.
+        for block in glyphs.chunks(100) {
+            cmap.push_str(&format!("{} beginbfchar\n", block.len()));
+            for (glyph, c) in block {
+                let unicode: String = c
+                    .encode_utf16(&mut [0; 2])
+                    .iter()
+                    .map(|unit| format!("{:04X}", unit))
+                    .collect();
+                cmap.push_str(&format!("<{:04X}> <{}>\n", glyph, unicode));
+            }
+            cmap.push_str("endbfchar\n");
+        }
+        cmap.push_str("endcmap\nCMapName currentdict /CMap defineresource pop\nend\nend\n");
+        Stream::new(Dictionary::new(), cmap.into_bytes())
+    }
+
+    // A `Type0` font with `Identity-H` encoding, whose codes are glyph ids.
+    fn into_dictionary(self, doc: &mut Document) -> Dictionary {
+        let name = self
+            .face
+            .names()
+            .into_iter()
+            .filter(|name| name.name_id == ttf_parser::name_id::POST_SCRIPT_NAME)
+            .find_map(|name| name.to_string())
+            .unwrap_or_else(|| "Embedded".to_string());
+        // Subsets are named with a tag of six capital letters.
+        let base_font = Object::Name(format!("PDFMRG+{}", name).into_bytes());
+
+        let glyphs: Vec<u16> = self.glyphs.keys().copied().collect();
+        let font_file = subset(&self.face, &glyphs).unwrap_or_default();
+        let mut font_file = Stream::new(
+            Dictionary::from_iter(vec![("Length1", Object::Integer(font_file.len() as i64))]),
+            font_file,
+        );
+        let _ = font_file.compress();
+        let font_file_id = doc.add_object(font_file);
+
+        let bbox = self.face.global_bounding_box();
+        let descriptor_id = doc.add_object(Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"FontDescriptor".to_vec())),
+            ("FontName", base_font.clone()),
+            ("Flags", Object::Integer(4)),
+            (
+                "FontBBox",
+                Object::Array(
+                    [bbox.x_min, bbox.y_min, bbox.x_max, bbox.y_max]
+                        .into_iter()
+                        .map(|value| Object::Integer(self.scale(value)))
+                        .collect(),
+                ),
+            ),
+            (
+                "ItalicAngle",
+                Object::Real(self.face.italic_angle().unwrap_or(0.0)),
+            ),
+            ("Ascent", Object::Integer(self.scale(self.face.ascender()))),
+            (
+                "Descent",
+                Object::Integer(self.scale(self.face.descender())),
+            ),
+            (
+                "CapHeight",
+                Object::Integer(
+                    self.scale(self.face.capital_height().unwrap_or(self.face.ascender())),
+                ),
+            ),
+            ("StemV", Object::Integer(80)),
+            ("FontFile2", Object::Reference(font_file_id)),
+        ]));
+
+        let widths = glyphs
+            .iter()
+            .flat_map(|&glyph| {
+                [
+                    Object::Integer(glyph.into()),
+                    Object::Array(vec![Object::Real(self.width(glyph))]),
+                ]
+            })
+            .collect();
+        let cid_font_id = doc.add_object(Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Font".to_vec())),
+            ("Subtype", Object::Name(b"CIDFontType2".to_vec())),
+            ("BaseFont", base_font.clone()),
+            (
+                "CIDSystemInfo",
+                Object::Dictionary(Dictionary::from_iter(vec![
+                    ("Registry", Object::string_literal("Adobe")),
+                    ("Ordering", Object::string_literal("Identity")),
+                    ("Supplement", Object::Integer(0)),
+                ])),
+            ),
+            ("FontDescriptor", Object::Reference(descriptor_id)),
+            ("CIDToGIDMap", Object::Name(b"Identity".to_vec())),
+            ("W", Object::Array(widths)),
+        ]));
+        let to_unicode_id = doc.add_object(self.to_unicode());
+        Dictionary::from_iter(vec![
+            ("Type", Object::Name(b"Font".to_vec())),
+            ("Subtype", Object::Name(b"Type0".to_vec())),
+            ("BaseFont", base_font),
+            ("Encoding", Object::Name(b"Identity-H".to_vec())),
+            ("DescendantFonts", Object::Array(vec![cid_font_id.into()])),
+            ("ToUnicode", Object::Reference(to_unicode_id)),
+        ])
+    }
+}
+
+pub struct TextFont<'a>(Option<TrueTypeFont<'a>>);
+
+impl<'a> TextFont<'a> {
+    // Helvetica when it can show all of `texts` and no font is given, the
+    // given TrueType font, checked with `check_font`, or DejaVu Sans
+    // otherwise.
+    pub fn new(font: Option<&'a [u8]>, texts: &[&str]) -> Self {
+        let is_latin1 = texts.iter().all(|text| text.chars().all(|c| c <= '\u{ff}'));
+        if font.is_none() && is_latin1 {
+            return TextFont(None);
+        }
+        let face = font
+            .and_then(|font| Face::parse(font, 0).ok())
+            .or_else(|| Face::parse(DEFAULT_FONT, 0).ok());
+        TextFont(face.map(|face| TrueTypeFont {
+            face,
+            glyphs: BTreeMap::new(),
+        }))
+    }
+
+    // `text` as a string to show with `Tj`.
+    pub fn encode(&mut self, text: &str) -> Vec<u8> {
+        let Some(font) = &mut self.0 else {
+            return literal_string(text);
+        };
+        let mut string = String::from("<");
+        for c in text.chars() {
+            let glyph = font.face.glyph_index(c).map_or(0, |glyph| glyph.0);
+            font.glyphs.entry(glyph).or_insert(c);
+            string.push_str(&format!("{:04X}", glyph));
+        }
+        string.push('>');
+        string.into_bytes()
+    }
+
+    // Adds the font, with the glyphs of the text encoded so far, to `doc` as
+    // the object `font_id`, which the text may already refer to.
+    pub fn add_to(self, doc: &mut Document, font_id: ObjectId) {
+        let font = match self.0 {
+            Some(font) => font.into_dictionary(doc),
+            None => Dictionary::from_iter(vec![
+                ("Type", Object::Name(b"Font".to_vec())),
+                ("Subtype", Object::Name(b"Type1".to_vec())),
+                ("BaseFont", Object::Name(b"Helvetica".to_vec())),
+                ("Encoding", Object::Name(b"WinAnsiEncoding".to_vec())),
+            ]),
+        };
+        doc.objects.insert(font_id, Object::Dictionary(font));
+    }
+}
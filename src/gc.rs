@@ -0,0 +1,51 @@
+// Removal of the objects which nothing refers to anymore: the outlines of the
+// inputs, which are rebuilt, the resources of removed pages and annotations,
+// or objects the generators of the inputs left behind.
+use std::collections::HashSet;
+
+use lopdf::{Document, Object, ObjectId};
+
+fn collect_references(object: &Object, references: &mut Vec<ObjectId>) {
+    match object {
+        Object::Reference(id) => references.push(*id),
+        Object::Array(array) => {
+            for item in array {
+                collect_references(item, references);
+            }
+        }
+        Object::Dictionary(dictionary) => {
+            for (_, value) in dictionary.iter() {
+                collect_references(value, references);
+            }
+        }
+        Object::Stream(stream) => {
+            for (_, value) in stream.dict.iter() {
+                collect_references(value, references);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Keeps the objects reachable from the trailer, and returns how many were
+// removed. Unlike `Document::prune_objects`, this stays linear in the number
+// of objects, which matters for merges of thousands of pages.
+pub fn remove_unreferenced_objects(doc: &mut Document) -> usize {
+    let mut reachable = HashSet::new();
+    let mut pending = Vec::new();
+    for (_, value) in doc.trailer.iter() {
+        collect_references(value, &mut pending);
+    }
+    while let Some(id) = pending.pop() {
+        if !reachable.insert(id) {
+            continue;
+        }
+        if let Some(object) = doc.objects.get(&id) {
+            collect_references(object, &mut pending);
+        }
+    }
+
+    let count = doc.objects.len();
+    doc.objects.retain(|id, _| reachable.contains(id));
+    count - doc.objects.len()
+}
@@ -0,0 +1,314 @@
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{ChildStdin, Command, Stdio},
+    sync::{
+        LazyLock, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+};
+
+use pm::merge::CancellationToken;
+use rfd::{FileDialog, MessageButtons, MessageDialog, MessageLevel};
+
+const DEFAULT_OUTPUT_FILE_NAME: &str = "merged.pdf";
+
+// File keeping the last input and output directories, one `key=path` per line.
+const LAST_DIRECTORIES_FILE_NAME: &str = "last-directories";
+
+// Platform configuration directory of the tool, e.g. `~/.config/pdf-merge`.
+fn config_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| Path::new(&home).join("Library/Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+    base.map(|dir| dir.join("pdf-merge"))
+}
+
+fn load_last_directory(key: &str) -> Option<PathBuf> {
+    let content = fs::read_to_string(config_dir()?.join(LAST_DIRECTORIES_FILE_NAME)).ok()?;
+    content
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(line_key, _)| *line_key == key)
+        .map(|(_, path)| PathBuf::from(path))
+        .filter(|path| path.is_dir())
+}
+
+// Failing to remember a directory is not worth bothering the user with, so
+// errors are ignored.
+fn save_last_directory(key: &str, dir: &Path) {
+    let Some(config_dir) = config_dir() else {
+        return;
+    };
+    let file = config_dir.join(LAST_DIRECTORIES_FILE_NAME);
+    let mut lines: Vec<String> = fs::read_to_string(&file)
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.starts_with(&format!("{}=", key)))
+        .map(String::from)
+        .collect();
+    lines.push(format!("{}={}", key, dir.display()));
+
+    let _ = fs::create_dir_all(&config_dir);
+    let _ = fs::write(file, lines.join("\n") + "\n");
+}
+
+// Set once a path has been picked in a dialog: the tool was started without a
+// command line (double-clicked, or given files dropped onto it), and reports
+// to the user in windows rather than on a console they may not see. A
+// cancelled dialog is not worth an error dialog on top.
+static DIALOG_SHOWN: AtomicBool = AtomicBool::new(false);
+
+pub fn is_active() -> bool {
+    DIALOG_SHOWN.load(Ordering::Relaxed)
+}
+
+pub fn pick_input_directory() -> Option<PathBuf> {
+    let mut dialog = FileDialog::new();
+    if let Some(dir) = load_last_directory("input") {
+        dialog = dialog.set_directory(dir);
+    }
+    let path = dialog.pick_folder()?;
+    DIALOG_SHOWN.store(true, Ordering::Relaxed);
+    save_last_directory("input", &path);
+    Some(path)
+}
+
+// Some viewers refuse to open a file without the `.pdf` extension, so it is
+// appended when the user typed a bare name.
+fn pick_output_file_with(dialog: FileDialog) -> Option<PathBuf> {
+    let mut path = dialog.add_filter("PDF document", &["pdf"]).save_file()?;
+    DIALOG_SHOWN.store(true, Ordering::Relaxed);
+
+    let has_pdf_extension = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pdf"));
+    if !has_pdf_extension {
+        let mut file_name = path.file_name()?.to_os_string();
+        file_name.push(".pdf");
+        path.set_file_name(file_name);
+    }
+
+    if let Some(dir) = path.parent() {
+        save_last_directory("output", dir);
+    }
+    Some(path)
+}
+
+pub fn pick_output_file() -> Option<PathBuf> {
+    let mut dialog = FileDialog::new().set_file_name(DEFAULT_OUTPUT_FILE_NAME);
+    if let Some(dir) = load_last_directory("output") {
+        dialog = dialog.set_directory(dir);
+    }
+    pick_output_file_with(dialog)
+}
+
+// Opens the save dialog next to the inputs selected in a file manager, with
+// the name of the folder for a single folder, e.g. `Scans.pdf` next to
+// `Scans`.
+pub fn pick_output_file_next_to(inputs: &[PathBuf]) -> Option<PathBuf> {
+    let first = fs::canonicalize(inputs.first()?).ok()?;
+    let dir = first.parent().unwrap_or(&first);
+    let file_name = match (inputs, first.file_name()) {
+        ([_], Some(name)) if first.is_dir() => format!("{}.pdf", name.to_string_lossy()),
+        _ => DEFAULT_OUTPUT_FILE_NAME.to_string(),
+    };
+    pick_output_file_with(
+        FileDialog::new()
+            .set_directory(dir)
+            .set_file_name(file_name),
+    )
+}
+
+// Opens the file in the default application of the platform, without waiting
+// for it to be closed.
+pub fn open_in_default_viewer(path: &Path) -> io::Result<()> {
+    let mut command = if cfg!(windows) {
+        // `start` takes its first quoted argument as the window title.
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else {
+        Command::new("xdg-open")
+    };
+    command
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok(())
+}
+
+// The progress window is a separate process fed through its stdin with the
+// protocol of `zenity --progress`: a percentage, or `# ` followed by the text
+// to show, per line. It closes at 100%, or when the pipe is closed as this
+// process exits. Running apart, it stays responsive while this process is
+// busy; its Cancel button makes it exit with a failure status.
+struct ProgressWindow {
+    input: ChildStdin,
+}
+
+static PROGRESS_WINDOW: Mutex<Option<ProgressWindow>> = Mutex::new(None);
+
+// Set when the progress window could not be opened, so that it is tried once.
+static PROGRESS_WINDOW_FAILED: AtomicBool = AtomicBool::new(false);
+
+// Cancelled when the user cancels the progress window.
+static CANCELLATION: LazyLock<CancellationToken> = LazyLock::new(CancellationToken::new);
+
+// A WinForms window with a label and a progress bar, driven by the same
+// protocol as zenity.
+#[cfg(windows)]
+const PROGRESS_SCRIPT: &str = r#"
+Add-Type -AssemblyName System.Windows.Forms
+$form = New-Object System.Windows.Forms.Form
+$form.Text = 'pdf-merge'
+$form.Width = 440
+$form.Height = 150
+$form.FormBorderStyle = 'FixedDialog'
+$form.ControlBox = $false
+$form.StartPosition = 'CenterScreen'
+$form.TopMost = $true
+$label = New-Object System.Windows.Forms.Label
+$label.SetBounds(12, 12, 400, 20)
+$label.AutoEllipsis = $true
+$bar = New-Object System.Windows.Forms.ProgressBar
+$bar.SetBounds(12, 40, 400, 24)
+$cancel = New-Object System.Windows.Forms.Button
+$cancel.Text = 'Cancel'
+$cancel.SetBounds(337, 74, 75, 24)
+$cancel.Add_Click({ [Environment]::Exit(1) })
+$form.Controls.AddRange(@($label, $bar, $cancel))
+$form.Show()
+# Lines are read in the background so that the window keeps handling its
+# events, the Cancel button among them, while no progress comes.
+$read = [Console]::In.ReadLineAsync()
+while ($true) {
+    [System.Windows.Forms.Application]::DoEvents()
+    if (-not $read.Wait(50)) { continue }
+    $line = $read.Result
+    if ($line -eq $null) { break }
+    if ($line.StartsWith('# ')) { $label.Text = $line.Substring(2) }
+    elseif ($line -match '^\d+$') { $bar.Value = [Math]::Min(100, [int]$line) }
+    if ($bar.Value -ge 100) { break }
+    $read = [Console]::In.ReadLineAsync()
+}
+$form.Close()
+"#;
+
+#[cfg(windows)]
+fn progress_window_command() -> Command {
+    use std::os::windows::process::CommandExt;
+
+    // Keeps PowerShell from opening a console window of its own.
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+    let mut command = Command::new("powershell");
+    command
+        .args(["-NoProfile", "-NonInteractive", "-Command", PROGRESS_SCRIPT])
+        .creation_flags(CREATE_NO_WINDOW);
+    command
+}
+
+#[cfg(not(windows))]
+fn progress_window_command() -> Command {
+    let mut command = Command::new("zenity");
+    command.args([
+        "--progress",
+        "--title=pdf-merge",
+        "--text=Starting",
+        "--width=440",
+        "--auto-close",
+    ]);
+    command
+}
+
+fn open_progress_window() -> Option<ProgressWindow> {
+    let mut process = progress_window_command()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let input = process.stdin.take()?;
+    // Closed at 100%, the window exits successfully, and with a failure
+    // when cancelled.
+    thread::spawn(move || {
+        if process.wait().is_ok_and(|status| !status.success()) {
+            CANCELLATION.cancel();
+        }
+    });
+    Some(ProgressWindow { input })
+}
+
+// The token cancelled from the progress window, to stop the merge with.
+pub fn cancellation() -> CancellationToken {
+    CANCELLATION.clone()
+}
+
+// Shows `fraction` (from 0 to 1) of the work done and what is being done in
+// the progress window, opened on the first call. Outside of the GUI mode, or
+// without a way to show the window, nothing is shown.
+pub fn show_progress(fraction: f64, text: &str) {
+    if !is_active() || PROGRESS_WINDOW_FAILED.load(Ordering::Relaxed) {
+        return;
+    }
+    let Ok(mut progress_window) = PROGRESS_WINDOW.lock() else {
+        return;
+    };
+    if progress_window.is_none() {
+        *progress_window = open_progress_window();
+        if progress_window.is_none() {
+            PROGRESS_WINDOW_FAILED.store(true, Ordering::Relaxed);
+            return;
+        }
+    }
+    // Below 100%, which closes the window.
+    let percent = (fraction.clamp(0.0, 1.0) * 99.0) as u32;
+    let text = text.replace(['\r', '\n'], " ");
+    let written = progress_window
+        .as_mut()
+        .is_some_and(|window| writeln!(window.input, "{}\n# {}", percent, text).is_ok());
+    if !written {
+        // The user cancelled or closed the window.
+        *progress_window = None;
+        PROGRESS_WINDOW_FAILED.store(true, Ordering::Relaxed);
+    }
+}
+
+pub fn close_progress_window() {
+    if let Ok(mut progress_window) = PROGRESS_WINDOW.lock()
+        && let Some(mut window) = progress_window.take()
+    {
+        let _ = writeln!(window.input, "100");
+    }
+}
+
+fn show_message(level: MessageLevel, description: &str) {
+    close_progress_window();
+    MessageDialog::new()
+        .set_level(level)
+        .set_title("pdf-merge")
+        .set_description(description)
+        .set_buttons(MessageButtons::Ok)
+        .show();
+}
+
+pub fn show_error(message: &str) {
+    show_message(MessageLevel::Error, message);
+}
+
+pub fn show_warning(message: &str) {
+    show_message(MessageLevel::Warning, message);
+}
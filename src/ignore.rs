@@ -0,0 +1,174 @@
+// `.pdfmergeignore` files: gitignore-style patterns excluding files from the
+// scan of the directory they are in and of its subdirectories, for templates,
+// drafts and other files a folder never wants merged.
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use glob::{MatchOptions, Pattern};
+
+const IGNORE_FILE_NAME: &str = ".pdfmergeignore";
+
+struct Rule {
+    pattern: Pattern,
+    // `!pattern`, including again what an earlier pattern excluded.
+    negated: bool,
+    // `pattern/`, matching directories only.
+    directory_only: bool,
+    // Patterns with a `/` match the path from the directory of the ignore
+    // file, the others the name of the file at any depth.
+    anchored: bool,
+}
+
+pub struct IgnoreFile {
+    dir: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl IgnoreFile {
+    // The ignore file of `dir`, if it has one. Invalid patterns are skipped.
+    pub fn read(dir: &Path) -> io::Result<Option<Self>> {
+        let content = match fs::read_to_string(dir.join(IGNORE_FILE_NAME)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(Some(IgnoreFile::parse(dir, &content)))
+    }
+
+    fn parse(dir: &Path, content: &str) -> Self {
+        let rules = content
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (negated, line) = match line.strip_prefix('!') {
+                    Some(line) => (true, line),
+                    None => (false, line),
+                };
+                let (directory_only, line) = match line.strip_suffix('/') {
+                    Some(line) => (true, line),
+                    None => (false, line),
+                };
+                let anchored = line.contains('/');
+                Some(Rule {
+                    pattern: Pattern::new(line.trim_start_matches('/')).ok()?,
+                    negated,
+                    directory_only,
+                    anchored,
+                })
+            })
+            .collect();
+        IgnoreFile {
+            dir: dir.to_path_buf(),
+            rules,
+        }
+    }
+
+    // Whether the last pattern matching `path` excludes it, `None` when no
+    // pattern matches.
+    fn decision(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.dir).ok()?;
+        let relative = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let name = path.file_name()?.to_string_lossy();
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        self.rules
+            .iter()
+            .rev()
+            .filter(|rule| is_dir || !rule.directory_only)
+            .find(|rule| {
+                let candidate = if rule.anchored { &relative } else { &*name };
+                rule.pattern.matches_with(candidate, options)
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+// Whether `path` is excluded by the ignore files of its directory and its
+// parents, given outermost first: the innermost file with a matching pattern
+// decides.
+pub fn is_ignored(ignore_files: &[IgnoreFile], path: &Path, is_dir: bool) -> bool {
+    ignore_files
+        .iter()
+        .rev()
+        .find_map(|ignore_file| ignore_file.decision(path, is_dir))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignored(ignore_files: &[IgnoreFile], path: &str) -> bool {
+        is_ignored(ignore_files, Path::new(path), path.ends_with('/'))
+    }
+
+    #[test]
+    fn matches_names_at_any_depth() {
+        let ignore_files = [IgnoreFile::parse(
+            Path::new("/in"),
+            "# templates\n\n*.draft.pdf\ncover.pdf\n",
+        )];
+        assert!(ignored(&ignore_files, "/in/a.draft.pdf"));
+        assert!(ignored(&ignore_files, "/in/sub/b.draft.pdf"));
+        assert!(ignored(&ignore_files, "/in/sub/cover.pdf"));
+        assert!(!ignored(&ignore_files, "/in/a.pdf"));
+        assert!(!ignored(&ignore_files, "/in/# templates"));
+    }
+
+    #[test]
+    fn matches_paths_with_a_slash_from_the_ignore_file() {
+        let ignore_files = [IgnoreFile::parse(
+            Path::new("/in"),
+            "drafts/*.pdf\n/cover.pdf\n",
+        )];
+        assert!(ignored(&ignore_files, "/in/drafts/a.pdf"));
+        assert!(!ignored(&ignore_files, "/in/sub/drafts/a.pdf"));
+        assert!(!ignored(&ignore_files, "/in/drafts/sub/a.pdf"));
+        assert!(ignored(&ignore_files, "/in/cover.pdf"));
+        assert!(!ignored(&ignore_files, "/in/sub/cover.pdf"));
+    }
+
+    #[test]
+    fn matches_directories_only_with_a_trailing_slash() {
+        let ignore_files = [IgnoreFile::parse(Path::new("/in"), "old/\n")];
+        assert!(ignored(&ignore_files, "/in/old/"));
+        assert!(ignored(&ignore_files, "/in/sub/old/"));
+        assert!(!ignored(&ignore_files, "/in/old"));
+    }
+
+    #[test]
+    fn includes_again_with_negated_patterns() {
+        let ignore_files = [IgnoreFile::parse(Path::new("/in"), "*.pdf\n!keep.pdf\n")];
+        assert!(ignored(&ignore_files, "/in/a.pdf"));
+        assert!(!ignored(&ignore_files, "/in/keep.pdf"));
+        assert!(!ignored(&ignore_files, "/in/a.png"));
+    }
+
+    #[test]
+    fn lets_the_innermost_ignore_file_decide() {
+        let ignore_files = [
+            IgnoreFile::parse(Path::new("/in"), "*.png\n"),
+            IgnoreFile::parse(Path::new("/in/scans"), "!*.png\nnotes.pdf\n"),
+        ];
+        assert!(ignored(&ignore_files, "/in/a.png"));
+        assert!(!ignored(&ignore_files, "/in/scans/a.png"));
+        assert!(ignored(&ignore_files, "/in/scans/notes.pdf"));
+        assert!(!ignored(&ignore_files, "/in/notes.pdf"));
+    }
+
+    #[test]
+    fn skips_invalid_patterns() {
+        let ignore_files = [IgnoreFile::parse(Path::new("/in"), "[\n*.tmp\n")];
+        assert_eq!(ignore_files[0].rules.len(), 1);
+        assert!(ignored(&ignore_files, "/in/a.tmp"));
+    }
+}
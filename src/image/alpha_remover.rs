@@ -1,41 +1,61 @@
+use crate::parallel::*;
 use printpdf::{ColorSpace, Image, image_crate::ColorType};
 
-trait Max {
-    fn max() -> Self;
-}
+// Pixels are processed in parallel by batches of at least this many, below
+// which splitting the work costs more than it saves.
+const MIN_PIXELS_PER_TASK: usize = 16 * 1024;
 
-impl Max for u8 {
-    fn max() -> u8 {
-        u8::MAX
-    }
+// Rounded integer form of `alpha * sample + (1 - alpha) * max`, with samples
+// and alpha from 0 to `max`.
+fn over_white(sample: u64, alpha: u64, max: u64) -> u64 {
+    (sample * alpha + max * (max - alpha) + max / 2) / max
 }
 
-impl Max for u16 {
-    fn max() -> u16 {
-        u16::MAX
-    }
+// Composites the pixels over a white background and drops their alpha
+// channel. Samples are `bytes_per_sample` bytes long, big endian for 16 bits
+// ones, and the alpha is the last of the `channels` of a pixel.
+fn remove_alpha_channel(image_data: &[u8], channels: usize, bytes_per_sample: usize) -> Vec<u8> {
+    let max = (1u64 << (8 * bytes_per_sample)) - 1;
+    let read_sample = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .fold(0u64, |sample, &byte| sample << 8 | u64::from(byte))
+    };
+
+    let pixel_len = channels * bytes_per_sample;
+    let color_len = pixel_len - bytes_per_sample;
+    let mut composited = vec![0; image_data.len() / pixel_len * color_len];
+    composited
+        .par_chunks_mut(color_len)
+        .zip(image_data.par_chunks_exact(pixel_len))
+        .with_min_len(MIN_PIXELS_PER_TASK)
+        .for_each(|(output, pixel)| {
+            let (color, alpha) = pixel.split_at(color_len);
+            let alpha = read_sample(alpha);
+            for (output, sample) in output
+                .chunks_exact_mut(bytes_per_sample)
+                .zip(color.chunks_exact(bytes_per_sample))
+            {
+                let value = over_white(read_sample(sample), alpha, max);
+                output.copy_from_slice(&value.to_be_bytes()[8 - bytes_per_sample..]);
+            }
+        });
+    composited
 }
 
-fn remove_alpha_from_4_channel<T>(image_data: &Vec<T>) -> Vec<T>
-where
-    T: Clone + num::ToPrimitive + num::FromPrimitive + Max,
-{
-    let max_value = T::max().to_f64().unwrap();
-    let new_image_data = image_data
-        .chunks(4)
-        .map(|rgba| {
-            let first = rgba[0].to_f64().unwrap();
-            let second = rgba[1].to_f64().unwrap();
-            let third = rgba[2].to_f64().unwrap();
-            let alpha = rgba[3].to_f64().unwrap() / max_value;
-            let new_first = T::from_f64((1.0 - alpha) * max_value + alpha * first).unwrap();
-            let new_second = T::from_f64((1.0 - alpha) * max_value + alpha * second).unwrap();
-            let new_third = T::from_f64((1.0 - alpha) * max_value + alpha * third).unwrap();
-            return [new_first, new_second, new_third];
-        })
-        .collect::<Vec<[T; 3]>>()
-        .concat();
-    return new_image_data;
+// Same as `remove_alpha_channel` for 8 bits RGB pixels whose alpha is apart,
+// in the soft mask `printpdf` splits it into.
+fn apply_soft_mask(rgb_data: &mut [u8], alpha: &[i64]) {
+    rgb_data
+        .par_chunks_exact_mut(3)
+        .zip(alpha.par_iter())
+        .with_min_len(MIN_PIXELS_PER_TASK)
+        .for_each(|(pixel, &alpha)| {
+            let alpha = alpha.clamp(0, 255) as u64;
+            for sample in pixel {
+                *sample = over_white(u64::from(*sample), alpha, 255) as u8;
+            }
+        });
 }
 
 pub trait RemoveAlpha {
@@ -45,35 +65,20 @@ pub trait RemoveAlpha {
 impl RemoveAlpha for Image {
     fn remove_alpha(&mut self, color_type: ColorType) {
         use ColorType::*;
-        match color_type {
-            Rgba8 => {
-                let new_image_data = remove_alpha_from_4_channel(&self.image.image_data);
-                self.image.image_data = new_image_data;
-                self.image.color_space = ColorSpace::Rgb;
-            }
-            Rgba16 => {
-                let u16_image_data: Vec<u16> = self
-                    .image
-                    .image_data
-                    .chunks(2)
-                    .map(|arr| {
-                        let x1 = arr[0];
-                        let x2 = arr[1];
-                        return (x1 as u16) * 256 + (x2 as u16);
-                    })
-                    .collect();
-                let new_u16_image_data = remove_alpha_from_4_channel(&u16_image_data);
-
-                let new_u8_image_data = new_u16_image_data
-                    .into_iter()
-                    .map(|num| [(num / 256) as u8, (num % 256) as u8])
-                    .collect::<Vec<[u8; 2]>>()
-                    .concat();
-
-                self.image.image_data = new_u8_image_data;
-                self.image.color_space = ColorSpace::Rgb;
-            }
-            _ => return,
+        // 8 bits RGBA images come with their alpha already split out.
+        if let Some(soft_mask) = self.image.smask.take() {
+            apply_soft_mask(&mut self.image.image_data, &soft_mask.matte);
+            return;
         }
+        let (channels, bytes_per_sample, color_space) = match color_type {
+            Rgba8 => (4, 1, ColorSpace::Rgb),
+            Rgba16 => (4, 2, ColorSpace::Rgb),
+            La8 => (2, 1, ColorSpace::Greyscale),
+            La16 => (2, 2, ColorSpace::Greyscale),
+            _ => return,
+        };
+        self.image.image_data =
+            remove_alpha_channel(&self.image.image_data, channels, bytes_per_sample);
+        self.image.color_space = color_space;
     }
 }
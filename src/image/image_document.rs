@@ -0,0 +1,141 @@
+use std::{cmp::max, path::Path};
+
+use anyhow::Result;
+use lopdf::Document;
+use printpdf::{
+    CustomPdfConformance, Image, ImageTransform, Mm, PdfConformance, PdfDocument,
+    PdfDocumentReference, image_crate::ColorType,
+};
+
+use crate::{
+    image::{
+        alpha_remover::RemoveAlpha,
+        image_reader::{ImageFile, read_image_from_bytes},
+        image_transform::get_image_transform_for_page_size,
+        image_x_object::get_image_dimension_in_mm,
+    },
+    pagesize::{A4, PageSizeInMm},
+};
+
+const MIN_WIDTH_IN_MM: f64 = 210.0;
+const MIN_HEIGHT_IN_MM: f64 = 297.0;
+
+// Density of the images which do not tell theirs.
+pub const DEFAULT_DPI: f64 = 300.0;
+
+// How images are turned into pages.
+#[derive(Debug, Clone)]
+pub struct ImageOptions {
+    // Expand animated GIF and PNG images to a page every `frame_step` frames,
+    // instead of a single page with their first frame.
+    pub frame_step: Option<usize>,
+    // Stretch images over the whole page instead of keeping their aspect
+    // ratio.
+    pub stretch: bool,
+    // Draw images at this fraction of their size instead of fitting them to
+    // the page.
+    pub scale: Option<f64>,
+    // Density of the images without density metadata, in dots per inch.
+    pub assume_dpi: f64,
+    // Size of the pages, `None` to size them to their image, scaled up to
+    // fill at least an A4 page.
+    pub page_size: Option<PageSizeInMm>,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions {
+            frame_step: None,
+            stretch: false,
+            scale: None,
+            assume_dpi: DEFAULT_DPI,
+            page_size: Some(A4),
+        }
+    }
+}
+
+fn decoded_images_to_doc(image_file: ImageFile, options: &ImageOptions) -> Result<Document> {
+    let _span = crate::trace_span!("convert_image", frames = image_file.images.len());
+    // Like text documents, images are written without the PDF/X ICC profile
+    // and XMP metadata, whose random instance ID would make every merge of
+    // the same inputs different.
+    let doc = PdfDocument::empty("Random Document Title")
+        .with_conformance(PdfConformance::Custom(CustomPdfConformance::default()));
+    let dpi = image_file.dpi.unwrap_or(options.assume_dpi);
+    for (color_type, img) in image_file.images {
+        add_image_page(&doc, color_type, img, dpi, options);
+    }
+
+    let bytes = doc.save_to_bytes()?;
+    Ok(Document::load_mem(bytes.as_slice())?)
+}
+
+fn add_image_page(
+    doc: &PdfDocumentReference,
+    color_type: ColorType,
+    mut img: Image,
+    dpi: f64,
+    options: &ImageOptions,
+) {
+    if let Some(page_size) = &options.page_size {
+        let image_transform =
+            get_image_transform_for_page_size(page_size, &img.image, dpi, options);
+        let PageSizeInMm(width, height) = page_size;
+        let (page, layer_index) = doc.add_page(
+            Mm(width.to_owned() as f32),
+            Mm(height.to_owned() as f32),
+            "Layer1",
+        );
+        let current_layer = doc.get_page(page).get_layer(layer_index);
+        img.remove_alpha(color_type);
+        img.add_to_layer(current_layer.clone(), image_transform);
+    } else {
+        let (original_image_width, original_image_height) =
+            get_image_dimension_in_mm(&img.image, dpi);
+
+        let image_scale = options.scale.unwrap_or_else(|| {
+            max(
+                1,
+                max(
+                    (MIN_WIDTH_IN_MM / original_image_width) as i32,
+                    (MIN_HEIGHT_IN_MM / original_image_height) as i32,
+                ),
+            ) as f64
+        });
+        let (page, layer_index) = doc.add_page(
+            Mm((original_image_width * image_scale) as f32),
+            Mm((original_image_height * image_scale) as f32),
+            "Layer1",
+        );
+        let current_layer = doc.get_page(page).get_layer(layer_index);
+        img.remove_alpha(color_type);
+        img.add_to_layer(
+            current_layer.clone(),
+            ImageTransform {
+                scale_x: Some(image_scale as f32),
+                scale_y: Some(image_scale as f32),
+                dpi: Some(dpi as f32),
+                ..Default::default()
+            },
+        );
+    };
+}
+
+#[cfg(feature = "native")]
+pub fn image_to_doc(path: &Path, options: &ImageOptions) -> Result<Document> {
+    use crate::image::image_reader::read_image_from_file;
+
+    decoded_images_to_doc(read_image_from_file(path, options.frame_step)?, options)
+}
+
+// `file_name` is only used to find out the image format from its extension.
+pub fn image_bytes_to_doc(
+    bytes: &[u8],
+    file_name: &Path,
+    options: &ImageOptions,
+) -> Result<Document> {
+    decoded_images_to_doc(
+        read_image_from_bytes(bytes, file_name, options.frame_step)?,
+        options,
+    )
+}
@@ -1,60 +1,217 @@
-use anyhow::{anyhow, Result};
+use anyhow::{Result, anyhow};
 use colored::Colorize;
 
-use std::fs::File;
+use std::{
+    io::{Cursor, Read, Seek, SeekFrom},
+    path::Path,
+};
 
-use image_crate::codecs::{bmp::BmpDecoder, jpeg::JpegDecoder, png::PngDecoder};
+#[cfg(feature = "raw")]
+use crate::image::raw_preview::{RAW_EXTENSIONS, largest_preview};
+use image_crate::codecs::{bmp::BmpDecoder, gif::GifDecoder, jpeg::JpegDecoder, png::PngDecoder};
 use printpdf::{
-    image_crate::{self, ColorType, ImageDecoder},
     Image,
+    image_crate::{self, AnimationDecoder, ColorType, DynamicImage, ImageDecoder},
 };
 
 enum ImageType {
-    BMP,
-    JPEG,
-    PNG,
-    UNSUPPORTED,
+    Bmp,
+    Gif,
+    Jpeg,
+    Png,
+    #[cfg(feature = "raw")]
+    Raw,
+    Unsupported,
 }
 
-fn get_image_type(img_file_name: &str) -> ImageType {
-    let extension_option = img_file_name.split('.').last();
+fn get_image_type(img_file: &Path) -> ImageType {
+    let extension_option = img_file.extension();
     if let Some(extension) = extension_option {
-        return match extension.to_lowercase().as_str() {
-            "bmp" => ImageType::BMP,
-            "png" => ImageType::PNG,
-            "jpg" => ImageType::JPEG,
-            "jpeg" => ImageType::JPEG,
-            _ => ImageType::UNSUPPORTED,
+        return match extension.to_ascii_lowercase().to_str() {
+            Some("bmp") => ImageType::Bmp,
+            Some("gif") => ImageType::Gif,
+            Some("png") => ImageType::Png,
+            Some("jpg") => ImageType::Jpeg,
+            Some("jpeg") => ImageType::Jpeg,
+            Some("jfif") => ImageType::Jpeg,
+            #[cfg(feature = "raw")]
+            Some(extension) if RAW_EXTENSIONS.contains(&extension) => ImageType::Raw,
+            _ => ImageType::Unsupported,
         };
     }
-    ImageType::UNSUPPORTED
+    ImageType::Unsupported
+}
+
+// Recognizes the format from the first bytes of the file, so that misnamed
+// files (a JPEG saved as `.png`) are decoded with the right decoder. The
+// extension is only used for unknown signatures.
+fn detect_image_type<R: Read + Seek>(img_file: &mut R, img_file_name: &Path) -> Result<ImageType> {
+    let mut signature = [0u8; 8];
+    let read = img_file.read(&mut signature)?;
+    img_file.seek(SeekFrom::Start(0))?;
+    let signature = &signature[..read];
+    Ok(if signature.starts_with(b"\x89PNG\r\n\x1a\n") {
+        ImageType::Png
+    } else if signature.starts_with(&[0xff, 0xd8, 0xff]) {
+        ImageType::Jpeg
+    } else if signature.starts_with(b"BM") {
+        ImageType::Bmp
+    } else if signature.starts_with(b"GIF8") {
+        ImageType::Gif
+    } else {
+        get_image_type(img_file_name)
+    })
+}
+
+// The decoded content of an image file.
+pub struct ImageFile {
+    // The image, or with a frame step the frames of an animated GIF or PNG.
+    pub images: Vec<(ColorType, Image)>,
+    // Density from the metadata of the file, in dots per inch.
+    pub dpi: Option<f64>,
+}
+
+const MM_PER_INCH: f64 = 25.4;
+
+fn jpeg_density<R: Read>(img_file: &mut R) -> Option<f64> {
+    // SOI marker, then the APP0 segment of the JFIF header.
+    let mut header = [0u8; 18];
+    img_file.read_exact(&mut header).ok()?;
+    if header[2..4] != [0xff, 0xe0] || &header[6..11] != b"JFIF\0" {
+        return None;
+    }
+    let density = f64::from(u16::from_be_bytes([header[14], header[15]]));
+    match header[13] {
+        1 => Some(density),
+        2 => Some(density * MM_PER_INCH / 10.0),
+        // Only the aspect ratio of the pixels is known.
+        _ => None,
+    }
+}
+
+fn png_density<R: Read + Seek>(img_file: &mut R) -> Option<f64> {
+    img_file.seek(SeekFrom::Start(8)).ok()?;
+    loop {
+        let mut chunk_header = [0u8; 8];
+        img_file.read_exact(&mut chunk_header).ok()?;
+        let length = u32::from_be_bytes(chunk_header[..4].try_into().ok()?);
+        match &chunk_header[4..] {
+            b"pHYs" => {
+                let mut physical_dimensions = [0u8; 9];
+                img_file.read_exact(&mut physical_dimensions).ok()?;
+                let pixels_per_meter = f64::from(u32::from_be_bytes(
+                    physical_dimensions[..4].try_into().ok()?,
+                ));
+                // The unit is either the meter or unknown.
+                return (physical_dimensions[8] == 1)
+                    .then_some(pixels_per_meter * MM_PER_INCH / 1000.0);
+            }
+            // The pHYs chunk comes before the image data.
+            b"IDAT" => return None,
+            _ => {
+                img_file
+                    .seek(SeekFrom::Current(i64::from(length) + 4))
+                    .ok()?;
+            }
+        }
+    }
+}
+
+fn bmp_density<R: Read>(img_file: &mut R) -> Option<f64> {
+    // The horizontal resolution comes 24 bytes into the BITMAPINFOHEADER,
+    // after the 14 bytes of the file header.
+    let mut header = [0u8; 42];
+    img_file.read_exact(&mut header).ok()?;
+    let pixels_per_meter = i32::from_le_bytes(header[38..42].try_into().ok()?);
+    Some(f64::from(pixels_per_meter) * MM_PER_INCH / 1000.0)
 }
 
-pub fn read_image_from_file(img_file_name: &str) -> Result<(ColorType, Image)> {
-    let mut img_file = File::open(&img_file_name)?;
+// Files without density, or with a nonsensical one, are left to the caller.
+fn read_density<R: Read + Seek>(img_file: &mut R, image_type: &ImageType) -> Result<Option<f64>> {
+    let density = match image_type {
+        ImageType::Bmp => bmp_density(img_file),
+        ImageType::Jpeg => jpeg_density(img_file),
+        ImageType::Png => png_density(img_file),
+        _ => None,
+    };
+    img_file.seek(SeekFrom::Start(0))?;
+    Ok(density.filter(|dpi| (1.0..=100_000.0).contains(dpi)))
+}
 
-    match get_image_type(img_file_name) {
-        ImageType::BMP => {
-            let bmp_decoder = BmpDecoder::new(&mut img_file)?;
-            let color_type = bmp_decoder.color_type();
-            let image = Image::try_from(bmp_decoder)?;
-            return Ok((color_type, image));
+#[cfg(feature = "native")]
+// The image, or with `frame_step` the frames of an animated GIF or PNG, one
+// every `frame_step` frames.
+pub fn read_image_from_file(img_file_name: &Path, frame_step: Option<usize>) -> Result<ImageFile> {
+    let img_file = std::fs::File::open(img_file_name)?;
+    read_image(img_file, img_file_name, frame_step)
+}
+
+pub fn read_image_from_bytes(
+    bytes: &[u8],
+    img_file_name: &Path,
+    frame_step: Option<usize>,
+) -> Result<ImageFile> {
+    read_image(Cursor::new(bytes), img_file_name, frame_step)
+}
+
+fn decode<'a>(decoder: impl ImageDecoder<'a>) -> Result<Vec<(ColorType, Image)>> {
+    let color_type = decoder.color_type();
+    let image = Image::try_from(decoder)?;
+    Ok(vec![(color_type, image)])
+}
+
+// Frames come out of the decoder composited over the previous ones, as they
+// are displayed.
+fn decode_frames<'a>(
+    decoder: impl AnimationDecoder<'a>,
+    frame_step: usize,
+) -> Result<Vec<(ColorType, Image)>> {
+    decoder
+        .into_frames()
+        .step_by(frame_step)
+        .map(|frame| {
+            let frame = DynamicImage::ImageRgba8(frame?.into_buffer());
+            Ok((ColorType::Rgba8, Image::from_dynamic_image(&frame)))
+        })
+        .collect()
+}
+
+fn read_image<R: Read + Seek>(
+    mut img_file: R,
+    img_file_name: &Path,
+    frame_step: Option<usize>,
+) -> Result<ImageFile> {
+    let image_type = detect_image_type(&mut img_file, img_file_name)?;
+    let dpi = read_density(&mut img_file, &image_type)?;
+    let images = match image_type {
+        ImageType::Bmp => decode(BmpDecoder::new(&mut img_file)?),
+        ImageType::Gif => {
+            let gif_decoder = GifDecoder::new(&mut img_file)?;
+            match frame_step {
+                Some(frame_step) => decode_frames(gif_decoder, frame_step),
+                None => decode(gif_decoder),
+            }
         }
-        ImageType::PNG => {
+        ImageType::Png => {
             let png_decoder = PngDecoder::new(&mut img_file)?;
-            let color_type = png_decoder.color_type();
-            let image = Image::try_from(png_decoder)?;
-            return Ok((color_type, image));
+            match frame_step {
+                Some(frame_step) if png_decoder.is_apng() => {
+                    decode_frames(png_decoder.apng(), frame_step)
+                }
+                _ => decode(png_decoder),
+            }
         }
-        ImageType::JPEG => {
-            let jpeg_decoder = JpegDecoder::new(&mut img_file)?;
-            let color_type = jpeg_decoder.color_type();
-            let image = Image::try_from(jpeg_decoder)?;
-            return Ok((color_type, image));
+        ImageType::Jpeg => decode(JpegDecoder::new(&mut img_file)?),
+        #[cfg(feature = "raw")]
+        ImageType::Raw => {
+            let mut raw_data = Vec::new();
+            img_file.read_to_end(&mut raw_data)?;
+            decode(JpegDecoder::new(Cursor::new(largest_preview(&raw_data)?))?)
         }
-        ImageType::UNSUPPORTED => Err(anyhow!(
-            "Format of image file {} is not supported. We only support BMP, PNG, JPEG and SVG",
-            img_file_name.blue().underline()
+        ImageType::Unsupported => Err(anyhow!(
+            "Format of image file {} is not supported. We only support BMP, GIF, PNG, JPEG and SVG",
+            img_file_name.display().to_string().blue().underline()
         )),
-    }
+    }?;
+    Ok(ImageFile { images, dpi })
 }
@@ -1,4 +1,7 @@
+pub mod alpha_remover;
+pub mod image_document;
+pub mod image_reader;
 pub mod image_transform;
 pub mod image_x_object;
-pub mod image_reader;
-pub mod alpha_remover;
+#[cfg(feature = "raw")]
+pub mod raw_preview;
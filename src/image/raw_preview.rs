@@ -0,0 +1,147 @@
+// Camera RAW files (CR2, NEF, ARW, DNG, ...) are TIFF files which, besides
+// the sensor data, embed JPEG previews developed by the camera. The largest
+// of them is as good as a proof print needs, and is taken instead of
+// developing the sensor data.
+use anyhow::{Result, bail};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+pub const RAW_EXTENSIONS: [&str; 6] = ["cr2", "nef", "arw", "dng", "orf", "pef"];
+
+const COMPRESSION: u16 = 0x103;
+const STRIP_OFFSETS: u16 = 0x111;
+const STRIP_BYTE_COUNTS: u16 = 0x117;
+const SUB_IFDS: u16 = 0x14a;
+const JPEG_OFFSET: u16 = 0x201;
+const JPEG_LENGTH: u16 = 0x202;
+const EXIF_IFD: u16 = 0x8769;
+
+// Old-style and new-style JPEG compression.
+const JPEG_COMPRESSIONS: [u32; 2] = [6, 7];
+
+// Guards against IFD loops in corrupted files.
+const MAX_IFDS: usize = 64;
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.data.get(offset..offset + 2)?;
+        Some(if self.big_endian {
+            BigEndian::read_u16(bytes)
+        } else {
+            LittleEndian::read_u16(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..offset + 4)?;
+        Some(if self.big_endian {
+            BigEndian::read_u32(bytes)
+        } else {
+            LittleEndian::read_u32(bytes)
+        })
+    }
+
+    // Values of an entry, for the SHORT and LONG types.
+    fn values(&self, entry: usize) -> Vec<u32> {
+        let (Some(field_type), Some(count)) = (self.u16(entry + 2), self.u32(entry + 4)) else {
+            return Vec::new();
+        };
+        let size = match field_type {
+            3 => 2,
+            4 | 13 => 4,
+            _ => return Vec::new(),
+        };
+        let count = count as usize;
+        let offset = if size * count <= 4 {
+            entry + 8
+        } else {
+            match self.u32(entry + 8) {
+                Some(offset) => offset as usize,
+                None => return Vec::new(),
+            }
+        };
+        (0..count.min(1024))
+            .filter_map(|i| match size {
+                2 => self.u16(offset + 2 * i).map(u32::from),
+                _ => self.u32(offset + 4 * i),
+            })
+            .collect()
+    }
+
+    // Collects the JPEG streams referenced by the IFD at `offset`, its
+    // sub-IFDs and the IFDs chained after it.
+    fn collect_jpegs(&self, offset: usize, visited: &mut Vec<usize>, jpegs: &mut Vec<&'a [u8]>) {
+        let mut next = offset;
+        while next != 0 && visited.len() < MAX_IFDS && !visited.contains(&next) {
+            visited.push(next);
+            let Some(count) = self.u16(next) else {
+                return;
+            };
+            let entries = (0..count as usize).map(|i| next + 2 + 12 * i);
+
+            let mut compression = None;
+            let (mut jpeg_offset, mut jpeg_length) = (None, None);
+            let (mut strip_offsets, mut strip_byte_counts) = (Vec::new(), Vec::new());
+            let mut children = Vec::new();
+            for entry in entries {
+                let first = || self.values(entry).first().copied();
+                match self.u16(entry) {
+                    Some(COMPRESSION) => compression = first(),
+                    Some(JPEG_OFFSET) => jpeg_offset = first(),
+                    Some(JPEG_LENGTH) => jpeg_length = first(),
+                    Some(STRIP_OFFSETS) => strip_offsets = self.values(entry),
+                    Some(STRIP_BYTE_COUNTS) => strip_byte_counts = self.values(entry),
+                    Some(SUB_IFDS | EXIF_IFD) => children.extend(self.values(entry)),
+                    _ => {}
+                }
+            }
+
+            let mut candidates = Vec::new();
+            if let (Some(offset), Some(length)) = (jpeg_offset, jpeg_length) {
+                candidates.push((offset, length));
+            }
+            // A single strip of JPEG data is a preview as well.
+            if compression.is_some_and(|compression| JPEG_COMPRESSIONS.contains(&compression))
+                && let ([offset], [length]) =
+                    (strip_offsets.as_slice(), strip_byte_counts.as_slice())
+            {
+                candidates.push((*offset, *length));
+            }
+            jpegs.extend(candidates.into_iter().filter_map(|(offset, length)| {
+                let jpeg = self
+                    .data
+                    .get(offset as usize..offset as usize + length as usize)?;
+                jpeg.starts_with(&[0xff, 0xd8]).then_some(jpeg)
+            }));
+
+            for child in children {
+                self.collect_jpegs(child as usize, visited, jpegs);
+            }
+            next = self
+                .u32(next + 2 + 12 * count as usize)
+                .map_or(0, |next| next as usize);
+        }
+    }
+}
+
+// The largest JPEG preview embedded in a RAW file.
+pub fn largest_preview(data: &[u8]) -> Result<&[u8]> {
+    let big_endian = match data.get(..2) {
+        Some(b"II") => false,
+        Some(b"MM") => true,
+        _ => bail!("not a TIFF-based RAW file"),
+    };
+    let tiff = Tiff { data, big_endian };
+    let first_ifd = tiff.u32(4).unwrap_or(0) as usize;
+
+    let mut jpegs = Vec::new();
+    tiff.collect_jpegs(first_ifd, &mut Vec::new(), &mut jpegs);
+    match jpegs.into_iter().max_by_key(|jpeg| jpeg.len()) {
+        Some(jpeg) => Ok(jpeg),
+        None => bail!("the RAW file has no embedded JPEG preview"),
+    }
+}
@@ -0,0 +1,213 @@
+// Index pages at the front of a merged document, showing a miniature of the
+// first page of every section, linked to it. Miniatures are the pages
+// themselves drawn as form XObjects, scaled down: no rendering is needed and
+// they stay sharp when zoomed in.
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+
+use crate::{font::TextFont, page::get_inherited, stamp::page_box};
+
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 36.0;
+const HEADER_HEIGHT: f32 = 36.0;
+const COLUMNS: usize = 3;
+const ROWS: usize = 4;
+const CELL_PADDING: f32 = 8.0;
+const CAPTION_HEIGHT: f32 = 14.0;
+const CAPTION_FONT_SIZE: f32 = 8.0;
+const CAPTION_MAX_CHARS: usize = 40;
+const INDEX_TITLE: &str = "Index";
+
+// A section of the index: its caption and the page it starts at.
+pub struct IndexEntry {
+    pub title: String,
+    pub page_id: ObjectId,
+}
+
+// Turns a page into a form XObject drawing the same content.
+pub(crate) fn page_to_form(doc: &mut Document, page_id: ObjectId) -> ObjectId {
+    let mut content = Vec::new();
+    for content_id in doc.get_page_contents(page_id) {
+        if let Ok(stream) = doc.get_object(content_id).and_then(Object::as_stream) {
+            content.extend(
+                stream
+                    .decompressed_content()
+                    .unwrap_or_else(|_| stream.content.clone()),
+            );
+            content.push(b'\n');
+        }
+    }
+
+    let resources = get_inherited(doc, page_id, b"Resources")
+        .cloned()
+        .unwrap_or_else(|| Object::Dictionary(Dictionary::new()));
+    let bbox: Vec<Object> = page_box(doc, page_id)
+        .into_iter()
+        .map(Object::Real)
+        .collect();
+    let dictionary = Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"XObject".to_vec())),
+        ("Subtype", Object::Name(b"Form".to_vec())),
+        ("BBox", Object::Array(bbox)),
+        ("Resources", resources),
+    ]);
+    doc.add_object(Stream::new(dictionary, content))
+}
+
+fn caption(title: &str) -> String {
+    if title.chars().count() <= CAPTION_MAX_CHARS {
+        return title.to_string();
+    }
+    let mut caption: String = title.chars().take(CAPTION_MAX_CHARS - 3).collect();
+    caption.push_str("...");
+    caption
+}
+
+fn link(rect: [f32; 4], page_id: ObjectId) -> Dictionary {
+    Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Annot".to_vec())),
+        ("Subtype", Object::Name(b"Link".to_vec())),
+        (
+            "Rect",
+            Object::Array(rect.into_iter().map(Object::Real).collect()),
+        ),
+        ("Border", Object::Array(vec![0.into(), 0.into(), 0.into()])),
+        (
+            "Dest",
+            Object::Array(vec![
+                Object::Reference(page_id),
+                Object::Name(b"Fit".to_vec()),
+            ]),
+        ),
+    ])
+}
+
+fn index_page(
+    doc: &mut Document,
+    entries: &[IndexEntry],
+    pages_id: ObjectId,
+    font_id: ObjectId,
+    font: &mut TextFont,
+) -> ObjectId {
+    let cell_width = (PAGE_WIDTH - 2.0 * MARGIN) / COLUMNS as f32;
+    let cell_height = (PAGE_HEIGHT - 2.0 * MARGIN - HEADER_HEIGHT) / ROWS as f32;
+
+    let mut content = format!(
+        "BT /F1 16 Tf {} {} Td ",
+        MARGIN,
+        PAGE_HEIGHT - MARGIN - 16.0
+    )
+    .into_bytes();
+    content.extend(font.encode(INDEX_TITLE));
+    content.extend(b" Tj ET\n");
+    let mut xobjects = Dictionary::new();
+    let mut annotations = Vec::new();
+
+    for (i, entry) in entries.iter().enumerate() {
+        let column = (i % COLUMNS) as f32;
+        let row = (i / COLUMNS) as f32;
+        let cell_left = MARGIN + column * cell_width;
+        let cell_top = PAGE_HEIGHT - MARGIN - HEADER_HEIGHT - row * cell_height;
+
+        let [x0, y0, x1, y1] = page_box(doc, entry.page_id);
+        let (page_width, page_height) = ((x1 - x0).abs(), (y1 - y0).abs());
+        let available_width = cell_width - 2.0 * CELL_PADDING;
+        let available_height = cell_height - 2.0 * CELL_PADDING - CAPTION_HEIGHT;
+        let scale = (available_width / page_width).min(available_height / page_height);
+        let (width, height) = (page_width * scale, page_height * scale);
+        let left = cell_left + (cell_width - width) / 2.0;
+        let bottom = cell_top - CELL_PADDING - height;
+
+        let name = format!("Thumb{}", i);
+        xobjects.set(name.as_bytes(), page_to_form(doc, entry.page_id));
+        content.extend(
+            format!(
+                "q {s} 0 0 {s} {} {} cm /{} Do Q\nq 0.6 G 0.5 w {} {} {} {} re S Q\n",
+                left - x0.min(x1) * scale,
+                bottom - y0.min(y1) * scale,
+                name,
+                left,
+                bottom,
+                width,
+                height,
+                s = scale,
+            )
+            .into_bytes(),
+        );
+        content.extend(
+            format!(
+                "BT /F1 {} Tf {} {} Td ",
+                CAPTION_FONT_SIZE,
+                cell_left + CELL_PADDING,
+                bottom - CAPTION_HEIGHT + 2.0
+            )
+            .into_bytes(),
+        );
+        content.extend(font.encode(&caption(&entry.title)));
+        content.extend(b" Tj ET\n");
+
+        let rect = [left, bottom - CAPTION_HEIGHT, left + width, bottom + height];
+        annotations.push(Object::Reference(doc.add_object(link(rect, entry.page_id))));
+    }
+
+    let resources = Dictionary::from_iter(vec![
+        (
+            "Font",
+            Object::Dictionary(Dictionary::from_iter(vec![(
+                "F1",
+                Object::Reference(font_id),
+            )])),
+        ),
+        ("XObject", Object::Dictionary(xobjects)),
+    ]);
+    let content_id = doc.add_object(Stream::new(Dictionary::new(), content));
+    doc.add_object(Dictionary::from_iter(vec![
+        ("Type", Object::Name(b"Page".to_vec())),
+        ("Parent", Object::Reference(pages_id)),
+        (
+            "MediaBox",
+            Object::Array(vec![
+                0.into(),
+                0.into(),
+                Object::Real(PAGE_WIDTH),
+                Object::Real(PAGE_HEIGHT),
+            ]),
+        ),
+        ("Resources", Object::Dictionary(resources)),
+        ("Contents", Object::Reference(content_id)),
+        ("Annots", Object::Array(annotations)),
+    ]))
+}
+
+// Inserts the index pages before the first page, as many as needed for all
+// the entries, with the TrueType `font` if given (see `TextFont`).
+pub fn add_index_pages(doc: &mut Document, entries: &[IndexEntry], font: Option<&[u8]>) {
+    let Ok(pages_id) = doc
+        .catalog()
+        .and_then(|catalog| catalog.get(b"Pages"))
+        .and_then(Object::as_reference)
+    else {
+        return;
+    };
+    let font_id = doc.new_object_id();
+    let captions: Vec<String> = entries.iter().map(|entry| caption(&entry.title)).collect();
+    let mut texts: Vec<&str> = captions.iter().map(String::as_str).collect();
+    texts.push(INDEX_TITLE);
+    let mut text_font = TextFont::new(font, &texts);
+
+    let index_pages: Vec<Object> = entries
+        .chunks(COLUMNS * ROWS)
+        .map(|chunk| Object::Reference(index_page(doc, chunk, pages_id, font_id, &mut text_font)))
+        .collect();
+    text_font.add_to(doc, font_id);
+
+    if let Ok(pages) = doc.get_dictionary_mut(pages_id) {
+        let count = pages.get(b"Count").and_then(Object::as_i64).unwrap_or(0);
+        pages.set("Count", count + index_pages.len() as i64);
+        let mut kids = index_pages;
+        if let Ok(Object::Array(existing)) = pages.get(b"Kids") {
+            kids.extend(existing.iter().cloned());
+        }
+        pages.set("Kids", kids);
+    }
+}
@@ -0,0 +1,612 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
+    ffi::OsStr,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local, Utc};
+use lopdf::Document;
+use pm::{
+    merge::{BookmarkMode, BookmarkStyle, InputDocument, MergeInput},
+    source::{
+        RAW_EXTENSIONS, SNIFF_LENGTH, is_image_file_name, is_text_file_name, sniff_extension,
+    },
+    spill::SpilledStreams,
+};
+use regex::Regex;
+
+use crate::{
+    ignore::{IgnoreFile, is_ignored},
+    log,
+    remote::{is_url, url_file_name},
+    s3::{self, is_s3_prefix, is_s3_url},
+};
+
+// Extensions picked up from the input directory, in merge order.
+const INPUT_EXTENSIONS: [&str; 10] = [
+    "pdf", "png", "jpg", "jpeg", "jfif", "bmp", "gif", "txt", "md", "zip",
+];
+
+// Time waited between two size checks to detect files still being written.
+const SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+// Files modified longer ago than this are taken as settled without waiting.
+const RECENT_WRITE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+pub struct ScanOptions {
+    // Include symlinked files and walk into symlinked directories.
+    pub follow_symlinks: bool,
+    // Walk into the subdirectories.
+    pub recursive: bool,
+    // Keep dotfiles, office lock files and files still being written.
+    pub include_hidden: bool,
+    // Skip the files larger than this, in bytes.
+    pub max_file_size: Option<u64>,
+    // Fail when more input files than this are found.
+    pub max_files: Option<usize>,
+    // Leave out the files last modified before or after these.
+    pub modified_since: Option<DateTime<Utc>>,
+    pub modified_until: Option<DateTime<Utc>>,
+    // Keep only the files of the scanned directories whose name without
+    // extension matches.
+    pub stem_pattern: Option<Regex>,
+    // Also pick up the files without a supported extension whose content is a
+    // PDF or an image.
+    pub sniff: bool,
+}
+
+// An input file once it has been loaded (or converted, for images) as a PDF.
+#[derive(Clone)]
+pub struct LoadedInput {
+    pub path: PathBuf,
+    pub document: Document,
+    // Title of the bookmark of the input, instead of the generated one.
+    pub bookmark_title: Option<String>,
+    pub bookmark_style: BookmarkStyle,
+    // Folders of the input under the scanned directory, with --recursive.
+    pub bookmark_folders: Vec<String>,
+    // Pages of the input given a bookmark, instead of --bookmarks.
+    pub bookmark_mode: Option<BookmarkMode>,
+    // Stream contents of the document, on disk until the merge with
+    // --max-memory. Shared by the copies of a repeated input.
+    pub spilled: Option<Arc<SpilledStreams>>,
+}
+
+impl LoadedInput {
+    pub fn new(path: PathBuf, document: Document) -> Self {
+        LoadedInput {
+            path,
+            document,
+            bookmark_title: None,
+            bookmark_style: BookmarkStyle::default(),
+            bookmark_folders: Vec::new(),
+            bookmark_mode: None,
+            spilled: None,
+        }
+    }
+
+    // The document with the contents of its streams, read back when they are
+    // spilled.
+    pub fn document_with_contents(&self) -> Result<Cow<'_, Document>> {
+        match &self.spilled {
+            Some(spilled) => {
+                let mut document = self.document.clone();
+                spilled.restore(&mut document)?;
+                Ok(Cow::Owned(document))
+            }
+            None => Ok(Cow::Borrowed(&self.document)),
+        }
+    }
+
+    // Path of the input, for URLs the last segment of their path.
+    fn local_name(&self) -> PathBuf {
+        if is_url(&self.path) || is_s3_url(&self.path) {
+            url_file_name(&self.path)
+        } else {
+            self.path.clone()
+        }
+    }
+
+    pub fn file_name(&self) -> String {
+        let path = self.local_name();
+        path.file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    // Whether the document was converted from an image or a text file.
+    pub fn is_converted(&self) -> bool {
+        let file_name = self.local_name();
+        is_image_file_name(&file_name) || is_text_file_name(&file_name)
+    }
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .map(OsStr::to_ascii_lowercase)
+        .is_some_and(|ext| ext == extension)
+}
+
+// `-` stands for a PDF document read from stdin.
+pub fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+pub fn is_supported(path: &Path) -> bool {
+    INPUT_EXTENSIONS
+        .iter()
+        .chain(&RAW_EXTENSIONS)
+        .any(|extension| has_extension(path, extension))
+}
+
+// Extension of the format of a file from its first bytes.
+pub fn sniff_file(path: &Path) -> Option<&'static str> {
+    let mut header = Vec::with_capacity(SNIFF_LENGTH);
+    File::open(path)
+        .ok()?
+        .take(SNIFF_LENGTH as u64)
+        .read_to_end(&mut header)
+        .ok()?;
+    sniff_extension(&header)
+}
+
+// Extension a file is merged as: its own when supported, otherwise, with
+// `sniff`, the one of its content.
+fn input_extension(path: &Path, sniff: bool) -> Option<String> {
+    if is_supported(path) {
+        return path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    }
+    sniff.then(|| sniff_file(path)).flatten().map(String::from)
+}
+
+#[cfg(windows)]
+fn has_hidden_attribute(metadata: &fs::Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0
+}
+
+#[cfg(not(windows))]
+fn has_hidden_attribute(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+// Dotfiles, and `~$` lock files left next to documents opened in an office suite.
+fn is_hidden_or_temporary(path: &Path, metadata: &fs::Metadata) -> bool {
+    let Some(file_name) = path.file_name() else {
+        return false;
+    };
+    let file_name = file_name.as_encoded_bytes();
+    file_name.starts_with(b".") || file_name.starts_with(b"~$") || has_hidden_attribute(metadata)
+}
+
+// Drops the files whose size changes over `SETTLE_DELAY`, which usually means
+// a scanner or a copy is still writing them. The wait is skipped when none of
+// the files was modified in the last `RECENT_WRITE`.
+fn remove_unsettled_files(files: Vec<PathBuf>) -> Vec<PathBuf> {
+    let file_size = |path: &Path| fs::metadata(path).map(|metadata| metadata.len()).ok();
+    let recently_modified = |path: &PathBuf| {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            // A modification time in the future, from a skewed clock, counts as recent.
+            .is_ok_and(|modified| modified.elapsed().map_or(true, |age| age < RECENT_WRITE))
+    };
+    if !files.iter().any(recently_modified) {
+        return files;
+    }
+
+    let sizes: Vec<Option<u64>> = files.iter().map(|path| file_size(path)).collect();
+    thread::sleep(SETTLE_DELAY);
+
+    files
+        .into_iter()
+        .zip(sizes)
+        .filter(|(path, size)| {
+            let settled = size.is_some() && *size == file_size(path);
+            if !settled {
+                log::file_skipped(path, "the file is still being written");
+            }
+            settled
+        })
+        .map(|(path, _)| path)
+        .collect()
+}
+
+// Counts the files found by a scan toward --max-files, so that the scan stops
+// as soon as there are too many, before the sniffing and the settle wait.
+// `found` starts with the inputs given before the scanned directory.
+struct FileLimit {
+    max: Option<usize>,
+    found: usize,
+}
+
+impl FileLimit {
+    fn add(&mut self, path: &Path, options: &ScanOptions) {
+        if self.max.is_some() && counts_toward_max_files(path, options) {
+            self.found += 1;
+        }
+    }
+
+    fn is_exceeded(&self) -> bool {
+        self.max.is_some_and(|max| self.found > max)
+    }
+}
+
+// Entries removed while their directory is scanned, e.g. the temporary files
+// of a scanner, are skipped instead of failing the whole scan.
+fn unless_removed<T>(path: &Path, result: io::Result<T>) -> io::Result<Option<T>> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            log::file_skipped(path, "it was removed during the scan");
+            Ok(None)
+        }
+        result => result.map(Some),
+    }
+}
+
+// `ignore_files` holds the ignore files of the parents of `dir`, outermost
+// first, to which the one of `dir` is added while it is scanned, and
+// `ancestors` the canonical paths of `dir` and its parents, to tell a symlink
+// to one of them from a second path to a directory walked elsewhere.
+fn scan_directory(
+    dir: &Path,
+    options: &ScanOptions,
+    visited_dirs: &mut HashSet<PathBuf>,
+    ancestors: &mut Vec<PathBuf>,
+    ignore_files: &mut Vec<IgnoreFile>,
+    files: &mut Vec<PathBuf>,
+    limit: &mut FileLimit,
+) -> io::Result<()> {
+    // Opened first, so that a directory removed since it was listed fails
+    // before its ignore file is added.
+    let entries = fs::read_dir(dir)?;
+    let has_ignore_file = match IgnoreFile::read(dir)? {
+        Some(ignore_file) => {
+            ignore_files.push(ignore_file);
+            true
+        }
+        None => false,
+    };
+    for entry in entries {
+        if limit.is_exceeded() {
+            break;
+        }
+        let entry = entry?;
+        let path = entry.path();
+        let Some(file_type) = unless_removed(&path, entry.file_type())? else {
+            continue;
+        };
+
+        if !options.include_hidden {
+            let Some(metadata) = unless_removed(&path, entry.metadata())? else {
+                continue;
+            };
+            if is_hidden_or_temporary(&path, &metadata) {
+                continue;
+            }
+        }
+
+        if file_type.is_file() {
+            if !is_ignored(ignore_files, &path, false) {
+                limit.add(&path, options);
+                files.push(path);
+            }
+            continue;
+        }
+        if file_type.is_dir() {
+            if !options.recursive || is_ignored(ignore_files, &path, true) {
+                continue;
+            }
+            // Recorded so that a symlink to it does not walk it again.
+            let Some(target) = unless_removed(&path, fs::canonicalize(&path))? else {
+                continue;
+            };
+            if visited_dirs.insert(target.clone()) {
+                ancestors.push(target);
+                let scan = scan_directory(
+                    &path,
+                    options,
+                    visited_dirs,
+                    ancestors,
+                    ignore_files,
+                    files,
+                    limit,
+                );
+                unless_removed(&path, scan)?;
+                ancestors.pop();
+            }
+            continue;
+        }
+        if !file_type.is_symlink() || !options.follow_symlinks {
+            continue;
+        }
+
+        // Dangling links are skipped silently, like any other unreadable entry.
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+        if is_ignored(ignore_files, &path, metadata.is_dir()) {
+            continue;
+        }
+        if metadata.is_file() {
+            limit.add(&path, options);
+            files.push(path);
+        } else if metadata.is_dir() && options.recursive {
+            // Symlinked directories are identified by their canonical path so
+            // that each directory is walked once.
+            let Some(target) = unless_removed(&path, fs::canonicalize(&path))? else {
+                continue;
+            };
+            if ancestors.contains(&target) {
+                log::file_skipped(&path, "it is a symlink loop");
+            } else if !visited_dirs.insert(target.clone()) {
+                log::file_skipped(
+                    &path,
+                    "its directory is already merged through another path",
+                );
+            } else {
+                ancestors.push(target);
+                let scan = scan_directory(
+                    &path,
+                    options,
+                    visited_dirs,
+                    ancestors,
+                    ignore_files,
+                    files,
+                    limit,
+                );
+                unless_removed(&path, scan)?;
+                ancestors.pop();
+            }
+        }
+    }
+    if has_ignore_file {
+        ignore_files.pop();
+    }
+    Ok(())
+}
+
+fn is_within_max_file_size(path: &Path, options: &ScanOptions) -> bool {
+    let Some(max_file_size) = options.max_file_size else {
+        return true;
+    };
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.len() > max_file_size => {
+            log::file_skipped(
+                path,
+                &format!("its {} bytes exceed the maximum file size", metadata.len()),
+            );
+            false
+        }
+        _ => true,
+    }
+}
+
+fn matches_stem_pattern(path: &Path, options: &ScanOptions) -> bool {
+    options.stem_pattern.as_ref().is_none_or(|pattern| {
+        path.file_stem()
+            .is_some_and(|stem| pattern.is_match(&stem.to_string_lossy()))
+    })
+}
+
+fn is_modified_within(modified: DateTime<Utc>, options: &ScanOptions) -> bool {
+    options.modified_since.is_none_or(|since| modified >= since)
+        && options.modified_until.is_none_or(|until| modified <= until)
+}
+
+fn is_within_date_range(path: &Path, options: &ScanOptions) -> bool {
+    if options.modified_since.is_none() && options.modified_until.is_none() {
+        return true;
+    }
+    let Ok(modified) = fs::metadata(path).and_then(|metadata| metadata.modified()) else {
+        return true;
+    };
+    let modified = DateTime::<Utc>::from(modified);
+    let within = is_modified_within(modified, options);
+    if !within {
+        log::info(&format!(
+            "Left out {}, modified on {}",
+            path.display(),
+            modified.with_timezone(&Local).format("%Y-%m-%d %H:%M")
+        ));
+    }
+    within
+}
+
+// Whether a scanned file is kept by the filters of `collect_inputs`, without
+// their messages. Files without a supported extension are not sniffed, they
+// are only counted once the scan is over.
+fn counts_toward_max_files(path: &Path, options: &ScanOptions) -> bool {
+    if !is_supported(path) || !matches_stem_pattern(path, options) {
+        return false;
+    }
+    let Ok(metadata) = fs::metadata(path) else {
+        return true;
+    };
+    options
+        .max_file_size
+        .is_none_or(|max_file_size| metadata.len() <= max_file_size)
+        && metadata.modified().map_or(true, |modified| {
+            is_modified_within(DateTime::<Utc>::from(modified), options)
+        })
+}
+
+// Keeps the supported files, grouped by extension and sorted by path inside
+// each group. With `sniff`, files without a supported extension join the group
+// of their content.
+fn group_by_extension(mut entries: Vec<PathBuf>, sniff: bool) -> Vec<PathBuf> {
+    entries.sort();
+    let entries: Vec<(PathBuf, String)> = entries
+        .into_iter()
+        .filter_map(|path| {
+            let extension = input_extension(&path, sniff)?;
+            Some((path, extension))
+        })
+        .collect();
+    INPUT_EXTENSIONS
+        .iter()
+        .chain(&RAW_EXTENSIONS)
+        .flat_map(|extension| {
+            entries
+                .iter()
+                .filter(|(_, input_extension)| input_extension == extension)
+                .map(|(path, _)| path.clone())
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+// Lists every file of `input_dir`, whatever its format, in the directories
+// and through the symlinks `options` lets the scan go. Paths are kept as
+// `OsStr` so that file names which are not valid UTF-8 are still picked up.
+// Files matched by a `.pdfmergeignore` of their directory or of a parent up
+// to `input_dir` are left out.
+pub fn scan_files(input_dir: &Path, options: &ScanOptions) -> io::Result<Vec<PathBuf>> {
+    let mut limit = FileLimit {
+        max: None,
+        found: 0,
+    };
+    scan_files_within(input_dir, options, &mut limit)
+}
+
+// Same as `scan_files`, stopping early once `limit` is exceeded.
+fn scan_files_within(
+    input_dir: &Path,
+    options: &ScanOptions,
+    limit: &mut FileLimit,
+) -> io::Result<Vec<PathBuf>> {
+    let root = fs::canonicalize(input_dir)?;
+    let mut visited_dirs = HashSet::from([root.clone()]);
+    let mut entries = Vec::new();
+    scan_directory(
+        input_dir,
+        options,
+        &mut visited_dirs,
+        &mut vec![root],
+        &mut Vec::new(),
+        &mut entries,
+        limit,
+    )?;
+    Ok(entries)
+}
+
+// The files of `entries`, from `scan_files`, which are merged, in merge order.
+pub fn select_input_files(entries: Vec<PathBuf>, options: &ScanOptions) -> Vec<PathBuf> {
+    let mut entries = if options.recursive {
+        let mut by_directory: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+        for entry in entries {
+            let dir = entry.parent().map(Path::to_path_buf).unwrap_or_default();
+            by_directory.entry(dir).or_default().push(entry);
+        }
+        by_directory
+            .into_values()
+            .flat_map(|entries| group_by_extension(entries, options.sniff))
+            .collect()
+    } else {
+        group_by_extension(entries, options.sniff)
+    };
+    if !options.include_hidden {
+        entries = remove_unsettled_files(entries);
+    }
+    entries
+}
+
+// Folders between the directory `path` was found in, among `roots`, and the
+// file itself: the parents of its bookmark with --recursive.
+pub fn bookmark_folders(path: &Path, roots: &[PathBuf]) -> Vec<String> {
+    roots
+        .iter()
+        .filter(|root| root.is_dir())
+        .find_map(|root| path.parent()?.strip_prefix(root).ok())
+        .map(|relative| {
+            relative
+                .components()
+                .map(|folder| folder.as_os_str().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Resolves the paths given on the command line: directories (and S3 prefixes)
+// are scanned, their files selected with `select_input_files`, while files and URLs are kept in the
+// order they were given, which is the selection order when files are dropped onto the
+// executable.
+pub fn collect_inputs(paths: &[PathBuf], options: &ScanOptions) -> Result<Vec<PathBuf>> {
+    if paths.iter().filter(|path| is_stdin(path)).count() > 1 {
+        bail!("stdin (`-`) can only be given once as an input");
+    }
+
+    let mut inputs = Vec::new();
+    for path in paths {
+        if is_stdin(path) {
+            inputs.push(path.clone());
+        } else if is_s3_prefix(path) {
+            inputs.extend(group_by_extension(s3::list(path)?, false));
+        } else if is_url(path) || is_s3_url(path) {
+            inputs.push(path.clone());
+        } else if path.is_dir() {
+            let mut limit = FileLimit {
+                max: options.max_files,
+                found: inputs.len(),
+            };
+            let entries = scan_files_within(path, options, &mut limit)
+                .with_context(|| format!("cannot read directory {}", path.display()))?;
+            if let Some(max_files) = limit.max.filter(|_| limit.is_exceeded()) {
+                bail!(
+                    "more than the maximum of {} input files found (--max-files)",
+                    max_files
+                );
+            }
+            inputs.extend(
+                select_input_files(entries, options)
+                    .into_iter()
+                    .filter(|file| matches_stem_pattern(file, options))
+                    .filter(|file| is_within_max_file_size(file, options))
+                    .filter(|file| is_within_date_range(file, options)),
+            );
+        } else if input_extension(path, options.sniff).is_some() {
+            if is_within_max_file_size(path, options) && is_within_date_range(path, options) {
+                inputs.push(path.clone());
+            }
+        } else {
+            log::file_skipped(path, "unsupported file type");
+        }
+    }
+    if let Some(max_files) = options.max_files
+        && inputs.len() > max_files
+    {
+        bail!(
+            "{} input files found, more than the maximum of {} (--max-files)",
+            inputs.len(),
+            max_files
+        );
+    }
+    Ok(inputs)
+}
+
+impl From<LoadedInput> for MergeInput {
+    fn from(input: LoadedInput) -> Self {
+        MergeInput {
+            document: match input.spilled {
+                Some(spilled) => InputDocument::Spilled(Box::new(input.document), spilled),
+                None => input.document.into(),
+            },
+            bookmark_title: input.bookmark_title,
+            bookmark_style: input.bookmark_style,
+            bookmark_folders: input.bookmark_folders,
+            bookmark_mode: input.bookmark_mode,
+        }
+    }
+}
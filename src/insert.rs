@@ -0,0 +1,116 @@
+use std::{fs, path::Path};
+
+use anyhow::{Result, bail};
+use pm::page::skip_pages;
+
+use crate::{
+    cli::{InsertPosition, Repeat},
+    input::LoadedInput,
+    log,
+};
+
+// Splits an input before its page `page`, counted from 0, for a document
+// inserted in its middle. Both parts keep the bookmark of the input.
+fn split_input(input: LoadedInput, page: usize) -> (LoadedInput, LoadedInput) {
+    let page_count = input.document.get_pages().len();
+    let mut head = input.clone();
+    skip_pages(&mut head.document, 0, page_count - page);
+    let mut tail = input;
+    skip_pages(&mut tail.document, page, 0);
+    (head, tail)
+}
+
+// Places the documents of --insert among the inputs. Their positions refer to
+// the inputs as sorted, without the inserted documents, and several documents
+// at the same position keep the order they were given in.
+pub fn insert_inputs(
+    inputs: Vec<LoadedInput>,
+    insertions: Vec<(InsertPosition, Vec<LoadedInput>)>,
+) -> Result<Vec<LoadedInput>> {
+    let page_counts: Vec<usize> = inputs
+        .iter()
+        .map(|input| input.document.get_pages().len())
+        .collect();
+    let total_pages: usize = page_counts.iter().sum();
+
+    // Each position as the index of an input and the number of its pages
+    // before the inserted documents.
+    let mut resolved = Vec::new();
+    for (position, documents) in insertions {
+        let at = match position {
+            InsertPosition::Input(index) if index > inputs.len() + 1 => bail!(
+                "cannot insert at input {}, the merge has {} inputs",
+                index,
+                inputs.len()
+            ),
+            InsertPosition::Input(index) => (index - 1, 0),
+            InsertPosition::AfterPage(page) if page > total_pages => bail!(
+                "cannot insert after page {}, the merge has {} pages",
+                page,
+                total_pages
+            ),
+            InsertPosition::AfterPage(page) => {
+                let (mut index, mut before) = (0, page);
+                while before > 0 && index < page_counts.len() && before >= page_counts[index] {
+                    before -= page_counts[index];
+                    index += 1;
+                }
+                (index, before)
+            }
+        };
+        resolved.push((at, documents));
+    }
+    resolved.sort_by_key(|(at, _)| *at);
+
+    let mut resolved = resolved.into_iter().peekable();
+    let mut merged = Vec::new();
+    for (index, mut input) in inputs.into_iter().enumerate() {
+        let mut emitted_pages = 0;
+        while let Some(((_, page), documents)) =
+            resolved.next_if(|((at_index, _), _)| *at_index == index)
+        {
+            if page > emitted_pages {
+                let (head, tail) = split_input(input, page - emitted_pages);
+                merged.push(head);
+                input = tail;
+                emitted_pages = page;
+            }
+            merged.extend(documents);
+        }
+        merged.push(input);
+    }
+    merged.extend(resolved.flat_map(|(_, documents)| documents));
+    Ok(merged)
+}
+
+fn is_same_file(first: &Path, second: &Path) -> bool {
+    first == second
+        || matches!(
+            (fs::canonicalize(first), fs::canonicalize(second)),
+            (Ok(first), Ok(second)) if first == second
+        )
+}
+
+// Includes the inputs of --repeat several times in a row.
+pub fn repeat_inputs(inputs: Vec<LoadedInput>, repeats: &[Repeat]) -> Vec<LoadedInput> {
+    for repeat in repeats {
+        if !inputs
+            .iter()
+            .any(|input| is_same_file(&input.path, &repeat.path))
+        {
+            log::warning(&format!(
+                "{} is not an input, it cannot be repeated",
+                repeat.path.display()
+            ));
+        }
+    }
+    let mut repeated = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let count = repeats
+            .iter()
+            .find(|repeat| is_same_file(&input.path, &repeat.path))
+            .map_or(1, |repeat| repeat.count);
+        repeated.extend(std::iter::repeat_n(input, count));
+    }
+    repeated
+}
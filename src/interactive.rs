@@ -0,0 +1,112 @@
+use std::io::{self, BufRead, Write};
+
+use colored::Colorize;
+
+use pm::page::rotate_document;
+
+use crate::input::LoadedInput;
+
+struct Entry {
+    input: LoadedInput,
+    excluded: bool,
+    rotation: i64,
+}
+
+fn print_entries(entries: &[Entry]) {
+    println!();
+    for (index, entry) in entries.iter().enumerate() {
+        let line = format!(
+            "{:>3}. {} ({} pages{})",
+            index + 1,
+            entry.input.path.display(),
+            entry.input.document.get_pages().len(),
+            if entry.rotation != 0 {
+                format!(", rotated {}°", entry.rotation)
+            } else {
+                String::new()
+            }
+        );
+        if entry.excluded {
+            println!("{} {}", line.dimmed(), "[excluded]".red());
+        } else {
+            println!("{}", line);
+        }
+    }
+    println!();
+    println!(
+        "Commands: {} move, {} exclude/include, {} rotate by 90°, {} merge, {} abort",
+        "m <from> <to>".cyan(),
+        "x <n>".cyan(),
+        "r <n>".cyan(),
+        "<enter>".cyan(),
+        "q".cyan()
+    );
+}
+
+fn parse_index(arg: Option<&str>, len: usize) -> Option<usize> {
+    let index = arg?.parse::<usize>().ok()?;
+    (1..=len).contains(&index).then(|| index - 1)
+}
+
+// Lets the user reorder, exclude or rotate the inputs before merging.
+// Returns `None` if the merge was aborted.
+pub fn review_inputs(inputs: Vec<LoadedInput>) -> io::Result<Option<Vec<LoadedInput>>> {
+    let mut entries: Vec<Entry> = inputs
+        .into_iter()
+        .map(|input| Entry {
+            input,
+            excluded: false,
+            rotation: 0,
+        })
+        .collect();
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print_entries(&entries);
+        print!("> ");
+        io::stdout().flush()?;
+
+        let Some(line) = lines.next().transpose()? else {
+            return Ok(None);
+        };
+        let mut words = line.split_whitespace();
+        let len = entries.len();
+        match words.next() {
+            None => break,
+            Some("q") => return Ok(None),
+            Some("m") => match (
+                parse_index(words.next(), len),
+                parse_index(words.next(), len),
+            ) {
+                (Some(from), Some(to)) => {
+                    let entry = entries.remove(from);
+                    entries.insert(to, entry);
+                }
+                _ => println!("{}: expected two entry numbers", "Error".red()),
+            },
+            Some("x") => match parse_index(words.next(), len) {
+                Some(index) => entries[index].excluded = !entries[index].excluded,
+                None => println!("{}: expected an entry number", "Error".red()),
+            },
+            Some("r") => match parse_index(words.next(), len) {
+                Some(index) => entries[index].rotation = (entries[index].rotation + 90) % 360,
+                None => println!("{}: expected an entry number", "Error".red()),
+            },
+            Some(command) => println!("{}: unknown command {}", "Error".red(), command),
+        }
+    }
+
+    Ok(Some(
+        entries
+            .into_iter()
+            .filter(|entry| !entry.excluded)
+            .map(|mut entry| {
+                if entry.rotation != 0 {
+                    rotate_document(&mut entry.input.document, entry.rotation);
+                }
+                entry.input
+            })
+            .collect(),
+    ))
+}
@@ -0,0 +1,53 @@
+use anyhow::Result;
+
+// Passwords of encrypted inputs kept in the keyring of the platform under a
+// key of the user's choosing, e.g. the sender of the documents: the Keychain
+// on macOS, the Credential Manager on Windows and the Secret Service
+// (GNOME Keyring, KWallet) on Linux.
+#[cfg(feature = "keyring")]
+mod platform {
+    use anyhow::{Context, Result};
+    use keyring::{Entry, Error};
+
+    const SERVICE: &str = "pdf-merge";
+
+    pub fn get(key: &str) -> Result<Option<String>> {
+        match Entry::new(SERVICE, key).and_then(|entry| entry.get_password()) {
+            Ok(password) => Ok(Some(password)),
+            Err(Error::NoEntry) => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("cannot read {} from the keyring", key)),
+        }
+    }
+
+    pub fn store(key: &str, password: &str) -> Result<()> {
+        Entry::new(SERVICE, key)
+            .and_then(|entry| entry.set_password(password))
+            .with_context(|| format!("cannot store {} in the keyring", key))
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+mod platform {
+    use anyhow::{Result, bail};
+
+    fn unsupported<T>() -> Result<T> {
+        bail!("pdf-merge was built without the `keyring` feature")
+    }
+
+    pub fn get(_key: &str) -> Result<Option<String>> {
+        unsupported()
+    }
+
+    pub fn store(_key: &str, _password: &str) -> Result<()> {
+        unsupported()
+    }
+}
+
+// The password stored under `key`, `None` when there is none.
+pub fn get(key: &str) -> Result<Option<String>> {
+    platform::get(key)
+}
+
+pub fn store(key: &str, password: &str) -> Result<()> {
+    platform::store(key, password)
+}
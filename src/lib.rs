@@ -0,0 +1,39 @@
+// Merge core of pdf-merge: everything needed to turn images into pages and
+// merge documents, without the command line, the dialogs or the file system
+// scanning. Without the `native` and `encryption` features it only works in
+// memory and builds for `wasm32-unknown-unknown`.
+pub mod annotation;
+pub mod archive;
+pub mod attachment;
+pub mod background;
+pub mod compression;
+pub mod dedup;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod font;
+pub mod gc;
+pub mod image;
+pub mod index;
+pub mod merge;
+pub mod metadata;
+pub mod outline;
+pub mod page;
+pub mod pagesize;
+mod parallel;
+pub mod portfolio;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod recompress;
+pub mod redact;
+pub mod signature;
+pub mod source;
+#[cfg(feature = "native")]
+pub mod spill;
+pub mod stamp;
+pub mod text;
+pub mod text_document;
+pub mod thumbnail;
+mod trace;
+pub mod verify;
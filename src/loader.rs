@@ -0,0 +1,307 @@
+use std::{
+    fmt, fs,
+    io::{self, Read},
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, bail};
+use lopdf::Document;
+use pm::{
+    image::image_document::ImageOptions,
+    merge::Cancelled,
+    portfolio::{embedded_pdfs, is_portfolio},
+    source::{is_pdf_data, is_zip_file_name, load_bytes, load_file, load_zip},
+};
+
+use crate::{
+    checkpoint::Checkpoint,
+    cli::PortfolioMode,
+    gui,
+    input::{LoadedInput, is_stdin},
+    log,
+    remote::{self, DownloadOptions, is_url, url_file_name},
+    s3,
+};
+
+// How the inputs are fetched and, for images, converted.
+#[derive(Debug, Clone)]
+pub struct LoadOptions {
+    pub download: DownloadOptions,
+    pub image: ImageOptions,
+    // Pages dropped at the start and the end of the PDF inputs.
+    pub skip_first: usize,
+    pub skip_last: usize,
+    // How long an input held by another process is retried.
+    pub locked_wait: Duration,
+    // Where the loaded inputs are kept, with --checkpoint.
+    pub checkpoint: Option<Checkpoint>,
+}
+
+// Delays between two attempts at loading a locked input, doubling from the
+// first to the last.
+const FIRST_RETRY_DELAY: Duration = Duration::from_millis(250);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(4);
+
+// Each document of the archive becomes an input, named after its path in the
+// archive, e.g. `pack.zip/invoices/1.pdf`. The encrypted documents and those
+// compressed with an unsupported method are skipped.
+fn load_zip_inputs(
+    zip_path: &Path,
+    bytes: &[u8],
+    image_options: &ImageOptions,
+) -> Result<Vec<LoadedInput>> {
+    let zip = load_zip(bytes, image_options)?;
+    for entry in zip.skipped {
+        log::file_skipped(&zip_path.join(entry.name), &entry.reason);
+    }
+    Ok(zip
+        .documents
+        .into_iter()
+        .map(|(name, document)| LoadedInput::new(zip_path.join(name), document))
+        .collect())
+}
+
+fn read_stdin_document() -> Result<Document> {
+    let mut bytes = Vec::new();
+    io::stdin()
+        .lock()
+        .read_to_end(&mut bytes)
+        .context("cannot read stdin")?;
+    if !is_pdf_data(&bytes) {
+        bail!("stdin does not contain a PDF document");
+    }
+    Document::load_mem(&bytes).context("cannot load the PDF document read from stdin")
+}
+
+// Loads an input path: a local file, `-` for stdin, an `http(s)://` or `s3://` URL. ZIP
+// archives expand to one input per document they contain.
+pub fn load_input(path: &Path, options: &LoadOptions) -> Result<Vec<LoadedInput>> {
+    let _span = pm::trace_span!("load", path = %path.display());
+    if is_stdin(path) {
+        return Ok(vec![LoadedInput::new(
+            path.to_path_buf(),
+            read_stdin_document()?,
+        )]);
+    }
+
+    if is_url(path) || s3::is_s3_url(path) {
+        let bytes = if is_url(path) {
+            remote::download(path, &options.download)?
+        } else {
+            s3::download(path)?
+        };
+        let file_name = url_file_name(path);
+        if is_zip_file_name(&file_name) {
+            return load_zip_inputs(path, &bytes, &options.image);
+        }
+        let document = load_bytes(&bytes, &file_name, &options.image)?;
+        return Ok(vec![LoadedInput::new(path.to_path_buf(), document)]);
+    }
+
+    if is_zip_file_name(path) {
+        let bytes = fs::read(path)?;
+        return load_zip_inputs(path, &bytes, &options.image);
+    }
+    Ok(vec![LoadedInput::new(
+        path.to_path_buf(),
+        load_file(path, &options.image)?,
+    )])
+}
+
+// Replaces the portfolios among the inputs by the PDFs embedded in them, named
+// after the portfolio, e.g. `binder.pdf/contract.pdf`, or adds them after the
+// cover sheet with `PortfolioMode::Both`.
+pub fn expand_portfolios(inputs: Vec<LoadedInput>, mode: PortfolioMode) -> Vec<LoadedInput> {
+    if mode == PortfolioMode::Cover {
+        return inputs;
+    }
+    let mut expanded = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        if !is_portfolio(&input.document) {
+            expanded.push(input);
+            continue;
+        }
+        let embedded = match input.document_with_contents() {
+            Ok(document) => embedded_pdfs(&document),
+            Err(e) => {
+                log::warning(&format!("{:#}", e));
+                Vec::new()
+            }
+        };
+        if embedded.is_empty() {
+            log::warning(&format!(
+                "{} is a portfolio without embedded PDFs, merging its cover sheet",
+                input.path.display()
+            ));
+            expanded.push(input);
+            continue;
+        }
+        log::info(&format!(
+            "{} is a portfolio, merging the {} PDFs embedded in it",
+            input.path.display(),
+            embedded.len()
+        ));
+        let path = input.path.clone();
+        if mode == PortfolioMode::Both {
+            expanded.push(input);
+        }
+        for (index, (name, document)) in embedded.into_iter().enumerate() {
+            let name = if name.is_empty() {
+                format!("embedded-{}.pdf", index + 1)
+            } else {
+                name
+            };
+            let embedded_input = LoadedInput::new(path.join(name), document);
+            log::file_loaded(
+                &embedded_input.path,
+                embedded_input.document.get_pages().len(),
+            );
+            expanded.push(embedded_input);
+        }
+    }
+    expanded
+}
+
+// Error of an input which took longer than the timeout to load.
+#[derive(Debug)]
+pub struct TimedOut(pub Duration);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "loading took more than {} s", self.0.as_secs())
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+// Loads the input like `load_input`, turning a panic of the parser or of an
+// image decoder on a malformed file into an error, so that the file is
+// skipped instead of the whole merge being aborted.
+fn load_input_catching_panics(path: &Path, options: &LoadOptions) -> Result<Vec<LoadedInput>> {
+    panic::catch_unwind(AssertUnwindSafe(|| load_input(path, options))).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or(payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown error");
+        bail!(
+            "cannot load {}: the loader crashed: {}",
+            path.display(),
+            message
+        )
+    })
+}
+
+// How often a thread waiting for an input checks for a cancel from the GUI.
+const CANCELLATION_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// Loads the input in a separate thread, given up on after `timeout` so that a
+// file sending the parser into a near-infinite loop does not hang the whole
+// merge. The thread cannot be killed: it keeps running in the background
+// until the process exits. In the GUI mode, inputs are always loaded in a
+// thread, so that cancelling the progress window is not held up by a large
+// file.
+pub fn load_input_with_timeout(
+    path: &Path,
+    options: &LoadOptions,
+    timeout: Option<Duration>,
+) -> Result<Vec<LoadedInput>> {
+    if timeout.is_none() && !gui::is_active() {
+        return load_input_catching_panics(path, options);
+    }
+
+    let cancellation = gui::cancellation();
+    let (sender, receiver) = mpsc::channel();
+    let thread_path = path.to_path_buf();
+    let thread_options = options.clone();
+    thread::spawn(move || {
+        let _ = sender.send(load_input_catching_panics(&thread_path, &thread_options));
+    });
+    let started = Instant::now();
+    loop {
+        if cancellation.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        let wait = timeout.map_or(CANCELLATION_CHECK_INTERVAL, |timeout| {
+            timeout
+                .saturating_sub(started.elapsed())
+                .min(CANCELLATION_CHECK_INTERVAL)
+        });
+        match receiver.recv_timeout(wait) {
+            Ok(loaded) => return loaded,
+            Err(RecvTimeoutError::Timeout) => {
+                if let Some(timeout) = timeout
+                    && started.elapsed() >= timeout
+                {
+                    return Err(TimedOut(timeout))
+                        .with_context(|| format!("cannot load {}", path.display()));
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                bail!("cannot load {}: the loader crashed", path.display())
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+fn io_errors(e: &anyhow::Error) -> impl Iterator<Item = &io::Error> {
+    e.chain().filter_map(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .or(match cause.downcast_ref::<lopdf::Error>() {
+                Some(lopdf::Error::IO(e)) => Some(e),
+                _ => None,
+            })
+    })
+}
+
+// Whether the input could not be opened because another process holds it,
+// which Windows reports as a sharing or a lock violation.
+#[cfg(windows)]
+fn is_locked(e: &anyhow::Error) -> bool {
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    const ERROR_LOCK_VIOLATION: i32 = 33;
+    io_errors(e).any(|e| {
+        matches!(
+            e.raw_os_error(),
+            Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)
+        )
+    })
+}
+
+// Other systems do not lock files being written.
+#[cfg(not(windows))]
+fn is_locked(_e: &anyhow::Error) -> bool {
+    false
+}
+
+// Loads the input like `load_input_with_timeout`, retrying with a growing
+// delay while another process holds it, for up to `options.locked_wait`.
+pub fn load_input_with_retries(
+    path: &Path,
+    options: &LoadOptions,
+    timeout: Option<Duration>,
+) -> Result<Vec<LoadedInput>> {
+    let deadline = Instant::now() + options.locked_wait;
+    let mut delay = FIRST_RETRY_DELAY;
+    loop {
+        match load_input_with_timeout(path, options, timeout) {
+            Err(e) if is_locked(&e) && Instant::now() + delay <= deadline => {
+                if delay == FIRST_RETRY_DELAY {
+                    log::info(&format!(
+                        "{} is in use by another process, waiting for it",
+                        path.display()
+                    ));
+                }
+                thread::sleep(delay);
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
+            loaded => return loaded,
+        }
+    }
+}
@@ -0,0 +1,309 @@
+use std::{
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
+
+use chrono::Local;
+use colored::Colorize;
+
+use crate::{gui, notify};
+
+// Diagnostics are printed to the terminal and, with `--log-file`, appended
+// without colors to the log file so that unattended runs leave a trail.
+static LOG_FILE: OnceLock<Mutex<File>> = OnceLock::new();
+
+// With `--log-format json`, the terminal output is replaced by one JSON
+// object per line on stderr, for log aggregators.
+static JSON_FORMAT: AtomicBool = AtomicBool::new(false);
+
+// With `--progress-json`, progress events are emitted on stderr as JSON lines,
+// whatever the format of the other diagnostics.
+static PROGRESS_JSON: AtomicBool = AtomicBool::new(false);
+
+// Warnings and skipped files so far, which `--strict` turns into a failure.
+static ANOMALIES: AtomicUsize = AtomicUsize::new(0);
+
+pub fn anomaly_count() -> usize {
+    ANOMALIES.load(Ordering::Relaxed)
+}
+
+// Skipped files with the reason, for the summary at the end of the run.
+static SKIPPED: Mutex<Vec<(PathBuf, String)>> = Mutex::new(Vec::new());
+
+pub fn skipped_count() -> usize {
+    SKIPPED.lock().unwrap_or_else(|e| e.into_inner()).len()
+}
+
+pub fn use_json_format() {
+    JSON_FORMAT.store(true, Ordering::Relaxed);
+}
+
+fn is_json_format() -> bool {
+    JSON_FORMAT.load(Ordering::Relaxed)
+}
+
+pub fn use_progress_json() {
+    PROGRESS_JSON.store(true, Ordering::Relaxed);
+}
+
+pub fn open_log_file(path: &Path) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let _ = LOG_FILE.set(Mutex::new(file));
+    Ok(())
+}
+
+// Removes the ANSI escape sequences `colored` wraps the highlighted parts of
+// the messages in.
+fn strip_colors(message: &str) -> String {
+    let mut stripped = String::with_capacity(message.len());
+    let mut chars = message.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+fn write_to_file(level: &str, message: &str) {
+    let Some(file) = LOG_FILE.get() else {
+        return;
+    };
+    let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+    // Logging must not interrupt a merge, a failed write is dropped.
+    let _ = writeln!(
+        file,
+        "{} {:<7} {}",
+        Local::now().format("%Y-%m-%d %H:%M:%S"),
+        level,
+        strip_colors(message)
+    );
+}
+
+fn json_string(string: &str) -> String {
+    let mut json = String::with_capacity(string.len() + 2);
+    json.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+// A JSON value, already encoded.
+struct Json(String);
+
+impl From<&str> for Json {
+    fn from(string: &str) -> Self {
+        Json(json_string(string))
+    }
+}
+
+impl From<&Path> for Json {
+    fn from(path: &Path) -> Self {
+        Json(json_string(&path.to_string_lossy()))
+    }
+}
+
+impl From<f64> for Json {
+    fn from(number: f64) -> Self {
+        Json(format!("{:.1}", number))
+    }
+}
+
+impl From<u64> for Json {
+    fn from(number: u64) -> Self {
+        Json(number.to_string())
+    }
+}
+
+impl From<usize> for Json {
+    fn from(number: usize) -> Self {
+        Json(number.to_string())
+    }
+}
+
+fn emit_json(event: &str, fields: Vec<(&str, Json)>) {
+    let mut line = format!(
+        "{{\"time\":{},\"event\":{}",
+        json_string(&Local::now().to_rfc3339()),
+        json_string(event)
+    );
+    for (name, Json(value)) in fields {
+        let _ = write!(line, ",{}:{}", json_string(name), value);
+    }
+    line.push('}');
+    eprintln!("{}", line);
+}
+
+fn message(level: &str, message: &str) {
+    if level == "warning" {
+        ANOMALIES.fetch_add(1, Ordering::Relaxed);
+    }
+    write_to_file(&level.to_ascii_uppercase(), message);
+    if is_json_format() {
+        emit_json(
+            level,
+            vec![("message", strip_colors(message).as_str().into())],
+        );
+        return;
+    }
+    match level {
+        "warning" => println!("{}: {}", "Warning".yellow(), message),
+        "error" => eprintln!("{}: {}", "Error".red(), message),
+        _ => println!("{}", message),
+    }
+}
+
+pub fn info(message: &str) {
+    self::message("info", message);
+}
+
+pub fn warning(message: &str) {
+    self::message("warning", message);
+}
+
+// In GUI mode, errors are also shown in a dialog, as they end the run.
+pub fn error(message: &str) {
+    self::message("error", message);
+    notify::send(&format!("Merge failed: {}", strip_colors(message)));
+    if gui::is_active() {
+        gui::show_error(&strip_colors(message));
+    }
+}
+
+pub fn file_loaded(path: &Path, pages: usize) {
+    write_to_file(
+        "INFO",
+        &format!("loaded {} ({} pages)", path.display(), pages),
+    );
+    if is_json_format() {
+        emit_json(
+            "file_loaded",
+            vec![("path", path.into()), ("pages", pages.into())],
+        );
+    }
+}
+
+pub fn file_skipped(path: &Path, reason: &str) {
+    SKIPPED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push((path.to_path_buf(), reason.to_string()));
+    if is_json_format() {
+        ANOMALIES.fetch_add(1, Ordering::Relaxed);
+        write_to_file(
+            "WARNING",
+            &format!("skipping {}, {}", path.display(), reason),
+        );
+        emit_json(
+            "file_skipped",
+            vec![("path", path.into()), ("reason", reason.into())],
+        );
+        return;
+    }
+    warning(&format!(
+        "skipping {}, {}",
+        path.display().to_string().blue().underline(),
+        reason
+    ));
+}
+
+pub fn pages_merged(documents: usize, pages: usize) {
+    write_to_file(
+        "INFO",
+        &format!("merged {} pages of {} documents", pages, documents),
+    );
+    if is_json_format() {
+        emit_json(
+            "pages_merged",
+            vec![("documents", documents.into()), ("pages", pages.into())],
+        );
+    }
+}
+
+pub fn saved(path: &Path, bytes: u64) {
+    let message = format!("Saved the merged PDF to {}", path.display());
+    write_to_file("INFO", &message);
+    if is_json_format() {
+        emit_json(
+            "saved",
+            vec![("path", path.into()), ("bytes", bytes.into())],
+        );
+    } else {
+        println!("{}", message);
+    }
+}
+
+// `fraction` of the run done, from 0 to 1, reported before each input is
+// loaded, after each one is merged and when the later phases start.
+pub fn progress(phase: &str, file: Option<&Path>, fraction: f64) {
+    if !PROGRESS_JSON.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut fields = vec![("phase", phase.into())];
+    if let Some(file) = file {
+        fields.push(("file", file.into()));
+    }
+    fields.push(("percent", (fraction * 100.0).into()));
+    emit_json("progress", fields);
+}
+
+// Consolidated report of the run: what was merged, what was skipped and why.
+pub fn summary(files: usize, pages: usize) {
+    let skipped = SKIPPED.lock().unwrap_or_else(|e| e.into_inner());
+    let warnings = anomaly_count() - skipped.len();
+    let line = format!(
+        "{} files merged, {} pages written, {} files skipped, {} warnings",
+        files,
+        pages,
+        skipped.len(),
+        warnings
+    );
+    write_to_file("INFO", &line);
+    if is_json_format() {
+        emit_json(
+            "summary",
+            vec![
+                ("files", files.into()),
+                ("pages", pages.into()),
+                ("skipped", skipped.len().into()),
+                ("warnings", warnings.into()),
+            ],
+        );
+        return;
+    }
+    println!("{}", line);
+    for (path, reason) in skipped.iter() {
+        println!(
+            "  skipped {}: {}",
+            path.display().to_string().blue(),
+            reason
+        );
+    }
+    if gui::is_active() && !skipped.is_empty() {
+        let mut description = format!("{}.\n", line);
+        for (path, reason) in skipped.iter() {
+            let _ = write!(description, "\nSkipped {}: {}", path.display(), reason);
+        }
+        gui::show_warning(&description);
+    }
+}
@@ -1,384 +1,1480 @@
-mod image;
-mod pagesize;
+mod certificate;
+mod checkpoint;
+mod checksum;
+mod cli;
+mod compare;
+mod disk_space;
+mod doctor;
+mod gui;
+mod ignore;
+mod input;
+mod insert;
+mod interactive;
+mod keyring;
+mod loader;
+mod log;
+mod notify;
+mod outline_file;
+mod password;
+mod plan;
+mod preview;
+mod remote;
+mod s3;
+mod server;
+mod shell_integration;
+mod stats;
 
-use lopdf::{Bookmark, Document, Object, ObjectId};
-use printpdf::{ImageTransform, Mm, PdfDocument};
-use std::{cmp::max, path::PathBuf, process::abort};
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local, Utc};
+use colored::Colorize;
+use lopdf::Document;
+use pm::{
+    annotation::{AnnotationFilter, flatten_annotations, strip_annotations, strip_external_links},
+    background::{Layer, add_background},
+    compression::{CompressionLevel, compress_document, prepare_debug_output},
+    encryption::{Permissions, decrypt, encrypt_with_owner_password},
+    font::check_font,
+    gc::remove_unreferenced_objects,
+    image::image_document::ImageOptions,
+    index::{IndexEntry, add_index_pages},
+    merge::{
+        BookmarkMode, BookmarkStyle, Cancelled, MergeInput, Merger, Phase, Progress,
+        save_atomically_unless_cancelled,
+    },
+    metadata,
+    page::{
+        blank_document, force_page_size, remove_page, rotate_document, select_pages,
+        shrink_oversized_pages, skip_pages,
+    },
+    pagesize::A4,
+    recompress::recompress_images,
+    redact::redact,
+    signature::signature_count,
+    spill::{SpilledStreams, estimated_size},
+    stamp::stamp_text,
+    text::matching_pages,
+    thumbnail::{add_thumbnails, remove_thumbnails},
+    verify::verify_document,
+};
+use regex::Regex;
+use remote::DownloadOptions;
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::exit,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use image::{
-    alpha_remover::RemoveAlpha, image_reader::read_image_from_file,
-    image_transform::get_image_transform_for_page_size, image_x_object::get_image_dimension_in_mm,
+use certificate::encrypt_to_certificates;
+use checkpoint::Checkpoint;
+use clap::Parser;
+use cli::{
+    Args, ChecksumAlgorithm, Command, DateSpec, DocumentId, InsertPosition, LogFormat, PageSize,
+    SortOrder,
 };
-use pagesize::PageSizeInMm;
+use disk_space::check_free_space;
+use input::{LoadedInput, ScanOptions, bookmark_folders, collect_inputs, is_stdin};
+use insert::{insert_inputs, repeat_inputs};
+use loader::{LoadOptions, TimedOut, expand_portfolios, load_input_with_retries};
+use outline_file::read_outline_file;
+use stats::Step;
 
-const MIN_WIDTH_IN_MM: f64 = 210.0;
-const MIN_HEIGHT_IN_MM: f64 = 297.0;
+// Reorders the PDF inputs by creation date while converted images and text
+// files keep their position.
+fn sort_pdfs_by_creation_date(inputs: Vec<LoadedInput>) -> Vec<LoadedInput> {
+    let is_pdf_slot: Vec<bool> = inputs.iter().map(|input| !input.is_converted()).collect();
+    let (mut pdfs, converted): (Vec<_>, Vec<_>) =
+        inputs.into_iter().partition(|input| !input.is_converted());
 
-use std::{collections::BTreeMap, env, path::Path, process::exit};
+    // `None` sorts before `Some`, so undated documents are moved to the end
+    // while the sort stays stable for the ones sharing the same date.
+    pdfs.sort_by_cached_key(|input| {
+        let date = metadata::creation_date(&input.document);
+        (date.is_none(), date)
+    });
 
-use glob::glob;
-//use lopdf::{Bookmark, Document, Object, ObjectId};
+    let mut pdfs = pdfs.into_iter();
+    let mut converted = converted.into_iter();
+    is_pdf_slot
+        .into_iter()
+        .filter_map(|is_pdf| {
+            if is_pdf {
+                pdfs.next()
+            } else {
+                converted.next()
+            }
+        })
+        .collect()
+}
 
-use std::process;
+// Merging holds the output on top of the inputs. Past half the memory
+// budget, the stream contents of the inputs are spilled to temporary files as
+// the inputs are loaded, then read back one input at a time while merging, so
+// that loading only holds their dictionaries and merging the output and a
+// single input. Encrypted inputs stay whole until they are unlocked.
+struct MemoryBudget {
+    max_memory: u64,
+    dir: PathBuf,
+    // Inputs counted so far, and the memory they hold.
+    counted: usize,
+    held: u64,
+    spilling: bool,
+}
+
+impl MemoryBudget {
+    fn new(max_memory: u64, temp_dir: Option<&Path>) -> Self {
+        MemoryBudget {
+            max_memory,
+            dir: temp_dir.map_or_else(env::temp_dir, Path::to_path_buf),
+            counted: 0,
+            held: 0,
+            spilling: false,
+        }
+    }
 
-// imports the `image` library with the exact version that we are using
-//use printpdf::*;
+    // Counts the inputs added to `inputs` since the last call, then spills
+    // those still in memory if they take more than the budget allows.
+    fn enforce(&mut self, inputs: &mut [LoadedInput]) -> Result<()> {
+        for input in &inputs[self.counted..] {
+            self.held += estimated_size(&input.document);
+        }
+        self.counted = inputs.len();
+        if self.held.saturating_mul(2) <= self.max_memory {
+            return Ok(());
+        }
+        if !self.spilling {
+            log::info(&format!(
+                "The inputs take about {} MiB, spilling them to disk to stay within the memory budget",
+                self.held >> 20
+            ));
+            self.spilling = true;
+        }
+        for input in inputs
+            .iter_mut()
+            .filter(|input| input.spilled.is_none() && !input.document.is_encrypted())
+        {
+            let size = estimated_size(&input.document);
+            input.spilled = Some(Arc::new(SpilledStreams::spill(
+                &mut input.document,
+                &self.dir,
+            )?));
+            self.held -= size - estimated_size(&input.document);
+        }
+        Ok(())
+    }
+}
 
-use rfd::FileDialog;
-use std::convert::From;
+// Index entries of the sections of the merged document, given the title and
+// page count of each input in merge order.
+fn index_entries(document: &Document, sections: Vec<(String, usize)>) -> Vec<IndexEntry> {
+    let page_ids: Vec<_> = document.get_pages().into_values().collect();
+    let mut first_page = 0;
+    sections
+        .into_iter()
+        .filter_map(|(title, pages)| {
+            let page_id = page_ids.get(first_page).copied().filter(|_| pages > 0);
+            first_page += pages;
+            Some(IndexEntry {
+                title,
+                page_id: page_id?,
+            })
+        })
+        .collect()
+}
 
-use colored::Colorize;
+fn store_password(key: &str) -> Result<()> {
+    let password = password::read_hidden_line(&format!("Password to store under {}: ", key))
+        .context("cannot read the password from the terminal")?;
+    keyring::store(key, &password)?;
+    log::info(&format!("Stored the password under {}", key));
+    Ok(())
+}
 
-//use image_crate::codecs::{bmp::BmpDecoder, jpeg::JpegDecoder, png::PngDecoder};
+// Renames an existing output to `<output>.bak`, or to a timestamped
+// `<output>.<date>.bak` when a backup already exists.
+fn backup_existing_output(output_path: &Path) -> io::Result<Option<PathBuf>> {
+    if !output_path.exists() {
+        return Ok(None);
+    }
+    let with_suffix = |suffix: String| {
+        let mut backup = output_path.as_os_str().to_owned();
+        backup.push(suffix);
+        PathBuf::from(backup)
+    };
+    let mut backup_path = with_suffix(".bak".to_string());
+    if backup_path.exists() {
+        backup_path = with_suffix(format!(".{}.bak", Local::now().format("%Y%m%d-%H%M%S")));
+    }
+    fs::rename(output_path, &backup_path)?;
+    Ok(Some(backup_path))
+}
 
-fn usage() {
-    println!("Usage: pdf-merge <input_directory> <output_file>");
-    println!("\nArguments:");
-    println!("\t<input_directory>  Directory where the tool will search for .pdf files");
-    println!("\t<output_file>  File to save the merged pdf result");
+// Prints the SHA-256 of the saved output and, with `sidecar`, writes it next
+// to the output in the `sha256sum` format, so that `sha256sum -c` checks it.
+fn write_checksum(output_path: &Path, sidecar: bool) -> Result<()> {
+    let bytes = if s3::is_s3_url(output_path) {
+        s3::download(output_path)?
+    } else {
+        fs::read(output_path).with_context(|| format!("cannot read {}", output_path.display()))?
+    };
+    let digest = checksum::to_hex(&checksum::sha256(&bytes));
+    log::info(&format!("SHA-256: {}", digest));
+
+    if sidecar {
+        let file_name = output_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let line = format!("{}  {}\n", digest, file_name);
+        let mut sidecar_path = output_path.as_os_str().to_owned();
+        sidecar_path.push(".sha256");
+        let sidecar_path = PathBuf::from(sidecar_path);
+        if s3::is_s3_url(&sidecar_path) {
+            s3::upload(&sidecar_path, line.as_bytes())?;
+        } else {
+            fs::write(&sidecar_path, line)
+                .with_context(|| format!("cannot write {}", sidecar_path.display()))?;
+        }
+    }
+    Ok(())
 }
 
-fn merge_documents(input_documents: Vec<Document>) -> Document {
-    // Define a starting `max_id` (will be used as start index for object_ids).
-    let mut max_id = 1;
-    let mut pagenum = 1;
-    // Collect all Documents Objects grouped by a map
-    let mut documents_pages = BTreeMap::new();
-    let mut documents_objects = BTreeMap::new();
-    let mut document = Document::with_version("1.5");
-
-    for mut doc in input_documents {
-        let mut first = false;
-        doc.renumber_objects_with(max_id);
-
-        max_id = doc.max_id + 1;
-
-        documents_pages.extend(
-            doc.get_pages()
-                .into_iter()
-                .map(|(_, object_id)| {
-                    if !first {
-                        let bookmark = Bookmark::new(
-                            String::from(format!("Page_{}", pagenum)),
-                            [0.0, 0.0, 1.0],
-                            0,
-                            object_id,
-                        );
-                        document.add_bookmark(bookmark, None);
-                        first = true;
-                        pagenum += 1;
-                    }
+// Reads the saved output back and returns its structural problems, if any.
+fn verify_output(output_path: &Path, expected_pages: usize) -> Result<Vec<String>> {
+    let document = if s3::is_s3_url(output_path) {
+        Document::load_mem(&s3::download(output_path)?)
+    } else {
+        Document::load(output_path)
+    }
+    .with_context(|| format!("cannot read {} back", output_path.display()))?;
+    Ok(verify_document(&document, expected_pages))
+}
 
-                    (object_id, doc.get_object(object_id).unwrap().to_owned())
-                })
-                .collect::<BTreeMap<ObjectId, Object>>(),
-        );
-        documents_objects.extend(doc.objects);
-    }
-
-    // "Catalog" and "Pages" are mandatory.
-    let mut catalog_object: Option<(ObjectId, Object)> = None;
-    let mut pages_object: Option<(ObjectId, Object)> = None;
-
-    // Process all objects except "Page" type
-    for (object_id, object) in documents_objects.iter() {
-        // We have to ignore "Page" (as are processed later), "Outlines" and "Outline" objects.
-        // All other objects should be collected and inserted into the main Document.
-        match object.type_name().unwrap_or(b"") {
-            b"Catalog" => {
-                // Collect a first "Catalog" object and use it for the future "Pages".
-                catalog_object = Some((
-                    if let Some((id, _)) = catalog_object {
-                        id
-                    } else {
-                        *object_id
-                    },
-                    object.clone(),
-                ));
+// Date of the reproducible outputs, from the `SOURCE_DATE_EPOCH` convention of
+// reproducible builds.
+fn source_date() -> DateTime<Utc> {
+    env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.trim().parse::<i64>().ok())
+        .and_then(|epoch| DateTime::from_timestamp(epoch, 0))
+        .unwrap_or_default()
+}
+
+#[derive(Default)]
+struct InputDates {
+    creation: Option<DateTime<Local>>,
+    modification: Option<DateTime<Local>>,
+}
+
+fn resolve_date(
+    spec: DateSpec,
+    first_input_date: Option<DateTime<Local>>,
+    deterministic: bool,
+) -> Option<DateTime<Utc>> {
+    match spec {
+        DateSpec::Now if deterministic => Some(source_date()),
+        DateSpec::Now => Some(Utc::now()),
+        DateSpec::FromFirstInput => {
+            if first_input_date.is_none() {
+                log::warning("the first input has no date, the output is left undated");
             }
-            b"Pages" => {
-                // Collect and update a first "Pages" object and use it for the future "Catalog"
-                // We have also to merge all dictionaries of the old and the new "Pages" object
-                if let Ok(dictionary) = object.as_dict() {
-                    let mut dictionary = dictionary.clone();
-                    if let Some((_, ref object)) = pages_object {
-                        if let Ok(old_dictionary) = object.as_dict() {
-                            dictionary.extend(old_dictionary);
-                        }
-                    }
+            first_input_date.map(|date| date.to_utc())
+        }
+        DateSpec::None => None,
+        DateSpec::At(date) => Some(date),
+    }
+}
 
-                    pages_object = Some((
-                        if let Some((id, _)) = pages_object {
-                            id
-                        } else {
-                            *object_id
-                        },
-                        Object::Dictionary(dictionary),
-                    ));
-                }
+// Sets the Producer, Creator and dates given on the command line, the output
+// having none of them otherwise.
+fn set_output_metadata(document: &mut Document, args: &Args, first_input_dates: &InputDates) {
+    for (key, value) in [("Producer", &args.producer), ("Creator", &args.creator)] {
+        if let Some(value) = value {
+            metadata::set_info_text(
+                document,
+                key,
+                Some(value.as_str()).filter(|value| !value.is_empty()),
+            );
+        }
+    }
+
+    for (key, spec, first_input_date) in [
+        (
+            "CreationDate",
+            args.creation_date.or(args.date),
+            first_input_dates.creation,
+        ),
+        (
+            "ModDate",
+            args.mod_date.or(args.date),
+            first_input_dates.modification,
+        ),
+    ] {
+        if let Some(spec) = spec {
+            let date = resolve_date(spec, first_input_date, args.deterministic);
+            metadata::set_info_date(document, key, date);
+        }
+    }
+}
+
+fn pick_output_file() -> PathBuf {
+    match gui::pick_output_file() {
+        Some(path) => {
+            log::info(&format!("Selected output file: {}", path.display()));
+            path
+        }
+        None => {
+            log::error("no output file was selected");
+            exit(1);
+        }
+    }
+}
+
+// Whether `paths` is an input directory followed by the output file.
+fn is_directory_and_output(paths: &[PathBuf]) -> bool {
+    let is_directory = |path: &PathBuf| path.is_dir() || s3::is_s3_prefix(path);
+    paths.len() == 2 && is_directory(&paths[0]) && !is_directory(&paths[1])
+}
+
+// Splits the positional arguments into inputs and output file:
+// - with `--output`, all of them are inputs,
+// - `<input_directory> <output_file>` is the classic command line usage,
+//   unless the second path is a directory too, e.g. two dropped folders,
+// - no argument at all opens a directory picker then a save dialog,
+// - anything else (e.g. files dropped onto the executable) is a list of
+//   inputs, and only the output file is asked for.
+fn resolve_paths(mut paths: Vec<PathBuf>, output: Option<PathBuf>) -> (Vec<PathBuf>, PathBuf) {
+    if !paths.is_empty()
+        && let Some(output_path) = output
+    {
+        return (paths, output_path);
+    }
+    if is_directory_and_output(&paths) {
+        let output_path = paths.pop().unwrap();
+        return (paths, output_path);
+    }
+
+    if paths.is_empty() {
+        // Open a directory picker dialog
+        match gui::pick_input_directory() {
+            Some(path) => {
+                log::info(&format!("Selected directory: {}", path.display()));
+                paths.push(path);
             }
-            b"Page" => {}     // Ignored, processed later and separately
-            b"Outlines" => {} // Ignored, not supported yet
-            b"Outline" => {}  // Ignored, not supported yet
-            _ => {
-                document.objects.insert(*object_id, object.clone());
+            None => {
+                log::error("no directory was selected");
+                exit(1);
             }
         }
     }
 
-    // If no "Pages" object found, abort.
-    if pages_object.is_none() {
-        println!("Pages root not found.");
+    (paths, output.unwrap_or_else(pick_output_file))
+}
 
-        return document;
+// With --quick-action, every path is an input and the only dialog is the save
+// dialog, opened next to them.
+fn quick_action_paths(paths: Vec<PathBuf>, output: Option<PathBuf>) -> (Vec<PathBuf>, PathBuf) {
+    if paths.is_empty() {
+        log::error("no file was selected");
+        exit(1);
     }
+    if let Some(output_path) = output {
+        return (paths, output_path);
+    }
+    match gui::pick_output_file_next_to(&paths) {
+        Some(output_path) => (paths, output_path),
+        None => {
+            log::error("no output file was selected");
+            exit(1);
+        }
+    }
+}
 
-    // Iterate over all "Page" objects and collect into the parent "Pages" created before
-    for (object_id, object) in documents_pages.iter() {
-        if let Ok(dictionary) = object.as_dict() {
-            let mut dictionary = dictionary.clone();
-            dictionary.set("Parent", pages_object.as_ref().unwrap().0);
-
-            document
-                .objects
-                .insert(*object_id, Object::Dictionary(dictionary));
+// Inputs that cannot be loaded, or time out, are skipped with a warning
+// rather than failing the whole merge, unless `strict` is set. So are the PDFs
+// left without pages once `skip_first` and `skip_last` are dropped.
+fn load_or_skip(
+    path: &Path,
+    load_options: &LoadOptions,
+    file_timeout: Option<Duration>,
+    strict: bool,
+) -> Result<Vec<LoadedInput>> {
+    let started = Instant::now();
+    let loaded = match &load_options.checkpoint {
+        Some(checkpoint) => checkpoint.load(path, load_options, file_timeout),
+        None => load_input_with_retries(path, load_options, file_timeout),
+    };
+    match loaded {
+        Ok(mut loaded) => {
+            let step = if !loaded.is_empty() && loaded.iter().all(LoadedInput::is_converted) {
+                Step::ImageConversion
+            } else {
+                Step::Load
+            };
+            stats::record(step, started.elapsed());
+            if let Ok(metadata) = fs::metadata(path) {
+                stats::add_input_bytes(metadata.len());
+            }
+            let (first, last) = (load_options.skip_first, load_options.skip_last);
+            if first > 0 || last > 0 {
+                loaded.retain_mut(|input| {
+                    if input.is_converted() {
+                        return true;
+                    }
+                    skip_pages(&mut input.document, first, last);
+                    let has_pages = !input.document.get_pages().is_empty();
+                    if !has_pages {
+                        log::file_skipped(
+                            &input.path,
+                            "no page left once the skipped pages are dropped",
+                        );
+                    }
+                    has_pages
+                });
+            }
+            for input in &loaded {
+                log::file_loaded(&input.path, input.document.get_pages().len());
+            }
+            Ok(loaded)
+        }
+        Err(e) if e.is::<Cancelled>() => exit_cancelled(),
+        Err(e) if strict => Err(e),
+        Err(e) => {
+            let reason = match e.downcast_ref::<TimedOut>() {
+                Some(timed_out) => timed_out.to_string(),
+                None => e.root_cause().to_string(),
+            };
+            log::file_skipped(path, &reason);
+            Ok(Vec::new())
         }
     }
+}
 
-    // If no "Catalog" found, abort.
-    if catalog_object.is_none() {
-        println!("Catalog root not found.");
+// Keeps only the pages whose text matches `pattern`, or with
+// `whole_documents` the inputs with such a page, and leaves out the others.
+fn keep_matching_pages(inputs: &mut Vec<LoadedInput>, pattern: &Regex, whole_documents: bool) {
+    inputs.retain_mut(|input| {
+        let matching = match input.document_with_contents() {
+            Ok(document) => matching_pages(&document, pattern),
+            Err(e) => {
+                log::error(&format!("{:#}", e));
+                exit(1);
+            }
+        };
+        if matching.is_empty() {
+            log::info(&format!(
+                "Left out {}, no page matches --grep",
+                input.path.display()
+            ));
+            return false;
+        }
+        if !whole_documents {
+            for (page_number, page_id) in input.document.get_pages() {
+                if !matching.contains(&page_number) {
+                    remove_page(&mut input.document, page_id);
+                }
+            }
+        }
+        true
+    });
+}
+
+// Shares of the work shown in the progress window of the GUI mode and in the
+// `--progress-json` events: loading the inputs up to the first, merging them
+// up to the second, then saving.
+const LOADING_PROGRESS_END: f64 = 0.5;
+const MERGING_PROGRESS_END: f64 = 0.9;
 
-        return document;
+fn show_progress(phase: &str, file: Option<&Path>, fraction: f64, text: &str) {
+    gui::show_progress(fraction, text);
+    log::progress(phase, file, fraction);
+}
+
+fn show_loading_progress(index: usize, total: usize, path: &Path) {
+    show_progress(
+        "loading",
+        Some(path),
+        LOADING_PROGRESS_END * index as f64 / total as f64,
+        &format!("Loading {}", path.display()),
+    );
+}
+
+fn show_merge_progress(progress: Progress) {
+    match progress {
+        Progress::Document { index, total } => show_progress(
+            "merging",
+            None,
+            LOADING_PROGRESS_END
+                + (MERGING_PROGRESS_END - LOADING_PROGRESS_END) * (index + 1) as f64 / total as f64,
+            &format!("Merging input {} of {}", index + 1, total),
+        ),
+        Progress::Phase(Phase::BuildingOutline) => show_progress(
+            "building_outline",
+            None,
+            MERGING_PROGRESS_END,
+            "Building the bookmarks",
+        ),
+        Progress::Phase(Phase::Compressing) => {
+            show_progress("compressing", None, MERGING_PROGRESS_END, "Compressing")
+        }
+        _ => {}
     }
+}
 
-    let catalog_object = catalog_object.unwrap();
-    let pages_object = pages_object.unwrap();
+// Exit status of a merge that succeeded without some of its inputs, for batch
+// jobs to flag.
+const EXIT_PARTIAL: i32 = 3;
 
-    // Build a new "Pages" with updated fields
-    if let Ok(dictionary) = pages_object.1.as_dict() {
-        let mut dictionary = dictionary.clone();
+// Exit status of a merge cancelled from the progress window, as for a command
+// interrupted with Ctrl+C.
+const EXIT_CANCELLED: i32 = 130;
 
-        // Set new pages count
-        dictionary.set("Count", documents_pages.len() as u32);
+fn exit_cancelled() -> ! {
+    log::info("Cancelled, the output was not written");
+    gui::close_progress_window();
+    exit(EXIT_CANCELLED);
+}
 
-        // Set new "Kids" list (collected from documents pages) for "Pages"
-        dictionary.set(
-            "Kids",
-            documents_pages
-                .into_iter()
-                .map(|(object_id, _)| Object::Reference(object_id))
-                .collect::<Vec<_>>(),
+// With `--strict`, the warnings and skipped files so far abort the merge.
+// Merging keeps the pages of signed documents but not their signatures, which
+// users must know before sending the output as if it were still signed.
+fn check_signatures(inputs: &[LoadedInput], fail_on_signed: bool) -> Result<()> {
+    let signed: Vec<String> = inputs
+        .iter()
+        .filter(|input| signature_count(&input.document) > 0)
+        .map(LoadedInput::file_name)
+        .collect();
+    if signed.is_empty() {
+        return Ok(());
+    }
+    if fail_on_signed {
+        bail!(
+            "{} digitally signed, aborting because of --fail-on-signed: {}",
+            if signed.len() == 1 {
+                "an input is"
+            } else {
+                "inputs are"
+            },
+            signed.join(", ")
         );
+    }
+    for name in &signed {
+        log::warning(&format!(
+            "{} is digitally signed; {}",
+            name.bold(),
+            "its signature will not be valid in the merged PDF".bold()
+        ));
+    }
+    if gui::is_active() {
+        gui::show_warning(&format!(
+            "These documents are digitally signed; their signatures will not be valid in the \
+             merged PDF:\n\n{}",
+            signed.join("\n")
+        ));
+    }
+    Ok(())
+}
 
-        document
-            .objects
-            .insert(pages_object.0, Object::Dictionary(dictionary));
+fn abort_on_anomalies(strict: bool) {
+    let anomalies = log::anomaly_count();
+    if strict && anomalies > 0 {
+        log::error(&format!(
+            "aborting because of --strict, after {} warnings",
+            anomalies
+        ));
+        exit(1);
     }
+}
 
-    // Build a new "Catalog" with updated fields
-    if let Ok(dictionary) = catalog_object.1.as_dict() {
-        let mut dictionary = dictionary.clone();
-        dictionary.set("Pages", pages_object.0);
-        dictionary.remove(b"Outlines"); // Outlines not supported in merged PDFs
+// A blank page, of the size images are converted to (A4 with `--page-size
+// auto`), without a bookmark.
+fn blank_input(load_options: &LoadOptions) -> Result<LoadedInput> {
+    let page_size = load_options.image.page_size.clone().unwrap_or(A4);
+    let mut input = LoadedInput::new(PathBuf::from("blank page"), blank_document(&page_size)?);
+    input.bookmark_mode = Some(BookmarkMode::None);
+    Ok(input)
+}
 
-        document
-            .objects
-            .insert(catalog_object.0, Object::Dictionary(dictionary));
+// With a plan, the inputs come from the plan and the only positional
+// argument is the output file.
+fn load_plan(
+    plan_path: &Path,
+    args: &Args,
+    load_options: &LoadOptions,
+    file_timeout: Option<Duration>,
+    mut budget: Option<&mut MemoryBudget>,
+) -> (Vec<LoadedInput>, PathBuf) {
+    let mut paths = args.paths.clone();
+    let output = args.output.clone();
+    let max_paths = if output.is_some() { 0 } else { 1 };
+    if paths.len() > max_paths {
+        log::error("only the output file can be given along with --plan");
+        exit(1);
     }
+    let output_path = output
+        .or_else(|| paths.pop())
+        .unwrap_or_else(pick_output_file);
 
-    document.trailer.set("Root", catalog_object.0);
+    let loaded = plan::read_plan(plan_path).and_then(|entries| {
+        let paths: Vec<PathBuf> = entries
+            .iter()
+            .filter(|entry| !entry.blank)
+            .map(|entry| entry.path.clone())
+            .collect();
+        check_free_space(&output_path, &paths, args.temp_dir.as_deref())?;
+        let mut inputs = Vec::new();
+        let total = entries.len();
+        for (index, entry) in entries.into_iter().enumerate() {
+            if entry.blank {
+                let mut input = blank_input(load_options)?;
+                if entry.bookmark_title.is_some() {
+                    input.bookmark_title = entry.bookmark_title;
+                    input.bookmark_style = entry.bookmark_style;
+                    input.bookmark_mode = None;
+                }
+                inputs.extend(std::iter::repeat_n(input, entry.repeat));
+                continue;
+            }
+            show_loading_progress(index, total, &entry.path);
+            let load_options = LoadOptions {
+                skip_first: entry.skip_first.unwrap_or(load_options.skip_first),
+                skip_last: entry.skip_last.unwrap_or(load_options.skip_last),
+                ..load_options.clone()
+            };
+            for mut input in load_or_skip(&entry.path, &load_options, file_timeout, args.strict)? {
+                let password = match (&entry.password, &entry.password_key) {
+                    (None, Some(key)) => Some(
+                        keyring::get(key)?
+                            .with_context(|| format!("no password stored under {}", key))?,
+                    ),
+                    (password, _) => password.clone(),
+                };
+                if let Some(password) = &password {
+                    decrypt(&mut input.document, password)
+                        .with_context(|| format!("cannot decrypt {}", input.path.display()))?;
+                }
+                if let Some(operations) = &entry.page_operations {
+                    operations.apply(&mut input.document);
+                }
+                if let Some(pages) = &entry.pages {
+                    select_pages(&mut input.document, pages);
+                }
+                if entry.rotation != 0 {
+                    rotate_document(&mut input.document, entry.rotation);
+                }
+                input.bookmark_title = entry.bookmark_title.clone();
+                input.bookmark_style = entry.bookmark_style;
+                inputs.extend(std::iter::repeat_n(input, entry.repeat));
+            }
+            if let Some(budget) = budget.as_deref_mut() {
+                budget.enforce(&mut inputs)?;
+            }
+        }
+        Ok(inputs)
+    });
+    match loaded {
+        Ok(inputs) => (inputs, output_path),
+        Err(e) => {
+            log::error(&format!("{:#}", e));
+            exit(1);
+        }
+    }
+}
 
-    // Update the max internal ID as wasn't updated before due to direct objects insertion
-    document.max_id = document.objects.len() as u32;
+fn main() {
+    let started = Instant::now();
+    // Paths are parsed as `OsString`s so that paths which are not valid UTF-8
+    // are still accepted.
+    let args = Args::parse();
 
-    // Reorder all new Document objects
-    document.renumber_objects();
+    if args.log_format == LogFormat::Json {
+        log::use_json_format();
+    }
+    if args.progress_json {
+        log::use_progress_json();
+    }
+    if args.notify {
+        notify::enable();
+    }
+    if let Some(log_file) = &args.log_file
+        && let Err(e) = log::open_log_file(log_file)
+    {
+        log::error(&format!("cannot open {}: {}", log_file.display(), e));
+        exit(1);
+    }
 
-    // Set any Bookmarks to the First child if they are not set to a page
-    document.adjust_zero_pages();
+    if let Some(Command::Serve {
+        listen,
+        root,
+        max_body_size,
+    }) = args.command
+    {
+        let options = server::ServeOptions {
+            root,
+            max_body_size,
+        };
+        if let Err(e) = server::serve(&listen, options) {
+            log::error(&format!("{:#}", e));
+            exit(1);
+        }
+        return;
+    }
 
-    // Set all bookmarks to the PDF Object tree then set the Outlines to the Bookmark content map.
-    if let Some(n) = document.build_outline() {
-        if let Ok(Object::Dictionary(dict)) = document.get_object_mut(catalog_object.0) {
-            dict.set("Outlines", Object::Reference(n));
+    if let Some(Command::StorePassword { key }) = &args.command {
+        if let Err(e) = store_password(key) {
+            log::error(&format!("{:#}", e));
+            exit(1);
         }
+        return;
     }
 
-    document.compress();
+    if let Some(Command::Compare { first, second }) = &args.command {
+        match compare::compare(first, second) {
+            Ok(differences) if differences.is_empty() => {
+                log::info("The documents match");
+            }
+            Ok(differences) => {
+                for difference in differences {
+                    println!("{}", difference);
+                }
+                exit(1);
+            }
+            Err(e) => {
+                log::error(&format!("{:#}", e));
+                exit(2);
+            }
+        }
+        return;
+    }
 
-    document
-}
+    if let Some(Command::InstallShellIntegration { remove }) = &args.command {
+        let (result, done) = if *remove {
+            (
+                shell_integration::uninstall(),
+                "Removed the shell integration",
+            )
+        } else {
+            (
+                shell_integration::install(),
+                "Installed the shell integration",
+            )
+        };
+        if let Err(e) = result {
+            log::error(&format!("{:#}", e));
+            exit(1);
+        }
+        log::info(done);
+        return;
+    }
 
-fn image_to_doc(path: PathBuf) -> Document {
-    //let pagesize = None;
-    let page_size_option = Some(PageSizeInMm(210.0, 297.0));
-
-    let doc = PdfDocument::empty("Random Document Title");
-    let input_img_file = path.to_str().unwrap();
-    let img_result = read_image_from_file(input_img_file);
-    if let Err(ref e) = img_result {
-        println!(
-            "{}: cannot read file {}. {}: {}",
-            "Warning".yellow(),
-            input_img_file.blue().underline(),
-            "Error".red(),
-            e
-        );
-        abort();
+    let checkpoint = match args
+        .checkpoint
+        .then(|| Checkpoint::new(args.temp_dir.as_deref()))
+    {
+        Some(Ok(checkpoint)) => Some(checkpoint),
+        Some(Err(e)) => {
+            log::error(&format!("cannot create the checkpoint directory: {}", e));
+            exit(1);
+        }
+        None => None,
     };
-    let (color_type, mut img) = img_result.unwrap();
-    if let Some(page_size) = &page_size_option {
-        let image_transform = get_image_transform_for_page_size(&page_size, &img.image);
-        let PageSizeInMm(width, height) = page_size;
-        let (page, layer_index) = doc.add_page(
-            Mm(width.to_owned() as f32),
-            Mm(height.to_owned() as f32),
-            "Layer1",
-        );
-        let current_layer = doc.get_page(page).get_layer(layer_index);
-        img.remove_alpha(color_type);
-        img.add_to_layer(current_layer.clone(), image_transform);
-    } else {
-        let (original_image_width, original_image_height) = get_image_dimension_in_mm(&img.image);
-
-        let image_scale = max(
-            1,
-            max(
-                (MIN_WIDTH_IN_MM / original_image_width) as i32,
-                (MIN_HEIGHT_IN_MM / original_image_height) as i32,
-            ),
-        ) as f64;
-        let (page, layer_index) = doc.add_page(
-            Mm((original_image_width * image_scale) as f32),
-            Mm((original_image_height * image_scale) as f32),
-            "Layer1",
-        );
-        let current_layer = doc.get_page(page).get_layer(layer_index);
-        img.remove_alpha(color_type);
-        img.add_to_layer(
-            current_layer.clone(),
-            ImageTransform {
-                scale_x: Some(image_scale as f32),
-                scale_y: Some(image_scale as f32),
-                ..Default::default()
+    let load_options = LoadOptions {
+        download: DownloadOptions {
+            max_size: args.download_max_size,
+            timeout: Duration::from_secs(args.download_timeout),
+        },
+        image: ImageOptions {
+            frame_step: args.animation_frames,
+            stretch: args.stretch_images,
+            scale: args.scale,
+            assume_dpi: args.assume_dpi,
+            page_size: match &args.page_size {
+                PageSize::Auto => None,
+                PageSize::Fixed(page_size) => Some(page_size.clone()),
             },
-        );
+        },
+        skip_first: args.skip_first,
+        skip_last: args.skip_last,
+        locked_wait: Duration::from_secs(args.locked_wait),
+        checkpoint,
     };
 
-    let bytes = doc.save_to_bytes();
-    let image_doc = Document::load_mem(bytes.unwrap().as_slice()).unwrap();
-    image_doc
-}
+    let file_timeout = args.file_timeout.map(Duration::from_secs);
 
-fn main() {
-    // Collect all command-line arguments into a vector
-    let args: Vec<String> = env::args().collect();
+    let scan_options = ScanOptions {
+        follow_symlinks: args.follow_symlinks,
+        recursive: args.recursive,
+        include_hidden: args.include_hidden,
+        max_file_size: args.max_file_size,
+        max_files: args.max_files,
+        modified_since: args.since,
+        modified_until: args.until,
+        stem_pattern: args.stem_pattern.clone(),
+        sniff: args.sniff,
+    };
 
-    // Check if the user requested help
-    if args.contains(&"--help".to_string()) || args.contains(&"-h".to_string()) {
-        usage();
-        process::exit(0); // Exit with a zero status code (successful termination)
+    if let Some(Command::Doctor {
+        dir,
+        recursive,
+        follow_symlinks,
+        include_hidden,
+        sniff,
+    }) = &args.command
+    {
+        let scan_options = ScanOptions {
+            recursive: *recursive,
+            follow_symlinks: *follow_symlinks,
+            include_hidden: *include_hidden,
+            sniff: *sniff,
+            ..scan_options
+        };
+        // Nothing is merged, there is nothing to resume.
+        let load_options = LoadOptions {
+            checkpoint: None,
+            ..load_options.clone()
+        };
+        match doctor::diagnose_directory(dir, &scan_options, &load_options, file_timeout) {
+            Ok(diagnoses) => {
+                let clean = diagnoses
+                    .iter()
+                    .filter(|diagnosis| diagnosis.problems.is_empty())
+                    .count();
+                for diagnosis in &diagnoses {
+                    if diagnosis.problems.is_empty() {
+                        println!(
+                            "{}: merges cleanly, {} pages",
+                            diagnosis.path.display(),
+                            diagnosis.pages
+                        );
+                    } else {
+                        println!("{}:", diagnosis.path.display());
+                        for problem in &diagnosis.problems {
+                            println!("  {}", problem);
+                        }
+                    }
+                }
+                log::info(&format!(
+                    "{} of {} files merge cleanly",
+                    clean,
+                    diagnoses.len()
+                ));
+                if clean < diagnoses.len() {
+                    exit(1);
+                }
+            }
+            Err(e) => {
+                log::error(&format!("{:#}", e));
+                exit(2);
+            }
+        }
+        return;
     }
 
-    let input_path;
-    let output_path;
+    let stamp_font = match args.stamp_font.as_ref().map(|path| {
+        fs::read(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|font| check_font(&font).map(|()| font))
+            .with_context(|| format!("cannot use the font {}", path.display()))
+    }) {
+        Some(Ok(font)) => Some(font),
+        Some(Err(e)) => {
+            log::error(&format!("{:#}", e));
+            exit(1);
+        }
+        None => None,
+    };
 
-    if args.len() != 3 {
-        // Open a directory picker dialog
-        match FileDialog::new().pick_folder() {
-            Some(path) => {
-                println!("Selected directory: {}", path.display());
-                input_path = path;
+    // The first page of each is drawn on every merged page.
+    let layers = [
+        (Layer::Background, &args.background),
+        (Layer::Overlay, &args.overlay),
+    ];
+    let mut layer_documents = Vec::new();
+    for (layer, path) in layers {
+        let Some(path) = path else {
+            continue;
+        };
+        match Document::load(path).with_context(|| format!("cannot read {}", path.display())) {
+            Ok(document) => layer_documents.push((layer, path, document)),
+            Err(e) => {
+                log::error(&format!("{:#}", e));
+                exit(1);
             }
-            None => {
-                eprintln!("No directory was selected.");
+        }
+    }
+
+    let annotation_filter = match args
+        .strip_annotations
+        .as_deref()
+        .map(AnnotationFilter::parse)
+        .transpose()
+    {
+        Ok(filter) => filter,
+        Err(e) => {
+            log::error(&format!("{:#}", e));
+            exit(1);
+        }
+    };
+
+    let mut budget = args
+        .max_memory
+        .map(|max_memory| MemoryBudget::new(max_memory, args.temp_dir.as_deref()));
+    let (mut inputs, output_path) = if let Some(plan_path) = &args.plan {
+        load_plan(
+            plan_path,
+            &args,
+            &load_options,
+            file_timeout,
+            budget.as_mut(),
+        )
+    } else {
+        let (input_paths, output_path) = if args.dry_run {
+            // Nothing is written: a trailing output file is dropped rather
+            // than asked for.
+            let mut paths = args.paths.clone();
+            if args.output.is_none() && is_directory_and_output(&paths) {
+                paths.pop();
+            }
+            (paths, PathBuf::new())
+        } else if args.quick_action {
+            quick_action_paths(args.paths.clone(), args.output.clone())
+        } else {
+            resolve_paths(args.paths.clone(), args.output.clone())
+        };
+        if args.interactive && input_paths.iter().any(|path| is_stdin(path)) {
+            log::error("--interactive reads its commands from stdin, which is already an input");
+            exit(1);
+        }
+        let input_files = match collect_inputs(&input_paths, &scan_options).and_then(|files| {
+            if !args.dry_run {
+                check_free_space(&output_path, &files, args.temp_dir.as_deref())?;
+            }
+            Ok(files)
+        }) {
+            Ok(files) => files,
+            Err(e) => {
+                log::error(&format!("{:#}", e));
                 exit(1);
             }
+        };
+
+        if args.dry_run {
+            preview::print_dry_run(&input_files);
+            exit(0);
         }
 
-        // Open a directory picker dialog
-        match FileDialog::new().save_file() {
-            Some(path) => {
-                println!("Selected directory: {}", path.display());
-                output_path = path;
+        let mut inputs: Vec<LoadedInput> = Vec::new();
+        let total = input_files.len();
+        for (index, path) in input_files.into_iter().enumerate() {
+            show_loading_progress(index, total, &path);
+            let loaded =
+                load_or_skip(&path, &load_options, file_timeout, args.strict).and_then(|loaded| {
+                    if args.recursive {
+                        let folders = bookmark_folders(&path, &input_paths);
+                        inputs.extend(loaded.into_iter().map(|input| LoadedInput {
+                            bookmark_folders: folders.clone(),
+                            ..input
+                        }));
+                    } else {
+                        inputs.extend(loaded);
+                    }
+                    budget
+                        .as_mut()
+                        .map_or(Ok(()), |budget| budget.enforce(&mut inputs))
+                });
+            if let Err(e) = loaded {
+                log::error(&format!("{:#}", e));
+                exit(1);
             }
-            None => {
-                eprintln!("No directory was selected.");
+        }
+        (inputs, output_path)
+    };
+
+    // The documents of --insert are extra documents, the pages skipped from
+    // every input, or not matching --grep, are kept in them.
+    let insert_load_options = LoadOptions {
+        skip_first: 0,
+        skip_last: 0,
+        ..load_options.clone()
+    };
+    let mut insertions = Vec::new();
+    for insertion in &args.insert {
+        match load_or_skip(
+            &insertion.path,
+            &insert_load_options,
+            file_timeout,
+            args.strict,
+        ) {
+            Ok(loaded) => insertions.push((insertion.position, loaded)),
+            Err(e) => {
+                log::error(&format!("{:#}", e));
                 exit(1);
             }
         }
-    } else {
-        // Extract the two arguments
-        let arg1 = &args[1];
-        let arg2 = &args[2];
-        input_path = Path::new(arg1.as_str()).to_path_buf();
-        output_path = Path::new(arg2.as_str()).to_path_buf();
     }
 
-    let input_path_glob = format!(
-        "{}/*.pdf",
-        input_path.canonicalize().unwrap().to_str().unwrap()
-    );
-    let mut input_documents: Vec<Document> = Vec::new();
-    for entry in glob(input_path_glob.as_str()).expect("Failed to read glob pattern") {
-        match entry {
-            Ok(path) => input_documents.push(Document::load(path).unwrap()),
-            Err(e) => println!("{:?}", e),
+    if let Err(e) =
+        password::unlock_inputs(&mut inputs, args.password_key.as_deref(), !args.no_prompt)
+            .and_then(|()| {
+                insertions.iter_mut().try_for_each(|(_, documents)| {
+                    password::unlock_inputs(
+                        documents,
+                        args.password_key.as_deref(),
+                        !args.no_prompt,
+                    )
+                })
+            })
+    {
+        log::error(&format!("{:#}", e));
+        exit(1);
+    }
+    // Encrypted inputs can only be spilled once they are decrypted.
+    if let Some(budget) = &mut budget
+        && let Err(e) = budget.enforce(&mut inputs)
+    {
+        log::error(&format!("{:#}", e));
+        exit(1);
+    }
+    // The text of encrypted inputs can only be searched once they are
+    // decrypted.
+    if let Some(pattern) = &args.grep {
+        keep_matching_pages(&mut inputs, pattern, args.grep_documents);
+    }
+    for &page in &args.blank_after {
+        match blank_input(&load_options) {
+            Ok(input) => insertions.push((InsertPosition::AfterPage(page), vec![input])),
+            Err(e) => {
+                log::error(&format!("{:#}", e));
+                exit(1);
+            }
         }
     }
+    inputs = expand_portfolios(inputs, args.portfolio);
+    if !args.repeat.is_empty() {
+        inputs = repeat_inputs(inputs, &args.repeat);
+    }
+    abort_on_anomalies(args.strict);
+    if inputs.is_empty() {
+        log::error("no input left to merge");
+        exit(1);
+    }
 
-    let input_path_glob_png = format!(
-        "{}/*.png",
-        input_path.canonicalize().unwrap().to_str().unwrap()
-    );
+    if let Err(e) = check_signatures(&inputs, args.fail_on_signed) {
+        log::error(&format!("{:#}", e));
+        exit(1);
+    }
 
-    for entry in glob(input_path_glob_png.as_str()).expect("Failed to read glob pattern") {
-        match entry {
-            Ok(path) => input_documents.push(image_to_doc(path)),
-            Err(e) => println!("{:?}", e),
+    if args.sort == SortOrder::PdfDate {
+        inputs = sort_pdfs_by_creation_date(inputs);
+    }
+
+    if !insertions.is_empty() {
+        inputs = match insert_inputs(inputs, insertions) {
+            Ok(inputs) => inputs,
+            Err(e) => {
+                log::error(&format!("{:#}", e));
+                exit(1);
+            }
+        };
+    }
+
+    if args.interactive {
+        match interactive::review_inputs(inputs) {
+            Ok(Some(reviewed)) => inputs = reviewed,
+            Ok(None) => {
+                log::error("merge aborted");
+                exit(1);
+            }
+            Err(e) => {
+                log::error(&format!("{}", e));
+                exit(1);
+            }
         }
     }
 
-    let input_path_glob_jpg = format!(
-        "{}/*.jpg",
-        input_path.canonicalize().unwrap().to_str().unwrap()
-    );
+    if let Some(max_pages) = args.max_pages_total {
+        let mut pages = 0;
+        let kept = inputs
+            .iter()
+            .take_while(|input| {
+                pages += input.document.get_pages().len();
+                pages <= max_pages
+            })
+            .count();
+        if kept < inputs.len() {
+            let left_out: Vec<String> = inputs[kept..]
+                .iter()
+                .map(|input| input.path.display().to_string())
+                .collect();
+            log::warning(&format!(
+                "Left out the last {} inputs to stay within {} pages (--max-pages-total): {}",
+                left_out.len(),
+                max_pages,
+                left_out.join(", ")
+            ));
+            inputs.truncate(kept);
+        }
+    }
 
-    for entry in glob(input_path_glob_jpg.as_str()).expect("Failed to read glob pattern") {
-        match entry {
-            Ok(path) => input_documents.push(image_to_doc(path)),
-            Err(e) => println!("{:?}", e),
+    // Taken before the inputs are consumed by the merge.
+    let first_input_dates = inputs
+        .first()
+        .map(|input| {
+            let creation = metadata::creation_date(&input.document);
+            InputDates {
+                creation,
+                modification: metadata::modification_date(&input.document).or(creation),
+            }
+        })
+        .unwrap_or_default();
+
+    let first_input_metadata = inputs
+        .first()
+        .filter(|_| args.inherit_metadata)
+        .map(|input| metadata::read_metadata(&input.document));
+
+    if args.stamp_source {
+        for input in &mut inputs {
+            let file_name = input.file_name();
+            stamp_text(&mut input.document, &file_name, stamp_font.as_deref());
         }
     }
 
-    let input_path_glob_jpeg = format!(
-        "{}/*.jpeg",
-        input_path.canonicalize().unwrap().to_str().unwrap()
-    );
+    if args.deterministic {
+        let date = source_date();
+        for input in inputs.iter_mut().filter(|input| input.is_converted()) {
+            metadata::set_dates(&mut input.document, date);
+        }
+    }
+
+    let index_sections: Vec<(String, usize)> = inputs
+        .iter()
+        .filter(|_| args.index_thumbnails)
+        .map(|input| {
+            let title = input
+                .bookmark_title
+                .clone()
+                .unwrap_or_else(|| input.file_name());
+            (title, input.document.get_pages().len())
+        })
+        .collect();
 
-    for entry in glob(input_path_glob_jpeg.as_str()).expect("Failed to read glob pattern") {
-        match entry {
-            Ok(path) => input_documents.push(image_to_doc(path)),
-            Err(e) => println!("{:?}", e),
+    let outline = args.outline.as_ref().map(|outline_path| {
+        let mut next_page = 1;
+        let first_pages: Vec<(PathBuf, usize)> = inputs
+            .iter()
+            .map(|input| {
+                let first_page = next_page;
+                next_page += input.document.get_pages().len();
+                (input.path.clone(), first_page)
+            })
+            .collect();
+        match read_outline_file(outline_path, &first_pages) {
+            Ok(outline) => outline,
+            Err(e) => {
+                log::error(&format!("{:#}", e));
+                exit(1);
+            }
+        }
+    });
+
+    // merge the pdfs
+    let input_count = inputs.len();
+    let compression = if args.debug_output {
+        CompressionLevel::None
+    } else {
+        args.compress
+    };
+    let mut merge_started = Instant::now();
+    let merged = {
+        let inputs: Vec<MergeInput> = inputs.into_iter().map(MergeInput::from).collect();
+        let mut merger = Merger::new(inputs)
+            .bookmark_style(BookmarkStyle {
+                color: args.bookmark_color,
+                font: args.bookmark_font,
+                open: None,
+            })
+            .bookmarks(args.bookmarks)
+            .destination_fit(args.bookmark_fit)
+            .outline_open_depth(args.bookmarks_open_depth)
+            .numbered_bookmarks(args.number_bookmarks)
+            .attachment_policy(args.attachments)
+            .open_action(args.keep_open_action)
+            .compression(compression)
+            .cancellation(gui::cancellation())
+            .on_progress(move |progress| {
+                // The merge ends where the compression of its output starts.
+                match progress {
+                    Progress::Phase(Phase::Compressing) => {
+                        stats::record(Step::Merge, merge_started.elapsed());
+                        merge_started = Instant::now();
+                    }
+                    Progress::Phase(Phase::Done) => {
+                        stats::record(Step::Compress, merge_started.elapsed());
+                    }
+                    _ => {}
+                }
+                show_merge_progress(progress);
+            });
+        if let Some(depth) = args.outline_depth {
+            merger = merger.outline_depth(depth);
+        }
+        if let Some(outline) = outline {
+            merger = merger.outline(outline);
         }
+        merger.merge()
+    };
+    let mut document = match merged {
+        Ok(document) => document,
+        Err(e) if e.is::<Cancelled>() => exit_cancelled(),
+        Err(e) => {
+            log::error(&format!("{:#}", e));
+            exit(1);
+        }
+    };
+    log::pages_merged(input_count, document.get_pages().len());
+    if let Some(page_size) = &args.force_size {
+        force_page_size(&mut document, page_size);
+    }
+    if let Some(max_size) = &args.max_page_size {
+        let shrunk = shrink_oversized_pages(&mut document, max_size);
+        log::info(&format!("Scaled down {} oversized pages", shrunk));
+    }
+    if !args.redact_pattern.is_empty() {
+        match redact(&mut document, &args.redact_pattern) {
+            Ok(redactions) => {
+                log::info(&format!(
+                    "Redacted {} matches on {} pages",
+                    redactions.matches, redactions.pages
+                ));
+                if !redactions.undecoded_pages.is_empty() {
+                    let pages: Vec<String> = redactions
+                        .undecoded_pages
+                        .iter()
+                        .map(u32::to_string)
+                        .collect();
+                    log::warning(&format!(
+                        "Pages {} have text in fonts without a known encoding, it may hold \
+                         unredacted matches",
+                        pages.join(", ")
+                    ));
+                }
+                for page in redactions.image_pages {
+                    log::warning(&format!(
+                        "Page {} has redacted text over an image, the image still shows what \
+                         the boxes hide",
+                        page
+                    ));
+                }
+            }
+            Err(e) => {
+                log::error(&format!("cannot redact the output: {:#}", e));
+                exit(1);
+            }
+        }
+    }
+    for (layer, path, layer_document) in layer_documents {
+        if let Err(e) = add_background(&mut document, layer_document, layer)
+            .with_context(|| format!("cannot use the first page of {}", path.display()))
+        {
+            log::error(&format!("{:#}", e));
+            exit(1);
+        }
+    }
+    if args.strip_thumbnails {
+        let removed = remove_thumbnails(&mut document);
+        log::info(&format!("Removed the thumbnails of {} pages", removed));
+    }
+    if let Some(filter) = &annotation_filter {
+        let removed = strip_annotations(&mut document, filter);
+        log::info(&format!("Removed {} annotations", removed));
+    }
+    if args.strip_links {
+        let removed = strip_external_links(&mut document);
+        log::info(&format!("Removed {} external links", removed));
+    }
+    if args.flatten_annotations {
+        let flattened = flatten_annotations(&mut document);
+        log::info(&format!("Flattened {} annotations", flattened));
+    }
+    if args.recompress_images {
+        let recompressed = recompress_images(&mut document, args.max_dpi);
+        log::info(&format!(
+            "Downsampled {} images to {} DPI",
+            recompressed, args.max_dpi
+        ));
     }
+    if args.index_thumbnails {
+        let entries = index_entries(&document, index_sections);
+        add_index_pages(&mut document, &entries, stamp_font.as_deref());
+        stats::time(Step::Compress, || {
+            compress_document(&mut document, compression)
+        });
+    }
+    if args.thumbnails {
+        let added = add_thumbnails(&mut document);
+        log::info(&format!("Embedded thumbnails for {} pages", added));
+    }
+    if let Some(first_input_metadata) = &first_input_metadata {
+        metadata::write_metadata(&mut document, first_input_metadata);
+    }
+    set_output_metadata(&mut document, &args, &first_input_dates);
+    if let Some(DocumentId(document_id)) = &args.document_id {
+        metadata::set_document_id(&mut document, document_id);
+    }
+    // Stripped annotations and links were only unlinked from their pages.
+    remove_unreferenced_objects(&mut document);
+    if args.debug_output {
+        prepare_debug_output(&mut document);
+    }
+    let permissions = Permissions {
+        print: !args.no_print,
+        copy: !args.no_copy,
+        modify: !args.no_modify,
+        annotate: args.allow_annotations,
+    };
+    if !args.encrypt_to.is_empty()
+        && let Err(e) = encrypt_to_certificates(
+            &mut document,
+            &args.encrypt_to,
+            args.encryption,
+            permissions,
+        )
+    {
+        log::error(&format!("{:#}", e));
+        exit(1);
+    }
+    if let Some(key) = &args.owner_password_key
+        && let Err(e) = keyring::get(key)
+            .and_then(|password| {
+                password.with_context(|| format!("no owner password stored under {}", key))
+            })
+            .and_then(|password| {
+                encrypt_with_owner_password(&mut document, &password, args.encryption, permissions)
+            })
+    {
+        log::error(&format!("{:#}", e));
+        exit(1);
+    }
+
+    abort_on_anomalies(args.strict);
+    // The steps since the merge cannot be cancelled, the save can.
+    let cancellation = gui::cancellation();
+    if cancellation.is_cancelled() {
+        exit_cancelled();
+    }
+    let written_pages = document.get_pages().len();
 
-    let input_path_glob_bmp = format!(
-        "{}/*.bmp",
-        input_path.canonicalize().unwrap().to_str().unwrap()
+    // Save the merged PDF.
+    show_progress(
+        "saving",
+        Some(&output_path),
+        MERGING_PROGRESS_END,
+        &format!("Saving {}", output_path.display()),
     );
+    let saving_started = Instant::now();
+    let saved_size = if s3::is_s3_url(&output_path) {
+        let mut bytes = Vec::new();
+        let uploaded = document
+            .save_to(&mut bytes)
+            .map_err(anyhow::Error::from)
+            .and_then(|()| {
+                if cancellation.is_cancelled() {
+                    return Err(Cancelled.into());
+                }
+                s3::upload(&output_path, &bytes)
+            });
+        if let Err(e) = uploaded {
+            if e.is::<Cancelled>() {
+                exit_cancelled();
+            }
+            log::error(&format!("{:#}", e));
+            exit(1);
+        }
+        bytes.len() as u64
+    } else {
+        if args.backup {
+            match backup_existing_output(&output_path) {
+                Ok(Some(backup_path)) => log::info(&format!(
+                    "Moved the previous output to {}",
+                    backup_path.display()
+                )),
+                Ok(None) => {}
+                Err(e) => {
+                    log::error(&format!("cannot back up {}: {}", output_path.display(), e));
+                    exit(1);
+                }
+            }
+        }
+        if let Err(e) = save_atomically_unless_cancelled(
+            &mut document,
+            &output_path,
+            args.temp_dir.as_deref(),
+            &cancellation,
+        ) {
+            if e.is::<Cancelled>() {
+                exit_cancelled();
+            }
+            log::error(&format!("{:#}", e));
+            exit(1);
+        }
+        fs::metadata(&output_path).map_or(0, |metadata| metadata.len())
+    };
+    stats::record(Step::Save, saving_started.elapsed());
+    if let Some(checkpoint) = &load_options.checkpoint {
+        checkpoint.remove_used_files();
+    }
+    log::saved(&output_path, saved_size);
 
-    for entry in glob(input_path_glob_bmp.as_str()).expect("Failed to read glob pattern") {
-        match entry {
-            Ok(path) => input_documents.push(image_to_doc(path)),
-            Err(e) => println!("{:?}", e),
+    if args.verify {
+        match verify_output(&output_path, written_pages) {
+            Ok(problems) if problems.is_empty() => {
+                log::info(&format!("Verified {}", output_path.display()))
+            }
+            Ok(problems) => {
+                for problem in problems {
+                    log::error(&format!("{}: {}", output_path.display(), problem));
+                }
+                exit(1);
+            }
+            Err(e) => {
+                log::error(&format!("{:#}", e));
+                exit(1);
+            }
         }
     }
 
-    // merge the pdfs
-    let mut document = merge_documents(input_documents);
+    if let Some(ChecksumAlgorithm::Sha256) = args.checksum
+        && let Err(e) = write_checksum(&output_path, args.checksum_file)
+    {
+        log::error(&format!("{:#}", e));
+        exit(1);
+    }
 
-    // Save the merged PDF.
-    document.save(output_path).unwrap();
+    log::progress("done", None, 1.0);
+    gui::close_progress_window();
+    log::summary(input_count, written_pages);
+    if args.stats {
+        stats::print(started.elapsed(), saved_size);
+    }
+    notify::send(&format!(
+        "Merged {} files, {} pages → {}",
+        input_count,
+        written_pages,
+        output_path
+            .file_name()
+            .unwrap_or(output_path.as_os_str())
+            .to_string_lossy()
+    ));
+    if args.open {
+        if s3::is_s3_url(&output_path) {
+            log::warning("--open cannot open an output uploaded to S3");
+        } else if let Err(e) = gui::open_in_default_viewer(&output_path) {
+            log::warning(&format!("cannot open {}: {}", output_path.display(), e));
+        }
+    }
+    if log::skipped_count() > 0 {
+        exit(EXIT_PARTIAL);
+    }
 }
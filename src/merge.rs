@@ -0,0 +1,1137 @@
+#[cfg(feature = "native")]
+use crate::spill::SpilledStreams;
+use crate::{
+    attachment::{AttachmentPolicy, Attachments, embedded_files},
+    compression::{CompressionLevel, compress_document},
+    dedup::deduplicate_streams,
+    gc::remove_unreferenced_objects,
+    outline::{OutlineEntry, OutlineItem, explicit_destination, read_outline},
+    page::{page_with_inherited, remove_inheritable},
+    parallel::*,
+    stamp::page_box,
+};
+#[cfg(feature = "native")]
+use anyhow::Context;
+use anyhow::{Result, bail};
+use lopdf::{Bookmark, Document, Object, ObjectId};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+    },
+};
+#[cfg(feature = "native")]
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+    process,
+};
+
+// A document to merge, either in memory or with its stream contents spilled
+// to disk until its turn comes.
+pub enum InputDocument {
+    Loaded(Box<Document>),
+    #[cfg(feature = "native")]
+    Spilled(Box<Document>, Arc<SpilledStreams>),
+}
+
+impl InputDocument {
+    fn is_spilled(&self) -> bool {
+        match self {
+            InputDocument::Loaded(_) => false,
+            #[cfg(feature = "native")]
+            InputDocument::Spilled(..) => true,
+        }
+    }
+}
+
+impl From<Document> for InputDocument {
+    fn from(document: Document) -> Self {
+        InputDocument::Loaded(Box::new(document))
+    }
+}
+
+// Color of the bookmarks unless styled otherwise.
+const DEFAULT_BOOKMARK_COLOR: [f32; 3] = [0.0, 0.0, 1.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontStyle {
+    Normal,
+    Bold,
+    Italic,
+    BoldItalic,
+}
+
+impl FontStyle {
+    pub fn parse(style: &str) -> Result<Self> {
+        Ok(match style.trim().to_ascii_lowercase().as_str() {
+            "normal" | "regular" => FontStyle::Normal,
+            "bold" => FontStyle::Bold,
+            "italic" => FontStyle::Italic,
+            "bold-italic" | "bold italic" | "italic-bold" => FontStyle::BoldItalic,
+            _ => bail!(
+                "unknown font style {:?}, expected normal, bold, italic or bold-italic",
+                style
+            ),
+        })
+    }
+
+    // Value of the `F` entry of outline items.
+    fn flags(self) -> u32 {
+        match self {
+            FontStyle::Normal => 0,
+            FontStyle::Italic => 1,
+            FontStyle::Bold => 2,
+            FontStyle::BoldItalic => 3,
+        }
+    }
+}
+
+// Parses a `#RRGGBB` color into the 0 to 1 components PDF uses.
+pub fn parse_color(color: &str) -> Result<[f32; 3]> {
+    let hex = color.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("invalid color {:?}, expected #RRGGBB", color);
+    }
+    let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap() as f32 / 255.0;
+    Ok([component(0), component(2), component(4)])
+}
+
+// Pages given a bookmark: the first page of every input, or every page, the
+// first one of each input carrying the outline of the input. `None` leaves
+// the output without outline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BookmarkMode {
+    #[default]
+    PerFile,
+    PerPage,
+    None,
+}
+
+impl BookmarkMode {
+    pub fn parse(mode: &str) -> Result<Self> {
+        Ok(match mode.trim().to_ascii_lowercase().as_str() {
+            "per-file" => BookmarkMode::PerFile,
+            "per-page" => BookmarkMode::PerPage,
+            "none" => BookmarkMode::None,
+            _ => bail!(
+                "unknown bookmark mode {:?}, expected per-file, per-page or none",
+                mode
+            ),
+        })
+    }
+}
+
+// How a bookmark shows its page: whole (`/Fit`), fitting its width (`/FitH`)
+// or at the zoom level of the viewer (`/XYZ` with a null zoom), the last two
+// scrolled to the top of the page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DestinationFit {
+    #[default]
+    Fit,
+    FitH,
+    Xyz,
+}
+
+impl DestinationFit {
+    pub fn parse(fit: &str) -> Result<Self> {
+        Ok(match fit.trim().to_ascii_lowercase().as_str() {
+            "fit" => DestinationFit::Fit,
+            "fith" | "fit-width" => DestinationFit::FitH,
+            "xyz" | "keep-zoom" => DestinationFit::Xyz,
+            _ => bail!("unknown destination {:?}, expected fit, fith or xyz", fit),
+        })
+    }
+
+    fn destination(self, page_id: ObjectId, [left, _, _, top]: [f32; 4]) -> Vec<Object> {
+        match self {
+            DestinationFit::Fit => vec![page_id.into(), Object::Name(b"Fit".to_vec())],
+            DestinationFit::FitH => vec![
+                page_id.into(),
+                Object::Name(b"FitH".to_vec()),
+                Object::Real(top),
+            ],
+            DestinationFit::Xyz => vec![
+                page_id.into(),
+                Object::Name(b"XYZ".to_vec()),
+                Object::Real(left),
+                Object::Real(top),
+                Object::Null,
+            ],
+        }
+    }
+}
+
+// Input whose `/OpenAction`, the view or the action run when the document is
+// opened, the merged document keeps. It keeps the catalog of the last input,
+// and so its open action, unless told otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OpenActionSource {
+    First,
+    #[default]
+    Last,
+    None,
+}
+
+impl OpenActionSource {
+    pub fn parse(source: &str) -> Result<Self> {
+        Ok(match source.trim().to_ascii_lowercase().as_str() {
+            "first" => OpenActionSource::First,
+            "last" => OpenActionSource::Last,
+            "none" => OpenActionSource::None,
+            _ => bail!(
+                "unknown open action source {:?}, expected first, last or none",
+                source
+            ),
+        })
+    }
+}
+
+// Appearance of a bookmark. Unset fields fall back to the merger's style, then
+// to blue, normal and the merger's open depth.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BookmarkStyle {
+    pub color: Option<[f32; 3]>,
+    pub font: Option<FontStyle>,
+    // Whether the entries under the bookmark are shown.
+    pub open: Option<bool>,
+}
+
+impl BookmarkStyle {
+    // The fields of `self`, falling back to those of `defaults`.
+    pub fn or(self, defaults: BookmarkStyle) -> Self {
+        BookmarkStyle {
+            color: self.color.or(defaults.color),
+            font: self.font.or(defaults.font),
+            open: self.open.or(defaults.open),
+        }
+    }
+}
+
+// A document to merge, with the title of the bookmark pointing to its first
+// page (`Page_<n>` when not set).
+pub struct MergeInput {
+    pub document: InputDocument,
+    pub bookmark_title: Option<String>,
+    pub bookmark_style: BookmarkStyle,
+    // Titles of the bookmarks the bookmark of the input goes under, outermost
+    // first, such as the folders of the input. Consecutive inputs share the
+    // bookmarks of the titles they have in common.
+    pub bookmark_folders: Vec<String>,
+    // Pages of the input given a bookmark, instead of the merger's mode.
+    pub bookmark_mode: Option<BookmarkMode>,
+}
+
+impl From<Document> for MergeInput {
+    fn from(document: Document) -> Self {
+        MergeInput {
+            document: document.into(),
+            bookmark_title: None,
+            bookmark_style: BookmarkStyle::default(),
+            bookmark_folders: Vec::new(),
+            bookmark_mode: None,
+        }
+    }
+}
+
+// Where a page of the merged document comes from: page `page` (counting from
+// 1) of the input at `input` (counting from 0).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageSource {
+    pub input: usize,
+    pub page: usize,
+}
+
+// Progress of a merge, reported to the callback of a `Merger`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    // A phase of the merge starts.
+    Phase(Phase),
+    // The input at `index` (counting from 0) out of `total` has been merged.
+    Document { index: usize, total: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    // Renumbering the objects of every input and collecting their pages,
+    // reported input by input with `Progress::Document`.
+    CollectingDocuments,
+    BuildingPageTree,
+    BuildingOutline,
+    Compressing,
+    Done,
+}
+
+// Shared flag to abort a merge from another thread: clones of a token are
+// cancelled together.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Error of a merge aborted through its `CancellationToken`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the merge was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+type ProgressCallback = Box<dyn FnMut(Progress) + Send>;
+
+// Merges documents into one, reporting its progress along the way:
+// `Merger::new(inputs).on_progress(|progress| ...).merge()`.
+pub struct Merger {
+    inputs: Vec<MergeInput>,
+    on_progress: Option<ProgressCallback>,
+    cancellation: Option<CancellationToken>,
+    bookmark_style: BookmarkStyle,
+    outline_open_depth: usize,
+    outline_depth: Option<usize>,
+    bookmarks: BookmarkMode,
+    destination_fit: DestinationFit,
+    outline: Option<Vec<OutlineEntry>>,
+    numbered_bookmarks: bool,
+    attachment_policy: AttachmentPolicy,
+    open_action: OpenActionSource,
+    temp_dir: Option<PathBuf>,
+    compression: CompressionLevel,
+    // Source of each page merged so far.
+    page_sources: Vec<PageSource>,
+}
+
+impl Merger {
+    pub fn new<I: Into<MergeInput>>(inputs: Vec<I>) -> Self {
+        Merger {
+            inputs: inputs.into_iter().map(Into::into).collect(),
+            on_progress: None,
+            cancellation: None,
+            bookmark_style: BookmarkStyle::default(),
+            outline_open_depth: 1,
+            outline_depth: None,
+            bookmarks: BookmarkMode::default(),
+            destination_fit: DestinationFit::default(),
+            outline: None,
+            numbered_bookmarks: false,
+            attachment_policy: AttachmentPolicy::default(),
+            open_action: OpenActionSource::default(),
+            temp_dir: None,
+            compression: CompressionLevel::default(),
+            page_sources: Vec::new(),
+        }
+    }
+
+    // Style of the bookmarks of the inputs that do not set their own.
+    pub fn bookmark_style(mut self, style: BookmarkStyle) -> Self {
+        self.bookmark_style = style;
+        self
+    }
+
+    // Number of outline levels shown expanded when the document is opened: 1
+    // (the default) opens the top-level entries and collapses nested ones, 0
+    // collapses everything.
+    pub fn outline_open_depth(mut self, depth: usize) -> Self {
+        self.outline_open_depth = depth;
+        self
+    }
+
+    // Which pages get a bookmark.
+    pub fn bookmarks(mut self, mode: BookmarkMode) -> Self {
+        self.bookmarks = mode;
+        self
+    }
+
+    // How the pages are shown when following the bookmarks.
+    pub fn destination_fit(mut self, fit: DestinationFit) -> Self {
+        self.destination_fit = fit;
+        self
+    }
+
+    // Number of outline levels kept, counting the bookmarks of the inputs as
+    // the first one: 1 drops the outlines of the inputs, carried over under
+    // their bookmark otherwise.
+    pub fn outline_depth(mut self, depth: usize) -> Self {
+        self.outline_depth = Some(depth);
+        self
+    }
+
+    // Bookmarks replacing the generated ones and the outlines of the inputs.
+    pub fn outline(mut self, entries: Vec<OutlineEntry>) -> Self {
+        self.outline = Some(entries);
+        self
+    }
+
+    // Prefixes the bookmark titles with their number in the outline: 1, 1.1,
+    // 1.2, 2...
+    pub fn numbered_bookmarks(mut self, numbered: bool) -> Self {
+        self.numbered_bookmarks = numbered;
+        self
+    }
+
+    // What to do with attachments of several inputs sharing a name.
+    pub fn attachment_policy(mut self, policy: AttachmentPolicy) -> Self {
+        self.attachment_policy = policy;
+        self
+    }
+
+    // Input whose open action the merged document keeps.
+    pub fn open_action(mut self, source: OpenActionSource) -> Self {
+        self.open_action = source;
+        self
+    }
+
+    // Effort put in compressing the streams of the merged document.
+    pub fn compression(mut self, level: CompressionLevel) -> Self {
+        self.compression = level;
+        self
+    }
+
+    // Directory of the temporary file written by `merge_to_file`, instead of
+    // the directory of the output.
+    pub fn temp_dir(mut self, dir: PathBuf) -> Self {
+        self.temp_dir = Some(dir);
+        self
+    }
+
+    pub fn on_progress(mut self, callback: impl FnMut(Progress) + Send + 'static) -> Self {
+        self.on_progress = Some(Box::new(callback));
+        self
+    }
+
+    // Sends the progress to a channel, for front-ends updating their display
+    // from another thread. A disconnected receiver does not stop the merge.
+    pub fn progress_channel(self, sender: Sender<Progress>) -> Self {
+        self.on_progress(move |progress| {
+            let _ = sender.send(progress);
+        })
+    }
+
+    // The merge stops at the next check point once the token is cancelled:
+    // between two inputs or two phases.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    // Reports the progress, then checks for a cancellation.
+    fn report(&mut self, progress: Progress) -> Result<(), Cancelled> {
+        if let Some(callback) = &mut self.on_progress {
+            callback(progress);
+        }
+        if self.is_cancelled() {
+            return Err(Cancelled);
+        }
+        Ok(())
+    }
+
+    // Everything held by the merger is dropped when it is cancelled, in which
+    // case the error is a `Cancelled`.
+    pub fn merge(self) -> Result<Document> {
+        self.merge_with_sources().map(|(document, _)| document)
+    }
+
+    // Like `merge`, along with the source of every page of the merged
+    // document, in order.
+    pub fn merge_with_sources(mut self) -> Result<(Document, Vec<PageSource>)> {
+        let document = self.merge_inputs()?;
+        self.report(Progress::Phase(Phase::Done))?;
+        Ok((document, std::mem::take(&mut self.page_sources)))
+    }
+
+    // Merges then saves to `path`, which is left untouched when the merge is
+    // cancelled or the write fails.
+    #[cfg(feature = "native")]
+    pub fn merge_to_file(self, path: &Path) -> Result<()> {
+        let cancellation = self.cancellation.clone();
+        let temp_dir = self.temp_dir.clone();
+        let mut document = self.merge()?;
+
+        write_atomically(path, temp_dir.as_deref(), |file| {
+            document.save_to(file)?;
+            match &cancellation {
+                Some(token) if token.is_cancelled() => Err(Cancelled.into()),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn merge_inputs(&mut self) -> Result<Document> {
+        // Define a starting `max_id` (will be used as start index for object_ids).
+        let mut max_id = 1;
+        let mut pagenum = 1;
+        // The objects of the inputs are moved into the merged document as
+        // they come. The pages are listed in order for the new page tree, and
+        // the first "Catalog" and "Pages" are kept aside to become its root.
+        let mut page_ids = Vec::new();
+        let mut catalog_object: Option<(ObjectId, Object)> = None;
+        let mut pages_object: Option<(ObjectId, Object)> = None;
+        let mut document = Document::with_version("1.5");
+
+        self.report(Progress::Phase(Phase::CollectingDocuments))?;
+        let inputs = std::mem::take(&mut self.inputs);
+        let total = inputs.len();
+        // Open state of each top-level bookmark, in order.
+        let mut bookmarks_open = Vec::new();
+        let outline_levels = self
+            .outline_depth
+            .map_or(usize::MAX, |depth| depth.saturating_sub(1));
+        let mut attachments = Attachments::new(self.attachment_policy);
+        let mut first_open_action = None;
+        // Folder bookmarks of the previous input, with their bookmark ids.
+        let mut folders: Vec<(String, u32)> = Vec::new();
+        // Loading and renumbering the inputs are done on worker threads, a
+        // batch of inputs at a time. A spilled input is read back alone, so
+        // that the merge holds a single one besides the output.
+        let batch_size = current_num_threads().max(1);
+        let mut inputs = inputs.into_iter().peekable();
+        let mut index = 0;
+        while let Some(first) = inputs.next() {
+            let mut batch = vec![first];
+            if !batch[0].document.is_spilled() {
+                while batch.len() < batch_size
+                    && let Some(input) = inputs.next_if(|input| !input.document.is_spilled())
+                {
+                    batch.push(input);
+                }
+            }
+            for input in prepare_batch(batch, &mut max_id)? {
+                let PreparedInput {
+                    document: doc,
+                    outline,
+                    attachments: input_attachments,
+                    open_action,
+                    pages,
+                    bookmark_title,
+                    bookmark_style,
+                    bookmark_folders,
+                    bookmark_mode,
+                } = input;
+                attachments.add(input_attachments)?;
+                if index == 0 {
+                    first_open_action = open_action;
+                }
+                let bookmark_mode = if self.outline.is_some() {
+                    BookmarkMode::None
+                } else {
+                    bookmark_mode.unwrap_or(self.bookmarks)
+                };
+                let bookmark_style = bookmark_style.or(self.bookmark_style);
+
+                let title = bookmark_title.unwrap_or_else(|| format!("Page_{}", pagenum));
+                if !pages.is_empty() && bookmark_mode != BookmarkMode::None {
+                    pagenum += 1;
+                }
+                let parent = match pages.first() {
+                    Some(&(first_page, _)) if bookmark_mode != BookmarkMode::None => {
+                        let shared = folders
+                            .iter()
+                            .zip(&bookmark_folders)
+                            .take_while(|((title, _), folder)| title == *folder)
+                            .count();
+                        folders.truncate(shared);
+                        for folder in &bookmark_folders[shared..] {
+                            let bookmark = Bookmark::new(
+                                folder.clone(),
+                                self.bookmark_style.color.unwrap_or(DEFAULT_BOOKMARK_COLOR),
+                                self.bookmark_style.font.map_or(0, FontStyle::flags),
+                                first_page,
+                            );
+                            let parent = folders.last().map(|&(_, id)| id);
+                            let bookmark_id = document.add_bookmark(bookmark, parent);
+                            if parent.is_none() {
+                                bookmarks_open.push(self.bookmark_style.open);
+                            }
+                            folders.push((folder.clone(), bookmark_id));
+                        }
+                        folders.last().map(|&(_, id)| id)
+                    }
+                    _ => None,
+                };
+                for (page_index, &(object_id, _)) in pages.iter().enumerate() {
+                    let page_title = match bookmark_mode {
+                        BookmarkMode::PerFile | BookmarkMode::PerPage if page_index == 0 => {
+                            Some(title.clone())
+                        }
+                        BookmarkMode::PerPage => {
+                            Some(format!("{}, page {}", title, page_index + 1))
+                        }
+                        _ => None,
+                    };
+                    if let Some(page_title) = page_title {
+                        let bookmark = Bookmark::new(
+                            page_title,
+                            bookmark_style.color.unwrap_or(DEFAULT_BOOKMARK_COLOR),
+                            bookmark_style.font.map_or(0, FontStyle::flags),
+                            object_id,
+                        );
+                        let bookmark_id = document.add_bookmark(bookmark, parent);
+                        if page_index == 0 {
+                            add_outline_items(&mut document, &outline, bookmark_id, outline_levels);
+                        }
+                        if parent.is_none() {
+                            bookmarks_open.push(bookmark_style.open);
+                        }
+                    }
+                }
+                collect_objects(
+                    &mut document,
+                    doc.objects,
+                    &mut catalog_object,
+                    &mut pages_object,
+                );
+                self.page_sources
+                    .extend((1..=pages.len()).map(|page| PageSource { input: index, page }));
+                for (page_id, page) in pages {
+                    page_ids.push(page_id);
+                    document.objects.insert(page_id, page);
+                }
+                self.report(Progress::Document { index, total })?;
+                index += 1;
+            }
+        }
+
+        if let Some(entries) = &self.outline {
+            add_outline_entries(&mut document, entries, None, &page_ids, self.bookmark_style)?;
+        }
+
+        self.report(Progress::Phase(Phase::BuildingPageTree))?;
+
+        if pages_object.is_none() {
+            bail!("none of the documents has a page tree");
+        }
+
+        // Attach all "Page" objects to the parent "Pages" found before
+        let pages_id = pages_object.as_ref().unwrap().0;
+        for page_id in &page_ids {
+            if let Ok(page) = document.get_dictionary_mut(*page_id) {
+                page.set("Parent", pages_id);
+            }
+        }
+
+        if catalog_object.is_none() {
+            bail!("none of the documents has a catalog");
+        }
+
+        let catalog_object = catalog_object.unwrap();
+        let pages_object = pages_object.unwrap();
+
+        // Build a new "Pages" with updated fields
+        if let Object::Dictionary(mut dictionary) = pages_object.1 {
+            // The attributes of the first input's tree would apply to every
+            // page that does not set its own, when all of them now do.
+            remove_inheritable(&mut dictionary);
+
+            // Set new pages count
+            dictionary.set("Count", page_ids.len() as u32);
+
+            // Set new "Kids" list (collected from documents pages) for "Pages"
+            dictionary.set(
+                "Kids",
+                page_ids
+                    .into_iter()
+                    .map(Object::Reference)
+                    .collect::<Vec<_>>(),
+            );
+
+            document
+                .objects
+                .insert(pages_object.0, Object::Dictionary(dictionary));
+        }
+
+        // Build a new "Catalog" with updated fields
+        if let Object::Dictionary(mut dictionary) = catalog_object.1 {
+            dictionary.set("Pages", pages_object.0);
+            dictionary.remove(b"Outlines"); // Rebuilt from the bookmarks below
+            attachments.set(&mut document, &mut dictionary);
+            match (self.open_action, first_open_action) {
+                (OpenActionSource::First, Some(open_action)) => {
+                    dictionary.set("OpenAction", open_action)
+                }
+                (OpenActionSource::First | OpenActionSource::None, _) => {
+                    dictionary.remove(b"OpenAction");
+                }
+                (OpenActionSource::Last, _) => {}
+            }
+
+            document
+                .objects
+                .insert(catalog_object.0, Object::Dictionary(dictionary));
+        }
+
+        document.trailer.set("Root", catalog_object.0);
+
+        deduplicate_streams(&mut document);
+
+        // Update the max internal ID as wasn't updated before due to direct objects insertion
+        document.max_id = document.objects.len() as u32;
+
+        // Reorder all new Document objects
+        document.renumber_objects();
+
+        // Set any Bookmarks to the First child if they are not set to a page
+        document.adjust_zero_pages();
+
+        self.report(Progress::Phase(Phase::BuildingOutline))?;
+        {
+            let _span = crate::trace_span!("build_outline");
+            if self.numbered_bookmarks {
+                let bookmarks = document.bookmarks.clone();
+                number_bookmarks(&mut document, &bookmarks, "");
+            }
+            // Set all bookmarks to the PDF Object tree then set the Outlines to the Bookmark content map.
+            if let Some(n) = document.build_outline()
+                && let Ok(Object::Dictionary(dict)) = document.get_object_mut(catalog_object.0)
+            {
+                dict.set("Outlines", Object::Reference(n));
+                set_outline_open_state(&mut document, n, &bookmarks_open, self.outline_open_depth);
+                let first = outline_link(&document, n, b"First");
+                set_destination_fit(&mut document, first, self.destination_fit);
+            }
+        }
+
+        // The outlines of the inputs, and whatever the inputs held unreferenced.
+        remove_unreferenced_objects(&mut document);
+
+        self.report(Progress::Phase(Phase::Compressing))?;
+        compress_document(&mut document, self.compression);
+
+        Ok(document)
+    }
+}
+
+// Moves the objects of an input into the merged document, except for its page
+// tree and its outline which are rebuilt. The first "Catalog" and "Pages" are
+// kept aside instead: the catalog with the fields of the last one, the pages
+// root merging the fields of all of them.
+fn collect_objects(
+    document: &mut Document,
+    objects: BTreeMap<ObjectId, Object>,
+    catalog_object: &mut Option<(ObjectId, Object)>,
+    pages_object: &mut Option<(ObjectId, Object)>,
+) {
+    for (object_id, object) in objects {
+        match object.type_name().unwrap_or(b"") {
+            b"Catalog" => {
+                let id = catalog_object.as_ref().map_or(object_id, |&(id, _)| id);
+                *catalog_object = Some((id, object));
+            }
+            b"Pages" => {
+                if let Object::Dictionary(mut dictionary) = object {
+                    let id = match pages_object.take() {
+                        Some((id, Object::Dictionary(old_dictionary))) => {
+                            dictionary.extend(&old_dictionary);
+                            id
+                        }
+                        Some((id, _)) => id,
+                        None => object_id,
+                    };
+                    *pages_object = Some((id, Object::Dictionary(dictionary)));
+                }
+            }
+            // Pages are moved separately, with what they inherit.
+            b"Page" => {}
+            b"Outlines" | b"Outline" => {}
+            _ => {
+                document.objects.insert(object_id, object);
+            }
+        }
+    }
+}
+
+// An input once loaded and renumbered, with what the merge takes from it.
+struct PreparedInput {
+    document: Document,
+    outline: Vec<OutlineItem>,
+    attachments: Vec<(String, Object)>,
+    open_action: Option<Object>,
+    // The pages in order, with what they inherit from their own page tree
+    // copied into them as they are moved to the root of the merged one.
+    pages: Vec<(ObjectId, Object)>,
+    bookmark_title: Option<String>,
+    bookmark_style: BookmarkStyle,
+    bookmark_folders: Vec<String>,
+    bookmark_mode: Option<BookmarkMode>,
+}
+
+// Loads the inputs of a batch and renumbers their objects from `max_id` on, in
+// parallel, then moves `max_id` past them. `renumber_objects_with` numbers the
+// objects of a document one after the other, which gives the range of each
+// input from the number of objects of the previous ones.
+fn prepare_batch(batch: Vec<MergeInput>, max_id: &mut u32) -> Result<Vec<PreparedInput>> {
+    let mut prepared = batch
+        .into_par_iter()
+        .map(|input| {
+            Ok(PreparedInput {
+                document: match input.document {
+                    InputDocument::Loaded(doc) => *doc,
+                    #[cfg(feature = "native")]
+                    InputDocument::Spilled(mut doc, streams) => {
+                        streams.restore(&mut doc)?;
+                        *doc
+                    }
+                },
+                outline: Vec::new(),
+                attachments: Vec::new(),
+                open_action: None,
+                pages: Vec::new(),
+                bookmark_title: input.bookmark_title,
+                bookmark_style: input.bookmark_style,
+                bookmark_folders: input.bookmark_folders,
+                bookmark_mode: input.bookmark_mode,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let start_ids: Vec<u32> = prepared
+        .iter()
+        .map(|input| {
+            let start_id = *max_id;
+            *max_id += input.document.objects.len() as u32;
+            start_id
+        })
+        .collect();
+    prepared
+        .par_iter_mut()
+        .zip(start_ids)
+        .for_each(|(input, start_id)| {
+            let _span = crate::trace_span!("renumber", objects = input.document.objects.len());
+            let document = &mut input.document;
+            document.renumber_objects_with(start_id);
+            input.outline = read_outline(document);
+            input.attachments = embedded_files(document);
+            input.open_action = open_action(document);
+            input.pages = document
+                .get_pages()
+                .into_values()
+                .map(|page_id| {
+                    let page = page_with_inherited(document, page_id)
+                        .map(Object::Dictionary)
+                        .unwrap_or_else(|| document.get_object(page_id).unwrap().to_owned());
+                    (page_id, page)
+                })
+                .collect();
+        });
+    Ok(prepared)
+}
+
+// The open action of an input, with a named destination replaced by the
+// explicit one it names, as only the names of the last input are kept.
+fn open_action(doc: &Document) -> Option<Object> {
+    let open_action = doc.catalog().ok()?.get(b"OpenAction").ok()?;
+    match doc.dereference(open_action).ok()?.1 {
+        Object::Dictionary(action) => {
+            let mut action = action.clone();
+            if action.get(b"S").and_then(Object::as_name).ok() == Some(b"GoTo")
+                && let Some(destination) = action
+                    .get(b"D")
+                    .ok()
+                    .and_then(|destination| explicit_destination(doc, destination))
+            {
+                action.set("D", destination);
+            }
+            Some(Object::Dictionary(action))
+        }
+        destination => explicit_destination(doc, destination),
+    }
+}
+
+// Adds the outline `items` of an input under its bookmark `parent`, down to
+// `levels` levels.
+fn add_outline_items(document: &mut Document, items: &[OutlineItem], parent: u32, levels: usize) {
+    if levels == 0 {
+        return;
+    }
+    for item in items {
+        let bookmark = Bookmark::new(item.title.clone(), item.color, item.format, item.page_id);
+        let bookmark_id = document.add_bookmark(bookmark, Some(parent));
+        add_outline_items(document, &item.children, bookmark_id, levels - 1);
+    }
+}
+
+fn add_outline_entries(
+    document: &mut Document,
+    entries: &[OutlineEntry],
+    parent: Option<u32>,
+    page_ids: &[ObjectId],
+    style: BookmarkStyle,
+) -> Result<()> {
+    for entry in entries {
+        let Some(&page_id) = entry
+            .page
+            .checked_sub(1)
+            .and_then(|index| page_ids.get(index))
+        else {
+            bail!(
+                "the outline entry \"{}\" points to page {} of a document of {} pages",
+                entry.title,
+                entry.page,
+                page_ids.len()
+            );
+        };
+        let bookmark = Bookmark::new(
+            entry.title.clone(),
+            style.color.unwrap_or(DEFAULT_BOOKMARK_COLOR),
+            style.font.map_or(0, FontStyle::flags),
+            page_id,
+        );
+        let bookmark_id = document.add_bookmark(bookmark, parent);
+        add_outline_entries(
+            document,
+            &entry.children,
+            Some(bookmark_id),
+            page_ids,
+            style,
+        )?;
+    }
+    Ok(())
+}
+
+// Prefixes the titles of `bookmarks` and their children with their number,
+// under the number `prefix` of their parent.
+fn number_bookmarks(document: &mut Document, bookmarks: &[u32], prefix: &str) {
+    for (index, id) in bookmarks.iter().enumerate() {
+        let number = format!("{}{}", prefix, index + 1);
+        let Some(bookmark) = document.bookmark_table.get_mut(id) else {
+            continue;
+        };
+        bookmark.title = format!("{} {}", number, bookmark.title);
+        let children = bookmark.children.clone();
+        number_bookmarks(document, &children, &format!("{}.", number));
+    }
+}
+
+fn outline_link(doc: &Document, item_id: ObjectId, key: &[u8]) -> Option<ObjectId> {
+    doc.get_dictionary(item_id)
+        .and_then(|item| item.get(key))
+        .and_then(Object::as_reference)
+        .ok()
+}
+
+// Rewrites the `/Fit` destinations of the `GoTo` actions of the outline items
+// made by `Document::build_outline`, from the level starting with `first` on.
+fn set_destination_fit(doc: &mut Document, first: Option<ObjectId>, fit: DestinationFit) {
+    if fit == DestinationFit::Fit {
+        return;
+    }
+    let mut item = first;
+    while let Some(item_id) = item {
+        let action_id = outline_link(doc, item_id, b"A");
+        let page_id = action_id
+            .and_then(|id| doc.get_dictionary(id).ok())
+            .and_then(|action| action.get(b"D").and_then(Object::as_array).ok())
+            .and_then(|destination| destination.first()?.as_reference().ok());
+        if let (Some(action_id), Some(page_id)) = (action_id, page_id) {
+            let destination = fit.destination(page_id, page_box(doc, page_id));
+            if let Ok(action) = doc.get_dictionary_mut(action_id) {
+                action.set("D", destination);
+            }
+        }
+        set_destination_fit(doc, outline_link(doc, item_id, b"First"), fit);
+        item = outline_link(doc, item_id, b"Next");
+    }
+}
+
+// Sets the `Count` of the outline items from the level `depth` on, opening or
+// collapsing them, and returns how many items of the level are visible when
+// their parent is open. `open` overrides the state of the items in order.
+fn set_level_open_state(
+    doc: &mut Document,
+    first: Option<ObjectId>,
+    depth: usize,
+    open: &[Option<bool>],
+    open_depth: usize,
+) -> i64 {
+    let mut visible = 0;
+    let mut item = first;
+    let mut index = 0;
+    while let Some(item_id) = item {
+        visible += 1;
+        if let Some(first_child) = outline_link(doc, item_id, b"First") {
+            let descendants =
+                set_level_open_state(doc, Some(first_child), depth + 1, &[], open_depth);
+            let is_open = open
+                .get(index)
+                .copied()
+                .flatten()
+                .unwrap_or(depth <= open_depth);
+            if is_open {
+                visible += descendants;
+            }
+            if let Ok(dictionary) = doc.get_dictionary_mut(item_id) {
+                dictionary.set("Count", if is_open { descendants } else { -descendants });
+            }
+        }
+        item = outline_link(doc, item_id, b"Next");
+        index += 1;
+    }
+    visible
+}
+
+fn set_outline_open_state(
+    doc: &mut Document,
+    outlines_id: ObjectId,
+    open: &[Option<bool>],
+    open_depth: usize,
+) {
+    let first = outline_link(doc, outlines_id, b"First");
+    let visible = set_level_open_state(doc, first, 1, open, open_depth);
+    if let Ok(outlines) = doc.get_dictionary_mut(outlines_id) {
+        outlines.set("Count", visible);
+    }
+}
+
+#[cfg(feature = "native")]
+// Moves a file, copying it when the destination is on another file system.
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to).with_context(|| format!("cannot copy to {}", to.display()))?;
+    File::open(to)?.sync_all()?;
+    fs::remove_file(from)?;
+    Ok(())
+}
+
+#[cfg(feature = "native")]
+// Writes to a temporary file in the destination directory, renamed into place
+// once complete: a crash or a full disk never leaves a truncated output for
+// downstream systems to pick up. With `temp_dir`, the file is written there
+// and copied next to the destination in one go once complete, for
+// destinations on slow network shares.
+fn write_atomically(
+    path: &Path,
+    temp_dir: Option<&Path>,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<()>,
+) -> Result<()> {
+    let _span = crate::trace_span!("save", path = %path.display());
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("{} is not a file path", path.display()))?;
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.tmp", process::id()));
+    let temp_path = path.with_file_name(&temp_name);
+    let staging_path = temp_dir.map(|dir| dir.join(&temp_name));
+    let write_path = staging_path.as_deref().unwrap_or(&temp_path);
+
+    let written = File::create(write_path)
+        .with_context(|| format!("cannot create {}", write_path.display()))
+        .and_then(|file| {
+            let mut file = BufWriter::new(file);
+            write(&mut file)?;
+            let file = file.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+            Ok(())
+        })
+        .and_then(|()| match &staging_path {
+            Some(staging_path) => move_file(staging_path, &temp_path),
+            None => Ok(()),
+        })
+        .and_then(|()| {
+            fs::rename(&temp_path, path).with_context(|| format!("cannot write {}", path.display()))
+        });
+    if written.is_err() {
+        if let Some(staging_path) = &staging_path {
+            let _ = fs::remove_file(staging_path);
+        }
+        let _ = fs::remove_file(&temp_path);
+    }
+    written
+}
+
+#[cfg(feature = "native")]
+// Saves to `path` through a temporary file, in `temp_dir` if given.
+pub fn save_atomically(
+    document: &mut Document,
+    path: &Path,
+    temp_dir: Option<&Path>,
+) -> Result<()> {
+    write_atomically(path, temp_dir, |file| Ok(document.save_to(file)?))
+}
+
+#[cfg(feature = "native")]
+// Saves like `save_atomically`, leaving `path` untouched when `cancellation`
+// is cancelled before the end of the write.
+pub fn save_atomically_unless_cancelled(
+    document: &mut Document,
+    path: &Path,
+    temp_dir: Option<&Path>,
+    cancellation: &CancellationToken,
+) -> Result<()> {
+    write_atomically(path, temp_dir, |file| {
+        document.save_to(file)?;
+        if cancellation.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        Ok(())
+    })
+}
+
+// Panics when the documents cannot be merged, e.g. when there are none or a
+// spilled input cannot be read back: use a `Merger` to handle these errors.
+pub fn merge_documents<I: Into<MergeInput>>(input_documents: Vec<I>) -> Document {
+    Merger::new(input_documents)
+        .merge()
+        .expect("cannot merge the documents")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_font_styles() {
+        assert_eq!(FontStyle::parse("normal").unwrap(), FontStyle::Normal);
+        assert_eq!(FontStyle::parse("Regular").unwrap(), FontStyle::Normal);
+        assert_eq!(FontStyle::parse(" bold ").unwrap(), FontStyle::Bold);
+        assert_eq!(FontStyle::parse("ITALIC").unwrap(), FontStyle::Italic);
+        for style in ["bold-italic", "bold italic", "italic-bold"] {
+            assert_eq!(FontStyle::parse(style).unwrap(), FontStyle::BoldItalic);
+        }
+        assert!(FontStyle::parse("underline").is_err());
+        assert!(FontStyle::parse("").is_err());
+    }
+
+    #[test]
+    fn parses_destination_fits() {
+        assert_eq!(DestinationFit::parse("fit").unwrap(), DestinationFit::Fit);
+        assert_eq!(DestinationFit::parse("FitH").unwrap(), DestinationFit::FitH);
+        assert_eq!(
+            DestinationFit::parse("fit-width").unwrap(),
+            DestinationFit::FitH
+        );
+        assert_eq!(DestinationFit::parse(" xyz ").unwrap(), DestinationFit::Xyz);
+        assert_eq!(
+            DestinationFit::parse("keep-zoom").unwrap(),
+            DestinationFit::Xyz
+        );
+        assert!(DestinationFit::parse("fitv").is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use lopdf::{Dictionary, Document, Object, Stream, StringFormat, decode_text_string};
+
+fn info_dictionary(doc: &Document) -> Option<&lopdf::Dictionary> {
+    let info = doc.trailer.get(b"Info").ok()?;
+    doc.dereference(info).ok()?.1.as_dict().ok()
+}
+
+// Returns the document information dictionary, added to the trailer when
+// missing.
+fn info_dictionary_mut(doc: &mut Document) -> &mut Dictionary {
+    let info_id = match doc.trailer.get(b"Info").and_then(Object::as_reference) {
+        Ok(id) if doc.get_dictionary(id).is_ok() => id,
+        _ => {
+            let id = doc.add_object(Dictionary::new());
+            doc.trailer.set("Info", id);
+            id
+        }
+    };
+    doc.get_dictionary_mut(info_id)
+        .expect("the information dictionary was just checked")
+}
+
+// Dates are written in UTC so that they do not depend on the timezone of the
+// machine.
+fn pdf_date(date: DateTime<Utc>) -> Object {
+    Object::string_literal(format!("D:{}Z", date.format("%Y%m%d%H%M%S")))
+}
+
+// Text strings are written in PDFDocEncoding when ASCII, and in UTF-16BE
+// with a byte order mark otherwise.
+pub(crate) fn text_string(text: &str) -> Object {
+    if text.is_ascii() {
+        return Object::string_literal(text);
+    }
+    let mut bytes = vec![0xFE, 0xFF];
+    bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+    Object::String(bytes, StringFormat::Hexadecimal)
+}
+
+// Sets an entry of the document information dictionary, e.g. `Producer`, or
+// removes it when `value` is `None`.
+pub fn set_info_text(doc: &mut Document, key: &str, value: Option<&str>) {
+    let info = info_dictionary_mut(doc);
+    match value {
+        Some(value) => info.set(key, text_string(value)),
+        None => {
+            info.remove(key.as_bytes());
+        }
+    }
+}
+
+// Sets a date of the document information dictionary, e.g. `ModDate`, or
+// removes it when `date` is `None`.
+pub fn set_info_date(doc: &mut Document, key: &str, date: Option<DateTime<Utc>>) {
+    let info = info_dictionary_mut(doc);
+    match date {
+        Some(date) => info.set(key, pdf_date(date)),
+        None => {
+            info.remove(key.as_bytes());
+        }
+    }
+}
+
+// Sets both the creation and the modification dates.
+pub fn set_dates(doc: &mut Document, date: DateTime<Utc>) {
+    set_info_date(doc, "CreationDate", Some(date));
+    set_info_date(doc, "ModDate", Some(date));
+}
+
+// Sets the `/ID` of the trailer, which identifies the document and otherwise
+// is random when the document is written by printpdf.
+pub fn set_document_id(doc: &mut Document, id: &[u8]) {
+    let id = Object::String(id.to_vec(), StringFormat::Hexadecimal);
+    doc.trailer.set("ID", vec![id.clone(), id]);
+}
+
+// Parses a PDF date string (`D:YYYYMMDDHHmmSSOHH'mm'`). Dates written without
+// a timezone are read as local time.
+fn parse_pdf_date(date: &Object) -> Option<DateTime<Local>> {
+    if let Some(date) = date.as_datetime() {
+        return Some(date);
+    }
+
+    let digits: String = date
+        .as_str()
+        .ok()
+        .map(String::from_utf8_lossy)?
+        .chars()
+        .skip_while(|c| !c.is_ascii_digit())
+        .take(14)
+        .collect();
+    let naive = NaiveDateTime::parse_from_str(&format!("{:0<14}", digits), "%Y%m%d%H%M%S").ok()?;
+    Local.from_local_datetime(&naive).earliest()
+}
+
+fn info_date(doc: &Document, key: &[u8]) -> Option<DateTime<Local>> {
+    let date = info_dictionary(doc)?.get(key).ok()?;
+    parse_pdf_date(doc.dereference(date).ok()?.1)
+}
+
+pub fn creation_date(doc: &Document) -> Option<DateTime<Local>> {
+    info_date(doc, b"CreationDate")
+}
+
+pub fn modification_date(doc: &Document) -> Option<DateTime<Local>> {
+    info_date(doc, b"ModDate")
+}
+
+// The entries of the document information dictionary, strings decoded and
+// other values as written in the file.
+pub fn info_entries(doc: &Document) -> BTreeMap<String, String> {
+    let Some(info) = info_dictionary(doc) else {
+        return BTreeMap::new();
+    };
+    info.iter()
+        .filter_map(|(key, value)| {
+            let value = doc.dereference(value).ok()?.1;
+            let value = match decode_text_string(value) {
+                Ok(text) => text,
+                Err(_) => format!("{:?}", value),
+            };
+            Some((String::from_utf8_lossy(key).into_owned(), value))
+        })
+        .collect()
+}
+
+// The document information dictionary and XMP metadata stream of a document,
+// detached from it so that they can be written to another one.
+#[derive(Debug, Clone, Default)]
+pub struct DocumentMetadata {
+    info: Option<Dictionary>,
+    xmp: Option<Stream>,
+}
+
+pub fn read_metadata(doc: &Document) -> DocumentMetadata {
+    // Indirect values are resolved, their objects not being copied along.
+    let info = info_dictionary(doc).map(|info| {
+        let mut copy = Dictionary::new();
+        for (key, value) in info.iter() {
+            if let Ok((_, value)) = doc.dereference(value) {
+                copy.set(key.clone(), value.clone());
+            }
+        }
+        copy
+    });
+    let xmp = doc
+        .catalog()
+        .and_then(|catalog| catalog.get(b"Metadata"))
+        .and_then(|metadata| doc.dereference(metadata))
+        .and_then(|(_, metadata)| metadata.as_stream())
+        .ok()
+        .cloned();
+    DocumentMetadata { info, xmp }
+}
+
+// Replaces the information dictionary and the XMP metadata of `doc`.
+pub fn write_metadata(doc: &mut Document, metadata: &DocumentMetadata) {
+    if let Some(info) = &metadata.info {
+        let info_id = doc.add_object(info.clone());
+        doc.trailer.set("Info", info_id);
+    }
+    if let Some(xmp) = &metadata.xmp {
+        let xmp_id = doc.add_object(xmp.clone());
+        if let Ok(catalog) = doc.catalog_mut() {
+            catalog.set("Metadata", xmp_id);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+// Desktop notifications at the end of the run, with `--notify`, through
+// `notify-send` on Linux, `osascript` on macOS and PowerShell on Windows.
+use std::{
+    process::{Command, Stdio},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+const TITLE: &str = "pdf-merge";
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// String literal of AppleScript.
+#[cfg(target_os = "macos")]
+fn quoted(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(target_os = "macos")]
+fn notification_command(message: &str) -> Command {
+    let mut command = Command::new("osascript");
+    command.arg("-e").arg(format!(
+        "display notification {} with title {}",
+        quoted(message),
+        quoted(TITLE)
+    ));
+    command
+}
+
+// A balloon tip of a temporary notification area icon, shown as a toast by
+// recent versions of Windows. The script outlives this process until the
+// notification is gone.
+#[cfg(windows)]
+fn notification_command(message: &str) -> Command {
+    use std::os::windows::process::CommandExt;
+
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+    let quoted = |text: &str| format!("'{}'", text.replace('\'', "''"));
+    let script = format!(
+        "Add-Type -AssemblyName System.Windows.Forms; \
+         $icon = New-Object System.Windows.Forms.NotifyIcon; \
+         $icon.Icon = [System.Drawing.SystemIcons]::Information; \
+         $icon.Visible = $true; \
+         $icon.ShowBalloonTip(5000, {}, {}, 'Info'); \
+         Start-Sleep -Seconds 6; \
+         $icon.Dispose()",
+        quoted(TITLE),
+        quoted(message)
+    );
+    let mut command = Command::new("powershell");
+    command
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .creation_flags(CREATE_NO_WINDOW);
+    command
+}
+
+#[cfg(not(any(windows, target_os = "macos")))]
+fn notification_command(message: &str) -> Command {
+    let mut command = Command::new("notify-send");
+    command.args(["--app-name", TITLE, TITLE, message]);
+    command
+}
+
+// Notifications are a convenience: failing to show one is ignored.
+pub fn send(message: &str) {
+    if !is_enabled() {
+        return;
+    }
+    let _ = notification_command(message)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+}
@@ -0,0 +1,220 @@
+// The outline (bookmarks) of an input, carried over under the bookmark of the
+// input in the merged document.
+use std::collections::HashSet;
+
+use lopdf::{Dictionary, Document, Object, ObjectId, decode_text_string};
+
+// Outline items of the inputs which do not set their color are black.
+const DEFAULT_COLOR: [f32; 3] = [0.0, 0.0, 0.0];
+
+// Guards against `Next` loops and absurdly deep trees in corrupted files.
+const MAX_ITEMS: usize = 100_000;
+
+#[derive(Debug, Clone)]
+pub struct OutlineItem {
+    pub title: String,
+    pub color: [f32; 3],
+    // Font flags of `/F`: 1 for italic, 2 for bold.
+    pub format: u32,
+    pub page_id: ObjectId,
+    pub children: Vec<OutlineItem>,
+}
+
+// An entry of an outline replacing the generated bookmarks, pointing to a
+// page of the merged document counting from 1.
+#[derive(Debug, Clone)]
+pub struct OutlineEntry {
+    pub title: String,
+    pub page: usize,
+    pub children: Vec<OutlineEntry>,
+}
+
+fn dereference<'a>(doc: &'a Document, object: &'a Object) -> Option<&'a Object> {
+    doc.dereference(object).ok().map(|(_, object)| object)
+}
+
+// Looks `name` up in the `/Dests` dictionary of the catalog (PDF 1.1), then
+// in the `/Dests` name tree of `/Names`.
+fn named_destination<'a>(doc: &'a Document, name: &[u8]) -> Option<&'a Object> {
+    let catalog = doc.catalog().ok()?;
+    if let Some(dests) = catalog
+        .get(b"Dests")
+        .ok()
+        .and_then(|dests| dereference(doc, dests))
+        .and_then(|dests| dests.as_dict().ok())
+        && let Ok(destination) = dests.get(name)
+    {
+        return dereference(doc, destination);
+    }
+    let names = dereference(doc, catalog.get(b"Names").ok()?)?
+        .as_dict()
+        .ok()?;
+    let tree = dereference(doc, names.get(b"Dests").ok()?)?
+        .as_dict()
+        .ok()?;
+    find_in_name_tree(doc, tree, name, &mut HashSet::new())
+}
+
+fn find_in_name_tree<'a>(
+    doc: &'a Document,
+    node: &'a Dictionary,
+    name: &[u8],
+    visited: &mut HashSet<ObjectId>,
+) -> Option<&'a Object> {
+    if let Ok(names) = node.get(b"Names").and_then(Object::as_array) {
+        return names
+            .chunks_exact(2)
+            .find(|pair| pair[0].as_str().is_ok_and(|key| key == name))
+            .and_then(|pair| dereference(doc, &pair[1]));
+    }
+    let kids = node.get(b"Kids").and_then(Object::as_array).ok()?;
+    kids.iter().find_map(|kid| {
+        let id = kid.as_reference().ok()?;
+        if !visited.insert(id) {
+            return None;
+        }
+        find_in_name_tree(doc, doc.get_dictionary(id).ok()?, name, visited)
+    })
+}
+
+// The page of an explicit destination `[page /XYZ ...]`, or of a named one.
+fn destination_page(doc: &Document, destination: &Object) -> Option<ObjectId> {
+    match dereference(doc, destination)? {
+        Object::Array(destination) => destination.first()?.as_reference().ok(),
+        Object::Name(name) | Object::String(name, _) => {
+            destination_page(doc, named_destination(doc, name)?)
+        }
+        // Named destinations may be a dictionary holding the array in `/D`.
+        Object::Dictionary(destination) => destination_page(doc, destination.get(b"D").ok()?),
+        _ => None,
+    }
+}
+
+// The explicit destination `[page /XYZ ...]` of a destination, which may be
+// named.
+pub(crate) fn explicit_destination(doc: &Document, destination: &Object) -> Option<Object> {
+    match dereference(doc, destination)? {
+        Object::Array(destination) => Some(Object::Array(destination.clone())),
+        Object::Name(name) | Object::String(name, _) => {
+            explicit_destination(doc, named_destination(doc, name)?)
+        }
+        Object::Dictionary(destination) => explicit_destination(doc, destination.get(b"D").ok()?),
+        _ => None,
+    }
+}
+
+// The page an outline item goes to, through `/Dest` or a `GoTo` action.
+pub(crate) fn item_page(doc: &Document, item: &Dictionary) -> Option<ObjectId> {
+    if let Ok(destination) = item.get(b"Dest") {
+        return destination_page(doc, destination);
+    }
+    let action = dereference(doc, item.get(b"A").ok()?)?.as_dict().ok()?;
+    if action.get(b"S").and_then(Object::as_name).ok()? != b"GoTo" {
+        return None;
+    }
+    destination_page(doc, action.get(b"D").ok()?)
+}
+
+// Reads the items of a level, starting with `first`. Items whose destination
+// is not a page of the document (actions other than `GoTo`, broken links)
+// go to the page of their parent.
+fn read_level(
+    doc: &Document,
+    first: Option<ObjectId>,
+    pages: &HashSet<ObjectId>,
+    parent_page: ObjectId,
+    visited: &mut HashSet<ObjectId>,
+) -> Vec<OutlineItem> {
+    let mut items = Vec::new();
+    let mut next = first;
+    while let Some(id) = next {
+        if visited.len() >= MAX_ITEMS || !visited.insert(id) {
+            break;
+        }
+        let Ok(item) = doc.get_dictionary(id) else {
+            break;
+        };
+        let page_id = item_page(doc, item)
+            .filter(|page_id| pages.contains(page_id))
+            .unwrap_or(parent_page);
+        let link = |key: &[u8]| item.get(key).and_then(Object::as_reference).ok();
+        let color = item
+            .get(b"C")
+            .and_then(Object::as_array)
+            .ok()
+            .and_then(|color| {
+                let color: Vec<f32> = color.iter().filter_map(|c| c.as_float().ok()).collect();
+                <[f32; 3]>::try_from(color).ok()
+            })
+            .unwrap_or(DEFAULT_COLOR);
+        items.push(OutlineItem {
+            title: item
+                .get(b"Title")
+                .ok()
+                .and_then(|title| decode_text_string(dereference(doc, title)?).ok())
+                .unwrap_or_default(),
+            color,
+            format: item
+                .get(b"F")
+                .and_then(Object::as_i64)
+                .map_or(0, |flags| (flags & 3) as u32),
+            page_id,
+            children: read_level(doc, link(b"First"), pages, page_id, visited),
+        });
+        next = link(b"Next");
+    }
+    items
+}
+
+// The outline of `doc`, its items without a page going to the first one.
+pub fn read_outline(doc: &Document) -> Vec<OutlineItem> {
+    let pages = doc.get_pages();
+    let Some(&first_page) = pages.values().next() else {
+        return Vec::new();
+    };
+    let first = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Outlines").ok())
+        .and_then(|outlines| dereference(doc, outlines))
+        .and_then(|outlines| outlines.as_dict().ok())
+        .and_then(|outlines| outlines.get(b"First").and_then(Object::as_reference).ok());
+    let pages = pages.into_values().collect();
+    read_level(doc, first, &pages, first_page, &mut HashSet::new())
+}
+
+// The number of items of the outline of `doc` going to none of its pages,
+// which `read_outline` sends to the page of their parent.
+pub fn count_items_without_page(doc: &Document) -> usize {
+    let pages: HashSet<ObjectId> = doc.get_pages().into_values().collect();
+    let first = doc
+        .catalog()
+        .ok()
+        .and_then(|catalog| catalog.get(b"Outlines").ok())
+        .and_then(|outlines| dereference(doc, outlines))
+        .and_then(|outlines| outlines.as_dict().ok())
+        .and_then(|outlines| outlines.get(b"First").and_then(Object::as_reference).ok());
+    let mut count = 0;
+    let mut visited = HashSet::new();
+    let mut pending: Vec<ObjectId> = first.into_iter().collect();
+    while let Some(id) = pending.pop() {
+        if visited.len() >= MAX_ITEMS {
+            break;
+        }
+        if !visited.insert(id) {
+            continue;
+        }
+        let Ok(item) = doc.get_dictionary(id) else {
+            continue;
+        };
+        if !item_page(doc, item).is_some_and(|page_id| pages.contains(&page_id)) {
+            count += 1;
+        }
+        for key in [b"Next".as_slice(), b"First"] {
+            if let Ok(next) = item.get(key).and_then(Object::as_reference) {
+                pending.push(next);
+            }
+        }
+    }
+    count
+}
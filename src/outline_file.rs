@@ -0,0 +1,162 @@
+// `--outline` files: a Markdown list of the bookmarks replacing the generated
+// ones, for curated bundles. Items are nested by indentation and link to an
+// input, by its path or the end of it, or to a page of the merged document:
+//
+//     - Board pack
+//       - [Agenda](agenda.pdf)
+//       - [Minutes of the last meeting](#page=7)
+//
+// An item without a link points to the page of its first child. Lines which
+// are not list items are ignored.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use pm::outline::OutlineEntry;
+
+enum Target {
+    Input(PathBuf),
+    Page(usize),
+    FirstChild,
+}
+
+struct Item {
+    title: String,
+    target: Target,
+    children: Vec<Item>,
+}
+
+// The indentation and the item of a list item line, `None` for other lines.
+fn parse_line(line: &str) -> Result<Option<(usize, Item)>> {
+    let text = line.trim_start();
+    let indentation = line[..line.len() - text.len()]
+        .chars()
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum();
+    let Some(text) = ["- ", "* ", "+ "]
+        .iter()
+        .find_map(|marker| text.strip_prefix(marker))
+    else {
+        return Ok(None);
+    };
+    let text = text.trim();
+    let link = text
+        .strip_prefix('[')
+        .and_then(|text| text.strip_suffix(')'))
+        .and_then(|text| text.rsplit_once("]("));
+    let (title, target) = match link {
+        Some((title, link)) => {
+            let link = link.trim();
+            let target = match link.strip_prefix("#page=") {
+                Some(page) => Target::Page(
+                    page.parse()
+                        .with_context(|| format!("invalid page number in {}", link))?,
+                ),
+                None => {
+                    let link = link.strip_prefix('<').unwrap_or(link);
+                    let link = link.strip_suffix('>').unwrap_or(link);
+                    Target::Input(PathBuf::from(link.strip_prefix("./").unwrap_or(link)))
+                }
+            };
+            (title, target)
+        }
+        None => (text, Target::FirstChild),
+    };
+    if title.is_empty() {
+        bail!("outline entry without a title: {}", line.trim());
+    }
+    Ok(Some((
+        indentation,
+        Item {
+            title: title.to_string(),
+            target,
+            children: Vec::new(),
+        },
+    )))
+}
+
+// Moves the innermost open item under its parent, or to the top level.
+fn close_item(open_items: &mut Vec<(usize, Item)>, items: &mut Vec<Item>) {
+    let Some((_, item)) = open_items.pop() else {
+        return;
+    };
+    match open_items.last_mut() {
+        Some((_, parent)) => parent.children.push(item),
+        None => items.push(item),
+    }
+}
+
+fn parse_outline(content: &str) -> Result<Vec<Item>> {
+    let mut items = Vec::new();
+    // Items whose children may still follow, with their indentation.
+    let mut open_items: Vec<(usize, Item)> = Vec::new();
+    for line in content.lines() {
+        let Some((indentation, item)) = parse_line(line)? else {
+            continue;
+        };
+        while open_items
+            .last()
+            .is_some_and(|&(open_indentation, _)| open_indentation >= indentation)
+        {
+            close_item(&mut open_items, &mut items);
+        }
+        open_items.push((indentation, item));
+    }
+    while !open_items.is_empty() {
+        close_item(&mut open_items, &mut items);
+    }
+    Ok(items)
+}
+
+fn resolve(item: Item, first_pages: &[(PathBuf, usize)]) -> Result<OutlineEntry> {
+    let children = item
+        .children
+        .into_iter()
+        .map(|child| resolve(child, first_pages))
+        .collect::<Result<Vec<_>>>()?;
+    let page = match item.target {
+        Target::Page(page) => page,
+        Target::Input(path) => first_pages
+            .iter()
+            .find(|(input, _)| input.ends_with(&path))
+            .map(|&(_, page)| page)
+            .with_context(|| {
+                format!(
+                    "the outline entry \"{}\" links to {}, which is not an input",
+                    item.title,
+                    path.display()
+                )
+            })?,
+        Target::FirstChild => children.first().map(|child| child.page).with_context(|| {
+            format!(
+                "the outline entry \"{}\" has neither a link nor entries under it",
+                item.title
+            )
+        })?,
+    };
+    Ok(OutlineEntry {
+        title: item.title,
+        page,
+        children,
+    })
+}
+
+// Reads the outline file at `path`, given the path of each input with the
+// number of its first page in the merged document.
+pub fn read_outline_file(
+    path: &Path,
+    first_pages: &[(PathBuf, usize)],
+) -> Result<Vec<OutlineEntry>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))?;
+    parse_outline(&content)
+        .and_then(|items| {
+            items
+                .into_iter()
+                .map(|item| resolve(item, first_pages))
+                .collect()
+        })
+        .with_context(|| format!("invalid outline file {}", path.display()))
+}
@@ -0,0 +1,400 @@
+use anyhow::{Result, bail};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use printpdf::{CustomPdfConformance, Mm, PdfConformance, PdfDocument};
+
+use crate::{annotation::page_annotations, pagesize::PageSizeInMm, stamp::page_box};
+
+// Page attributes which, when missing from a page, are taken from its
+// ancestors in the page tree.
+const INHERITABLE: [&[u8]; 4] = [b"Resources", b"MediaBox", b"CropBox", b"Rotate"];
+
+// Looks up a page attribute, walking up the page tree for the inheritable
+// ones (`Rotate`, `MediaBox`, `CropBox`, `Resources`).
+pub fn get_inherited<'a>(doc: &'a Document, page_id: ObjectId, key: &[u8]) -> Option<&'a Object> {
+    let mut node_id = Some(page_id);
+    while let Some(id) = node_id {
+        let dictionary = doc.get_dictionary(id).ok()?;
+        if let Ok(value) = dictionary.get(key) {
+            return doc.dereference(value).ok().map(|(_, object)| object);
+        }
+        node_id = dictionary
+            .get(b"Parent")
+            .and_then(Object::as_reference)
+            .ok();
+    }
+    None
+}
+
+// The page dictionary with the attributes it inherits from the page tree
+// copied into it, so that it keeps its size, rotation and resources once
+// moved to another tree.
+pub fn page_with_inherited(doc: &Document, page_id: ObjectId) -> Option<Dictionary> {
+    let mut page = doc.get_dictionary(page_id).ok()?.clone();
+    let mut node_id = page.get(b"Parent").and_then(Object::as_reference).ok();
+    while let Some(id) = node_id {
+        let Ok(node) = doc.get_dictionary(id) else {
+            break;
+        };
+        for key in INHERITABLE {
+            if !page.has(key)
+                && let Ok(value) = node.get(key)
+            {
+                page.set(key, value.clone());
+            }
+        }
+        node_id = node.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+    Some(page)
+}
+
+// Removes the inheritable attributes of a page tree node, which would
+// otherwise apply to the pages lacking them.
+pub fn remove_inheritable(node: &mut Dictionary) {
+    for key in INHERITABLE {
+        node.remove(key);
+    }
+}
+
+const POINTS_PER_MM: f32 = 72.0 / 25.4;
+
+// Scales the numbers of a rectangle or a list of points, `[x1 y1 x2 y2 ...]`.
+fn transform_points(
+    points: &[Object],
+    scale: f32,
+    translate_x: f32,
+    translate_y: f32,
+) -> Vec<Object> {
+    points
+        .iter()
+        .enumerate()
+        .map(|(i, value)| match value.as_float() {
+            Ok(value) if i % 2 == 0 => Object::Real(value * scale + translate_x),
+            Ok(value) => Object::Real(value * scale + translate_y),
+            Err(_) => value.clone(),
+        })
+        .collect()
+}
+
+// Gives every page the size `page_size`, its visible area being scaled to fit
+// and centered, for printing on a single paper size. Rotated pages get the
+// size once rotated. The rectangles of the annotations follow their page.
+pub fn force_page_size(doc: &mut Document, page_size: &PageSizeInMm) {
+    let PageSizeInMm(width, height) = page_size;
+    for page_id in doc.get_pages().into_values() {
+        let rotate = get_inherited(doc, page_id, b"Rotate")
+            .and_then(|rotate| rotate.as_i64().ok())
+            .unwrap_or(0);
+        let (width, height) = if rotate.rem_euclid(180) == 90 {
+            (
+                *height as f32 * POINTS_PER_MM,
+                *width as f32 * POINTS_PER_MM,
+            )
+        } else {
+            (
+                *width as f32 * POINTS_PER_MM,
+                *height as f32 * POINTS_PER_MM,
+            )
+        };
+        let [x0, y0, x1, y1] = page_box(doc, page_id);
+        let (box_width, box_height) = ((x1 - x0).abs(), (y1 - y0).abs());
+        if box_width <= 0.0 || box_height <= 0.0 {
+            continue;
+        }
+        let scale = (width / box_width).min(height / box_height);
+        let translate_x = (width - box_width * scale) / 2.0 - x0.min(x1) * scale;
+        let translate_y = (height - box_height * scale) / 2.0 - y0.min(y1) * scale;
+        transform_page(
+            doc,
+            page_id,
+            scale,
+            (translate_x, translate_y),
+            (width, height),
+        );
+    }
+}
+
+// Scales the content and the annotations of a page by `scale` then moves them
+// by `translate`, on a page of `size` points.
+fn transform_page(
+    doc: &mut Document,
+    page_id: ObjectId,
+    scale: f32,
+    (translate_x, translate_y): (f32, f32),
+    (width, height): (f32, f32),
+) {
+    for annotation in page_annotations(doc, page_id) {
+        let Ok(id) = annotation.as_reference() else {
+            continue;
+        };
+        if let Ok(annotation) = doc.get_dictionary_mut(id) {
+            for key in [b"Rect".as_slice(), b"QuadPoints"] {
+                if let Ok(points) = annotation.get(key).and_then(Object::as_array) {
+                    let points = transform_points(points, scale, translate_x, translate_y);
+                    annotation.set(key, points);
+                }
+            }
+        }
+    }
+
+    let existing: Vec<Object> = match doc
+        .get_dictionary(page_id)
+        .and_then(|page| page.get(b"Contents"))
+    {
+        Ok(Object::Array(contents)) => contents.clone(),
+        Ok(contents) => vec![contents.clone()],
+        Err(_) => Vec::new(),
+    };
+    let transform = format!(
+        "q {} 0 0 {} {} {} cm\n",
+        scale, scale, translate_x, translate_y
+    );
+    let transform_id = doc.add_object(Stream::new(Dictionary::new(), transform.into_bytes()));
+    let restore_id = doc.add_object(Stream::new(Dictionary::new(), b"\nQ\n".to_vec()));
+    let mut contents = vec![Object::Reference(transform_id)];
+    contents.extend(existing);
+    contents.push(Object::Reference(restore_id));
+
+    if let Ok(page) = doc.get_dictionary_mut(page_id) {
+        page.set("Contents", contents);
+        page.set(
+            "MediaBox",
+            vec![
+                0.into(),
+                0.into(),
+                Object::Real(width),
+                Object::Real(height),
+            ],
+        );
+        for key in [b"CropBox".as_slice(), b"BleedBox", b"TrimBox", b"ArtBox"] {
+            page.remove(key);
+        }
+    }
+}
+
+// Scales down the pages larger than `max_size` (in either orientation) to fit
+// it, keeping their aspect ratio, e.g. A0 drawings to print them on A3. Returns
+// how many pages were scaled down.
+pub fn shrink_oversized_pages(doc: &mut Document, max_size: &PageSizeInMm) -> usize {
+    // Sizes within a point of the limit are rounding errors of their unit.
+    const TOLERANCE: f32 = 1.0;
+    let PageSizeInMm(width, height) = max_size;
+    let (max_short, max_long) = {
+        let (width, height) = (
+            *width as f32 * POINTS_PER_MM,
+            *height as f32 * POINTS_PER_MM,
+        );
+        (width.min(height), width.max(height))
+    };
+    let mut shrunk = 0;
+    for page_id in doc.get_pages().into_values() {
+        let [x0, y0, x1, y1] = page_box(doc, page_id);
+        let (width, height) = ((x1 - x0).abs(), (y1 - y0).abs());
+        let (short, long) = (width.min(height), width.max(height));
+        if short <= 0.0 || (short <= max_short + TOLERANCE && long <= max_long + TOLERANCE) {
+            continue;
+        }
+        scale_page(doc, page_id, (max_short / short).min(max_long / long));
+        shrunk += 1;
+    }
+    shrunk
+}
+
+// Scales a page, its size as well as its content, by `scale`.
+pub fn scale_page(doc: &mut Document, page_id: ObjectId, scale: f32) {
+    let [x0, y0, x1, y1] = page_box(doc, page_id);
+    let (width, height) = ((x1 - x0).abs(), (y1 - y0).abs());
+    if width <= 0.0 || height <= 0.0 {
+        return;
+    }
+    transform_page(
+        doc,
+        page_id,
+        scale,
+        (-x0.min(x1) * scale, -y0.min(y1) * scale),
+        (width * scale, height * scale),
+    );
+}
+
+// Adds `degrees` (a multiple of 90) to the rotation of the page.
+pub fn rotate_page(doc: &mut Document, page_id: ObjectId, degrees: i64) {
+    let current = get_inherited(doc, page_id, b"Rotate")
+        .and_then(|rotate| rotate.as_i64().ok())
+        .unwrap_or(0);
+    if let Ok(page) = doc.get_dictionary_mut(page_id) {
+        page.set("Rotate", (current + degrees).rem_euclid(360));
+    }
+}
+
+pub fn rotate_document(doc: &mut Document, degrees: i64) {
+    for page_id in doc.get_pages().into_values() {
+        rotate_page(doc, page_id, degrees);
+    }
+}
+
+// Page ranges such as `1-3,5,8-` (1-based, an open end meaning the last page).
+#[derive(Debug, Clone)]
+pub struct PageRanges(Vec<(u32, Option<u32>)>);
+
+impl PageRanges {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut ranges = Vec::new();
+        for part in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+        {
+            let parse_page = |page: &str| -> Result<u32> {
+                match page.trim().parse::<u32>() {
+                    Ok(page) if page > 0 => Ok(page),
+                    _ => bail!("invalid page number {:?} in page range {:?}", page, spec),
+                }
+            };
+            let range = match part.split_once('-') {
+                Some((start, end)) if end.trim().is_empty() => (parse_page(start)?, None),
+                Some((start, end)) => (parse_page(start)?, Some(parse_page(end)?)),
+                None => (parse_page(part)?, Some(parse_page(part)?)),
+            };
+            ranges.push(range);
+        }
+        Ok(PageRanges(ranges))
+    }
+
+    pub fn contains(&self, page_number: u32) -> bool {
+        self.0
+            .iter()
+            .any(|&(start, end)| page_number >= start && end.is_none_or(|end| page_number <= end))
+    }
+}
+
+// An operation on a single page of an input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PageOperation {
+    // Degrees added to the rotation, a multiple of 90.
+    Rotate(i64),
+    Skip,
+    Scale(f32),
+}
+
+// Operations on given pages (1-based), such as `3:rotate=90 5:skip
+// 7:scale=0.5`, several operations on a page being given one after the other.
+#[derive(Debug, Clone, Default)]
+pub struct PageOperations(Vec<(u32, PageOperation)>);
+
+impl PageOperations {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut operations = Vec::new();
+        for part in spec.split_whitespace() {
+            let Some((page, operation)) = part.split_once(':') else {
+                bail!("expected PAGE:OPERATION, got {:?}", part);
+            };
+            let page = match page.parse::<u32>() {
+                Ok(page) if page > 0 => page,
+                _ => bail!("invalid page number {:?} in {:?}", page, part),
+            };
+            let operation = match operation.split_once('=') {
+                None if operation.eq_ignore_ascii_case("skip") => PageOperation::Skip,
+                Some((name, degrees)) if name.eq_ignore_ascii_case("rotate") => {
+                    match degrees.parse::<i64>() {
+                        Ok(degrees) if degrees % 90 == 0 => PageOperation::Rotate(degrees),
+                        _ => bail!("rotation must be a multiple of 90, got {:?}", degrees),
+                    }
+                }
+                Some((name, scale)) if name.eq_ignore_ascii_case("scale") => {
+                    let factor = match scale.strip_suffix('%') {
+                        Some(percent) => percent.parse::<f32>().map(|percent| percent / 100.0),
+                        None => scale.parse::<f32>(),
+                    };
+                    match factor {
+                        Ok(factor) if factor > 0.0 && factor.is_finite() => {
+                            PageOperation::Scale(factor)
+                        }
+                        _ => bail!("invalid scale {:?}, expected e.g. 0.5 or 50%", scale),
+                    }
+                }
+                _ => bail!(
+                    "unknown page operation {:?}, expected rotate=DEGREES, scale=FACTOR or skip",
+                    operation
+                ),
+            };
+            operations.push((page, operation));
+        }
+        Ok(PageOperations(operations))
+    }
+
+    // Applies the operations, the page numbers referring to the document as
+    // given. Operations on pages it does not have are ignored.
+    pub fn apply(&self, doc: &mut Document) {
+        let pages = doc.get_pages();
+        let mut skipped = Vec::new();
+        for &(page_number, operation) in &self.0 {
+            let Some(&page_id) = pages.get(&page_number) else {
+                continue;
+            };
+            match operation {
+                PageOperation::Rotate(degrees) => rotate_page(doc, page_id, degrees),
+                PageOperation::Scale(scale) => scale_page(doc, page_id, scale),
+                PageOperation::Skip if !skipped.contains(&page_id) => skipped.push(page_id),
+                PageOperation::Skip => {}
+            }
+        }
+        for page_id in skipped {
+            remove_page(doc, page_id);
+        }
+    }
+}
+
+// Removes a page from the page tree, fixing the `Count` of its ancestors.
+pub fn remove_page(doc: &mut Document, page_id: ObjectId) {
+    let mut parent_id = doc
+        .get_dictionary(page_id)
+        .and_then(|page| page.get(b"Parent"))
+        .and_then(Object::as_reference)
+        .ok();
+    if let Some(Ok(kids)) = parent_id.map(|id| {
+        doc.get_dictionary_mut(id)
+            .and_then(|parent| parent.get_mut(b"Kids"))
+            .and_then(Object::as_array_mut)
+    }) {
+        kids.retain(|kid| kid.as_reference().ok() != Some(page_id));
+    }
+    while let Some(id) = parent_id {
+        let Ok(node) = doc.get_dictionary_mut(id) else {
+            break;
+        };
+        if let Ok(count) = node.get(b"Count").and_then(Object::as_i64) {
+            node.set("Count", count - 1);
+        }
+        parent_id = node.get(b"Parent").and_then(Object::as_reference).ok();
+    }
+    doc.objects.remove(&page_id);
+}
+
+// A document of a single blank page, e.g. to leave room for a signature.
+pub fn blank_document(page_size: &PageSizeInMm) -> Result<Document> {
+    let PageSizeInMm(width, height) = page_size;
+    let doc = PdfDocument::empty("Blank page")
+        .with_conformance(PdfConformance::Custom(CustomPdfConformance::default()));
+    doc.add_page(Mm(*width as f32), Mm(*height as f32), "Layer1");
+    let bytes = doc.save_to_bytes()?;
+    Ok(Document::load_mem(&bytes)?)
+}
+
+// Drops the first `first` and the last `last` pages.
+pub fn skip_pages(doc: &mut Document, first: usize, last: usize) {
+    let pages: Vec<ObjectId> = doc.get_pages().into_values().collect();
+    let kept = first..pages.len().saturating_sub(last);
+    for (index, page_id) in pages.into_iter().enumerate() {
+        if !kept.contains(&index) {
+            remove_page(doc, page_id);
+        }
+    }
+}
+
+// Keeps only the pages of `ranges`, in document order.
+pub fn select_pages(doc: &mut Document, ranges: &PageRanges) {
+    for (page_number, page_id) in doc.get_pages() {
+        if !ranges.contains(page_number) {
+            remove_page(doc, page_id);
+        }
+    }
+}
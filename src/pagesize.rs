@@ -1,93 +1,169 @@
-//use std::collections::HashMap;
-
-//use colored::*;
-//use regex::Regex;
+use anyhow::{Result, bail};
+use colored::*;
+use regex::Regex;
 
 // PageSizeInMm(width, height)
 #[derive(Debug, Clone)]
 pub struct PageSizeInMm(pub f64, pub f64);
 
-//impl PageSizeInMm {
-// pub fn new(pagesize: &str) -> Self {
-//     let pagesize = pagesize.to_lowercase().trim().to_string();
-//     let pagesize = pagesize.as_str();
-//     let page_size_map = HashMap::from([
-//         ("a0", PageSizeInMm(841.0, 1189.0)),
-//         ("a1", PageSizeInMm(594.0, 841.0)),
-//         ("a2", PageSizeInMm(420.0, 594.0)),
-//         ("a3", PageSizeInMm(297.0, 420.0)),
-//         ("a4", PageSizeInMm(210.0, 297.0)),
-//         ("a5", PageSizeInMm(148.0, 210.0)),
-//         ("a6", PageSizeInMm(105.0, 148.0)),
-//         ("b0", PageSizeInMm(1000.0, 1414.0)),
-//         ("b1", PageSizeInMm(707.0, 1000.0)),
-//         ("b2", PageSizeInMm(500.0, 707.0)),
-//         ("b3", PageSizeInMm(353.0, 500.0)),
-//         ("b4", PageSizeInMm(250.0, 353.0)),
-//         ("b5", PageSizeInMm(176.0, 250.0)),
-//         ("b6", PageSizeInMm(125.0, 176.0)),
-//         ("jb0", PageSizeInMm(1030.0, 456.0)),
-//         ("jb1", PageSizeInMm(728.0, 1030.0)),
-//         ("jb2", PageSizeInMm(515.0, 728.0)),
-//         ("jb3", PageSizeInMm(364.0, 515.0)),
-//         ("jb4", PageSizeInMm(257.0, 364.0)),
-//         ("jb5", PageSizeInMm(182.0, 257.0)),
-//         ("jb6", PageSizeInMm(128.0, 182.0)),
-//         ("letter", PageSizeInMm(215.9, 279.4)),
-//         ("legal", PageSizeInMm(215.9, 355.6)),
-//         ("tabloid", PageSizeInMm(279.4, 431.8)),
-//     ]);
-
-//     if page_size_map.contains_key(pagesize) {
-//         return page_size_map.get(pagesize).unwrap().to_owned();
-//     }
-
-//     if pagesize.ends_with("^t") {
-//         let pdf_format = pagesize.split('^').nth(0).unwrap();
-//         if !page_size_map.contains_key(pdf_format) {
-//             panic!(
-//                 "PDF format {} is not recognized. Run {} to see valid pagesize value.",
-//                 pdf_format.blue().underline(),
-//                 "-h/--help".cyan()
-//             );
-//         };
-//         return page_size_map.get(pdf_format).unwrap().invert();
-//     }
-
-//     let float_regex_str = r"\d+(\.\d+)?";
-//     let float_size_regex_str = format!("{}(?:mm|cm|in)", float_regex_str);
-//     let pagesize_regex_str = format!("^{}x{}$", float_size_regex_str, float_size_regex_str);
-
-//     let customized_pagesize_regex = Regex::new(pagesize_regex_str.as_str()).unwrap();
-
-//     if customized_pagesize_regex.is_match(pagesize) {
-//         let mut pagesize_split = pagesize.split('x');
-//         let width_str = pagesize_split.next().unwrap();
-//         let height_str = pagesize_split.next().unwrap();
-
-//         let get_size_in_mm = |size_str: &str| {
-//             let size_num = &size_str[..size_str.len() - 2].parse::<f64>().unwrap();
-//             let size_unit = &size_str[(size_str.len() - 2)..];
-
-//             match size_unit {
-//                 "mm" => *size_num,
-//                 "cm" => size_num * 10.0,
-//                 "in" => size_num * 25.4,
-//                 _ => panic!("Invalid unit"),
-//             }
-//         };
-//         return PageSizeInMm(get_size_in_mm(width_str), get_size_in_mm(height_str));
-//     }
-
-//     panic!(
-//         "Pagesize value {} is invalid. Run {} to see valid pagesize value.",
-//         pagesize.blue().underline(),
-//         "-h/--help".cyan()
-//     );
-// }
-
-// pub fn invert(&self) -> Self {
-//     let PageSizeInMm(width, height) = *self;
-//     PageSizeInMm(height, width)
-// }
-//}
+pub const A4: PageSizeInMm = PageSizeInMm(210.0, 297.0);
+
+const NAMED_PAGE_SIZES: [(&str, PageSizeInMm); 24] = [
+    ("a0", PageSizeInMm(841.0, 1189.0)),
+    ("a1", PageSizeInMm(594.0, 841.0)),
+    ("a2", PageSizeInMm(420.0, 594.0)),
+    ("a3", PageSizeInMm(297.0, 420.0)),
+    ("a4", A4),
+    ("a5", PageSizeInMm(148.0, 210.0)),
+    ("a6", PageSizeInMm(105.0, 148.0)),
+    ("b0", PageSizeInMm(1000.0, 1414.0)),
+    ("b1", PageSizeInMm(707.0, 1000.0)),
+    ("b2", PageSizeInMm(500.0, 707.0)),
+    ("b3", PageSizeInMm(353.0, 500.0)),
+    ("b4", PageSizeInMm(250.0, 353.0)),
+    ("b5", PageSizeInMm(176.0, 250.0)),
+    ("b6", PageSizeInMm(125.0, 176.0)),
+    ("jb0", PageSizeInMm(1030.0, 1456.0)),
+    ("jb1", PageSizeInMm(728.0, 1030.0)),
+    ("jb2", PageSizeInMm(515.0, 728.0)),
+    ("jb3", PageSizeInMm(364.0, 515.0)),
+    ("jb4", PageSizeInMm(257.0, 364.0)),
+    ("jb5", PageSizeInMm(182.0, 257.0)),
+    ("jb6", PageSizeInMm(128.0, 182.0)),
+    ("letter", PageSizeInMm(215.9, 279.4)),
+    ("legal", PageSizeInMm(215.9, 355.6)),
+    ("tabloid", PageSizeInMm(279.4, 431.8)),
+];
+
+const NUMBER_REGEX: &str = r"\d+(?:\.\d+)?";
+const UNITS_REGEX: &str = "mm|cm|in|pt";
+
+const MM_PER_INCH: f64 = 25.4;
+
+fn length_in_mm(length: f64, unit: &str) -> f64 {
+    match unit {
+        "cm" => length * 10.0,
+        "in" => length * MM_PER_INCH,
+        "pt" => length * MM_PER_INCH / 72.0,
+        _ => length,
+    }
+}
+
+// Parses a length in millimeters, centimeters, inches or points, e.g. `12mm`,
+// `0.5in` or `36pt`.
+pub fn parse_length(length: &str) -> Result<f64> {
+    let length = length.trim().to_lowercase();
+    let length_regex = Regex::new(&format!(
+        r"^({number})\s*({units})$",
+        number = NUMBER_REGEX,
+        units = UNITS_REGEX
+    ))?;
+    let Some(captures) = length_regex.captures(&length) else {
+        bail!(
+            "Length {} is invalid, expected a number followed by mm, cm, in or pt",
+            length.blue().underline()
+        );
+    };
+    Ok(length_in_mm(captures[1].parse()?, &captures[2]))
+}
+
+impl PageSizeInMm {
+    // Parses a named size (`a4`, `letter`, ...), turned to landscape with a
+    // `^t` suffix, or a custom size in mm, cm, in or pt such as `210mmx297mm`
+    // or `8.5x11in`.
+    pub fn new(pagesize: &str) -> Result<Self> {
+        let pagesize = pagesize.to_lowercase().trim().to_string();
+        let pagesize = pagesize.as_str();
+        let named_page_size = |name: &str| {
+            NAMED_PAGE_SIZES
+                .iter()
+                .find(|(candidate, _)| *candidate == name)
+                .map(|(_, page_size)| page_size.clone())
+        };
+
+        if let Some(page_size) = named_page_size(pagesize) {
+            return Ok(page_size);
+        }
+
+        if let Some(pdf_format) = pagesize.strip_suffix("^t") {
+            let Some(page_size) = named_page_size(pdf_format) else {
+                bail!(
+                    "PDF format {} is not recognized. Run {} to see valid pagesize value.",
+                    pdf_format.blue().underline(),
+                    "-h/--help".cyan()
+                );
+            };
+            return Ok(page_size.invert());
+        }
+
+        // The unit of the width may be left out when both share one, as in
+        // `8.5x11in`.
+        let customized_pagesize_regex = Regex::new(&format!(
+            r"^({number})\s*({units})?\s*x\s*({number})\s*({units})$",
+            number = NUMBER_REGEX,
+            units = UNITS_REGEX
+        ))?;
+
+        if let Some(captures) = customized_pagesize_regex.captures(pagesize) {
+            let height_unit = &captures[4];
+            let width_unit = captures.get(2).map_or(height_unit, |unit| unit.as_str());
+            return Ok(PageSizeInMm(
+                length_in_mm(captures[1].parse()?, width_unit),
+                length_in_mm(captures[3].parse()?, height_unit),
+            ));
+        }
+
+        bail!(
+            "Pagesize value {} is invalid, expected a name such as a4 or a size with units such \
+             as 8.5x11in. Run {} to see valid pagesize value.",
+            pagesize.blue().underline(),
+            "-h/--help".cyan()
+        );
+    }
+
+    pub fn invert(&self) -> Self {
+        let PageSizeInMm(width, height) = *self;
+        PageSizeInMm(height, width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_size(size: &str, width: f64, height: f64) {
+        let PageSizeInMm(actual_width, actual_height) = PageSizeInMm::new(size).unwrap();
+        assert!((actual_width - width).abs() < 1e-9, "width of {}", size);
+        assert!((actual_height - height).abs() < 1e-9, "height of {}", size);
+    }
+
+    #[test]
+    fn parses_lengths_in_every_unit() {
+        assert_eq!(parse_length("12mm").unwrap(), 12.0);
+        assert_eq!(parse_length("1.5cm").unwrap(), 15.0);
+        assert_eq!(parse_length("0.5 in").unwrap(), 12.7);
+        assert_eq!(parse_length("72PT").unwrap(), 25.4);
+        assert!(parse_length("12").is_err());
+        assert!(parse_length("12px").is_err());
+        assert!(parse_length("-1mm").is_err());
+    }
+
+    #[test]
+    fn parses_named_sizes() {
+        assert_size("a4", 210.0, 297.0);
+        assert_size(" Letter ", 215.9, 279.4);
+        assert_size("a3^t", 420.0, 297.0);
+        assert!(PageSizeInMm::new("a11").is_err());
+        assert!(PageSizeInMm::new("a11^t").is_err());
+    }
+
+    #[test]
+    fn parses_custom_sizes() {
+        assert_size("210mmx297mm", 210.0, 297.0);
+        assert_size("8.5x11in", 215.9, 279.4);
+        assert_size("10cm x 4in", 100.0, 101.6);
+        assert_size("612x792pt", 215.9, 279.4);
+        assert!(PageSizeInMm::new("210x297").is_err());
+        assert!(PageSizeInMm::new("210mm").is_err());
+    }
+}
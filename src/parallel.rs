@@ -0,0 +1,74 @@
+// Iteration over all the cores through rayon with the `native` feature, and on
+// the calling thread without it, e.g. on `wasm32-unknown-unknown`, with the
+// same method names.
+#[cfg(feature = "native")]
+pub(crate) use rayon::{current_num_threads, prelude::*};
+
+#[cfg(not(feature = "native"))]
+pub(crate) use sequential::*;
+
+#[cfg(not(feature = "native"))]
+mod sequential {
+    use std::slice::{ChunksExact, ChunksExactMut, ChunksMut, Iter};
+
+    pub(crate) fn current_num_threads() -> usize {
+        1
+    }
+
+    pub(crate) trait IntoParallelIterator: IntoIterator + Sized {
+        fn into_par_iter(self) -> Self::IntoIter {
+            self.into_iter()
+        }
+    }
+
+    impl<T: IntoIterator> IntoParallelIterator for T {}
+
+    pub(crate) trait ParallelIterMut {
+        fn par_iter_mut<'a>(&'a mut self) -> <&'a mut Self as IntoIterator>::IntoIter
+        where
+            &'a mut Self: IntoIterator,
+        {
+            self.into_iter()
+        }
+    }
+
+    impl<T: ?Sized> ParallelIterMut for T {}
+
+    pub(crate) trait IndexedParallelIterator: Iterator + Sized {
+        fn with_min_len(self, _min: usize) -> Self {
+            self
+        }
+    }
+
+    impl<I: Iterator> IndexedParallelIterator for I {}
+
+    pub(crate) trait ParallelSlice<T> {
+        fn par_iter(&self) -> Iter<'_, T>;
+        fn par_chunks_exact(&self, size: usize) -> ChunksExact<'_, T>;
+    }
+
+    impl<T> ParallelSlice<T> for [T] {
+        fn par_iter(&self) -> Iter<'_, T> {
+            self.iter()
+        }
+
+        fn par_chunks_exact(&self, size: usize) -> ChunksExact<'_, T> {
+            self.chunks_exact(size)
+        }
+    }
+
+    pub(crate) trait ParallelSliceMut<T> {
+        fn par_chunks_mut(&mut self, size: usize) -> ChunksMut<'_, T>;
+        fn par_chunks_exact_mut(&mut self, size: usize) -> ChunksExactMut<'_, T>;
+    }
+
+    impl<T> ParallelSliceMut<T> for [T] {
+        fn par_chunks_mut(&mut self, size: usize) -> ChunksMut<'_, T> {
+            self.chunks_mut(size)
+        }
+
+        fn par_chunks_exact_mut(&mut self, size: usize) -> ChunksExactMut<'_, T> {
+            self.chunks_exact_mut(size)
+        }
+    }
+}
@@ -0,0 +1,200 @@
+// Passwords of the encrypted inputs, asked on the terminal when they are not
+// given in the plan.
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, IsTerminal, Write},
+};
+
+use anyhow::{Context, Result, bail};
+use pm::encryption::decrypt;
+
+use crate::{input::LoadedInput, keyring, log};
+
+const ATTEMPTS: usize = 3;
+
+// The terminal, even when stdin is a pipe (e.g. `-` as an input).
+#[cfg(unix)]
+fn open_terminal() -> io::Result<File> {
+    OpenOptions::new().read(true).write(true).open("/dev/tty")
+}
+
+#[cfg(windows)]
+fn open_terminal() -> io::Result<File> {
+    OpenOptions::new().read(true).write(true).open("CONIN$")
+}
+
+// Hides what is typed on the terminal until dropped, so that the settings are
+// restored however the reading ends. Ctrl-C is read as a character instead of
+// killing the process, which would leave the echo off.
+struct HiddenInput {
+    terminal: File,
+    #[cfg(unix)]
+    saved: String,
+    #[cfg(windows)]
+    saved: windows_sys::Win32::System::Console::CONSOLE_MODE,
+}
+
+// Runs `stty` on the terminal it is given as stdin.
+#[cfg(unix)]
+fn stty(terminal: &File, args: &[&str]) -> io::Result<String> {
+    use std::process::{Command, Stdio};
+
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::from(terminal.try_clone()?))
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other("stty failed"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// Turns the echo and the signals off, and makes Ctrl-C end the line as Enter
+// does.
+#[cfg(unix)]
+impl HiddenInput {
+    fn new(terminal: &File) -> io::Result<Self> {
+        let saved = stty(terminal, &["-g"])?;
+        stty(terminal, &["-echo", "-isig", "eol", "^C"])?;
+        Ok(Self {
+            terminal: terminal.try_clone()?,
+            saved,
+        })
+    }
+
+    fn restore(&self) -> io::Result<()> {
+        stty(&self.terminal, &[&self.saved]).map(drop)
+    }
+}
+
+#[cfg(windows)]
+fn set_console_mode(
+    terminal: &File,
+    mode: windows_sys::Win32::System::Console::CONSOLE_MODE,
+) -> io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+
+    use windows_sys::Win32::System::Console::SetConsoleMode;
+
+    if unsafe { SetConsoleMode(terminal.as_raw_handle(), mode) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Clears `ENABLE_ECHO_INPUT` and `ENABLE_PROCESSED_INPUT` in the mode of the
+// console.
+#[cfg(windows)]
+impl HiddenInput {
+    fn new(terminal: &File) -> io::Result<Self> {
+        use std::os::windows::io::AsRawHandle;
+
+        use windows_sys::Win32::System::Console::{
+            ENABLE_ECHO_INPUT, ENABLE_PROCESSED_INPUT, GetConsoleMode,
+        };
+
+        let mut saved = 0;
+        if unsafe { GetConsoleMode(terminal.as_raw_handle(), &mut saved) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let hidden = Self {
+            terminal: terminal.try_clone()?,
+            saved,
+        };
+        set_console_mode(
+            terminal,
+            saved & !(ENABLE_ECHO_INPUT | ENABLE_PROCESSED_INPUT),
+        )?;
+        Ok(hidden)
+    }
+
+    fn restore(&self) -> io::Result<()> {
+        set_console_mode(&self.terminal, self.saved)
+    }
+}
+
+impl Drop for HiddenInput {
+    fn drop(&mut self) {
+        let _ = self.restore();
+    }
+}
+
+pub fn read_hidden_line(prompt: &str) -> io::Result<String> {
+    let terminal = open_terminal()?;
+    let mut output: Box<dyn Write> = if cfg!(windows) {
+        Box::new(io::stderr())
+    } else {
+        Box::new(terminal.try_clone()?)
+    };
+    write!(output, "{}", prompt)?;
+    output.flush()?;
+
+    let hidden = HiddenInput::new(&terminal)?;
+    let mut line = String::new();
+    let read = BufReader::new(&terminal).read_line(&mut line);
+    drop(hidden);
+    writeln!(output)?;
+    read?;
+    if line.contains('\u{3}') {
+        return Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"));
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn has_terminal() -> bool {
+    io::stderr().is_terminal() && open_terminal().is_ok()
+}
+
+// Decrypts the encrypted inputs, most of which only have an owner password
+// and open with an empty one. The others need their password, taken from the
+// keyring under `password_key`, or else asked on the terminal unless `prompt`
+// is false.
+pub fn unlock_inputs(
+    inputs: &mut [LoadedInput],
+    password_key: Option<&str>,
+    prompt: bool,
+) -> Result<()> {
+    let stored_password = match password_key {
+        Some(key) => keyring::get(key)?,
+        None => None,
+    };
+    for input in inputs {
+        if !input.document.is_encrypted() || decrypt(&mut input.document, "").is_ok() {
+            continue;
+        }
+        if let Some(password) = &stored_password
+            && decrypt(&mut input.document, password).is_ok()
+        {
+            continue;
+        }
+        if !prompt || !has_terminal() {
+            let reason = if prompt {
+                "there is no terminal to ask for it"
+            } else {
+                "--no-prompt forbids asking for it"
+            };
+            bail!(
+                "{} needs a password and {}, give it through --password-key or a --plan",
+                input.path.display(),
+                reason
+            );
+        }
+
+        let prompt = format!("Password for {}: ", input.path.display());
+        let mut unlocked = false;
+        for _ in 0..ATTEMPTS {
+            let password =
+                read_hidden_line(&prompt).context("cannot read the password from the terminal")?;
+            if decrypt(&mut input.document, &password).is_ok() {
+                unlocked = true;
+                break;
+            }
+            log::warning("wrong password");
+        }
+        if !unlocked {
+            bail!("cannot decrypt {}: wrong password", input.path.display());
+        }
+    }
+    Ok(())
+}
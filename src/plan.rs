@@ -0,0 +1,370 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use pm::{
+    merge::{BookmarkStyle, FontStyle, parse_color},
+    page::{PageOperations, PageRanges},
+};
+
+use crate::remote::is_url;
+
+// One line of a merge plan.
+pub struct PlanEntry {
+    // Empty for a blank page.
+    pub path: PathBuf,
+    pub blank: bool,
+    pub pages: Option<PageRanges>,
+    pub page_operations: Option<PageOperations>,
+    pub rotation: i64,
+    // Pages dropped at the start and the end, instead of --skip-first and
+    // --skip-last.
+    pub skip_first: Option<usize>,
+    pub skip_last: Option<usize>,
+    // Number of times the input is included in a row.
+    pub repeat: usize,
+    pub bookmark_title: Option<String>,
+    pub bookmark_style: BookmarkStyle,
+    pub password: Option<String>,
+    // Key of the password in the keyring, when the password is not given.
+    pub password_key: Option<String>,
+}
+
+// Splits CSV records, honoring quoted fields (which may contain delimiters,
+// doubled quotes and line breaks).
+fn parse_csv(content: &str, delimiter: char) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => in_quotes = !in_quotes,
+            c if c == delimiter && !in_quotes => record.push(std::mem::take(&mut field)),
+            '\r' if !in_quotes => {}
+            '\n' if !in_quotes => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+
+    records
+        .into_iter()
+        .filter(|record| record.iter().any(|field| !field.trim().is_empty()))
+        .collect()
+}
+
+fn parse_yes_no(line: usize, column: &str, value: &str) -> Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "yes" | "true" | "1" => Ok(true),
+        "no" | "false" | "0" => Ok(false),
+        _ => bail!(
+            "line {}: {} must be yes or no, got {:?}",
+            line,
+            column,
+            value
+        ),
+    }
+}
+
+fn column_index(header: &[String], names: &[&str]) -> Option<usize> {
+    header.iter().position(|column| {
+        names
+            .iter()
+            .any(|name| column.trim().eq_ignore_ascii_case(name))
+    })
+}
+
+// Reads a CSV merge plan. The first line names the columns: `path` (required),
+// `pages` (e.g. `1-3,5`), `page operations` (on single pages, numbered like
+// `pages`, e.g. `3:rotate=90 5:skip 7:scale=0.5`), `rotation` (a multiple of
+// 90), `skip first` and `skip last` (pages dropped before `pages` applies),
+// `repeat` (number of times the input is included in a row), `title` (bookmark
+// title), `color` (bookmark color, `#RRGGBB`), `style` (bookmark font style),
+// `open` (whether the bookmark is expanded, yes or no), `password` (of an
+// encrypted PDF), `password key` (of a password in the keyring) and `blank`
+// (yes for a blank page instead of a file, the bookmark only shown with a
+// title), in any order. Spreadsheets in some locales export with `;`, which is
+// detected from the header. Relative paths are resolved from the plan's
+// directory.
+pub fn read_csv_plan(plan_path: &Path) -> Result<Vec<PlanEntry>> {
+    let content = fs::read_to_string(plan_path)
+        .with_context(|| format!("cannot read {}", plan_path.display()))?;
+    parse_csv_plan(&content, plan_path.parent().unwrap_or(Path::new("")))
+}
+
+// Same as `read_csv_plan` for the content of a plan, its relative paths being
+// resolved from `base_dir`.
+fn parse_csv_plan(content: &str, base_dir: &Path) -> Result<Vec<PlanEntry>> {
+    let content = content.trim_start_matches('\u{feff}');
+    let header_line = content.lines().next().unwrap_or_default();
+    let delimiter = if header_line.matches(';').count() > header_line.matches(',').count() {
+        ';'
+    } else {
+        ','
+    };
+
+    let mut records = parse_csv(content, delimiter).into_iter();
+    let header = records.next().context("the plan is empty")?;
+    let path_column =
+        column_index(&header, &["path", "file"]).context("the plan has no \"path\" column")?;
+    let pages_column = column_index(&header, &["pages", "page range", "range"]);
+    let page_operations_column = column_index(&header, &["page operations", "page ops"]);
+    let rotation_column = column_index(&header, &["rotation", "rotate"]);
+    let skip_first_column = column_index(&header, &["skip first", "skip_first"]);
+    let skip_last_column = column_index(&header, &["skip last", "skip_last"]);
+    let repeat_column = column_index(&header, &["repeat", "copies"]);
+    let title_column = column_index(&header, &["title", "bookmark", "bookmark title"]);
+    let color_column = column_index(&header, &["color", "bookmark color"]);
+    let style_column = column_index(&header, &["style", "bookmark style"]);
+    let open_column = column_index(&header, &["open", "bookmark open"]);
+    let password_column = column_index(&header, &["password"]);
+    let password_key_column = column_index(&header, &["password key", "keyring"]);
+    let blank_column = column_index(&header, &["blank"]);
+
+    let mut entries = Vec::new();
+    for (index, record) in records.enumerate() {
+        let line = index + 2;
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|column| record.get(column))
+                .map(|field| field.trim())
+                .filter(|field| !field.is_empty())
+        };
+
+        let blank = field(blank_column)
+            .map(|blank| parse_yes_no(line, "blank", blank))
+            .transpose()?
+            .unwrap_or(false);
+        let path = match field(Some(path_column)) {
+            None if blank => PathBuf::new(),
+            None => bail!("line {}: missing path", line),
+            Some(path) => {
+                let path = PathBuf::from(path);
+                if path.is_absolute()
+                    || is_url(&path)
+                    || path.to_string_lossy().starts_with("s3://")
+                {
+                    path
+                } else {
+                    base_dir.join(path)
+                }
+            }
+        };
+
+        let pages = field(pages_column)
+            .map(PageRanges::parse)
+            .transpose()
+            .with_context(|| format!("line {}", line))?;
+
+        let page_operations = field(page_operations_column)
+            .map(PageOperations::parse)
+            .transpose()
+            .with_context(|| format!("line {}", line))?;
+
+        let rotation = match field(rotation_column) {
+            None => 0,
+            Some(rotation) => match rotation.parse::<i64>() {
+                Ok(rotation) if rotation % 90 == 0 => rotation,
+                _ => bail!(
+                    "line {}: rotation must be a multiple of 90, got {:?}",
+                    line,
+                    rotation
+                ),
+            },
+        };
+
+        let skip = |column: Option<usize>| match field(column) {
+            None => Ok(None),
+            Some(count) => count
+                .parse::<usize>()
+                .map(Some)
+                .with_context(|| format!("line {}: invalid page count {:?}", line, count)),
+        };
+        let skip_first = skip(skip_first_column)?;
+        let skip_last = skip(skip_last_column)?;
+
+        let repeat = match field(repeat_column) {
+            None => 1,
+            Some(repeat) => match repeat.parse::<usize>() {
+                Ok(repeat) if repeat > 0 => repeat,
+                _ => bail!("line {}: repeat must be at least 1, got {:?}", line, repeat),
+            },
+        };
+
+        let bookmark_style = BookmarkStyle {
+            color: field(color_column)
+                .map(parse_color)
+                .transpose()
+                .with_context(|| format!("line {}", line))?,
+            font: field(style_column)
+                .map(FontStyle::parse)
+                .transpose()
+                .with_context(|| format!("line {}", line))?,
+            open: field(open_column)
+                .map(|open| parse_yes_no(line, "open", open))
+                .transpose()?,
+        };
+
+        entries.push(PlanEntry {
+            path,
+            blank,
+            pages,
+            page_operations,
+            rotation,
+            skip_first,
+            skip_last,
+            repeat,
+            bookmark_title: field(title_column).map(String::from),
+            bookmark_style,
+            // Passwords are taken as is, surrounding spaces included.
+            password: password_column
+                .and_then(|column| record.get(column))
+                .filter(|password| !password.is_empty())
+                .cloned(),
+            password_key: field(password_key_column).map(String::from),
+        });
+    }
+    Ok(entries)
+}
+
+// Reads a merge plan, whose format is given by its extension.
+pub fn read_plan(plan_path: &Path) -> Result<Vec<PlanEntry>> {
+    let is_csv = plan_path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+    if !is_csv {
+        bail!(
+            "unsupported plan format {}, expected a .csv file",
+            plan_path.display()
+        );
+    }
+    read_csv_plan(plan_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> Result<Vec<PlanEntry>> {
+        parse_csv_plan(content, Path::new("plans"))
+    }
+
+    fn error(content: &str) -> String {
+        format!("{:#}", parse(content).err().unwrap())
+    }
+
+    #[test]
+    fn splits_quoted_fields() {
+        let records = parse_csv(
+            "path,title\r\n\"a,b.pdf\",\"Say \"\"hi\"\"\"\n\"c.pdf\",\"two\nlines\"\n\n , \n",
+            ',',
+        );
+        assert_eq!(
+            records,
+            [
+                vec!["path", "title"],
+                vec!["a,b.pdf", "Say \"hi\""],
+                vec!["c.pdf", "two\nlines"],
+            ]
+        );
+    }
+
+    #[test]
+    fn detects_semicolons_from_the_header() {
+        let entries = parse("\u{feff}path;title\n\"a;b.pdf\";Intro, part 1\n").unwrap();
+        assert_eq!(entries[0].path, Path::new("plans/a;b.pdf"));
+        assert_eq!(entries[0].bookmark_title.as_deref(), Some("Intro, part 1"));
+    }
+
+    #[test]
+    fn resolves_relative_paths_from_the_plan() {
+        let entries = parse("File\na.pdf\n/tmp/b.pdf\nhttps://example.com/c.pdf\n").unwrap();
+        let paths: Vec<&Path> = entries.iter().map(|entry| entry.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            [
+                Path::new("plans/a.pdf"),
+                Path::new("/tmp/b.pdf"),
+                Path::new("https://example.com/c.pdf"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_page_ranges_and_operations() {
+        let entries = parse("path,pages,page ops\na.pdf,\"1-3,5\",2:skip 5:rotate=90\n").unwrap();
+        let pages = entries[0].pages.as_ref().unwrap();
+        let included: Vec<u32> = (1..=6).filter(|page| pages.contains(*page)).collect();
+        assert_eq!(included, [1, 2, 3, 5]);
+        assert!(entries[0].page_operations.is_some());
+    }
+
+    #[test]
+    fn reads_rotation_bookmark_and_blank_columns() {
+        let entries = parse(
+            "path,rotation,title,color,style,open,repeat,skip first,blank\n\
+             a.pdf,-90,Intro,#ff0000,bold,no,2,1,\n\
+             ,,Notes,,,,,,yes\n",
+        )
+        .unwrap();
+        let entry = &entries[0];
+        assert_eq!(entry.rotation, -90);
+        assert_eq!(entry.bookmark_title.as_deref(), Some("Intro"));
+        assert_eq!(
+            entry.bookmark_style,
+            BookmarkStyle {
+                color: Some([1.0, 0.0, 0.0]),
+                font: Some(FontStyle::Bold),
+                open: Some(false),
+            }
+        );
+        assert_eq!(entry.repeat, 2);
+        assert_eq!(entry.skip_first, Some(1));
+        assert!(!entry.blank);
+        assert!(entries[1].blank);
+        assert_eq!(entries[1].path, PathBuf::new());
+        assert_eq!(entries[1].bookmark_style, BookmarkStyle::default());
+    }
+
+    #[test]
+    fn keeps_the_spaces_of_passwords() {
+        let entries = parse("path,password\na.pdf, secret \n").unwrap();
+        assert_eq!(entries[0].password.as_deref(), Some(" secret "));
+    }
+
+    #[test]
+    fn rejects_bad_rows_with_their_line_number() {
+        assert_eq!(error("title\nIntro\n"), "the plan has no \"path\" column");
+        assert_eq!(error("path,title\na.pdf,A\n,B\n"), "line 3: missing path");
+        assert_eq!(
+            error("path,rotation\na.pdf,45\n"),
+            "line 2: rotation must be a multiple of 90, got \"45\""
+        );
+        assert_eq!(
+            error("path,repeat\na.pdf,0\n"),
+            "line 2: repeat must be at least 1, got \"0\""
+        );
+        assert_eq!(
+            error("path,open\na.pdf,maybe\n"),
+            "line 2: open must be yes or no, got \"maybe\""
+        );
+        assert!(error("path,pages\na.pdf,1\nb.pdf,3-x\n").starts_with("line 3: "));
+        assert!(error("path,page ops\na.pdf,2:fold\n").starts_with("line 2: "));
+        assert!(error("path,skip first\na.pdf,-1\n").starts_with("line 2: invalid page count"));
+    }
+}
@@ -0,0 +1,87 @@
+// PDF portfolios (collections): a cover sheet whose actual content is the
+// files embedded in the document.
+use lopdf::{Dictionary, Document, Object, decode_text_string};
+
+use crate::source::is_pdf_data;
+
+pub fn is_portfolio(doc: &Document) -> bool {
+    doc.catalog()
+        .is_ok_and(|catalog| catalog.has(b"Collection"))
+}
+
+fn resolve<'a>(doc: &'a Document, object: &'a Object) -> Option<&'a Object> {
+    doc.dereference(object).ok().map(|(_, object)| object)
+}
+
+// Collects the values of a name tree, in key order.
+fn name_tree_values<'a>(doc: &'a Document, node: &'a Dictionary, values: &mut Vec<&'a Object>) {
+    if let Some(Ok(names)) = node
+        .get(b"Names")
+        .ok()
+        .and_then(|names| resolve(doc, names))
+        .map(Object::as_array)
+    {
+        values.extend(names.iter().skip(1).step_by(2));
+    }
+    if let Some(Ok(kids)) = node
+        .get(b"Kids")
+        .ok()
+        .and_then(|kids| resolve(doc, kids))
+        .map(Object::as_array)
+    {
+        for kid in kids {
+            if let Some(Ok(kid)) = resolve(doc, kid).map(Object::as_dict) {
+                name_tree_values(doc, kid, values);
+            }
+        }
+    }
+}
+
+// Name and content of an embedded file specification.
+fn embedded_file(doc: &Document, file_spec: &Dictionary) -> Option<(String, Vec<u8>)> {
+    let name = [b"UF".as_slice(), b"F"]
+        .into_iter()
+        .find_map(|key| decode_text_string(file_spec.get(key).ok()?).ok())
+        .unwrap_or_default();
+    let embedded = resolve(doc, file_spec.get(b"EF").ok()?)?.as_dict().ok()?;
+    let stream = [b"UF".as_slice(), b"F"]
+        .into_iter()
+        .find_map(|key| resolve(doc, embedded.get(key).ok()?)?.as_stream().ok())?;
+    let content = if stream.dict.has(b"Filter") {
+        stream.decompressed_content().ok()?
+    } else {
+        stream.content.clone()
+    };
+    Some((name, content))
+}
+
+// The PDF documents embedded in `doc`, with their file names, in the order of
+// the embedded files name tree. Embedded files that are not PDFs, or cannot
+// be parsed, are left out.
+pub fn embedded_pdfs(doc: &Document) -> Vec<(String, Document)> {
+    let Some(Ok(tree)) = doc
+        .catalog()
+        .and_then(|catalog| catalog.get(b"Names"))
+        .ok()
+        .and_then(|names| resolve(doc, names))
+        .and_then(|names| names.as_dict().ok()?.get(b"EmbeddedFiles").ok())
+        .and_then(|tree| resolve(doc, tree))
+        .map(Object::as_dict)
+    else {
+        return Vec::new();
+    };
+
+    let mut file_specs = Vec::new();
+    name_tree_values(doc, tree, &mut file_specs);
+    file_specs
+        .into_iter()
+        .filter_map(|file_spec| {
+            let file_spec = resolve(doc, file_spec)?.as_dict().ok()?;
+            let (name, content) = embedded_file(doc, file_spec)?;
+            if !is_pdf_data(&content) {
+                return None;
+            }
+            Some((name, Document::load_mem(&content).ok()?))
+        })
+        .collect()
+}
@@ -0,0 +1,88 @@
+// `--dry-run`: the inputs that would be merged, with their page counts and
+// sizes, read without loading the documents.
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use lopdf::{Document, Object, ObjectId};
+use pm::{
+    source::{is_image_file_name, is_pdf_file_name, is_text_file_name},
+    stamp::page_box,
+};
+
+use crate::{
+    input::{is_stdin, sniff_file},
+    remote::is_url,
+    s3::is_s3_url,
+};
+
+const MM_PER_POINT: f32 = 25.4 / 72.0;
+
+// Keeps the objects of the document structure, and the object streams they may
+// be stored in, dropping the page contents, images and fonts which make most of
+// the bytes of a document. lopdf keeps the object it passed for the objects
+// of the file, and the returned one for those of object streams.
+fn keep_structure(object_id: ObjectId, object: &mut Object) -> Option<(ObjectId, Object)> {
+    match object {
+        Object::Stream(stream) if !stream.dict.has_type(b"ObjStm") => None,
+        object => Some((object_id, object.clone())),
+    }
+}
+
+// Number of pages of a PDF and size of its first page in millimeters, from its
+// page tree only.
+fn scan_pdf(path: &Path) -> Result<(usize, Option<(f32, f32)>)> {
+    let document = Document::load_filtered(path, keep_structure)?;
+    let pages = document.get_pages();
+    let size = pages.values().next().map(|&page_id| {
+        let [x0, y0, x1, y1] = page_box(&document, page_id);
+        (
+            (x1 - x0).abs() * MM_PER_POINT,
+            (y1 - y0).abs() * MM_PER_POINT,
+        )
+    });
+    Ok((pages.len(), size))
+}
+
+pub fn print_dry_run(files: &[PathBuf]) {
+    let mut total_pages = 0;
+    let mut unknown = 0;
+    for path in files {
+        let description = if is_stdin(path) || is_url(path) || is_s3_url(path) {
+            unknown += 1;
+            "not scanned".to_string()
+        } else if is_image_file_name(path)
+            || !is_pdf_file_name(path) && matches!(sniff_file(path), Some(kind) if kind != "pdf")
+        {
+            total_pages += 1;
+            "image".to_string()
+        } else if is_text_file_name(path) {
+            unknown += 1;
+            "text".to_string()
+        } else if is_pdf_file_name(path) || sniff_file(path) == Some("pdf") {
+            match scan_pdf(path) {
+                Ok((pages, size)) => {
+                    total_pages += pages;
+                    match size {
+                        Some((width, height)) => {
+                            format!("{} pages, {:.0}x{:.0} mm", pages, width, height)
+                        }
+                        None => format!("{} pages", pages),
+                    }
+                }
+                Err(e) => {
+                    unknown += 1;
+                    format!("unreadable: {}", e)
+                }
+            }
+        } else {
+            unknown += 1;
+            "not scanned".to_string()
+        };
+        println!("{}  {}", path.display(), description);
+    }
+    let mut summary = format!("{} files, {} pages", files.len(), total_pages);
+    if unknown > 0 {
+        summary.push_str(&format!(", not counting {} files", unknown));
+    }
+    println!("{}", summary);
+}
@@ -0,0 +1,151 @@
+// Python module `pdf_merge`, enabled with the `python` feature and built with
+// maturin (see `python/pyproject.toml`). `merge(paths, output, options)`
+// merges the PDF and image files of `paths` into `output` and returns where
+// every page of the merged document comes from, as the merge placed them.
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+use pyo3::{create_exception, exceptions::PyException, prelude::*, types::PyDict};
+
+use crate::{
+    compression::CompressionLevel,
+    image::image_document::ImageOptions,
+    merge::{BookmarkMode, DestinationFit, Merger, OpenActionSource, save_atomically},
+    source::load_file,
+};
+
+create_exception!(pdf_merge, PdfMergeError, PyException);
+
+fn to_py_err(error: anyhow::Error) -> PyErr {
+    PdfMergeError::new_err(format!("{:#}", error))
+}
+
+// Where a page of the merged document comes from (pages count from 1).
+#[pyclass(frozen, get_all, module = "pdf_merge")]
+pub struct PageSource {
+    output_page: usize,
+    source: PathBuf,
+    source_page: usize,
+}
+
+#[pymethods]
+impl PageSource {
+    fn __repr__(&self) -> String {
+        format!(
+            "PageSource(output_page={}, source={:?}, source_page={})",
+            self.output_page, self.source, self.source_page
+        )
+    }
+}
+
+// Keys of the `options` dict of `merge`, named after the command line options.
+#[derive(Default)]
+struct MergeOptions {
+    bookmarks: Option<BookmarkMode>,
+    bookmark_fit: Option<DestinationFit>,
+    compression: Option<CompressionLevel>,
+    open_action: Option<OpenActionSource>,
+    outline_depth: Option<usize>,
+    numbered_bookmarks: bool,
+}
+
+impl MergeOptions {
+    fn extract(options: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let mut merge_options = MergeOptions::default();
+        for (key, value) in options.into_iter().flatten() {
+            let key: String = key.extract()?;
+            let parsed: Result<()> = match key.as_str() {
+                "bookmarks" => BookmarkMode::parse(&value.extract::<String>()?)
+                    .map(|mode| merge_options.bookmarks = Some(mode)),
+                "bookmark_fit" => DestinationFit::parse(&value.extract::<String>()?)
+                    .map(|fit| merge_options.bookmark_fit = Some(fit)),
+                "compression" => CompressionLevel::parse(&value.extract::<String>()?)
+                    .map(|level| merge_options.compression = Some(level)),
+                "open_action" => OpenActionSource::parse(&value.extract::<String>()?)
+                    .map(|source| merge_options.open_action = Some(source)),
+                "outline_depth" => {
+                    merge_options.outline_depth = Some(value.extract()?);
+                    Ok(())
+                }
+                "numbered_bookmarks" => {
+                    merge_options.numbered_bookmarks = value.extract()?;
+                    Ok(())
+                }
+                _ => Err(anyhow::anyhow!(
+                    "unknown option {:?}, expected bookmarks, bookmark_fit, compression, \
+                     open_action, outline_depth or numbered_bookmarks",
+                    key
+                )),
+            };
+            parsed.map_err(to_py_err)?;
+        }
+        Ok(merge_options)
+    }
+
+    fn apply(&self, mut merger: Merger) -> Merger {
+        if let Some(mode) = self.bookmarks {
+            merger = merger.bookmarks(mode);
+        }
+        if let Some(fit) = self.bookmark_fit {
+            merger = merger.destination_fit(fit);
+        }
+        if let Some(level) = self.compression {
+            merger = merger.compression(level);
+        }
+        if let Some(source) = self.open_action {
+            merger = merger.open_action(source);
+        }
+        if let Some(depth) = self.outline_depth {
+            merger = merger.outline_depth(depth);
+        }
+        merger.numbered_bookmarks(self.numbered_bookmarks)
+    }
+}
+
+fn merge_files(
+    paths: &[PathBuf],
+    output: &Path,
+    options: &MergeOptions,
+) -> Result<Vec<PageSource>> {
+    if paths.is_empty() {
+        bail!("no input to merge");
+    }
+    let documents = paths
+        .iter()
+        .map(|path| load_file(path, &ImageOptions::default()))
+        .collect::<Result<Vec<_>>>()?;
+    let (mut document, sources) = options.apply(Merger::new(documents)).merge_with_sources()?;
+    save_atomically(&mut document, output, None)?;
+    Ok(sources
+        .into_iter()
+        .enumerate()
+        .map(|(index, source)| PageSource {
+            output_page: index + 1,
+            source: paths[source.input].clone(),
+            source_page: source.page,
+        })
+        .collect())
+}
+
+/// Merges the PDF and image files of `paths`, in order, into `output`, and
+/// returns the `PageSource` of every page of the merged document.
+#[pyfunction]
+#[pyo3(signature = (paths, output, options = None))]
+fn merge(
+    py: Python<'_>,
+    paths: Vec<PathBuf>,
+    output: PathBuf,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<Vec<PageSource>> {
+    let options = MergeOptions::extract(options)?;
+    py.detach(|| merge_files(&paths, &output, &options))
+        .map_err(to_py_err)
+}
+
+#[pymodule]
+fn pdf_merge(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(merge, module)?)?;
+    module.add_class::<PageSource>()?;
+    module.add("PdfMergeError", module.py().get_type::<PdfMergeError>())?;
+    Ok(())
+}
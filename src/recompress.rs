@@ -0,0 +1,174 @@
+// Downsampling of the images drawn at a higher resolution than needed, which
+// shrinks bundles of scans made at 600 DPI to be read on a screen. The
+// resolution of an image comes from the size it is drawn at on the pages, so
+// images drawn in forms, whose size is unknown here, are left as they are.
+use std::collections::BTreeMap;
+
+use image::{DynamicImage, codecs::jpeg::JpegEncoder, imageops::FilterType};
+use lopdf::{Document, Object, ObjectId, Stream, content::Content};
+
+use crate::{page::get_inherited, thumbnail::decode_image};
+
+const POINTS_PER_INCH: f32 = 72.0;
+const JPEG_QUALITY: u8 = 85;
+
+// `[a b c d e f]` of the `cm` operator.
+type Matrix = [f32; 6];
+
+const IDENTITY: Matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+// `m` applied before `n`.
+fn multiply(m: &Matrix, n: &Matrix) -> Matrix {
+    [
+        m[0] * n[0] + m[1] * n[2],
+        m[0] * n[1] + m[1] * n[3],
+        m[2] * n[0] + m[3] * n[2],
+        m[2] * n[1] + m[3] * n[3],
+        m[4] * n[0] + m[5] * n[2] + n[4],
+        m[4] * n[1] + m[5] * n[3] + n[5],
+    ]
+}
+
+// Image XObjects of the page by resource name.
+fn page_images(doc: &Document, page_id: ObjectId) -> BTreeMap<Vec<u8>, ObjectId> {
+    let Some(Ok((_, Object::Dictionary(resources)))) =
+        get_inherited(doc, page_id, b"Resources").map(|resources| doc.dereference(resources))
+    else {
+        return BTreeMap::new();
+    };
+    let Ok((_, Object::Dictionary(xobjects))) = resources
+        .get(b"XObject")
+        .and_then(|xobjects| doc.dereference(xobjects))
+    else {
+        return BTreeMap::new();
+    };
+    xobjects
+        .iter()
+        .filter_map(|(name, xobject)| {
+            let id = xobject.as_reference().ok()?;
+            let stream = doc.get_object(id).and_then(Object::as_stream).ok()?;
+            let subtype = stream.dict.get(b"Subtype").and_then(Object::as_name).ok()?;
+            (subtype == b"Image").then_some((name.clone(), id))
+        })
+        .collect()
+}
+
+// Largest size, in points, each image is drawn at on the pages: the unit
+// square of the image goes through the current transformation matrix.
+fn drawn_sizes(doc: &Document) -> BTreeMap<ObjectId, (f32, f32)> {
+    let mut sizes: BTreeMap<ObjectId, (f32, f32)> = BTreeMap::new();
+    for page_id in doc.get_pages().into_values() {
+        let images = page_images(doc, page_id);
+        if images.is_empty() {
+            continue;
+        }
+        let Ok(content) = doc
+            .get_page_content(page_id)
+            .and_then(|content| Content::decode(&content))
+        else {
+            continue;
+        };
+        let mut matrix = IDENTITY;
+        let mut saved = Vec::new();
+        for operation in content.operations {
+            match operation.operator.as_str() {
+                "q" => saved.push(matrix),
+                "Q" => matrix = saved.pop().unwrap_or(IDENTITY),
+                "cm" => {
+                    let operands: Vec<f32> = operation
+                        .operands
+                        .iter()
+                        .filter_map(|operand| operand.as_float().ok())
+                        .collect();
+                    if let Ok(cm) = Matrix::try_from(operands) {
+                        matrix = multiply(&cm, &matrix);
+                    }
+                }
+                "Do" => {
+                    let Some(&id) = operation
+                        .operands
+                        .first()
+                        .and_then(|name| name.as_name().ok())
+                        .and_then(|name| images.get(name))
+                    else {
+                        continue;
+                    };
+                    let width = matrix[0].hypot(matrix[1]);
+                    let height = matrix[2].hypot(matrix[3]);
+                    let size = sizes.entry(id).or_default();
+                    *size = (size.0.max(width), size.1.max(height));
+                }
+                _ => {}
+            }
+        }
+    }
+    sizes
+}
+
+// The image at `max_dpi` once drawn `size` points large, `None` when it is
+// not above it, or when its encoding is not one that can be decoded here.
+fn downsample(stream: &Stream, size: (f32, f32), max_dpi: f32) -> Option<Stream> {
+    let dict = &stream.dict;
+    // Masks, decode arrays and color spaces other than RGB and gray would
+    // need more than the pixels to be redrawn the same.
+    let color_space = dict.get(b"ColorSpace").and_then(Object::as_name).ok()?;
+    if dict.has(b"Decode")
+        || dict.has(b"ImageMask")
+        || !matches!(color_space, b"DeviceRGB" | b"DeviceGray")
+    {
+        return None;
+    }
+    let image = decode_image(stream)?;
+    let dpi_x = image.width() as f32 / (size.0 / POINTS_PER_INCH);
+    let dpi_y = image.height() as f32 / (size.1 / POINTS_PER_INCH);
+    let scale = max_dpi / dpi_x.min(dpi_y);
+    if !scale.is_finite() || scale >= 1.0 {
+        return None;
+    }
+    let width = ((image.width() as f32 * scale).round() as u32).max(1);
+    let height = ((image.height() as f32 * scale).round() as u32).max(1);
+    let image = image.resize_exact(width, height, FilterType::CatmullRom);
+    let image = if color_space == b"DeviceGray" {
+        DynamicImage::ImageLuma8(image.into_luma8())
+    } else {
+        DynamicImage::ImageRgb8(image.into_rgb8())
+    };
+
+    let mut downsampled = stream.clone();
+    downsampled.dict.set("Width", width);
+    downsampled.dict.set("Height", height);
+    downsampled.dict.set("BitsPerComponent", 8);
+    downsampled.dict.remove(b"DecodeParms");
+    let is_jpeg = dict.get(b"Filter").and_then(Object::as_name).ok() == Some(b"DCTDecode");
+    if is_jpeg {
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY)
+            .encode_image(&image)
+            .ok()?;
+        downsampled.set_content(jpeg);
+    } else {
+        downsampled.dict.remove(b"Filter");
+        downsampled.set_content(image.into_bytes());
+        downsampled.compress().ok()?;
+    }
+    Some(downsampled)
+}
+
+// Downsamples the images drawn at more than `max_dpi` dots per inch, keeping
+// their format (JPEG or Flate), and returns how many were.
+pub fn recompress_images(doc: &mut Document, max_dpi: f64) -> usize {
+    let mut recompressed = 0;
+    for (id, size) in drawn_sizes(doc) {
+        let Ok(stream) = doc.get_object(id).and_then(Object::as_stream) else {
+            continue;
+        };
+        let Some(downsampled) = downsample(stream, size, max_dpi as f32) else {
+            continue;
+        };
+        if downsampled.content.len() < stream.content.len() {
+            doc.objects.insert(id, Object::Stream(downsampled));
+            recompressed += 1;
+        }
+    }
+    recompressed
+}
@@ -0,0 +1,453 @@
+// Redaction of the text matching patterns: the matching glyphs are removed
+// from the content streams, the text around them staying in place, and black
+// boxes are drawn where they were. Text is found as described in `text`, so
+// that the image of a scan with a text layer still holds what the box over it
+// hides.
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result, bail};
+use lopdf::{
+    Dictionary, Document, Object, ObjectId, Stream,
+    content::{Content, Operation},
+};
+use regex::Regex;
+
+use crate::{
+    stamp::append_content,
+    text::{PageText, TextLayout, TextWalker},
+};
+// The number of matches of `patterns` in the page, and the groups of glyphs
+// to cover with a box, one per match and line.
+fn matching_glyphs(text: &PageText, patterns: &[Regex]) -> (usize, Vec<Vec<usize>>) {
+    let TextLayout {
+        text: page_text,
+        spans,
+        lines,
+    } = text.layout();
+    let mut matches = 0;
+    let mut groups = Vec::new();
+    for pattern in patterns {
+        for found in pattern.find_iter(&page_text) {
+            let matched: Vec<usize> = (0..spans.len())
+                .filter(|index| {
+                    spans[*index].start < found.end() && found.start() < spans[*index].end
+                })
+                .collect();
+            if matched.is_empty() {
+                continue;
+            }
+            matches += 1;
+            let mut group: Vec<usize> = Vec::new();
+            for index in matched {
+                if group
+                    .last()
+                    .is_some_and(|last| lines[*last] != lines[index])
+                {
+                    groups.push(std::mem::take(&mut group));
+                }
+                group.push(index);
+            }
+            groups.push(group);
+        }
+    }
+    (matches, groups)
+}
+
+// The glyphs removed from a text showing operation: the end of their bytes
+// and their adjustment, by string index and start of their bytes.
+type RemovedGlyphs = BTreeMap<(usize, usize), (usize, f32)>;
+
+// Adds a `TJ` adjustment, to the previous one if the array ends with one.
+fn push_adjustment(items: &mut Vec<Object>, adjustment: f32) {
+    if let Some(Ok(previous)) = items.last().map(Object::as_float) {
+        items.pop();
+        items.push(Object::Real(previous + adjustment));
+    } else {
+        items.push(Object::Real(adjustment));
+    }
+}
+
+// The operations replacing a text showing operation without the removed
+// glyphs, given by string index and byte range with their adjustment. The
+// strings are turned into a `TJ` array where adjustments take the place of
+// the removed glyphs.
+fn redacted_operation(operation: &Operation, removed: &RemovedGlyphs) -> Vec<Operation> {
+    let operands = &operation.operands;
+    let (mut operations, items) = match operation.operator.as_str() {
+        "TJ" => (
+            Vec::new(),
+            operands
+                .first()
+                .and_then(|items| items.as_array().ok())
+                .cloned()
+                .unwrap_or_default(),
+        ),
+        "'" => (vec![Operation::new("T*", vec![])], operands.clone()),
+        "\"" if operands.len() == 3 => (
+            vec![
+                Operation::new("Tw", vec![operands[0].clone()]),
+                Operation::new("Tc", vec![operands[1].clone()]),
+                Operation::new("T*", vec![]),
+            ],
+            vec![operands[2].clone()],
+        ),
+        _ => (Vec::new(), operands.clone()),
+    };
+
+    let mut redacted = Vec::new();
+    for (item, object) in items.into_iter().enumerate() {
+        let Object::String(bytes, format) = object else {
+            match object.as_float() {
+                Ok(adjustment) => push_adjustment(&mut redacted, adjustment),
+                Err(_) => redacted.push(object),
+            }
+            continue;
+        };
+        let mut kept = 0;
+        for (&(_, start), &(end, adjustment)) in removed.range((item, 0)..(item + 1, 0)) {
+            if kept < start {
+                redacted.push(Object::String(bytes[kept..start].to_vec(), format));
+            }
+            push_adjustment(&mut redacted, adjustment);
+            kept = end;
+        }
+        if kept < bytes.len() {
+            redacted.push(Object::String(bytes[kept..].to_vec(), format));
+        }
+    }
+    operations.push(Operation::new("TJ", vec![Object::Array(redacted)]));
+    operations
+}
+
+// Encodes content operations, which lopdf cannot do for inline images.
+fn encode_operations(operations: &[Operation]) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    for operation in operations {
+        if let ("BI", Some(Object::Stream(image))) =
+            (operation.operator.as_str(), operation.operands.first())
+        {
+            let entries = image
+                .dict
+                .iter()
+                .filter(|(key, _)| key.as_slice() != b"Length")
+                .flat_map(|(key, value)| [Object::Name(key.clone()), value.clone()])
+                .collect();
+            content.extend(b"BI\n");
+            content.extend(
+                Content {
+                    operations: vec![Operation::new("ID", entries)],
+                }
+                .encode()?,
+            );
+            content.push(b' ');
+            content.extend(&image.content);
+            content.extend(b"\nEI\n");
+        } else {
+            content.extend(
+                Content {
+                    operations: std::slice::from_ref(operation),
+                }
+                .encode()?,
+            );
+            content.push(b'\n');
+        }
+    }
+    Ok(content)
+}
+
+fn compressed_stream(dictionary: Dictionary, content: Vec<u8>) -> Stream {
+    let mut stream = Stream::new(dictionary, content);
+    let _ = stream.compress();
+    stream
+}
+
+pub struct Redactions {
+    pub matches: usize,
+    pub pages: usize,
+    // The pages (1-based) with glyphs of unknown text, where the patterns
+    // may have missed text.
+    pub undecoded_pages: Vec<u32>,
+    // The pages (1-based) with boxes over images, whose pixels still show
+    // what the boxes hide.
+    pub image_pages: Vec<u32>,
+}
+
+fn overlaps(a: &[f32; 4], b: &[f32; 4]) -> bool {
+    a[0] < b[2] && b[0] < a[2] && a[1] < b[3] && b[1] < a[3]
+}
+
+// Removes the text matching any of `patterns` from the pages and draws black
+// boxes over where it was.
+pub fn redact(doc: &mut Document, patterns: &[Regex]) -> Result<Redactions> {
+    let mut redactions = Redactions {
+        matches: 0,
+        pages: 0,
+        undecoded_pages: Vec::new(),
+        image_pages: Vec::new(),
+    };
+    // Glyphs to remove by stream and operation, then by string and byte range.
+    let mut removed: BTreeMap<(ObjectId, usize), RemovedGlyphs> = BTreeMap::new();
+    let mut boxes = Vec::new();
+    let mut walker = TextWalker::new(doc);
+    for (page_number, page_id) in doc.get_pages() {
+        let text = walker
+            .page_text(page_id)
+            .with_context(|| format!("cannot read the text of page {}", page_number))?;
+        if text.undecoded {
+            redactions.undecoded_pages.push(page_number);
+        }
+        let (matches, groups) = matching_glyphs(&text, patterns);
+        if matches == 0 {
+            continue;
+        }
+        redactions.matches += matches;
+        redactions.pages += 1;
+
+        let mut content = b"0 g\n".to_vec();
+        let mut over_image = false;
+        for group in groups {
+            let rect = group.iter().map(|index| text.glyphs[*index].rect).fold(
+                [f32::MAX, f32::MAX, f32::MIN, f32::MIN],
+                |[x0, y0, x1, y1], rect| {
+                    [
+                        x0.min(rect[0]),
+                        y0.min(rect[1]),
+                        x1.max(rect[2]),
+                        y1.max(rect[3]),
+                    ]
+                },
+            );
+            over_image |= text.images.iter().any(|image| overlaps(&image.rect, &rect));
+            let [x0, y0, x1, y1] = rect;
+            content.extend(format!("{} {} {} {} re f\n", x0, y0, x1 - x0, y1 - y0).into_bytes());
+            for index in group {
+                let glyph = &text.glyphs[index];
+                removed
+                    .entry((glyph.stream, glyph.operation))
+                    .or_default()
+                    .insert(
+                        (glyph.item, glyph.bytes.start),
+                        (glyph.bytes.end, glyph.adjustment),
+                    );
+            }
+        }
+        if over_image {
+            redactions.image_pages.push(page_number);
+        }
+        boxes.push((page_id, content));
+    }
+
+    let contents = walker.contents;
+
+    // Operations replacing the redacted ones, by stream.
+    let mut replacements: BTreeMap<ObjectId, BTreeMap<usize, Vec<Operation>>> = BTreeMap::new();
+    for ((stream, index), removed) in &removed {
+        let Some(operation) = contents
+            .get(stream)
+            .and_then(|operations| operations.get(*index))
+        else {
+            bail!("cannot find the text to redact");
+        };
+        replacements
+            .entry(*stream)
+            .or_default()
+            .insert(*index, redacted_operation(operation, removed));
+    }
+
+    for (stream, mut replacements) in replacements {
+        let mut operations = Vec::new();
+        for (index, operation) in contents[&stream].iter().enumerate() {
+            match replacements.remove(&index) {
+                Some(replacement) => operations.extend(replacement),
+                None => operations.push(operation.clone()),
+            }
+        }
+        let content = encode_operations(&operations)?;
+        match doc.get_object_mut(stream)? {
+            // A form, rewritten in place as other pages may draw it.
+            Object::Stream(form) => {
+                form.set_plain_content(content);
+                let _ = form.compress();
+            }
+            // The page's own content, which may have been split in several
+            // streams, some shared with other pages.
+            _ => {
+                let content_id = doc.add_object(compressed_stream(Dictionary::new(), content));
+                doc.get_dictionary_mut(stream)?.set("Contents", content_id);
+            }
+        }
+    }
+
+    for (page_id, content) in boxes {
+        append_content(doc, page_id, content);
+    }
+    Ok(redactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::{Object, StringFormat, dictionary};
+
+    use super::*;
+
+    fn string(text: &str) -> Object {
+        Object::String(text.as_bytes().to_vec(), StringFormat::Literal)
+    }
+
+    // A one page document drawing `content` with Helvetica as `F1` and a
+    // 100x100 image as `Im1`.
+    fn document(content: &str) -> (Document, ObjectId) {
+        let mut doc = Document::with_version("1.7");
+        let font_id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+            "Encoding" => "WinAnsiEncoding",
+        });
+        let image_id = doc.add_object(Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => 1,
+                "Height" => 1,
+                "ColorSpace" => "DeviceGray",
+                "BitsPerComponent" => 8,
+            },
+            vec![0],
+        ));
+        let content_id =
+            doc.add_object(Stream::new(Dictionary::new(), content.as_bytes().to_vec()));
+        let pages_id = doc.new_object_id();
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            "Contents" => content_id,
+            "Resources" => dictionary! {
+                "Font" => dictionary! { "F1" => font_id },
+                "XObject" => dictionary! { "Im1" => image_id },
+            },
+        });
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        doc.trailer.set("Root", catalog_id);
+        (doc, page_id)
+    }
+
+    fn page_text(doc: &Document, page_id: ObjectId) -> String {
+        TextWalker::new(doc)
+            .page_text(page_id)
+            .unwrap()
+            .layout()
+            .text
+    }
+
+    #[test]
+    fn removes_the_matching_text_only() {
+        let (mut doc, page_id) = document("BT /F1 12 Tf 100 700 Td (card 1234 5678 end) Tj ET");
+        let patterns = [Regex::new(r"\d{4} \d{4}").unwrap()];
+        let redactions = redact(&mut doc, &patterns).unwrap();
+        assert_eq!(redactions.matches, 1);
+        assert_eq!(redactions.pages, 1);
+        assert!(redactions.image_pages.is_empty());
+        let text = page_text(&doc, page_id);
+        assert_eq!(text.split_whitespace().collect::<Vec<_>>(), ["card", "end"]);
+    }
+
+    #[test]
+    fn keeps_pages_without_matches() {
+        let content = "BT /F1 12 Tf 100 700 Td (nothing here) Tj ET";
+        let (mut doc, page_id) = document(content);
+        let patterns = [Regex::new("secret").unwrap()];
+        let redactions = redact(&mut doc, &patterns).unwrap();
+        assert_eq!(redactions.matches, 0);
+        assert_eq!(redactions.pages, 0);
+        assert_eq!(doc.get_page_content(page_id).unwrap(), content.as_bytes());
+    }
+
+    #[test]
+    fn reports_boxes_over_images() {
+        let (mut doc, _) = document(
+            "q 200 0 0 100 90 650 cm /Im1 Do Q \
+             BT /F1 12 Tf 100 700 Td (secret) Tj 0 -200 Td (secret) Tj ET",
+        );
+        let patterns = [Regex::new("secret").unwrap()];
+        let redactions = redact(&mut doc, &patterns).unwrap();
+        assert_eq!(redactions.matches, 2);
+        assert_eq!(redactions.image_pages, vec![1]);
+    }
+
+    #[test]
+    fn reports_no_images_away_from_the_boxes() {
+        let (mut doc, _) =
+            document("q 100 0 0 100 300 100 cm /Im1 Do Q BT /F1 12 Tf 100 700 Td (secret) Tj ET");
+        let patterns = [Regex::new("secret").unwrap()];
+        let redactions = redact(&mut doc, &patterns).unwrap();
+        assert!(redactions.image_pages.is_empty());
+    }
+
+    #[test]
+    fn replaces_removed_glyphs_by_adjustments() {
+        let operation = Operation::new(
+            "TJ",
+            vec![Object::Array(vec![
+                string("abc"),
+                Object::Integer(-50),
+                string("def"),
+            ])],
+        );
+        let removed = RemovedGlyphs::from([((0, 1), (2, -500.0)), ((2, 0), (1, -400.0))]);
+        let operations = redacted_operation(&operation, &removed);
+        assert_eq!(operations.len(), 1);
+        assert_eq!(operations[0].operator, "TJ");
+        assert_eq!(
+            operations[0].operands,
+            vec![Object::Array(vec![
+                string("a"),
+                Object::Real(-500.0),
+                string("c"),
+                Object::Real(-450.0),
+                string("ef"),
+            ])]
+        );
+    }
+
+    #[test]
+    fn merges_adjustments_around_removed_glyphs() {
+        let operation = Operation::new(
+            "TJ",
+            vec![Object::Array(vec![
+                string("ab"),
+                Object::Integer(-50),
+                string("cd"),
+            ])],
+        );
+        let removed = RemovedGlyphs::from([((0, 1), (2, -500.0)), ((2, 0), (2, -1000.0))]);
+        let operations = redacted_operation(&operation, &removed);
+        assert_eq!(
+            operations[0].operands,
+            vec![Object::Array(vec![string("a"), Object::Real(-1550.0)])]
+        );
+    }
+
+    #[test]
+    fn turns_next_line_operators_into_tj() {
+        let operation = Operation::new("\"", vec![1.into(), 2.into(), string("abc")]);
+        let removed = RemovedGlyphs::from([((0, 0), (1, -500.0))]);
+        let operators: Vec<_> = redacted_operation(&operation, &removed)
+            .into_iter()
+            .map(|operation| operation.operator)
+            .collect();
+        assert_eq!(operators, ["Tw", "Tc", "T*", "TJ"]);
+    }
+}
@@ -0,0 +1,57 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow};
+use ureq::Agent;
+
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    pub max_size: u64,
+    pub timeout: Duration,
+}
+
+pub fn is_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| {
+        let path = path.to_ascii_lowercase();
+        path.starts_with("https://") || path.starts_with("http://")
+    })
+}
+
+// File name used to guess the format of a downloaded image: the last segment
+// of the URL, without query string or fragment.
+pub fn url_file_name(url: &Path) -> PathBuf {
+    let url = url.to_string_lossy();
+    let url = url.split(['?', '#']).next().unwrap_or_default();
+    PathBuf::from(url.rsplit('/').next().unwrap_or_default())
+}
+
+// Downloads follow redirects, to `http` and `https` URLs only, and fail on
+// error statuses. The body is read up to `max_size`, whether the server
+// announces its size or not.
+pub fn download(url: &Path, options: &DownloadOptions) -> Result<Vec<u8>> {
+    let agent: Agent = Agent::config_builder()
+        .timeout_global(Some(options.timeout.max(Duration::from_secs(1))))
+        .build()
+        .into();
+    let body = url
+        .to_str()
+        .context("the URL is not valid UTF-8")
+        .and_then(|address| {
+            agent
+                .get(address)
+                .call()?
+                .body_mut()
+                .with_config()
+                .limit(options.max_size)
+                .read_to_vec()
+                .map_err(|e| match e {
+                    ureq::Error::BodyExceedsLimit(_) => {
+                        anyhow!("larger than {} bytes", options.max_size)
+                    }
+                    e => e.into(),
+                })
+        });
+    body.with_context(|| format!("cannot download {}", url.display()))
+}
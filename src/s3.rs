@@ -0,0 +1,164 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+pub fn is_s3_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|path| path.starts_with("s3://"))
+}
+
+// `s3://bucket/prefix/` designates a "directory" of objects.
+pub fn is_s3_prefix(path: &Path) -> bool {
+    is_s3_url(path) && path.to_str().is_some_and(|path| path.ends_with('/'))
+}
+
+// Objects are transferred through the AWS SDK, which finds credentials,
+// regions and endpoints the same way as the rest of the AWS tooling: from the
+// environment, the profiles, or the role of the instance or Lambda function.
+#[cfg(feature = "s3")]
+mod aws_sdk {
+    use std::{
+        path::{Path, PathBuf},
+        sync::LazyLock,
+    };
+
+    use anyhow::{Context, Result, anyhow};
+    use aws_config::BehaviorVersion;
+    use aws_sdk_s3::{Client, primitives::ByteStream};
+    use tokio::runtime::{Builder, Runtime};
+
+    // The client only works on the runtime it was made on, so both are kept
+    // for the whole run.
+    struct S3 {
+        runtime: Runtime,
+        client: Client,
+    }
+
+    static S3: LazyLock<Result<S3, String>> = LazyLock::new(|| {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| format!("cannot start the S3 client: {}", e))?;
+        let config = runtime.block_on(aws_config::load_defaults(BehaviorVersion::latest()));
+        Ok(S3 {
+            client: Client::new(&config),
+            runtime,
+        })
+    });
+
+    fn s3() -> Result<&'static S3> {
+        S3.as_ref().map_err(|e| anyhow!("{}", e))
+    }
+
+    fn split_url(url: &Path) -> Result<(&str, &str)> {
+        let url = url.to_str().context("S3 URLs must be valid UTF-8")?;
+        url.strip_prefix("s3://")
+            .and_then(|url| url.split_once('/'))
+            .filter(|(bucket, _)| !bucket.is_empty())
+            .with_context(|| format!("invalid S3 URL {}", url))
+    }
+
+    fn sdk_error(
+        url: &Path,
+        error: impl std::error::Error + Send + Sync + 'static,
+    ) -> anyhow::Error {
+        anyhow::Error::new(error).context(url.display().to_string())
+    }
+
+    pub fn list(prefix: &Path) -> Result<Vec<PathBuf>> {
+        let (bucket, key_prefix) = split_url(prefix)?;
+        let s3 = s3()?;
+        s3.runtime.block_on(async {
+            let mut pages = s3
+                .client
+                .list_objects_v2()
+                .bucket(bucket)
+                .prefix(key_prefix)
+                .delimiter("/")
+                .into_paginator()
+                .send();
+            let mut urls = Vec::new();
+            while let Some(page) = pages.next().await {
+                let page = page.map_err(|e| sdk_error(prefix, e))?;
+                urls.extend(
+                    page.contents()
+                        .iter()
+                        .filter_map(|object| object.key())
+                        .map(|key| PathBuf::from(format!("s3://{}/{}", bucket, key))),
+                );
+            }
+            Ok(urls)
+        })
+    }
+
+    pub fn download(url: &Path) -> Result<Vec<u8>> {
+        let (bucket, key) = split_url(url)?;
+        let s3 = s3()?;
+        s3.runtime.block_on(async {
+            let object = s3
+                .client
+                .get_object()
+                .bucket(bucket)
+                .key(key)
+                .send()
+                .await
+                .map_err(|e| sdk_error(url, e))?;
+            let body = object.body.collect().await.map_err(|e| sdk_error(url, e))?;
+            Ok(body.into_bytes().to_vec())
+        })
+    }
+
+    pub fn upload(url: &Path, bytes: &[u8]) -> Result<()> {
+        let (bucket, key) = split_url(url)?;
+        let s3 = s3()?;
+        s3.runtime.block_on(async {
+            s3.client
+                .put_object()
+                .bucket(bucket)
+                .key(key)
+                .body(ByteStream::from(bytes.to_vec()))
+                .send()
+                .await
+                .map_err(|e| sdk_error(url, e))?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(not(feature = "s3"))]
+mod aws_sdk {
+    use std::path::{Path, PathBuf};
+
+    use anyhow::{Result, bail};
+
+    fn unsupported<T>(url: &Path) -> Result<T> {
+        bail!(
+            "cannot access {}: pdf-merge was built without the `s3` feature",
+            url.display()
+        )
+    }
+
+    pub fn list(prefix: &Path) -> Result<Vec<PathBuf>> {
+        unsupported(prefix)
+    }
+
+    pub fn download(url: &Path) -> Result<Vec<u8>> {
+        unsupported(url)
+    }
+
+    pub fn upload(url: &Path, _bytes: &[u8]) -> Result<()> {
+        unsupported(url)
+    }
+}
+
+// Objects directly under `prefix`, not the ones of its "subdirectories".
+pub fn list(prefix: &Path) -> Result<Vec<PathBuf>> {
+    aws_sdk::list(prefix)
+}
+
+pub fn download(url: &Path) -> Result<Vec<u8>> {
+    aws_sdk::download(url)
+}
+
+pub fn upload(url: &Path, bytes: &[u8]) -> Result<()> {
+    aws_sdk::upload(url, bytes)
+}
@@ -0,0 +1,586 @@
+use std::{
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    net::{TcpListener, TcpStream},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, TrySendError},
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::log;
+use anyhow::{Context, Result, anyhow};
+use lopdf::Document;
+use pm::{
+    image::image_document::ImageOptions,
+    merge::Merger,
+    source::{load_bytes, load_file},
+};
+use serde::Deserialize;
+
+// A client sending or reading nothing for this long is dropped, so that idle
+// connections do not hold the workers.
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Limit of the request line and headers together.
+const MAX_HEADER_SIZE: u64 = 16 * 1024;
+
+// Connections waiting for a worker, beyond which they are answered 503.
+const MAX_QUEUED_CONNECTIONS: usize = 16;
+
+// Size of the chunks the body is read and the merged PDF sent in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+pub struct ServeOptions {
+    // Directory the server-side paths of JSON requests are resolved in. JSON
+    // requests are refused when it is not set.
+    pub root: Option<PathBuf>,
+    pub max_body_size: usize,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    content_type: String,
+    body: Vec<u8>,
+}
+
+// Error answered to the client, with its HTTP status.
+struct HttpError(u16, String);
+
+impl From<anyhow::Error> for HttpError {
+    fn from(error: anyhow::Error) -> Self {
+        HttpError(422, format!("{:#}", error))
+    }
+}
+
+fn status_text(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        422 => "Unprocessable Entity",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+fn write_response(
+    stream: &mut TcpStream,
+    status: u16,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        status_text(status),
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+// Writes the body in the chunks of `Transfer-Encoding: chunked`, so that the
+// merged PDF is sent as it is written and a client can tell a response cut
+// short by an error from a complete one, which ends with an empty chunk.
+struct ChunkedWriter<W: Write>(W);
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !buf.is_empty() {
+            write!(self.0, "{:x}\r\n", buf.len())?;
+            self.0.write_all(buf)?;
+            self.0.write_all(b"\r\n")?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+fn write_pdf_response(stream: &mut TcpStream, document: &mut Document) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/pdf\r\nTransfer-Encoding: chunked\r\n\
+         Connection: close\r\n\r\n"
+    )?;
+    let mut body = BufWriter::with_capacity(CHUNK_SIZE, ChunkedWriter(&mut *stream));
+    document.save_to(&mut body)?;
+    body.flush()?;
+    drop(body);
+    stream.write_all(b"0\r\n\r\n")?;
+    stream.flush()
+}
+
+// Reads a line of the request head, failing once the head is longer than
+// `MAX_HEADER_SIZE`.
+fn read_head_line(reader: &mut impl BufRead, line: &mut String) -> Result<(), HttpError> {
+    match reader.read_line(line) {
+        Ok(_) if line.ends_with('\n') => Ok(()),
+        Ok(_) if reader.fill_buf().is_ok_and(|buffer| buffer.is_empty()) && line.is_empty() => {
+            Err(HttpError(400, "truncated request".to_string()))
+        }
+        Ok(_) => Err(HttpError(
+            431,
+            format!("request headers are larger than {} bytes", MAX_HEADER_SIZE),
+        )),
+        Err(_) => Err(HttpError(400, "cannot read the request".to_string())),
+    }
+}
+
+fn read_request(stream: &TcpStream, max_body_size: usize) -> Result<Request, HttpError> {
+    read_request_from(BufReader::new(stream), max_body_size)
+}
+
+fn read_request_from(mut reader: impl BufRead, max_body_size: usize) -> Result<Request, HttpError> {
+    let bad_request = |message: &str| HttpError(400, message.to_string());
+    let mut head = (&mut reader).take(MAX_HEADER_SIZE);
+
+    let mut request_line = String::new();
+    read_head_line(&mut head, &mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(bad_request("malformed request line"));
+    };
+
+    let mut content_type = String::new();
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        read_head_line(&mut head, &mut line)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-type") {
+                content_type = value.to_string();
+            } else if name.eq_ignore_ascii_case("content-length") {
+                content_length = Some(
+                    value
+                        .parse::<usize>()
+                        .map_err(|_| bad_request("invalid Content-Length"))?,
+                );
+            }
+        }
+    }
+
+    let mut body = Vec::new();
+    if method == "POST" {
+        let Some(content_length) = content_length else {
+            return Err(HttpError(411, "Content-Length is required".to_string()));
+        };
+        if content_length > max_body_size {
+            return Err(HttpError(
+                413,
+                format!("request body is larger than {} bytes", max_body_size),
+            ));
+        }
+        // Read as it comes rather than allocated upfront from a length the
+        // client may not send.
+        let mut chunk = vec![0; CHUNK_SIZE];
+        while body.len() < content_length {
+            let wanted = (content_length - body.len()).min(CHUNK_SIZE);
+            match reader.read(&mut chunk[..wanted]) {
+                Ok(0) | Err(_) => return Err(bad_request("truncated request body")),
+                Ok(read) => body.extend_from_slice(&chunk[..read]),
+            }
+        }
+    }
+
+    Ok(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        content_type,
+        body,
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| position + from)
+}
+
+fn content_type_parameter<'a>(content_type: &'a str, name: &str) -> Option<&'a str> {
+    content_type.split(';').skip(1).find_map(|parameter| {
+        let (key, value) = parameter.trim().split_once('=')?;
+        key.eq_ignore_ascii_case(name)
+            .then(|| value.trim_matches('"'))
+    })
+}
+
+// Returns the `(file name, content)` of the file parts of a
+// `multipart/form-data` body, in order.
+fn parse_multipart<'a>(body: &'a [u8], boundary: &str) -> Result<Vec<(String, &'a [u8])>> {
+    let delimiter = format!("--{}", boundary);
+    let mut files = Vec::new();
+    let mut position = find(body, delimiter.as_bytes(), 0)
+        .context("multipart boundary not found")?
+        + delimiter.len();
+
+    // Each part is `\r\n<headers>\r\n\r\n<content>\r\n--boundary`, the last
+    // delimiter being followed by `--`.
+    while !body[position..].starts_with(b"--") {
+        let headers_start = position + 2;
+        let headers_end =
+            find(body, b"\r\n\r\n", headers_start).context("malformed multipart part")?;
+        let content_start = headers_end + 4;
+        let next_delimiter = format!("\r\n{}", delimiter);
+        let content_end = find(body, next_delimiter.as_bytes(), content_start)
+            .context("unterminated multipart part")?;
+
+        let headers = String::from_utf8_lossy(&body[headers_start..headers_end]);
+        let file_name = headers
+            .lines()
+            .filter(|line| {
+                line.to_ascii_lowercase()
+                    .starts_with("content-disposition:")
+            })
+            .find_map(|line| content_type_parameter(line, "filename"));
+        if let Some(file_name) = file_name {
+            files.push((file_name.to_string(), &body[content_start..content_end]));
+        }
+        position = content_end + next_delimiter.len();
+    }
+    Ok(files)
+}
+
+// Accepts either `["a.pdf", "b.pdf"]` or `{"paths": ["a.pdf", "b.pdf"]}`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonPaths {
+    Array(Vec<String>),
+    Object { paths: Vec<String> },
+}
+
+fn parse_json_paths(body: &[u8]) -> Result<Vec<String>> {
+    match serde_json::from_slice(body)
+        .context("expected an array of paths or {\"paths\": [...]}")?
+    {
+        JsonPaths::Array(paths) | JsonPaths::Object { paths } => Ok(paths),
+    }
+}
+
+// Server-side paths must stay inside the root directory.
+fn resolve_server_path(root: &Path, path: &str) -> Result<PathBuf, HttpError> {
+    let resolved = root
+        .join(path)
+        .canonicalize()
+        .map_err(|e| HttpError(422, format!("cannot open {}: {}", path, e)))?;
+    if !resolved.starts_with(root) {
+        return Err(HttpError(
+            403,
+            format!("{} is outside of the served directory", path),
+        ));
+    }
+    Ok(resolved)
+}
+
+fn merge_request(request: &Request, options: &ServeOptions) -> Result<Document, HttpError> {
+    let media_type = request
+        .content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    let documents = match media_type.as_str() {
+        "multipart/form-data" => {
+            let boundary = content_type_parameter(&request.content_type, "boundary")
+                .ok_or_else(|| HttpError(400, "missing multipart boundary".to_string()))?;
+            let files = parse_multipart(&request.body, boundary)
+                .map_err(|e| HttpError(400, e.to_string()))?;
+            files
+                .iter()
+                .map(|(file_name, content)| {
+                    load_bytes(content, Path::new(file_name), &ImageOptions::default())
+                })
+                .collect::<Result<Vec<_>>>()?
+        }
+        "application/json" => {
+            let Some(root) = &options.root else {
+                return Err(HttpError(
+                    403,
+                    "server-side paths are disabled, start the server with --root".to_string(),
+                ));
+            };
+            let paths =
+                parse_json_paths(&request.body).map_err(|e| HttpError(400, format!("{:#}", e)))?;
+            let mut documents = Vec::new();
+            for path in paths {
+                documents.push(load_file(
+                    &resolve_server_path(root, &path)?,
+                    &ImageOptions::default(),
+                )?);
+            }
+            documents
+        }
+        _ => {
+            return Err(HttpError(
+                415,
+                "expected multipart/form-data or application/json".to_string(),
+            ));
+        }
+    };
+
+    if documents.is_empty() {
+        return Err(HttpError(400, "no input document".to_string()));
+    }
+
+    Merger::new(documents)
+        .merge()
+        .map_err(|e| HttpError(500, format!("{:#}", e)))
+}
+
+fn handle_connection(mut stream: TcpStream, options: &ServeOptions) -> io::Result<()> {
+    stream.set_read_timeout(Some(SOCKET_TIMEOUT))?;
+    stream.set_write_timeout(Some(SOCKET_TIMEOUT))?;
+    let result = read_request(&stream, options.max_body_size).and_then(|request| {
+        match (request.method.as_str(), request.path.as_str()) {
+            // A malformed input may make the parser or an image decoder
+            // panic, which must not leave the client without an answer.
+            ("POST", "/merge") => {
+                panic::catch_unwind(AssertUnwindSafe(|| merge_request(&request, options)))
+                    .unwrap_or_else(|_| Err(HttpError(500, "the merge crashed".to_string())))
+            }
+            (_, "/merge") => Err(HttpError(405, "use POST".to_string())),
+            _ => Err(HttpError(
+                404,
+                "the only endpoint is POST /merge".to_string(),
+            )),
+        }
+    });
+
+    match result {
+        Ok(mut document) => write_pdf_response(&mut stream, &mut document),
+        Err(HttpError(status, message)) => write_response(
+            &mut stream,
+            status,
+            "text/plain; charset=utf-8",
+            message.as_bytes(),
+        ),
+    }
+}
+
+// Serves `POST /merge`, which answers the merged PDF of either the files of a
+// multipart upload or a JSON list of paths relative to `options.root`.
+pub fn serve(listen: &str, mut options: ServeOptions) -> Result<()> {
+    if let Some(root) = &options.root {
+        options.root = Some(
+            root.canonicalize()
+                .with_context(|| format!("cannot open {}", root.display()))?,
+        );
+    }
+    let listener =
+        TcpListener::bind(listen).with_context(|| format!("cannot listen on {}", listen))?;
+    log::info(&format!("Listening on {}", listener.local_addr()?));
+
+    // A fixed pool of workers, one merge per core, takes the connections from
+    // a bounded queue: a flood of clients gets 503 answers instead of a
+    // thread and a request body each.
+    let options = Arc::new(options);
+    let (sender, receiver) = mpsc::sync_channel::<TcpStream>(MAX_QUEUED_CONNECTIONS);
+    let receiver = Arc::new(Mutex::new(receiver));
+    let workers = thread::available_parallelism().map_or(1, |workers| workers.get());
+    for _ in 0..workers {
+        let options = Arc::clone(&options);
+        let receiver = Arc::clone(&receiver);
+        thread::spawn(move || {
+            loop {
+                let stream = match receiver.lock() {
+                    Ok(receiver) => receiver.recv(),
+                    Err(_) => return,
+                };
+                let Ok(stream) = stream else {
+                    return;
+                };
+                if let Err(e) = handle_connection(stream, &options) {
+                    log::warning(&format!("{}", e));
+                }
+            }
+        });
+    }
+
+    for stream in listener.incoming() {
+        match stream.map(|stream| sender.try_send(stream)) {
+            Ok(Ok(())) => {}
+            Ok(Err(TrySendError::Full(mut stream))) => {
+                let _ = stream.set_write_timeout(Some(SOCKET_TIMEOUT));
+                let _ = write_response(
+                    &mut stream,
+                    503,
+                    "text/plain; charset=utf-8",
+                    b"too many requests, try again later",
+                );
+            }
+            Ok(Err(TrySendError::Disconnected(_))) => break,
+            Err(e) => log::warning(&format!("{}", e)),
+        }
+    }
+    Err(anyhow!("the listener stopped"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(bytes: &[u8]) -> Result<Request, HttpError> {
+        read_request_from(bytes, 100)
+    }
+
+    fn status(result: Result<Request, HttpError>) -> u16 {
+        match result {
+            Ok(_) => 200,
+            Err(HttpError(status, _)) => status,
+        }
+    }
+
+    #[test]
+    fn reads_get_requests() {
+        let request = request(b"GET /health HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .ok()
+            .unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/health");
+        assert!(request.content_type.is_empty());
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn reads_post_bodies() {
+        let request = request(
+            b"POST /merge HTTP/1.1\r\ncontent-type: application/json\r\nContent-Length: 9\r\n\r\n\
+              [\"a.pdf\"]ignored",
+        )
+        .ok()
+        .unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.content_type, "application/json");
+        assert_eq!(request.body, b"[\"a.pdf\"]");
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        assert_eq!(status(request(b"")), 400);
+        assert_eq!(status(request(b"GET\r\n\r\n")), 400);
+        let long_header = format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", "a".repeat(20_000));
+        assert_eq!(status(request(long_header.as_bytes())), 431);
+    }
+
+    #[test]
+    fn checks_the_content_length() {
+        assert_eq!(status(request(b"POST / HTTP/1.1\r\n\r\n")), 411);
+        assert_eq!(
+            status(request(b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n")),
+            400
+        );
+        assert_eq!(
+            status(request(b"POST / HTTP/1.1\r\nContent-Length: 101\r\n\r\n")),
+            413
+        );
+        assert_eq!(
+            status(request(
+                b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort"
+            )),
+            400
+        );
+    }
+
+    #[test]
+    fn reads_content_type_parameters() {
+        let content_type = "multipart/form-data; charset=utf-8; Boundary=\"abc\"";
+        assert_eq!(
+            content_type_parameter(content_type, "boundary"),
+            Some("abc")
+        );
+        assert_eq!(
+            content_type_parameter(content_type, "charset"),
+            Some("utf-8")
+        );
+        assert_eq!(content_type_parameter(content_type, "name"), None);
+        assert_eq!(content_type_parameter("boundary=abc", "boundary"), None);
+    }
+
+    #[test]
+    fn parses_the_files_of_multipart_bodies() {
+        let body = b"preamble\r\n--xyz\r\n\
+            Content-Disposition: form-data; name=\"options\"\r\n\r\n\
+            value\r\n--xyz\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a.pdf\"\r\n\
+            Content-Type: application/pdf\r\n\r\n\
+            %PDF\r\n--x\r\n--xyz\r\n\
+            content-disposition: form-data; name=\"file\"; filename=\"b.png\"\r\n\r\n\
+            PNG\r\n--xyz--\r\n";
+        let files = parse_multipart(body, "xyz").unwrap();
+        assert_eq!(
+            files,
+            [
+                ("a.pdf".to_string(), b"%PDF\r\n--x".as_slice()),
+                ("b.png".to_string(), b"PNG".as_slice()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_multipart_bodies() {
+        assert!(parse_multipart(b"--abc\r\n\r\n", "xyz").is_err());
+        assert!(parse_multipart(b"--xyz\r\nContent-Disposition: form-data", "xyz").is_err());
+        assert!(parse_multipart(b"--xyz\r\nA: b\r\n\r\ncontent", "xyz").is_err());
+        assert!(parse_multipart(b"--xyz--", "xyz").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parses_json_paths() {
+        assert_eq!(
+            parse_json_paths(br#" ["a.pdf", "b.pdf"] "#).unwrap(),
+            ["a.pdf", "b.pdf"]
+        );
+        assert_eq!(
+            parse_json_paths(br#"{"name": "x", "paths": ["a.pdf"]}"#).unwrap(),
+            ["a.pdf"]
+        );
+        assert!(parse_json_paths(b"[]").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parses_json_escapes() {
+        assert_eq!(
+            parse_json_paths(r#"["a\"b\\c\/d\n", "café 😀", "\u00e9\ud83d\ude00"]"#.as_bytes())
+                .unwrap(),
+            ["a\"b\\c/d\n", "café 😀", "é😀"]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_json() {
+        assert!(parse_json_paths(br#"{"files": ["a.pdf"]}"#).is_err());
+        assert!(parse_json_paths(br#"{"paths": "a.pdf"}"#).is_err());
+        assert!(parse_json_paths(br#""a.pdf""#).is_err());
+        assert!(parse_json_paths(br#"["a.pdf""#).is_err());
+        assert!(parse_json_paths(br#"["a.pdf"#).is_err());
+        assert!(parse_json_paths(br#"[1]"#).is_err());
+        assert!(parse_json_paths(br#"["\ud83d"]"#).is_err());
+        assert!(parse_json_paths(br#"["\uzzzz"]"#).is_err());
+        assert!(parse_json_paths(br#"["a.pdf"] x"#).is_err());
+        assert!(parse_json_paths(b"[\"\xff\"]").is_err());
+    }
+}
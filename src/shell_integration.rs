@@ -0,0 +1,139 @@
+use std::env;
+
+use anyhow::{Context, Result};
+
+// Entries of the file manager of the platform starting the GUI flow with the
+// selected files or folders as inputs, through `--quick-action`.
+#[cfg(windows)]
+mod platform {
+    use std::{
+        env, fs,
+        path::{Path, PathBuf},
+        process::Command,
+    };
+
+    use anyhow::{Context, Result, bail};
+
+    const MENU_TEXT: &str = "Merge to PDF…";
+    const VERB: &str = "PdfMerge";
+    const SHORTCUT_NAME: &str = "Merge to PDF.lnk";
+
+    // Context menus of the folders, and of the background of an open folder,
+    // with the argument Explorer replaces by the folder.
+    const SHELL_KEYS: [(&str, &str); 2] = [
+        (r"HKCU\Software\Classes\Directory\shell", "%1"),
+        (r"HKCU\Software\Classes\Directory\Background\shell", "%V"),
+    ];
+
+    fn run(command: &mut Command, tool: &str) -> Result<()> {
+        let output = command
+            .output()
+            .with_context(|| format!("cannot run {}", tool))?;
+        if !output.status.success() {
+            bail!(
+                "{} failed: {}",
+                tool,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+
+    fn send_to_dir() -> Result<PathBuf> {
+        let app_data = env::var_os("APPDATA").context("APPDATA is not set")?;
+        Ok(Path::new(&app_data).join(r"Microsoft\Windows\SendTo"))
+    }
+
+    // Single-quoted PowerShell string.
+    fn powershell_string(path: &Path) -> String {
+        format!("'{}'", path.display().to_string().replace('\'', "''"))
+    }
+
+    pub fn install(executable: &Path) -> Result<()> {
+        let executable_name = executable.display().to_string();
+        for (key, argument) in SHELL_KEYS {
+            let verb_key = format!(r"{}\{}", key, VERB);
+            let command = format!("\"{}\" --quick-action \"{}\"", executable_name, argument);
+            run(
+                Command::new("reg").args(["add", &verb_key, "/ve", "/d", MENU_TEXT, "/f"]),
+                "reg",
+            )?;
+            run(
+                Command::new("reg").args([
+                    "add",
+                    &verb_key,
+                    "/v",
+                    "Icon",
+                    "/d",
+                    &executable_name,
+                    "/f",
+                ]),
+                "reg",
+            )?;
+            run(
+                Command::new("reg").args([
+                    "add",
+                    &format!(r"{}\command", verb_key),
+                    "/ve",
+                    "/d",
+                    &command,
+                    "/f",
+                ]),
+                "reg",
+            )?;
+        }
+
+        // Files go through "Send to", which hands all the selected files to a
+        // single process where a context menu entry would start one per file.
+        let script = format!(
+            "$shortcut = (New-Object -ComObject WScript.Shell).CreateShortcut({}); \
+             $shortcut.TargetPath = {}; $shortcut.Arguments = '--quick-action'; $shortcut.Save()",
+            powershell_string(&send_to_dir()?.join(SHORTCUT_NAME)),
+            powershell_string(executable)
+        );
+        run(
+            Command::new("powershell").args(["-NoProfile", "-NonInteractive", "-Command", &script]),
+            "powershell",
+        )
+    }
+
+    pub fn uninstall() -> Result<()> {
+        for (key, _) in SHELL_KEYS {
+            // Missing entries are fine.
+            let _ = Command::new("reg")
+                .args(["delete", &format!(r"{}\{}", key, VERB), "/f"])
+                .output();
+        }
+        let shortcut = send_to_dir()?.join(SHORTCUT_NAME);
+        if shortcut.exists() {
+            fs::remove_file(&shortcut)
+                .with_context(|| format!("cannot remove {}", shortcut.display()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(windows))]
+mod platform {
+    use std::path::Path;
+
+    use anyhow::{Result, bail};
+
+    pub fn install(_executable: &Path) -> Result<()> {
+        bail!("the shell integration is only available on Windows")
+    }
+
+    pub fn uninstall() -> Result<()> {
+        bail!("the shell integration is only available on Windows")
+    }
+}
+
+// Registers the entries for the current user, pointing to this executable.
+pub fn install() -> Result<()> {
+    let executable = env::current_exe().context("cannot find the path of pdf-merge")?;
+    platform::install(&executable)
+}
+
+pub fn uninstall() -> Result<()> {
+    platform::uninstall()
+}
@@ -0,0 +1,17 @@
+// Digital signatures cover the bytes of the file they are in: once its pages
+// are merged into another file, a signed document no longer verifies.
+use lopdf::{Document, Object};
+
+// Number of signatures (and document timestamps) of the document: the
+// signature dictionaries, recognized by the byte range they sign.
+pub fn signature_count(doc: &Document) -> usize {
+    doc.objects
+        .values()
+        .filter(|object| match object {
+            Object::Dictionary(dictionary) => {
+                dictionary.has(b"ByteRange") && dictionary.has(b"Contents")
+            }
+            _ => false,
+        })
+        .count()
+}
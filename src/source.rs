@@ -0,0 +1,155 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use lopdf::Document;
+
+use crate::{
+    archive::{SkippedEntry, read_zip_entries},
+    image::image_document::{ImageOptions, image_bytes_to_doc},
+    text_document::text_to_doc,
+};
+
+const IMAGE_EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "jfif", "bmp", "gif"];
+const TEXT_EXTENSIONS: [&str; 2] = ["txt", "md"];
+
+#[cfg(feature = "raw")]
+pub use crate::image::raw_preview::RAW_EXTENSIONS;
+#[cfg(not(feature = "raw"))]
+pub const RAW_EXTENSIONS: [&str; 0] = [];
+
+fn has_any_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension().is_some_and(|extension| {
+        extensions
+            .iter()
+            .any(|candidate| extension.eq_ignore_ascii_case(candidate))
+    })
+}
+
+pub fn is_pdf_file_name(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pdf"))
+}
+
+pub fn is_image_file_name(path: &Path) -> bool {
+    has_any_extension(path, &IMAGE_EXTENSIONS) || has_any_extension(path, &RAW_EXTENSIONS)
+}
+
+pub fn is_text_file_name(path: &Path) -> bool {
+    has_any_extension(path, &TEXT_EXTENSIONS)
+}
+
+fn text_bytes_to_doc(bytes: &[u8], file_name: &Path) -> Result<Document> {
+    let title = file_name
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let markdown = has_any_extension(file_name, &["md"]);
+    text_to_doc(&title, &String::from_utf8_lossy(bytes), markdown)
+}
+
+pub fn is_zip_file_name(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+pub fn is_pdf_data(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"%PDF-")
+}
+
+// Signatures of the formats recognized by their content, with the extension
+// they are loaded as.
+const SIGNATURES: [(&[u8], &str); 6] = [
+    (b"%PDF-", "pdf"),
+    (b"\x89PNG\r\n\x1a\n", "png"),
+    (b"\xff\xd8\xff", "jpg"),
+    (b"GIF87a", "gif"),
+    (b"GIF89a", "gif"),
+    (b"BM", "bmp"),
+];
+
+// Number of bytes `sniff_extension` needs at the start of a file.
+pub const SNIFF_LENGTH: usize = 8;
+
+// Extension of the format of a file from its first bytes, for files without a
+// recognized extension such as the exports of document management systems.
+pub fn sniff_extension(bytes: &[u8]) -> Option<&'static str> {
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| bytes.starts_with(signature))
+        .map(|&(_, extension)| extension)
+}
+
+fn bytes_to_doc(bytes: &[u8], file_name: &Path, image_options: &ImageOptions) -> Result<Document> {
+    if is_pdf_data(bytes) {
+        Ok(Document::load_mem(bytes)?)
+    } else if is_text_file_name(file_name) {
+        text_bytes_to_doc(bytes, file_name)
+    } else if is_image_file_name(file_name) {
+        image_bytes_to_doc(bytes, file_name, image_options)
+    } else {
+        let extension = sniff_extension(bytes).unwrap_or_default();
+        image_bytes_to_doc(bytes, &file_name.with_extension(extension), image_options)
+    }
+}
+
+#[cfg(feature = "native")]
+// Loads a PDF, or converts an image to a single page document and a text or
+// Markdown file to typeset pages. Files without a recognized extension are
+// recognized by their content.
+pub fn load_file(path: &Path, image_options: &ImageOptions) -> Result<Document> {
+    let document = if is_pdf_file_name(path) {
+        Document::load(path).map_err(anyhow::Error::from)
+    } else if is_text_file_name(path) {
+        std::fs::read(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| text_bytes_to_doc(&bytes, path))
+    } else if is_image_file_name(path) {
+        crate::image::image_document::image_to_doc(path, image_options)
+    } else {
+        std::fs::read(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| bytes_to_doc(&bytes, path, image_options))
+    };
+    document.with_context(|| format!("cannot load {}", path.display()))
+}
+
+// Same as `load_file` for in-memory data. PDFs are recognized by their header,
+// the other formats by the extension of `file_name`, or by their content
+// without a recognized extension.
+pub fn load_bytes(
+    bytes: &[u8],
+    file_name: &Path,
+    image_options: &ImageOptions,
+) -> Result<Document> {
+    bytes_to_doc(bytes, file_name, image_options)
+        .with_context(|| format!("cannot load {}", file_name.display()))
+}
+
+// The documents of a ZIP archive, by name in the archive, and those which
+// cannot be extracted.
+pub struct ZipDocuments {
+    pub documents: Vec<(String, Document)>,
+    pub skipped: Vec<SkippedEntry>,
+}
+
+// Loads the PDFs, images and text files of a ZIP archive, in archive order.
+// Other files, hidden files and macOS resource forks (`__MACOSX/`) are
+// ignored without being extracted.
+pub fn load_zip(bytes: &[u8], image_options: &ImageOptions) -> Result<ZipDocuments> {
+    let (entries, skipped) = read_zip_entries(bytes, |entry_name| {
+        let name = Path::new(entry_name);
+        let hidden = entry_name.starts_with("__MACOSX/")
+            || name
+                .file_name()
+                .is_some_and(|file_name| file_name.as_encoded_bytes().starts_with(b"."));
+        let supported =
+            is_pdf_file_name(name) || is_image_file_name(name) || is_text_file_name(name);
+        !hidden && supported
+    })?;
+    let mut documents = Vec::new();
+    for entry in entries {
+        let document = load_bytes(&entry.data, Path::new(&entry.name), image_options)?;
+        documents.push((entry.name, document));
+    }
+    Ok(ZipDocuments { documents, skipped })
+}
@@ -0,0 +1,103 @@
+// Stream contents spilled to temporary files, to merge inputs that would not
+// fit in memory all at once.
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use anyhow::{Context, Result};
+use lopdf::{Document, Object, ObjectId};
+
+// Rough in-memory cost of an object besides its stream content.
+const OBJECT_OVERHEAD: u64 = 128;
+
+static SPILL_COUNTER: AtomicU32 = AtomicU32::new(0);
+
+// Estimates the memory held by a parsed document, which is much more than
+// the size of its file for documents made of many small objects.
+pub fn estimated_size(document: &Document) -> u64 {
+    document
+        .objects
+        .values()
+        .map(|object| match object {
+            Object::Stream(stream) => OBJECT_OVERHEAD + stream.content.len() as u64,
+            _ => OBJECT_OVERHEAD,
+        })
+        .sum()
+}
+
+// The stream contents of a document, moved to a temporary file deleted once
+// dropped. The document keeps its dictionaries, so that its pages can still
+// be counted, selected or rotated while its contents are on disk.
+#[derive(Debug)]
+pub struct SpilledStreams {
+    path: PathBuf,
+    // Object, offset in the file and length of each spilled content.
+    streams: Vec<(ObjectId, u64, usize)>,
+}
+
+impl SpilledStreams {
+    pub fn spill(document: &mut Document, dir: &Path) -> Result<Self> {
+        let path = dir.join(format!(
+            "pdf-merge-{}-{}.bin",
+            process::id(),
+            SPILL_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut spilled = SpilledStreams {
+            path,
+            streams: Vec::new(),
+        };
+        let mut offset = 0;
+        let written = File::create(&spilled.path).and_then(|file| {
+            let mut file = BufWriter::new(file);
+            for (&id, object) in &document.objects {
+                if let Object::Stream(stream) = object
+                    && !stream.content.is_empty()
+                {
+                    file.write_all(&stream.content)?;
+                    spilled.streams.push((id, offset, stream.content.len()));
+                    offset += stream.content.len() as u64;
+                }
+            }
+            file.flush()
+        });
+        written.with_context(|| format!("cannot spill to {}", spilled.path.display()))?;
+        // Emptied only once all of them are written, so that a failed spill
+        // leaves the document whole.
+        for (id, _, _) in &spilled.streams {
+            if let Some(Object::Stream(stream)) = document.objects.get_mut(id) {
+                stream.content = Vec::new();
+            }
+        }
+        Ok(spilled)
+    }
+
+    // Puts the contents back in the streams of `document`, the document they
+    // were spilled from. Streams given other contents since keep them.
+    pub fn restore(&self, document: &mut Document) -> Result<()> {
+        let read = File::open(&self.path).and_then(|file| {
+            let mut file = BufReader::new(file);
+            for &(id, offset, length) in &self.streams {
+                if let Some(Object::Stream(stream)) = document.objects.get_mut(&id)
+                    && stream.content.is_empty()
+                {
+                    file.seek(SeekFrom::Start(offset))?;
+                    let mut content = vec![0; length];
+                    file.read_exact(&mut content)?;
+                    stream.content = content;
+                }
+            }
+            Ok(())
+        });
+        read.with_context(|| format!("cannot read back {}", self.path.display()))
+    }
+}
+
+impl Drop for SpilledStreams {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,119 @@
+// Text stamped over the pages of a document, e.g. the name of the file each
+// page comes from.
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+
+use crate::{font::TextFont, page::get_inherited};
+
+// Resource name of the stamp font, unlikely to clash with the page's own.
+const STAMP_FONT: &str = "PdfMergeStamp";
+const STAMP_FONT_SIZE: f32 = 7.0;
+const STAMP_MARGIN: f32 = 8.0;
+
+// Visible area of the page: its CropBox, or its MediaBox.
+pub fn page_box(doc: &Document, page_id: ObjectId) -> [f32; 4] {
+    [b"CropBox".as_slice(), b"MediaBox"]
+        .into_iter()
+        .find_map(|key| {
+            let values = get_inherited(doc, page_id, key)?.as_array().ok()?;
+            let values: Vec<f32> = values
+                .iter()
+                .filter_map(|value| doc.dereference(value).ok()?.1.as_float().ok())
+                .collect();
+            <[f32; 4]>::try_from(values).ok()
+        })
+        .unwrap_or([0.0, 0.0, 612.0, 792.0])
+}
+
+// Encodes text as a literal string for the WinAnsi encoding of the standard
+// fonts, characters outside of Latin-1 becoming `?`.
+pub(crate) fn literal_string(text: &str) -> Vec<u8> {
+    let mut string = vec![b'('];
+    for c in text.chars() {
+        let byte = u8::try_from(u32::from(c)).unwrap_or(b'?');
+        if matches!(byte, b'(' | b')' | b'\\') {
+            string.push(b'\\');
+        }
+        string.push(byte);
+    }
+    string.push(b')');
+    string
+}
+
+// Adds a resource of the given category (`Font`, `XObject`, ...) to the page.
+// The resources are copied into the page first, as they may be inherited or
+// shared with other pages.
+pub(crate) fn add_resource(
+    doc: &mut Document,
+    page_id: ObjectId,
+    category: &str,
+    name: &str,
+    id: ObjectId,
+) {
+    let mut resources = get_inherited(doc, page_id, b"Resources")
+        .and_then(|resources| doc.dereference(resources).ok())
+        .and_then(|(_, resources)| resources.as_dict().ok())
+        .cloned()
+        .unwrap_or_default();
+    let mut entries = resources
+        .get(category.as_bytes())
+        .and_then(|entries| doc.dereference(entries))
+        .and_then(|(_, entries)| entries.as_dict())
+        .cloned()
+        .unwrap_or_default();
+    entries.set(name, id);
+    resources.set(category, entries);
+    if let Ok(page) = doc.get_dictionary_mut(page_id) {
+        page.set("Resources", resources);
+    }
+}
+
+// Draws `content` over the page. The existing content is wrapped in `q`/`Q`
+// so that the graphics state it leaves behind does not affect the addition.
+pub fn append_content(doc: &mut Document, page_id: ObjectId, content: Vec<u8>) {
+    let existing: Vec<Object> = match doc
+        .get_dictionary(page_id)
+        .and_then(|page| page.get(b"Contents"))
+    {
+        Ok(Object::Array(contents)) => contents.clone(),
+        Ok(contents) => vec![contents.clone()],
+        Err(_) => Vec::new(),
+    };
+
+    let save_id = doc.add_object(Stream::new(Dictionary::new(), b"q\n".to_vec()));
+    let mut restored = b"\nQ\n".to_vec();
+    restored.extend(content);
+    let content_id = doc.add_object(Stream::new(Dictionary::new(), restored));
+
+    let mut contents = vec![Object::Reference(save_id)];
+    contents.extend(existing);
+    contents.push(Object::Reference(content_id));
+    if let Ok(page) = doc.get_dictionary_mut(page_id) {
+        page.set("Contents", contents);
+    }
+}
+
+// Prints `text` in small gray type in the bottom left corner of every page,
+// with the TrueType `font` if given (see `TextFont`).
+pub fn stamp_text(doc: &mut Document, text: &str, font: Option<&[u8]>) {
+    let mut text_font = TextFont::new(font, &[text]);
+    let encoded = text_font.encode(text);
+    let font_id = doc.new_object_id();
+    text_font.add_to(doc, font_id);
+
+    for page_id in doc.get_pages().into_values() {
+        add_resource(doc, page_id, "Font", STAMP_FONT, font_id);
+        let [x0, y0, x1, y1] = page_box(doc, page_id);
+        let (left, bottom) = (x0.min(x1), y0.min(y1));
+        let mut content = format!(
+            "q 0.45 g BT /{} {} Tf {} {} Td ",
+            STAMP_FONT,
+            STAMP_FONT_SIZE,
+            left + STAMP_MARGIN,
+            bottom + STAMP_MARGIN
+        )
+        .into_bytes();
+        content.extend(&encoded);
+        content.extend(b" Tj ET Q\n");
+        append_content(doc, page_id, content);
+    }
+}
@@ -0,0 +1,75 @@
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+// Steps of the run timed for `--stats`. Images are converted while they are
+// loaded, the inputs made of converted images count as conversion time.
+#[derive(Debug, Clone, Copy)]
+pub enum Step {
+    Load,
+    ImageConversion,
+    Merge,
+    Compress,
+    Save,
+}
+
+const STEPS: [(Step, &str); 5] = [
+    (Step::Load, "load"),
+    (Step::ImageConversion, "image conversion"),
+    (Step::Merge, "merge"),
+    (Step::Compress, "compress"),
+    (Step::Save, "save"),
+];
+
+// Recorded whether or not `--stats` is given, which costs a few clock reads.
+static DURATIONS: Mutex<[Duration; STEPS.len()]> = Mutex::new([Duration::ZERO; STEPS.len()]);
+
+static INPUT_BYTES: AtomicU64 = AtomicU64::new(0);
+
+pub fn record(step: Step, duration: Duration) {
+    DURATIONS.lock().unwrap_or_else(|e| e.into_inner())[step as usize] += duration;
+}
+
+// Runs `f`, adding the time it takes to `step`.
+pub fn time<T>(step: Step, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    record(step, started.elapsed());
+    result
+}
+
+pub fn add_input_bytes(bytes: u64) {
+    INPUT_BYTES.fetch_add(bytes, Ordering::Relaxed);
+}
+
+// Prints the time taken by each step and the size of the output against the
+// size of the inputs read from disk. Downloaded inputs and stdin are not
+// counted in the input size.
+pub fn print(total: Duration, output_bytes: u64) {
+    let durations = DURATIONS.lock().unwrap_or_else(|e| e.into_inner());
+    println!("Timings:");
+    for (step, name) in STEPS {
+        println!(
+            "  {:<17} {:>9.3} s",
+            name,
+            durations[step as usize].as_secs_f64()
+        );
+    }
+    println!("  {:<17} {:>9.3} s", "total", total.as_secs_f64());
+    let input_bytes = INPUT_BYTES.load(Ordering::Relaxed);
+    print!(
+        "Sizes: {} input bytes, {} output bytes",
+        input_bytes, output_bytes
+    );
+    if input_bytes > 0 {
+        print!(
+            " ({:.1}% of the input)",
+            output_bytes as f64 * 100.0 / input_bytes as f64
+        );
+    }
+    println!();
+}
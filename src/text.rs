@@ -0,0 +1,668 @@
+// Text of the pages, found by following the text operators of their content
+// and of the forms they draw, with where each glyph is drawn and where its
+// code is in the content streams. Text that is only in images, such as scans
+// without a text layer, is not found.
+use std::{collections::HashMap, ops::Range, rc::Rc};
+
+use anyhow::{Context, Result};
+use lopdf::{
+    Dictionary, Document, Encoding, Object, ObjectId,
+    content::{Content, Operation},
+};
+use regex::Regex;
+
+use crate::page::get_inherited;
+
+type Matrix = [f32; 6];
+
+const IDENTITY: Matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+// Extent of the glyphs below and above the baseline, relative to the font
+// size, as fonts do not reliably give theirs: that of most Latin fonts.
+const DESCENT: f32 = -0.2;
+const ASCENT: f32 = 0.8;
+
+// Width of the glyphs missing from the widths of their font, in thousandths
+// of the font size. The standard fonts usually come without widths: Courier
+// is exactly 600 wide, the others about 500, so that their glyphs are only
+// placed roughly.
+const DEFAULT_WIDTH: f32 = 500.0;
+const COURIER_WIDTH: f32 = 600.0;
+
+// Forms drawing forms are followed this deep, which real documents never
+// reach, so that forms drawing themselves do not loop forever.
+const MAX_FORM_DEPTH: usize = 16;
+
+// Replaces the text of glyphs which cannot be decoded.
+const UNKNOWN: &str = "\u{FFFD}";
+
+fn multiply(m: &Matrix, n: &Matrix) -> Matrix {
+    [
+        m[0] * n[0] + m[1] * n[2],
+        m[0] * n[1] + m[1] * n[3],
+        m[2] * n[0] + m[3] * n[2],
+        m[2] * n[1] + m[3] * n[3],
+        m[4] * n[0] + m[5] * n[2] + n[4],
+        m[4] * n[1] + m[5] * n[3] + n[5],
+    ]
+}
+
+fn transform(m: &Matrix, x: f32, y: f32) -> (f32, f32) {
+    (m[0] * x + m[2] * y + m[4], m[1] * x + m[3] * y + m[5])
+}
+
+fn translation(x: f32, y: f32) -> Matrix {
+    [1.0, 0.0, 0.0, 1.0, x, y]
+}
+
+// Moves to the start of the next line, offset from the start of the current
+// one, and returns the new text matrix.
+fn next_line(line_matrix: &mut Matrix, tx: f32, ty: f32) -> Matrix {
+    *line_matrix = multiply(&translation(tx, ty), line_matrix);
+    *line_matrix
+}
+
+// The bounding box of the unit square, where images are drawn, through `m`.
+fn unit_square(m: &Matrix) -> [f32; 4] {
+    [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)]
+        .map(|(x, y)| transform(m, x, y))
+        .iter()
+        .fold(
+            [f32::MAX, f32::MAX, f32::MIN, f32::MIN],
+            |[x0, y0, x1, y1], (x, y)| [x0.min(*x), y0.min(*y), x1.max(*x), y1.max(*y)],
+        )
+}
+
+fn numbers(operands: &[Object]) -> Vec<f32> {
+    operands
+        .iter()
+        .filter_map(|operand| operand.as_float().ok())
+        .collect()
+}
+
+fn matrix(operands: &[Object]) -> Option<Matrix> {
+    <[f32; 6]>::try_from(numbers(operands)).ok()
+}
+
+fn dictionary<'a>(doc: &'a Document, object: &'a Object) -> Option<&'a Dictionary> {
+    doc.dereference(object).ok()?.1.as_dict().ok()
+}
+
+enum Decoder<'a> {
+    // The text of the 256 codes of a simple font.
+    Table(Vec<String>),
+    Encoding(Encoding<'a>),
+    Unknown,
+}
+
+struct Font<'a> {
+    // Composite fonts have two-byte codes, simple fonts one-byte codes.
+    code_length: usize,
+    widths: HashMap<u32, f32>,
+    default_width: f32,
+    // Text space units per glyph space unit: 1/1000 but for Type 3 fonts.
+    scale: f32,
+    decoder: Decoder<'a>,
+}
+
+impl<'a> Font<'a> {
+    fn new(doc: &'a Document, font: &'a Dictionary) -> Font<'a> {
+        let subtype = font.get(b"Subtype").and_then(Object::as_name).ok();
+        if subtype == Some(b"Type0".as_slice()) {
+            return Font::composite(doc, font);
+        }
+
+        let mut widths = HashMap::new();
+        let first_char = font.get(b"FirstChar").and_then(Object::as_i64).unwrap_or(0);
+        if let Some(values) = font
+            .get(b"Widths")
+            .ok()
+            .and_then(|widths| doc.dereference(widths).ok()?.1.as_array().ok())
+        {
+            for (code, width) in (first_char..).zip(values) {
+                if let Ok(width) = doc
+                    .dereference(width)
+                    .and_then(|(_, width)| width.as_float())
+                {
+                    widths.insert(code as u32, width);
+                }
+            }
+        }
+        let default_width = font
+            .get(b"FontDescriptor")
+            .ok()
+            .and_then(|descriptor| dictionary(doc, descriptor))
+            .and_then(|descriptor| descriptor.get(b"MissingWidth").ok())
+            .and_then(|width| width.as_float().ok())
+            .unwrap_or_else(|| {
+                let base_font = font.get(b"BaseFont").and_then(Object::as_name);
+                if base_font.is_ok_and(|name| name.starts_with(b"Courier")) {
+                    COURIER_WIDTH
+                } else {
+                    DEFAULT_WIDTH
+                }
+            });
+        let scale = match subtype {
+            Some(b"Type3") => font
+                .get(b"FontMatrix")
+                .and_then(Object::as_array)
+                .ok()
+                .and_then(|values| values.first()?.as_float().ok())
+                .unwrap_or(0.001),
+            _ => 0.001,
+        };
+
+        // A `ToUnicode` map is the most reliable, but lopdf only reads it
+        // when the font has no named encoding.
+        let mut unicode_font;
+        let encoding_font = if font.has(b"ToUnicode") {
+            unicode_font = font.clone();
+            unicode_font.remove(b"Encoding");
+            &unicode_font
+        } else {
+            font
+        };
+        let decoder = match encoding_font.get_font_encoding(doc) {
+            Ok(encoding) => Decoder::Table(
+                (0..=255u8)
+                    .map(|code| {
+                        encoding
+                            .bytes_to_string(&[code])
+                            .unwrap_or_else(|_| UNKNOWN.to_string())
+                    })
+                    .collect(),
+            ),
+            Err(_) => Decoder::Unknown,
+        };
+
+        Font {
+            code_length: 1,
+            widths,
+            default_width,
+            scale,
+            decoder,
+        }
+    }
+
+    fn composite(doc: &'a Document, font: &'a Dictionary) -> Font<'a> {
+        let descendant = font
+            .get(b"DescendantFonts")
+            .ok()
+            .and_then(|fonts| doc.dereference(fonts).ok()?.1.as_array().ok())
+            .and_then(|fonts| dictionary(doc, fonts.first()?));
+        let default_width = descendant
+            .and_then(|descendant| descendant.get(b"DW").ok())
+            .and_then(|width| width.as_float().ok())
+            .unwrap_or(1000.0);
+
+        // `W` lists either `first [w1 w2 ...]` or `first last w`.
+        let mut widths = HashMap::new();
+        let values: &[Object] = descendant
+            .and_then(|descendant| descendant.get(b"W").ok())
+            .and_then(|values| doc.dereference(values).ok()?.1.as_array().ok())
+            .map_or(&[], Vec::as_slice);
+        let mut index = 0;
+        while index + 1 < values.len() {
+            let Ok(first) = values[index].as_i64() else {
+                break;
+            };
+            if let Ok((_, Object::Array(list))) = doc.dereference(&values[index + 1]) {
+                for (code, width) in (first..).zip(list) {
+                    if let Ok(width) = width.as_float() {
+                        widths.insert(code as u32, width);
+                    }
+                }
+                index += 2;
+            } else {
+                let (Ok(last), Some(Ok(width))) = (
+                    values[index + 1].as_i64(),
+                    values.get(index + 2).map(Object::as_float),
+                ) else {
+                    break;
+                };
+                for code in first..=last {
+                    widths.insert(code as u32, width);
+                }
+                index += 3;
+            }
+        }
+
+        let decoder = match font.get_font_encoding(doc) {
+            Ok(Encoding::SimpleEncoding(_)) | Err(_) => Decoder::Unknown,
+            Ok(encoding) => Decoder::Encoding(encoding),
+        };
+        Font {
+            code_length: 2,
+            widths,
+            default_width,
+            scale: 0.001,
+            decoder,
+        }
+    }
+
+    fn width(&self, code: u32) -> f32 {
+        self.widths
+            .get(&code)
+            .copied()
+            .unwrap_or(self.default_width)
+            * self.scale
+    }
+
+    fn text(&self, code: &[u8]) -> Option<String> {
+        match &self.decoder {
+            Decoder::Table(table) => table.get(usize::from(*code.first()?)).cloned(),
+            Decoder::Encoding(encoding) => encoding.bytes_to_string(code).ok(),
+            Decoder::Unknown => None,
+        }
+    }
+}
+
+// A glyph shown on a page, and where its code is in the content streams.
+pub(crate) struct Glyph {
+    // The page for the page's own content, or the form.
+    pub(crate) stream: ObjectId,
+    pub(crate) operation: usize,
+    // The index of the string in the array of `TJ`, 0 for the other operators.
+    pub(crate) item: usize,
+    pub(crate) bytes: Range<usize>,
+    // The `TJ` adjustment moving the following text as much as the glyph did.
+    pub(crate) adjustment: f32,
+    pub(crate) text: String,
+    pub(crate) rect: [f32; 4],
+    origin: (f32, f32),
+    end: (f32, f32),
+    direction: (f32, f32),
+    size: f32,
+}
+
+// An image drawn on a page: its image XObject, none for inline images, and
+// the bounding box of where it is drawn.
+pub(crate) struct DrawnImage {
+    pub(crate) id: Option<ObjectId>,
+    pub(crate) rect: [f32; 4],
+}
+
+#[derive(Clone)]
+struct GraphicsState<'a> {
+    ctm: Matrix,
+    font: Option<Rc<Font<'a>>>,
+    font_size: f32,
+    char_spacing: f32,
+    word_spacing: f32,
+    horizontal_scaling: f32,
+    leading: f32,
+    rise: f32,
+}
+
+impl GraphicsState<'_> {
+    fn new(ctm: Matrix) -> Self {
+        GraphicsState {
+            ctm,
+            font: None,
+            font_size: 0.0,
+            char_spacing: 0.0,
+            word_spacing: 0.0,
+            horizontal_scaling: 1.0,
+            leading: 0.0,
+            rise: 0.0,
+        }
+    }
+}
+
+pub(crate) struct TextWalker<'a> {
+    doc: &'a Document,
+    // The operations of the pages and forms read so far.
+    pub(crate) contents: HashMap<ObjectId, Rc<Vec<Operation>>>,
+    fonts: HashMap<ObjectId, Rc<Font<'a>>>,
+}
+
+// The glyphs of a page, in the order they are drawn.
+pub(crate) struct PageText {
+    pub(crate) glyphs: Vec<Glyph>,
+    // Whether some glyphs have no known text, so that the page may hold more
+    // text than found.
+    pub(crate) undecoded: bool,
+    // Where the images are drawn, which text operators do not change.
+    pub(crate) images: Vec<DrawnImage>,
+}
+
+impl<'a> TextWalker<'a> {
+    pub(crate) fn new(doc: &'a Document) -> Self {
+        TextWalker {
+            doc,
+            contents: HashMap::new(),
+            fonts: HashMap::new(),
+        }
+    }
+
+    fn operations(
+        &mut self,
+        id: ObjectId,
+        content: impl FnOnce() -> Vec<u8>,
+    ) -> Result<Rc<Vec<Operation>>> {
+        if let Some(operations) = self.contents.get(&id) {
+            return Ok(operations.clone());
+        }
+        let operations = Rc::new(Content::decode(&content())?.operations);
+        self.contents.insert(id, operations.clone());
+        Ok(operations)
+    }
+
+    fn font(&mut self, resources: &'a Dictionary, name: &[u8]) -> Option<Rc<Font<'a>>> {
+        let fonts = dictionary(self.doc, resources.get(b"Font").ok()?)?;
+        let font = fonts.get(name).ok()?;
+        let Ok(id) = font.as_reference() else {
+            return Some(Rc::new(Font::new(self.doc, font.as_dict().ok()?)));
+        };
+        if let Some(font) = self.fonts.get(&id) {
+            return Some(font.clone());
+        }
+        let font = Rc::new(Font::new(self.doc, self.doc.get_dictionary(id).ok()?));
+        self.fonts.insert(id, font.clone());
+        Some(font)
+    }
+
+    pub(crate) fn page_text(&mut self, page_id: ObjectId) -> Result<PageText> {
+        let doc = self.doc;
+        let operations = self.operations(page_id, || {
+            let mut content = Vec::new();
+            for content_id in doc.get_page_contents(page_id) {
+                if let Ok(stream) = doc.get_object(content_id).and_then(Object::as_stream) {
+                    content.extend(stream.get_plain_content().unwrap_or_default());
+                    content.push(b'\n');
+                }
+            }
+            content
+        })?;
+        let resources = get_inherited(doc, page_id, b"Resources")
+            .and_then(|resources| resources.as_dict().ok());
+        let mut text = PageText {
+            glyphs: Vec::new(),
+            undecoded: false,
+            images: Vec::new(),
+        };
+        self.walk(page_id, &operations, resources, IDENTITY, &mut text, 0)?;
+        Ok(text)
+    }
+
+    fn walk(
+        &mut self,
+        stream: ObjectId,
+        operations: &[Operation],
+        resources: Option<&'a Dictionary>,
+        ctm: Matrix,
+        text: &mut PageText,
+        depth: usize,
+    ) -> Result<()> {
+        let mut state = GraphicsState::new(ctm);
+        let mut saved = Vec::new();
+        let mut text_matrix = IDENTITY;
+        let mut line_matrix = IDENTITY;
+
+        for (index, operation) in operations.iter().enumerate() {
+            let operands = operation.operands.as_slice();
+            match (operation.operator.as_str(), numbers(operands).as_slice()) {
+                ("q", _) => saved.push(state.clone()),
+                ("Q", _) => state = saved.pop().unwrap_or(state),
+                ("cm", _) => {
+                    if let Some(m) = matrix(operands) {
+                        state.ctm = multiply(&m, &state.ctm);
+                    }
+                }
+                ("BT", _) => {
+                    text_matrix = IDENTITY;
+                    line_matrix = IDENTITY;
+                }
+                ("Tc", [value]) => state.char_spacing = *value,
+                ("Tw", [value]) => state.word_spacing = *value,
+                ("Tz", [value]) => state.horizontal_scaling = value / 100.0,
+                ("TL", [value]) => state.leading = *value,
+                ("Ts", [value]) => state.rise = *value,
+                ("Tf", [size]) => {
+                    state.font = operands
+                        .first()
+                        .and_then(|name| name.as_name().ok())
+                        .zip(resources)
+                        .and_then(|(name, resources)| self.font(resources, name));
+                    state.font_size = *size;
+                }
+                ("Td", [tx, ty]) => text_matrix = next_line(&mut line_matrix, *tx, *ty),
+                ("TD", [tx, ty]) => {
+                    state.leading = -ty;
+                    text_matrix = next_line(&mut line_matrix, *tx, *ty);
+                }
+                ("Tm", _) => {
+                    if let Some(m) = matrix(operands) {
+                        text_matrix = m;
+                        line_matrix = m;
+                    }
+                }
+                ("T*", _) => text_matrix = next_line(&mut line_matrix, 0.0, -state.leading),
+                ("Tj", _) | ("'", _) | ("\"", _) => {
+                    if let ("\"", [word_spacing, char_spacing, ..]) =
+                        (operation.operator.as_str(), &numbers(operands)[..])
+                    {
+                        state.word_spacing = *word_spacing;
+                        state.char_spacing = *char_spacing;
+                    }
+                    if operation.operator != "Tj" {
+                        text_matrix = next_line(&mut line_matrix, 0.0, -state.leading);
+                    }
+                    if let Some(Ok(bytes)) = operands.last().map(Object::as_str) {
+                        let location = (stream, index, 0);
+                        show(&state, &mut text_matrix, bytes, location, text);
+                    }
+                }
+                ("TJ", _) => {
+                    let items = operands.first().and_then(|items| items.as_array().ok());
+                    for (item, object) in items.into_iter().flatten().enumerate() {
+                        match object {
+                            Object::String(bytes, _) => {
+                                show(&state, &mut text_matrix, bytes, (stream, index, item), text);
+                            }
+                            _ => {
+                                if let Ok(adjustment) = object.as_float() {
+                                    let tx = -adjustment / 1000.0
+                                        * state.font_size
+                                        * state.horizontal_scaling;
+                                    text_matrix = multiply(&translation(tx, 0.0), &text_matrix);
+                                }
+                            }
+                        }
+                    }
+                }
+                ("BI", _) => text.images.push(DrawnImage {
+                    id: None,
+                    rect: unit_square(&state.ctm),
+                }),
+                ("Do", _) if depth < MAX_FORM_DEPTH => {
+                    let form_id = operands
+                        .first()
+                        .and_then(|name| name.as_name().ok())
+                        .zip(resources)
+                        .and_then(|(name, resources)| {
+                            let xobjects = dictionary(self.doc, resources.get(b"XObject").ok()?)?;
+                            xobjects.get(name).ok()?.as_reference().ok()
+                        });
+                    let Some(form_id) = form_id else {
+                        continue;
+                    };
+                    let Ok(form) = self.doc.get_object(form_id).and_then(Object::as_stream) else {
+                        continue;
+                    };
+                    match form.dict.get(b"Subtype").and_then(Object::as_name).ok() {
+                        Some(b"Form") => {}
+                        Some(b"Image") => {
+                            text.images.push(DrawnImage {
+                                id: Some(form_id),
+                                rect: unit_square(&state.ctm),
+                            });
+                            continue;
+                        }
+                        _ => continue,
+                    }
+                    let form_matrix = form
+                        .dict
+                        .get(b"Matrix")
+                        .and_then(Object::as_array)
+                        .ok()
+                        .and_then(|values| matrix(values))
+                        .unwrap_or(IDENTITY);
+                    let form_resources = form
+                        .dict
+                        .get(b"Resources")
+                        .ok()
+                        .and_then(|resources| dictionary(self.doc, resources))
+                        .or(resources);
+                    let form_operations = self
+                        .operations(form_id, || form.get_plain_content().unwrap_or_default())
+                        .with_context(|| {
+                            format!("cannot read the form {} {} R", form_id.0, form_id.1)
+                        })?;
+                    self.walk(
+                        form_id,
+                        &form_operations,
+                        form_resources,
+                        multiply(&form_matrix, &state.ctm),
+                        text,
+                        depth + 1,
+                    )?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+// Adds the glyphs of a string shown with the state's font to the page text,
+// moving the text matrix past them.
+fn show(
+    state: &GraphicsState,
+    text_matrix: &mut Matrix,
+    bytes: &[u8],
+    (stream, operation, item): (ObjectId, usize, usize),
+    text: &mut PageText,
+) {
+    let Some(font) = &state.font else {
+        return;
+    };
+    let font_size = state.font_size;
+    let scaling = state.horizontal_scaling;
+    for (position, code) in bytes.chunks(font.code_length).enumerate() {
+        let start = position * font.code_length;
+        let value = code
+            .iter()
+            .fold(0u32, |value, byte| value << 8 | u32::from(*byte));
+        let width = font.width(value);
+        let word_spacing = if code == [b' '] {
+            state.word_spacing
+        } else {
+            0.0
+        };
+        let advance = width * font_size + state.char_spacing + word_spacing;
+
+        let rendering = multiply(
+            &[font_size * scaling, 0.0, 0.0, font_size, 0.0, state.rise],
+            &multiply(text_matrix, &state.ctm),
+        );
+        let corners = [
+            (0.0, DESCENT),
+            (width, DESCENT),
+            (0.0, ASCENT),
+            (width, ASCENT),
+        ]
+        .map(|(x, y)| transform(&rendering, x, y));
+        let rect = corners.iter().fold(
+            [f32::MAX, f32::MAX, f32::MIN, f32::MIN],
+            |[x0, y0, x1, y1], (x, y)| [x0.min(*x), y0.min(*y), x1.max(*x), y1.max(*y)],
+        );
+        let origin = transform(&rendering, 0.0, 0.0);
+        let size = (rendering[0] * rendering[3] - rendering[1] * rendering[2])
+            .abs()
+            .sqrt();
+        let length = rendering[0].hypot(rendering[1]);
+        let direction = if length > 0.0 {
+            (rendering[0] / length, rendering[1] / length)
+        } else {
+            (1.0, 0.0)
+        };
+
+        *text_matrix = multiply(&translation(advance * scaling, 0.0), text_matrix);
+        let end = transform(&multiply(text_matrix, &state.ctm), 0.0, state.rise);
+
+        let glyph_text = font.text(code);
+        text.undecoded |= glyph_text.is_none();
+        text.glyphs.push(Glyph {
+            stream,
+            operation,
+            item,
+            bytes: start..start + code.len(),
+            adjustment: if font_size == 0.0 {
+                0.0
+            } else {
+                -advance * 1000.0 / font_size
+            },
+            text: glyph_text.unwrap_or_else(|| UNKNOWN.to_string()),
+            rect,
+            origin,
+            end,
+            direction,
+            size,
+        });
+    }
+}
+
+// The text of a page, with the byte range of every glyph in it and the line
+// it is on. Glyphs apart from the previous one count as separated by a space,
+// or by a line break when not on its baseline, so that patterns can use `\b`
+// and `\s`.
+pub(crate) struct TextLayout {
+    pub(crate) text: String,
+    pub(crate) spans: Vec<Range<usize>>,
+    pub(crate) lines: Vec<usize>,
+}
+
+impl PageText {
+    pub(crate) fn layout(&self) -> TextLayout {
+        let glyphs = &self.glyphs;
+        let mut text = String::new();
+        let mut spans = Vec::new();
+        let mut lines = Vec::new();
+        let mut line = 0;
+        for (index, glyph) in glyphs.iter().enumerate() {
+            if let Some(previous) = index.checked_sub(1).map(|index| &glyphs[index]) {
+                let (dx, dy) = (
+                    glyph.origin.0 - previous.end.0,
+                    glyph.origin.1 - previous.end.1,
+                );
+                let (ux, uy) = previous.direction;
+                if (dy * ux - dx * uy).abs() > previous.size * 0.5 {
+                    text.push('\n');
+                    line += 1;
+                } else if (dx * ux + dy * uy).abs() > previous.size * 0.25 {
+                    text.push(' ');
+                }
+            }
+            spans.push(text.len()..text.len() + glyph.text.len());
+            lines.push(line);
+            text.push_str(&glyph.text);
+        }
+        TextLayout { text, spans, lines }
+    }
+}
+
+// The numbers (1-based) of the pages whose text matches `pattern`. Pages
+// whose content cannot be read do not match.
+pub fn matching_pages(doc: &Document, pattern: &Regex) -> Vec<u32> {
+    let mut walker = TextWalker::new(doc);
+    doc.get_pages()
+        .into_iter()
+        .filter(|(_, page_id)| {
+            walker
+                .page_text(*page_id)
+                .is_ok_and(|text| pattern.is_match(&text.layout().text))
+        })
+        .map(|(page_number, _)| page_number)
+        .collect()
+}
@@ -0,0 +1,239 @@
+use anyhow::Result;
+use lopdf::Document;
+use printpdf::{
+    BuiltinFont, CustomPdfConformance, IndirectFontRef, Mm, PdfConformance, PdfDocument,
+    PdfDocumentReference, PdfLayerReference,
+};
+
+const PAGE_WIDTH_IN_MM: f32 = 210.0;
+const PAGE_HEIGHT_IN_MM: f32 = 297.0;
+const MARGIN_IN_MM: f32 = 20.0;
+const BODY_FONT_SIZE: f32 = 11.0;
+const LINE_SPACING: f32 = 1.35;
+const MM_PER_POINT: f32 = 0.352_778;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Style {
+    Regular,
+    Bold,
+    Monospace,
+}
+
+impl Style {
+    // Average glyph width as a fraction of the font size, used to wrap lines:
+    // built-in fonts come without metrics, and Courier is exactly 0.6.
+    fn average_char_width(self) -> f32 {
+        match self {
+            Style::Regular => 0.5,
+            Style::Bold => 0.55,
+            Style::Monospace => 0.6,
+        }
+    }
+}
+
+// A block of text laid out as one or more lines.
+struct Block {
+    text: String,
+    style: Style,
+    font_size: f32,
+    indent_in_mm: f32,
+    space_before_in_mm: f32,
+}
+
+impl Block {
+    fn new(text: String, style: Style) -> Self {
+        Block {
+            text,
+            style,
+            font_size: BODY_FONT_SIZE,
+            indent_in_mm: 0.0,
+            space_before_in_mm: 0.0,
+        }
+    }
+}
+
+fn wrap(text: &str, max_chars: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split(' ') {
+        let line_len = line.chars().count();
+        if line_len > 0 && line_len + 1 + word.chars().count() > max_chars {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+        // Words longer than a line are cut.
+        while line.chars().count() > max_chars {
+            let rest = line.split_off(line.char_indices().nth(max_chars).unwrap().0);
+            lines.push(std::mem::replace(&mut line, rest));
+        }
+    }
+    lines.push(line);
+    lines
+}
+
+fn plain_text_blocks(text: &str) -> Vec<Block> {
+    text.lines()
+        .map(|line| Block::new(line.replace('\t', "    "), Style::Monospace))
+        .collect()
+}
+
+// Inline markup is dropped rather than rendered: `**bold**`, `` `code` `` and
+// `[text](url)` become `bold`, `code` and `text (url)`.
+fn strip_inline_markdown(text: &str) -> String {
+    let mut text = text.replace("**", "").replace("__", "").replace('`', "");
+    while let Some(start) = text.find('[') {
+        let Some(middle) = text[start..].find("](").map(|i| i + start) else {
+            break;
+        };
+        let Some(end) = text[middle..].find(')').map(|i| i + middle) else {
+            break;
+        };
+        let replacement = format!("{} ({})", &text[start + 1..middle], &text[middle + 2..end]);
+        text.replace_range(start..=end, &replacement);
+    }
+    text
+}
+
+fn markdown_blocks(text: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut in_code_block = false;
+
+    let flush = |paragraph: &mut Vec<&str>, blocks: &mut Vec<Block>| {
+        if !paragraph.is_empty() {
+            let mut block = Block::new(strip_inline_markdown(&paragraph.join(" ")), Style::Regular);
+            block.space_before_in_mm = 2.0;
+            blocks.push(block);
+            paragraph.clear();
+        }
+    };
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") {
+            flush(&mut paragraph, &mut blocks);
+            in_code_block = !in_code_block;
+            continue;
+        }
+        if in_code_block {
+            blocks.push(Block::new(line.replace('\t', "    "), Style::Monospace));
+            continue;
+        }
+        if trimmed.is_empty() {
+            flush(&mut paragraph, &mut blocks);
+            continue;
+        }
+
+        let heading_level = trimmed.chars().take_while(|&c| c == '#').count();
+        let list_item = ["- ", "* ", "+ "]
+            .iter()
+            .find_map(|marker| trimmed.strip_prefix(marker));
+        if (1..=6).contains(&heading_level) && trimmed[heading_level..].starts_with(' ') {
+            flush(&mut paragraph, &mut blocks);
+            let mut block = Block::new(
+                strip_inline_markdown(trimmed[heading_level..].trim()),
+                Style::Bold,
+            );
+            block.font_size = match heading_level {
+                1 => 18.0,
+                2 => 15.0,
+                3 => 13.0,
+                _ => BODY_FONT_SIZE,
+            };
+            block.space_before_in_mm = 4.0;
+            blocks.push(block);
+        } else if let Some(item) = list_item {
+            flush(&mut paragraph, &mut blocks);
+            let indent = (line.len() - line.trim_start().len()) as f32;
+            let mut block =
+                Block::new(format!("• {}", strip_inline_markdown(item)), Style::Regular);
+            block.indent_in_mm = 5.0 + indent * 2.0;
+            blocks.push(block);
+        } else if trimmed.chars().all(|c| c == '-' || c == '*' || c == '_') && trimmed.len() >= 3 {
+            // Horizontal rules only separate paragraphs.
+            flush(&mut paragraph, &mut blocks);
+        } else {
+            paragraph.push(trimmed);
+        }
+    }
+    flush(&mut paragraph, &mut blocks);
+    blocks
+}
+
+struct Fonts {
+    regular: IndirectFontRef,
+    bold: IndirectFontRef,
+    monospace: IndirectFontRef,
+}
+
+impl Fonts {
+    fn get(&self, style: Style) -> &IndirectFontRef {
+        match style {
+            Style::Regular => &self.regular,
+            Style::Bold => &self.bold,
+            Style::Monospace => &self.monospace,
+        }
+    }
+}
+
+fn add_page(doc: &PdfDocumentReference) -> PdfLayerReference {
+    let (page, layer) = doc.add_page(Mm(PAGE_WIDTH_IN_MM), Mm(PAGE_HEIGHT_IN_MM), "Layer1");
+    doc.get_page(page).get_layer(layer)
+}
+
+fn render(title: &str, blocks: Vec<Block>) -> Result<Document> {
+    // The default PDF/X conformance embeds an ICC profile in every document,
+    // which is pointless for a few pages of black text.
+    let doc =
+        PdfDocument::empty(title).with_conformance(PdfConformance::Custom(CustomPdfConformance {
+            allows_default_fonts: true,
+            ..Default::default()
+        }));
+    let fonts = Fonts {
+        regular: doc.add_builtin_font(BuiltinFont::Helvetica)?,
+        bold: doc.add_builtin_font(BuiltinFont::HelveticaBold)?,
+        monospace: doc.add_builtin_font(BuiltinFont::Courier)?,
+    };
+
+    let mut layer = add_page(&doc);
+    let mut y = PAGE_HEIGHT_IN_MM - MARGIN_IN_MM;
+    for block in blocks {
+        let line_height = block.font_size * LINE_SPACING * MM_PER_POINT;
+        let width = PAGE_WIDTH_IN_MM - 2.0 * MARGIN_IN_MM - block.indent_in_mm;
+        let char_width = block.font_size * block.style.average_char_width() * MM_PER_POINT;
+        let max_chars = ((width / char_width) as usize).max(1);
+
+        y -= block.space_before_in_mm;
+        for line in wrap(&block.text, max_chars) {
+            if y - line_height < MARGIN_IN_MM {
+                layer = add_page(&doc);
+                y = PAGE_HEIGHT_IN_MM - MARGIN_IN_MM;
+            }
+            y -= line_height;
+            layer.use_text(
+                line,
+                block.font_size,
+                Mm(MARGIN_IN_MM + block.indent_in_mm),
+                Mm(y),
+                fonts.get(block.style),
+            );
+        }
+    }
+
+    let bytes = doc.save_to_bytes()?;
+    Ok(Document::load_mem(&bytes)?)
+}
+
+// Typesets plain text (in a monospaced font, keeping the line breaks) or
+// basic Markdown (headings, paragraphs, lists and code blocks) on A4 pages.
+pub fn text_to_doc(title: &str, text: &str, markdown: bool) -> Result<Document> {
+    let blocks = if markdown {
+        markdown_blocks(text)
+    } else {
+        plain_text_blocks(text)
+    };
+    render(title, blocks)
+}
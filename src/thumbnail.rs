@@ -0,0 +1,259 @@
+// `/Thumb` images of the pages, shown by viewers in their sidebar without
+// rendering the pages. There is no PDF renderer here: thumbnails are drawn
+// from what the text walker finds on the page, the images reduced and the
+// text as gray lines, which is enough to tell the pages apart at this size.
+use image::{
+    DynamicImage, GrayImage, ImageFormat, Rgb, RgbImage,
+    imageops::{self, FilterType},
+};
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+
+use crate::{stamp::page_box, text::TextWalker};
+
+// Thumbnails are meant to be small, the specification suggests about 106
+// pixels on their longest side.
+const THUMBNAIL_SIZE: u32 = 106;
+
+fn name<'a>(dictionary: &'a Dictionary, key: &[u8]) -> Option<&'a [u8]> {
+    dictionary.get(key).and_then(Object::as_name).ok()
+}
+
+pub fn decode_image(stream: &Stream) -> Option<DynamicImage> {
+    let width = stream.dict.get(b"Width").and_then(Object::as_i64).ok()? as u32;
+    let height = stream.dict.get(b"Height").and_then(Object::as_i64).ok()? as u32;
+    match name(&stream.dict, b"Filter") {
+        Some(b"DCTDecode") => {
+            image::load_from_memory_with_format(&stream.content, ImageFormat::Jpeg).ok()
+        }
+        Some(b"FlateDecode") | None => {
+            if stream
+                .dict
+                .get(b"BitsPerComponent")
+                .and_then(Object::as_i64)
+                .ok()?
+                != 8
+            {
+                return None;
+            }
+            let data = if stream.dict.has(b"Filter") {
+                stream.decompressed_content().ok()?
+            } else {
+                stream.content.clone()
+            };
+            match name(&stream.dict, b"ColorSpace")? {
+                b"DeviceRGB" => RgbImage::from_raw(width, height, data).map(DynamicImage::from),
+                b"DeviceGray" => GrayImage::from_raw(width, height, data).map(DynamicImage::from),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// Color of the lines standing for the text, and of the images which cannot
+// be decoded.
+const TEXT_COLOR: Rgb<u8> = Rgb([128, 128, 128]);
+const IMAGE_COLOR: Rgb<u8> = Rgb([208, 208, 208]);
+
+// Draws the page at thumbnail size: the images it shows, reduced, and its
+// glyphs as gray boxes, which at this size look like the lines of text.
+// Vector graphics are left out. A page whose content cannot be read is blank.
+fn render(walker: &mut TextWalker, doc: &Document, page_id: ObjectId) -> RgbImage {
+    let [x0, y0, x1, y1] = page_box(doc, page_id);
+    let (left, top) = (x0.min(x1), y0.max(y1));
+    let (width, height) = ((x1 - x0).abs().max(1.0), (y1 - y0).abs().max(1.0));
+    let scale = THUMBNAIL_SIZE as f32 / width.max(height);
+    let mut canvas = RgbImage::from_pixel(
+        ((width * scale).round() as u32).max(1),
+        ((height * scale).round() as u32).max(1),
+        Rgb([255, 255, 255]),
+    );
+    let Ok(text) = walker.page_text(page_id) else {
+        return canvas;
+    };
+
+    // The pixels covered by a rectangle of the page, at least one.
+    let pixels = |[x0, y0, x1, y1]: [f32; 4]| {
+        let column = ((x0 - left) * scale).floor() as i64;
+        let row = ((top - y1) * scale).floor() as i64;
+        let columns = (((x1 - x0) * scale).round() as u32).max(1);
+        let rows = (((y1 - y0) * scale).round() as u32).max(1);
+        (column, row, columns, rows)
+    };
+    for image in &text.images {
+        let (column, row, columns, rows) = pixels(image.rect);
+        let decoded = image
+            .id
+            .and_then(|id| doc.get_object(id).and_then(Object::as_stream).ok())
+            .and_then(decode_image);
+        let reduced = match decoded {
+            Some(decoded) => decoded
+                .resize_exact(columns, rows, FilterType::Triangle)
+                .into_rgb8(),
+            None => RgbImage::from_pixel(columns, rows, IMAGE_COLOR),
+        };
+        imageops::overlay(&mut canvas, &reduced, column, row);
+    }
+    for glyph in text
+        .glyphs
+        .iter()
+        .filter(|glyph| !glyph.text.trim().is_empty())
+    {
+        let (column, row, columns, rows) = pixels(glyph.rect);
+        let line = RgbImage::from_pixel(columns, rows, TEXT_COLOR);
+        imageops::overlay(&mut canvas, &line, column, row);
+    }
+    canvas
+}
+
+fn thumbnail(image: RgbImage) -> Stream {
+    let dictionary = Dictionary::from_iter(vec![
+        ("Width", Object::Integer(image.width().into())),
+        ("Height", Object::Integer(image.height().into())),
+        ("ColorSpace", Object::Name(b"DeviceRGB".to_vec())),
+        ("BitsPerComponent", Object::Integer(8)),
+    ]);
+    let mut stream = Stream::new(dictionary, image.into_raw());
+    let _ = stream.compress();
+    stream
+}
+
+// Embeds a thumbnail in every page, and returns how many pages got one.
+pub fn add_thumbnails(doc: &mut Document) -> usize {
+    let thumbnails: Vec<(ObjectId, Stream)> = {
+        let mut walker = TextWalker::new(doc);
+        doc.get_pages()
+            .into_values()
+            .map(|page_id| (page_id, thumbnail(render(&mut walker, doc, page_id))))
+            .collect()
+    };
+    let mut added = 0;
+    for (page_id, thumbnail) in thumbnails {
+        let thumbnail_id = doc.add_object(thumbnail);
+        if let Ok(page) = doc.get_dictionary_mut(page_id) {
+            page.set("Thumb", thumbnail_id);
+            added += 1;
+        }
+    }
+    added
+}
+
+// Removes the thumbnails embedded by the generators of the inputs, which
+// viewers can make again, and returns how many pages had one.
+pub fn remove_thumbnails(doc: &mut Document) -> usize {
+    let mut removed = 0;
+    for page_id in doc.get_pages().into_values() {
+        let Some(thumbnail) = doc
+            .get_dictionary_mut(page_id)
+            .ok()
+            .and_then(|page| page.remove(b"Thumb"))
+        else {
+            continue;
+        };
+        if let Ok(thumbnail_id) = thumbnail.as_reference() {
+            doc.objects.remove(&thumbnail_id);
+        }
+        removed += 1;
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::dictionary;
+
+    use super::*;
+
+    // A one page document of 200x100 points drawing `content` with Helvetica
+    // as `F1` and a black 1x1 image as `Im1`.
+    fn document(content: &str) -> (Document, ObjectId) {
+        let mut doc = Document::with_version("1.7");
+        let font_id = doc.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+        });
+        let image_id = doc.add_object(Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => 1,
+                "Height" => 1,
+                "ColorSpace" => "DeviceGray",
+                "BitsPerComponent" => 8,
+            },
+            vec![0],
+        ));
+        let content_id =
+            doc.add_object(Stream::new(Dictionary::new(), content.as_bytes().to_vec()));
+        let pages_id = doc.new_object_id();
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 200.into(), 100.into()],
+            "Contents" => content_id,
+            "Resources" => dictionary! {
+                "Font" => dictionary! { "F1" => font_id },
+                "XObject" => dictionary! { "Im1" => image_id },
+            },
+        });
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        doc.trailer.set("Root", catalog_id);
+        (doc, page_id)
+    }
+
+    fn thumbnail_of(doc: &mut Document, page_id: ObjectId) -> RgbImage {
+        assert_eq!(add_thumbnails(doc), 1);
+        let thumbnail_id = doc
+            .get_dictionary(page_id)
+            .unwrap()
+            .get(b"Thumb")
+            .and_then(Object::as_reference)
+            .unwrap();
+        let stream = doc.get_object(thumbnail_id).unwrap().as_stream().unwrap();
+        decode_image(stream).unwrap().into_rgb8()
+    }
+
+    #[test]
+    fn draws_the_text_as_gray_lines() {
+        let (mut doc, page_id) = document("BT /F1 20 Tf 10 50 Td (Hello) Tj ET");
+        let thumbnail = thumbnail_of(&mut doc, page_id);
+        assert_eq!(thumbnail.dimensions(), (THUMBNAIL_SIZE, THUMBNAIL_SIZE / 2));
+        // The text is 20 points high from 46 to 66, about 11 pixels.
+        let rows: Vec<u32> = (0..thumbnail.height())
+            .filter(|row| *thumbnail.get_pixel(10, *row) == TEXT_COLOR)
+            .collect();
+        assert_eq!(rows, (18..29).collect::<Vec<_>>());
+        assert_eq!(*thumbnail.get_pixel(100, 25), Rgb([255, 255, 255]));
+    }
+
+    #[test]
+    fn reduces_the_images() {
+        let (mut doc, page_id) = document("q 100 0 0 100 100 0 cm /Im1 Do Q");
+        let thumbnail = thumbnail_of(&mut doc, page_id);
+        assert_eq!(*thumbnail.get_pixel(80, 25), Rgb([0, 0, 0]));
+        assert_eq!(*thumbnail.get_pixel(20, 25), Rgb([255, 255, 255]));
+    }
+
+    #[test]
+    fn leaves_unreadable_pages_blank() {
+        let (mut doc, page_id) = document("BT (unterminated");
+        let thumbnail = thumbnail_of(&mut doc, page_id);
+        assert!(
+            thumbnail
+                .pixels()
+                .all(|pixel| *pixel == Rgb([255, 255, 255]))
+        );
+    }
+}
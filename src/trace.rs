@@ -0,0 +1,18 @@
+// Spans around the expensive steps of a merge with the `tracing` feature, for
+// profiling with the subscribers of the `tracing` ecosystem. Without the
+// feature, the spans and their fields compile to nothing.
+#[cfg(feature = "tracing")]
+#[macro_export]
+macro_rules! trace_span {
+    ($($args:tt)*) => {
+        ::tracing::info_span!($($args)*).entered()
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+#[macro_export]
+macro_rules! trace_span {
+    ($($args:tt)*) => {
+        ()
+    };
+}
@@ -0,0 +1,170 @@
+// Structural checks of a written document, read back to catch a broken file
+// before it is handed over: its page tree and its outline.
+use std::collections::HashSet;
+
+use lopdf::{Document, Object, ObjectId};
+
+use crate::outline::item_page;
+
+// Guards against loops and absurdly large trees, as in `outline`.
+const MAX_NODES: usize = 1_000_000;
+
+fn describe((number, generation): ObjectId) -> String {
+    format!("{} {} R", number, generation)
+}
+
+// Walks the page tree under `node_id`, returning the number of pages found.
+fn check_page_tree(
+    doc: &Document,
+    node_id: ObjectId,
+    parent_id: Option<ObjectId>,
+    visited: &mut HashSet<ObjectId>,
+    problems: &mut Vec<String>,
+) -> usize {
+    if visited.len() >= MAX_NODES || !visited.insert(node_id) {
+        problems.push(format!("the page tree loops through {}", describe(node_id)));
+        return 0;
+    }
+    let Ok(node) = doc.get_dictionary(node_id) else {
+        problems.push(format!(
+            "the page tree node {} is missing",
+            describe(node_id)
+        ));
+        return 0;
+    };
+    let parent = node.get(b"Parent").and_then(Object::as_reference).ok();
+    if parent_id.is_some() && parent != parent_id {
+        problems.push(format!(
+            "the page tree node {} does not point back to its parent",
+            describe(node_id)
+        ));
+    }
+
+    match node.get(b"Type").and_then(Object::as_name) {
+        Ok(b"Page") => 1,
+        Ok(b"Pages") => {
+            let Ok(kids) = node.get(b"Kids").and_then(Object::as_array) else {
+                problems.push(format!(
+                    "the page tree node {} has no Kids",
+                    describe(node_id)
+                ));
+                return 0;
+            };
+            let mut pages = 0;
+            for kid in kids {
+                match kid.as_reference() {
+                    Ok(kid_id) => {
+                        pages += check_page_tree(doc, kid_id, Some(node_id), visited, problems);
+                    }
+                    Err(_) => problems.push(format!(
+                        "the page tree node {} has a kid which is not a reference",
+                        describe(node_id)
+                    )),
+                }
+            }
+            let count = node.get(b"Count").and_then(Object::as_i64).ok();
+            if count != Some(pages as i64) {
+                problems.push(format!(
+                    "the page tree node {} counts {} pages but has {}",
+                    describe(node_id),
+                    count.map_or_else(|| "no".to_string(), |count| count.to_string()),
+                    pages
+                ));
+            }
+            pages
+        }
+        _ => {
+            problems.push(format!(
+                "the page tree node {} is neither a Page nor a Pages",
+                describe(node_id)
+            ));
+            0
+        }
+    }
+}
+
+// Walks the outline items of a level, checking that they point back to
+// their parent and that the ones going to a page of the document find it.
+// Named destinations cannot be looked up when the document is encrypted.
+fn check_outline_level(
+    doc: &Document,
+    parent_id: ObjectId,
+    pages: &HashSet<ObjectId>,
+    visited: &mut HashSet<ObjectId>,
+    problems: &mut Vec<String>,
+) {
+    let Ok(parent) = doc.get_dictionary(parent_id) else {
+        return;
+    };
+    let mut next = parent.get(b"First").and_then(Object::as_reference).ok();
+    let mut last = None;
+    while let Some(id) = next {
+        if visited.len() >= MAX_NODES || !visited.insert(id) {
+            problems.push(format!("the outline loops through {}", describe(id)));
+            return;
+        }
+        let Ok(item) = doc.get_dictionary(id) else {
+            problems.push(format!("the bookmark {} is missing", describe(id)));
+            return;
+        };
+        if item.get(b"Parent").and_then(Object::as_reference).ok() != Some(parent_id) {
+            problems.push(format!(
+                "the bookmark {} does not point back to its parent",
+                describe(id)
+            ));
+        }
+        let goes_to_page = item.has(b"Dest")
+            || item
+                .get(b"A")
+                .and_then(|action| doc.dereference(action))
+                .and_then(|(_, action)| action.as_dict())
+                .and_then(|action| action.get(b"S"))
+                .and_then(Object::as_name)
+                .is_ok_and(|action_type| action_type == b"GoTo");
+        if goes_to_page && !doc.is_encrypted() {
+            match item_page(doc, item) {
+                Some(page_id) if pages.contains(&page_id) => {}
+                _ => problems.push(format!(
+                    "the bookmark {} does not go to a page of the document",
+                    describe(id)
+                )),
+            }
+        }
+        check_outline_level(doc, id, pages, visited, problems);
+        last = Some(id);
+        next = item.get(b"Next").and_then(Object::as_reference).ok();
+    }
+    if parent.get(b"Last").and_then(Object::as_reference).ok() != last {
+        problems.push(format!(
+            "the Last bookmark of {} is not the end of its list",
+            describe(parent_id)
+        ));
+    }
+}
+
+// The structural problems of `doc`, one sentence each, none when it is sound.
+// `expected_pages` is the number of pages it was written with.
+pub fn verify_document(doc: &Document, expected_pages: usize) -> Vec<String> {
+    let mut problems = Vec::new();
+    let Ok(catalog) = doc.catalog() else {
+        return vec!["the document has no catalog".to_string()];
+    };
+    let Ok(pages_id) = catalog.get(b"Pages").and_then(Object::as_reference) else {
+        return vec!["the catalog has no page tree".to_string()];
+    };
+
+    let mut visited = HashSet::new();
+    let page_count = check_page_tree(doc, pages_id, None, &mut visited, &mut problems);
+    if page_count != expected_pages {
+        problems.push(format!(
+            "the document has {} pages instead of {}",
+            page_count, expected_pages
+        ));
+    }
+
+    if let Ok(outlines_id) = catalog.get(b"Outlines").and_then(Object::as_reference) {
+        let pages: HashSet<ObjectId> = doc.get_pages().into_values().collect();
+        check_outline_level(doc, outlines_id, &pages, &mut HashSet::new(), &mut problems);
+    }
+    problems
+}